@@ -0,0 +1,97 @@
+//! A process-wide "please stop" flag set from a SIGINT/SIGTERM handler, so a
+//! Ctrl-C or `systemctl stop` during a backup cycle can be noticed between
+//! phases instead of just killing the process wherever it happens to be --
+//! potentially mid-statefile-write or with an S3 repo left thawed.
+//!
+//! [`CancellationToken::install`] does the actual signal registration;
+//! [`CancellationToken::check`] is what [`crate::engine::backup_cycle`] calls
+//! between phases. [`CancellationToken::cancel`] lets a test trip the flag
+//! directly without sending a real signal.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::HalleyError;
+
+/// A cheaply-cloneable handle on the cancellation flag -- clones share the
+/// same underlying flag, so installing the signal handler once and cloning
+/// the token into every repo's cycle is the intended use, the same way
+/// [`crate::state::StateFile::lock`]'s guard is held once per run rather
+/// than re-acquired per repo.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// A token that's never cancelled unless [`CancellationToken::cancel`]
+    /// is called on it directly, for tests that want to exercise a phase's
+    /// cancellation check without registering a real signal handler.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers SIGINT and SIGTERM handlers that set this token's flag,
+    /// returning the token to thread through the run. Safe to call at most
+    /// once per signal per process -- [`signal_hook::flag::register`] itself
+    /// only ever sets a flag from the handler, which is the one thing this
+    /// crate can rely on being safe to do from a signal handler without a
+    /// dedicated async-signal-safe runtime.
+    pub fn install() -> Result<Self, HalleyError> {
+        let token = Self::new();
+        signal_hook::flag::register(signal_hook::consts::SIGINT, token.cancelled.clone())
+            .map_err(HalleyError::Io)?;
+        signal_hook::flag::register(signal_hook::consts::SIGTERM, token.cancelled.clone())
+            .map_err(HalleyError::Io)?;
+        Ok(token)
+    }
+
+    /// Trips the flag directly, the same way a delivered SIGINT/SIGTERM
+    /// would -- for tests, and for nothing else in production code.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// [`HalleyError::Cancelled`] if the flag is set, `Ok(())` otherwise --
+    /// meant to be called with `?` at each phase boundary in
+    /// [`crate::engine::backup_cycle`].
+    pub fn check(&self) -> Result<(), HalleyError> {
+        if self.is_cancelled() {
+            Err(HalleyError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_fresh_token_is_not_cancelled() {
+        let token = CancellationToken::new();
+        assert!(!token.is_cancelled());
+        assert!(token.check().is_ok());
+    }
+
+    #[test]
+    fn cancel_trips_the_flag_and_check_reports_it() {
+        let token = CancellationToken::new();
+        token.cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(token.check(), Err(HalleyError::Cancelled)));
+    }
+
+    #[test]
+    fn clones_share_the_same_flag() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        clone.cancel();
+        assert!(token.is_cancelled());
+    }
+}
@@ -0,0 +1,214 @@
+//! Command-line surface.
+
+use clap::{Parser, Subcommand};
+
+#[derive(Debug, Parser)]
+#[command(name = "halley", about = "Offsite backup manager using restic")]
+pub struct Cli {
+    /// Path to Halley's config file.
+    #[arg(long, global = true, default_value = "/etc/halley/config.toml")]
+    pub config: String,
+
+    /// Emit `backup_cycle`'s tracing spans (repo id, phase timing) to
+    /// stderr at INFO level, on top of the usual eprintln! status lines.
+    /// Off by default since a plain run's output is meant to stay readable
+    /// on a single-repo cron log; turn it on when a multi-repo run's
+    /// output needs to be untangled by repo.
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run a repository's full backup cycle: init if needed, back up,
+    /// forget/prune if configured, then archive to cold storage if
+    /// configured.
+    ///
+    /// Backs up every repo, oldest-last-backup first (see
+    /// [`crate::engine::due_repos`]), continuing past a per-repo failure,
+    /// when the repository name is omitted.
+    Backup {
+        /// Name of the repository. Backs up every repo, in order of how
+        /// overdue it is, when omitted.
+        repo: Option<String>,
+        /// Print the run report as JSON instead of a human-readable summary
+        /// line, for machine consumption (e.g. a cron job parsing the
+        /// outcome instead of scraping stdout).
+        #[arg(long)]
+        json: bool,
+        /// Preview the backup instead of running it: real change/size
+        /// numbers from `restic backup --dry-run` and what retention would
+        /// remove, without touching the repository or the statefile. Local
+        /// repositories only -- see [`crate::engine::dry_run_backup_cycle`].
+        #[arg(long)]
+        dry: bool,
+    },
+    /// Reports which repos are due for a backup right now and why, without
+    /// running one -- no restic/S3 call, no statefile write. For a
+    /// monitoring script asking "would `halley backup` do anything?" See
+    /// [`crate::engine::check_due`].
+    Check {
+        /// Print one JSON object per repo instead of a human-readable line.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Verify a repository's integrity on demand.
+    ///
+    /// Read-only: runs `restic check` and, for a cold-storage-backed repo,
+    /// lists its cold storage contents. Never touches snapshots or objects
+    /// beyond that (see [`crate::engine::verify_repo`]).
+    Verify {
+        /// Name of the repository. Verifies every repo when omitted.
+        repo: Option<String>,
+        /// Run `restic check --read-data-subset <pct>%` instead of a
+        /// metadata-only check.
+        #[arg(long, value_name = "PCT")]
+        read_data: Option<u8>,
+        /// Verify by restoring a random sample of files, not just checking
+        /// repository metadata.
+        #[arg(long)]
+        sample_restore: bool,
+        /// Run the check but don't record it in the statefile.
+        #[arg(long)]
+        dry: bool,
+    },
+    /// Render Halley's current per-repo state as a Markdown table, suitable
+    /// for pasting into a ticket.
+    ///
+    /// A snapshot of the latest recorded state, not a run history: Halley
+    /// only remembers each repo's *last* backup/verify (see
+    /// [`crate::state::RepoState`]), so there's no "runs this week" or
+    /// "data added this week" to report on yet (see
+    /// [`crate::report::render_markdown`]).
+    Report,
+    /// Print parsed `restic stats` output for a repository.
+    Stats {
+        /// Name of the repository, as it appears in the config.
+        repo: String,
+    },
+    /// Migrate a repository to a newer format, e.g. to enable compression.
+    Migrate {
+        /// Name of the repository, as it appears in the config.
+        repo: String,
+    },
+    /// Inspect Halley's persisted per-repo state.
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+    /// Gather a redacted config/state/restic-probe bundle for bug reports.
+    DebugDump {
+        /// Directory to write the bundle into. Created if it doesn't exist.
+        #[arg(long, value_name = "PATH")]
+        output: std::path::PathBuf,
+    },
+    /// Disaster-recovery helpers for S3-backed repositories.
+    S3 {
+        #[command(subcommand)]
+        command: S3Command,
+    },
+    /// Check the config for problems without running a backup.
+    ///
+    /// Always runs the same offline checks [`crate::config::RepoConfig::validate`]
+    /// already does at startup (e.g. a missing exclude file, an unresolvable
+    /// interval, two password sources set at once). With `--online` set, also
+    /// resolves each S3 repo's credentials for real, so a broken
+    /// `credential_command` or a stale key pair shows up here instead of at the
+    /// start of the next backup. Halley has no S3 client, so `--online` can't
+    /// confirm the bucket itself is reachable, just that credentials for it
+    /// resolve.
+    Validate {
+        /// Name of the repository. Checks every repo when omitted.
+        repo: Option<String>,
+        /// Also resolve S3 repos' credentials, not just check config shape.
+        #[arg(long)]
+        online: bool,
+        /// Print each repo's excludes with `preset:` entries expanded to
+        /// the concrete patterns restic will actually receive.
+        #[arg(long)]
+        show_effective: bool,
+    },
+    /// Run `restic forget` for a repository, bypassing `max_auto_forget`.
+    ///
+    /// Needed when a backup cycle refused to forget automatically because
+    /// the preview crossed `max_auto_forget`; run this once you've reviewed
+    /// the preview and are sure the retention policy is correct.
+    Forget {
+        /// Name of the repository, as it appears in the config.
+        repo: String,
+        /// Required: acknowledges the forget was reviewed and should run
+        /// regardless of `max_auto_forget`.
+        #[arg(long)]
+        confirm: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum S3Command {
+    /// Generate a scratch repo definition for cloning a repo's objects into
+    /// a different bucket, e.g. for a disaster-recovery drill.
+    ///
+    /// Only emits the repo definition TOML; Halley has no S3 client yet to
+    /// actually thaw the source or copy objects between buckets (see
+    /// [`crate::s3::generate_clone_repo_config`]), so the copy itself still
+    /// has to be done by hand (e.g. with `aws s3 sync`) before the emitted
+    /// repo definition is usable.
+    Clone {
+        /// Name of the source repository, as it appears in the config.
+        repo: String,
+        /// Bucket to clone the repository's objects into.
+        #[arg(long, value_name = "BUCKET")]
+        to_bucket: String,
+        /// Restrict the clone to objects under this prefix.
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+    /// Archives a repo's cold storage now, bypassing any `archive_delay_hours`
+    /// due-time still in the future.
+    ///
+    /// Meant for a repo whose delayed archive is left hot for a
+    /// verification or sample-restore, once that's done and there's no
+    /// reason to wait out the rest of the delay.
+    Archive {
+        /// Name of the repository, as it appears in the config.
+        repo: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum StateCommand {
+    /// Print the recorded state for one repository, or all of them.
+    Show {
+        /// Name of the repository. Shows every repo when omitted.
+        repo: Option<String>,
+    },
+    /// Move a statefile entry left behind by a repo rename onto its new
+    /// name, so backup/verify history isn't lost.
+    Adopt {
+        /// The repo name the state is currently recorded under.
+        from: String,
+        /// The repo name, as it now appears in the config, to move it to.
+        to: String,
+    },
+    /// Enforce Halley's own artifact-retention caps (see [`crate::janitor`])
+    /// immediately, rather than waiting for the next invocation to do it
+    /// automatically.
+    Clean {
+        /// List what would be removed without deleting anything.
+        #[arg(long)]
+        dry: bool,
+    },
+    /// Checks the statefile against config for self-consistency problems:
+    /// future timestamps, a malformed fingerprint digest, an archival state
+    /// machine left in an illegal combination, and entries for repos no
+    /// longer in config. See [`crate::state::fsck`].
+    Fsck {
+        /// Apply the safe fixes [`crate::state::repair`] knows how to make,
+        /// after backing up the original statefile alongside it.
+        #[arg(long)]
+        repair: bool,
+    },
+}
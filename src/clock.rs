@@ -0,0 +1,252 @@
+//! Detects a large gap between successive polls of a blocking loop — e.g.
+//! [`crate::restic::RealCall`]'s timeout wait, or a future Glacier restore
+//! poll — that's better explained by the host having suspended mid-run than
+//! by ordinary scheduling jitter.
+//!
+//! Every elapsed-time measurement in Halley already uses
+//! [`std::time::Instant`] rather than [`std::time::SystemTime`] (audited
+//! across the crate: the only `SystemTime` uses are [`crate::state`] and
+//! [`crate::notify`] stamping wall-clock timestamps meant to be read by a
+//! human or compared across process restarts, which is exactly what
+//! `SystemTime` is for — `Instant` can't be serialized or compared across
+//! runs at all). `Instant` being monotonic doesn't protect against a
+//! suspend/resume cycle on its own, though: real time still passes while
+//! the host is asleep, so a poll interval that should have been a couple of
+//! seconds can come back reading hours. [`SleepDetector`] flags that gap so
+//! a caller can log it and re-validate whatever assumption it was relying
+//! on instead of trusting it blindly across the gap.
+
+use std::time::Duration;
+
+use crate::error::HalleyError;
+
+/// A source of elapsed time, abstracted so [`SleepDetector`] can be driven
+/// by fake readings in tests instead of the real wall clock.
+pub trait Clock {
+    /// Time elapsed since some fixed, arbitrary reference point specific to
+    /// this `Clock`. Only meaningful as a difference between two readings
+    /// taken from the same instance.
+    fn elapsed(&self) -> Duration;
+}
+
+/// The real clock, backed by [`std::time::Instant`].
+pub struct SystemClock(std::time::Instant);
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self(std::time::Instant::now())
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn elapsed(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
+
+/// How many times larger than the expected poll interval a gap has to be
+/// before it counts as a likely sleep, rather than the host just running a
+/// poll late under load.
+const DEFAULT_SLACK_FACTOR: u32 = 5;
+
+/// Flags a poll-to-poll gap much larger than the loop's own interval.
+pub struct SleepDetector {
+    expected_interval: Duration,
+    slack_factor: u32,
+    last_reading: Option<Duration>,
+}
+
+impl SleepDetector {
+    pub fn new(expected_interval: Duration) -> Self {
+        Self {
+            expected_interval,
+            slack_factor: DEFAULT_SLACK_FACTOR,
+            last_reading: None,
+        }
+    }
+
+    /// Records a new reading from `clock` and returns `Some(gap)` if it
+    /// looks like the host slept since the last reading. Returns `None` on
+    /// the very first call (nothing to compare against yet) and on any
+    /// gap within `slack_factor` times the expected interval.
+    pub fn check(&mut self, clock: &dyn Clock) -> Option<Duration> {
+        let now = clock.elapsed();
+        let likely_sleep = self.last_reading.and_then(|last| {
+            let gap = now.saturating_sub(last);
+            let threshold = self.expected_interval.saturating_mul(self.slack_factor);
+            (gap > threshold).then_some(gap)
+        });
+        self.last_reading = Some(now);
+        likely_sleep
+    }
+}
+
+/// Polls `is_ready` every `interval` until it returns `Ok(Some(_))`, or
+/// until `timeout` has elapsed, extending the deadline by any gap
+/// [`SleepDetector`] flags along the way. Returns `Ok(None)` on timeout,
+/// or whatever error `is_ready` itself returns.
+///
+/// [`crate::restic::RealCall::call_with_timeout`] and
+/// [`crate::util::run_hook`]'s timeout path both used to run this exact
+/// loop themselves, each with its own `std::thread::sleep` call; sharing
+/// it here means there's one blocking sleep in a poll loop to reason
+/// about, not two kept in sync by hand. `on_gap` lets a caller (e.g. to
+/// log a warning) react to a detected gap without this function knowing
+/// anything about logging.
+pub fn poll_until<T>(
+    interval: Duration,
+    timeout: Duration,
+    mut on_gap: impl FnMut(Duration),
+    mut is_ready: impl FnMut() -> Result<Option<T>, HalleyError>,
+) -> Result<Option<T>, HalleyError> {
+    let clock = SystemClock::new();
+    let mut sleep_detector = SleepDetector::new(interval);
+    sleep_detector.check(&clock);
+    let mut remaining = timeout;
+    loop {
+        if let Some(value) = is_ready()? {
+            return Ok(Some(value));
+        }
+        if let Some(gap) = sleep_detector.check(&clock) {
+            remaining += gap;
+            on_gap(gap);
+        }
+        if clock.elapsed() >= remaining {
+            return Ok(None);
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// A fake [`Clock`] whose reading only moves when a test explicitly
+    /// advances it, standing in for the injected time source the real
+    /// clock can't provide deterministically.
+    struct FakeClock(Cell<Duration>);
+
+    impl FakeClock {
+        fn new() -> Self {
+            Self(Cell::new(Duration::ZERO))
+        }
+
+        fn advance(&self, by: Duration) {
+            self.0.set(self.0.get() + by);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn elapsed(&self) -> Duration {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn first_check_never_flags_a_sleep() {
+        let clock = FakeClock::new();
+        let mut detector = SleepDetector::new(Duration::from_secs(1));
+        assert_eq!(detector.check(&clock), None);
+    }
+
+    #[test]
+    fn a_normal_polling_interval_is_not_flagged() {
+        let clock = FakeClock::new();
+        let mut detector = SleepDetector::new(Duration::from_secs(1));
+        detector.check(&clock);
+        clock.advance(Duration::from_millis(1100));
+        assert_eq!(detector.check(&clock), None);
+    }
+
+    #[test]
+    fn a_gap_just_under_the_slack_threshold_is_not_flagged() {
+        let clock = FakeClock::new();
+        let mut detector = SleepDetector::new(Duration::from_secs(1));
+        detector.check(&clock);
+        clock.advance(Duration::from_millis(4999));
+        assert_eq!(detector.check(&clock), None);
+    }
+
+    #[test]
+    fn a_large_gap_is_flagged_as_a_likely_sleep() {
+        let clock = FakeClock::new();
+        let mut detector = SleepDetector::new(Duration::from_secs(1));
+        detector.check(&clock);
+        clock.advance(Duration::from_secs(3600));
+        let gap = detector.check(&clock).unwrap();
+        assert_eq!(gap, Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn detector_stops_flagging_once_polling_resumes_normally() {
+        let clock = FakeClock::new();
+        let mut detector = SleepDetector::new(Duration::from_secs(1));
+        detector.check(&clock);
+        clock.advance(Duration::from_secs(3600));
+        assert!(detector.check(&clock).is_some());
+
+        clock.advance(Duration::from_millis(1100));
+        assert_eq!(detector.check(&clock), None);
+    }
+
+    #[test]
+    fn repeated_sleeps_are_each_flagged_independently() {
+        let clock = FakeClock::new();
+        let mut detector = SleepDetector::new(Duration::from_millis(200));
+        detector.check(&clock);
+
+        clock.advance(Duration::from_secs(10));
+        assert!(detector.check(&clock).is_some());
+
+        clock.advance(Duration::from_millis(200));
+        assert_eq!(detector.check(&clock), None);
+
+        clock.advance(Duration::from_secs(20));
+        assert!(detector.check(&clock).is_some());
+    }
+
+    #[test]
+    fn poll_until_returns_as_soon_as_is_ready_reports_a_value() {
+        let mut calls = 0;
+        let result = poll_until(
+            Duration::from_millis(5),
+            Duration::from_secs(5),
+            |_| {},
+            || {
+                calls += 1;
+                Ok(Some(calls))
+            },
+        );
+        assert_eq!(result.unwrap(), Some(1));
+    }
+
+    #[test]
+    fn poll_until_times_out_when_never_ready() {
+        let result: Result<Option<()>, HalleyError> = poll_until(
+            Duration::from_millis(5),
+            Duration::from_millis(30),
+            |_| {},
+            || Ok(None),
+        );
+        assert_eq!(result.unwrap(), None);
+    }
+
+    #[test]
+    fn poll_until_propagates_an_error_from_is_ready() {
+        let result: Result<Option<()>, HalleyError> = poll_until(
+            Duration::from_millis(5),
+            Duration::from_secs(5),
+            |_| {},
+            || Err(HalleyError::S3("boom".into())),
+        );
+        assert!(result.is_err());
+    }
+}
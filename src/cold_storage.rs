@@ -0,0 +1,427 @@
+//! Backends that move a repository's data in and out of cold storage.
+//!
+//! Halley's only cold-tier repositories so far are S3 Glacier-backed ones
+//! (see [`crate::config::S3RepoConfig`] and [`crate::s3`]), but the same
+//! shape — archive everything eligible, restore it back, report on what's
+//! archived — applies to setups that don't speak S3 at all, e.g. a NAS repo
+//! where "archive" means `rclone move` to a cloud drive. [`ColdStorageBackend`]
+//! is the extension point: [`CommandBackend`] implements it for those exotic
+//! setups by running configured shell commands, and a future S3 handler
+//! would implement it the same way for Glacier thaw/freeze.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::HalleyError;
+
+/// What a completed archive or restore transition moved, for
+/// [`crate::engine::run_on_archive_complete`]/
+/// [`crate::engine::run_on_restore_complete`] to report to a hook.
+///
+/// `object_count` comes from [`ColdStorageBackend::list`], so it's only as
+/// accurate as that backend's own listing. There's no `total_bytes` field:
+/// nothing in this layer tracks transferred bytes yet (see
+/// [`crate::s3::ObjectPage`], which doesn't carry sizes either) — add one
+/// here once something does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransitionReport {
+    pub object_count: usize,
+    pub duration: Duration,
+}
+
+/// Moves a repository's data in and out of cold storage.
+///
+/// Implemented by [`CommandBackend`] for shell-command-driven setups; the
+/// engine only ever talks to this trait, never to a concrete backend, so
+/// [`crate::engine::backup_cycle`] and [`crate::engine::restore_cycle`] work
+/// the same way regardless of what's actually moving the data.
+pub trait ColdStorageBackend {
+    /// Lists everything currently archived, as opaque backend-specific keys.
+    fn list(&self) -> Result<Vec<String>, HalleyError>;
+
+    /// Moves everything eligible into cold storage.
+    fn archive_all(&self) -> Result<TransitionReport, HalleyError>;
+
+    /// Requests a restore of everything archived. For a backend with an
+    /// asynchronous restore step (e.g. Glacier's thaw request), this only
+    /// starts it; call [`Self::restore_blocking`] to wait until the data is
+    /// actually usable.
+    fn restore_all(&self) -> Result<(), HalleyError>;
+
+    /// Restores everything archived and waits until it's actually usable.
+    fn restore_blocking(&self) -> Result<TransitionReport, HalleyError>;
+
+    /// A short, human-readable summary of this backend's configuration,
+    /// e.g. for a future debug dump entry or CLI status line.
+    fn report(&self) -> String;
+}
+
+/// A [`ColdStorageBackend`] driven entirely by shell commands, configured
+/// per repo via [`crate::config::CommandColdStorageConfig`]. Each command's
+/// first element is the binary, the rest its arguments — same convention as
+/// [`crate::restic::RealCall::binary`]/`args`.
+pub struct CommandBackend {
+    pub list_command: Option<Vec<String>>,
+    pub archive_command: Vec<String>,
+    pub restore_command: Vec<String>,
+}
+
+impl CommandBackend {
+    fn run(command: &[String]) -> Result<std::process::Output, HalleyError> {
+        let [binary, args @ ..] = command else {
+            return Err(HalleyError::S3("cold storage command is empty".to_string()));
+        };
+        let output = std::process::Command::new(binary).args(args).output()?;
+        if !output.status.success() {
+            return Err(HalleyError::S3(format!(
+                "cold storage command `{}` exited with status {}: {}",
+                command.join(" "),
+                output.status.code().unwrap_or(-1),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(output)
+    }
+}
+
+impl ColdStorageBackend for CommandBackend {
+    fn list(&self) -> Result<Vec<String>, HalleyError> {
+        let Some(command) = &self.list_command else {
+            return Ok(Vec::new());
+        };
+        let output = Self::run(command)?;
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn archive_all(&self) -> Result<TransitionReport, HalleyError> {
+        let started = Instant::now();
+        Self::run(&self.archive_command)?;
+        Ok(TransitionReport {
+            object_count: self.list()?.len(),
+            duration: started.elapsed(),
+        })
+    }
+
+    fn restore_all(&self) -> Result<(), HalleyError> {
+        Self::run(&self.restore_command).map(|_| ())
+    }
+
+    /// The restore command itself blocks until the data is back in place —
+    /// there's no separate "request a thaw, then poll for it" step the way
+    /// there is for Glacier — so by the time [`Self::restore_all`] has
+    /// returned successfully, this has nothing left to wait for.
+    fn restore_blocking(&self) -> Result<TransitionReport, HalleyError> {
+        Ok(TransitionReport {
+            object_count: self.list()?.len(),
+            duration: Duration::ZERO,
+        })
+    }
+
+    fn report(&self) -> String {
+        format!(
+            "command backend (archive: `{}`, restore: `{}`)",
+            self.archive_command.join(" "),
+            self.restore_command.join(" ")
+        )
+    }
+}
+
+/// A bounded record of which archived keys (as returned by
+/// [`ColdStorageBackend::list`]) have already been confirmed restored, so a
+/// thaw interrupted partway through -- e.g. by a crashed run or a killed
+/// process -- doesn't have to reconfirm every object again from scratch.
+///
+/// Bounded at [`MAX_TRACKED_KEYS`]: at or below that many confirmed keys the
+/// full list is kept; past it, the raw list is dropped in favor of a digest
+/// of the sorted list -- the same non-cryptographic approach
+/// [`crate::state::fingerprint_sources`] uses to notice a set has changed
+/// shape without needing to reconstruct what it held.
+///
+/// Meant to be persisted on [`crate::state::RepoState`]; not yet consulted
+/// by [`restore_phase`][crate::engine::restore_phase] or
+/// [`restore_cycle`][crate::engine::restore_cycle], since neither has a
+/// per-object restore step to resume -- see [`resumable_restore_plan`] for
+/// the planning logic a future one would use.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestoreProgress {
+    confirmed_keys: Vec<String>,
+    #[serde(default)]
+    digest: Option<String>,
+}
+
+const MAX_TRACKED_KEYS: usize = 1000;
+
+impl RestoreProgress {
+    /// Records `confirmed` as the full current set of confirmed-restored
+    /// keys, replacing whatever was tracked before.
+    pub fn record(&mut self, confirmed: &[String]) {
+        let mut sorted: Vec<String> = confirmed.to_vec();
+        sorted.sort();
+        sorted.dedup();
+        if sorted.len() <= MAX_TRACKED_KEYS {
+            self.digest = None;
+            self.confirmed_keys = sorted;
+        } else {
+            self.digest = Some(digest_keys(&sorted));
+            self.confirmed_keys.clear();
+        }
+    }
+
+    /// True if `key` is known to have been confirmed restored already. Once
+    /// the tracked set has grown past [`MAX_TRACKED_KEYS`] and collapsed to
+    /// a digest, this conservatively answers `false` for everything -- a
+    /// key already restored just gets reconfirmed, rather than risking
+    /// skipping one that wasn't.
+    pub fn is_confirmed(&self, key: &str) -> bool {
+        self.confirmed_keys.iter().any(|k| k == key)
+    }
+}
+
+fn digest_keys(sorted_keys: &[String]) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    let mut hasher = DefaultHasher::new();
+    sorted_keys.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// What a resumed restore still needs to do, per [`resumable_restore_plan`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResumableRestorePlan {
+    /// Keys never confirmed restored before; need a full restore/confirm.
+    pub to_confirm: Vec<String>,
+    /// Already-confirmed keys sampled for a spot-check re-verification,
+    /// e.g. in case a lifecycle rule quietly re-archived one since it was
+    /// last seen.
+    pub to_reverify: Vec<String>,
+}
+
+/// Splits `all_keys` (a fresh [`ColdStorageBackend::list`] result) into
+/// which ones still need confirming and a deterministic sample of
+/// already-confirmed ones worth reconfirming anyway.
+///
+/// The sample is picked by stride rather than at random -- this crate has
+/// no `rand` dependency, and a deterministic stride keeps repeated runs
+/// against the same key set reproducible.
+pub fn resumable_restore_plan(all_keys: &[String], progress: &RestoreProgress, sample_size: usize) -> ResumableRestorePlan {
+    let mut to_confirm = Vec::new();
+    let mut already_confirmed: Vec<&String> = Vec::new();
+    for key in all_keys {
+        if progress.is_confirmed(key) {
+            already_confirmed.push(key);
+        } else {
+            to_confirm.push(key.clone());
+        }
+    }
+    let to_reverify = if already_confirmed.is_empty() || sample_size == 0 {
+        Vec::new()
+    } else {
+        let stride = (already_confirmed.len() + sample_size - 1) / sample_size;
+        already_confirmed
+            .iter()
+            .step_by(stride.max(1))
+            .map(|key| (*key).clone())
+            .collect()
+    };
+    ResumableRestorePlan { to_confirm, to_reverify }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// A [`ColdStorageBackend`] scripted with canned results, for tests that
+    /// need to observe the engine calling into a cold storage backend
+    /// without shelling out to anything real.
+    pub struct ScriptedBackend {
+        pub calls: RefCell<Vec<&'static str>>,
+        pub list_result: Result<Vec<String>, ()>,
+        pub archive_result: Result<TransitionReport, ()>,
+        pub restore_all_result: Result<(), ()>,
+        pub restore_blocking_result: Result<TransitionReport, ()>,
+    }
+
+    impl ScriptedBackend {
+        /// A backend where every call succeeds, for tests that only care
+        /// about which methods the engine invoked.
+        pub fn ok() -> Self {
+            Self {
+                calls: RefCell::new(Vec::new()),
+                list_result: Ok(Vec::new()),
+                archive_result: Ok(TransitionReport::default()),
+                restore_all_result: Ok(()),
+                restore_blocking_result: Ok(TransitionReport::default()),
+            }
+        }
+    }
+
+    impl ColdStorageBackend for ScriptedBackend {
+        fn list(&self) -> Result<Vec<String>, HalleyError> {
+            self.calls.borrow_mut().push("list");
+            self.list_result
+                .clone()
+                .map_err(|_| HalleyError::S3("scripted list failure".to_string()))
+        }
+
+        fn archive_all(&self) -> Result<TransitionReport, HalleyError> {
+            self.calls.borrow_mut().push("archive_all");
+            self.archive_result
+                .map_err(|_| HalleyError::S3("scripted archive failure".to_string()))
+        }
+
+        fn restore_all(&self) -> Result<(), HalleyError> {
+            self.calls.borrow_mut().push("restore_all");
+            self.restore_all_result
+                .map_err(|_| HalleyError::S3("scripted restore_all failure".to_string()))
+        }
+
+        fn restore_blocking(&self) -> Result<TransitionReport, HalleyError> {
+            self.calls.borrow_mut().push("restore_blocking");
+            self.restore_blocking_result
+                .map_err(|_| HalleyError::S3("scripted restore_blocking failure".to_string()))
+        }
+
+        fn report(&self) -> String {
+            "scripted backend".to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_backend_archive_all_runs_the_configured_command() {
+        let backend = CommandBackend {
+            list_command: None,
+            archive_command: vec!["true".to_string()],
+            restore_command: vec!["true".to_string()],
+        };
+        backend.archive_all().unwrap();
+    }
+
+    #[test]
+    fn command_backend_surfaces_a_failing_archive_command() {
+        let backend = CommandBackend {
+            list_command: None,
+            archive_command: vec!["false".to_string()],
+            restore_command: vec!["true".to_string()],
+        };
+        let err = backend.archive_all().unwrap_err();
+        assert!(matches!(err, HalleyError::S3(_)));
+    }
+
+    #[test]
+    fn command_backend_restore_blocking_is_a_no_op_after_restore_all() {
+        let backend = CommandBackend {
+            list_command: None,
+            archive_command: vec!["true".to_string()],
+            restore_command: vec!["true".to_string()],
+        };
+        backend.restore_all().unwrap();
+        backend.restore_blocking().unwrap();
+    }
+
+    #[test]
+    fn command_backend_list_without_a_list_command_is_empty() {
+        let backend = CommandBackend {
+            list_command: None,
+            archive_command: vec!["true".to_string()],
+            restore_command: vec!["true".to_string()],
+        };
+        assert_eq!(backend.list().unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn command_backend_list_parses_one_key_per_line() {
+        let backend = CommandBackend {
+            list_command: Some(vec!["printf".to_string(), "a\\nb\\n".to_string()]),
+            archive_command: vec!["true".to_string()],
+            restore_command: vec!["true".to_string()],
+        };
+        assert_eq!(backend.list().unwrap(), vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn command_backend_report_names_its_commands() {
+        let backend = CommandBackend {
+            list_command: None,
+            archive_command: vec!["rclone".to_string(), "move".to_string(), "a".to_string(), "b".to_string()],
+            restore_command: vec!["rclone".to_string(), "move".to_string(), "b".to_string(), "a".to_string()],
+        };
+        let report = backend.report();
+        assert!(report.contains("rclone move a b"));
+        assert!(report.contains("rclone move b a"));
+    }
+
+    #[test]
+    fn resumable_restore_plan_seeds_from_a_fresh_list_when_nothing_was_confirmed_before() {
+        let backend = mock::ScriptedBackend {
+            list_result: Ok(vec!["a".to_string(), "b".to_string(), "c".to_string()]),
+            ..mock::ScriptedBackend::ok()
+        };
+        let keys = backend.list().unwrap();
+        let progress = RestoreProgress::default();
+
+        let plan = resumable_restore_plan(&keys, &progress, 2);
+
+        assert_eq!(plan.to_confirm, vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        assert!(plan.to_reverify.is_empty());
+    }
+
+    #[test]
+    fn resumable_restore_plan_only_confirms_keys_not_already_tracked() {
+        let backend = mock::ScriptedBackend {
+            list_result: Ok(vec!["a".to_string(), "b".to_string(), "c".to_string()]),
+            ..mock::ScriptedBackend::ok()
+        };
+        let keys = backend.list().unwrap();
+        let mut progress = RestoreProgress::default();
+        progress.record(&["a".to_string()]);
+
+        let plan = resumable_restore_plan(&keys, &progress, 10);
+
+        assert_eq!(plan.to_confirm, vec!["b".to_string(), "c".to_string()]);
+        assert_eq!(plan.to_reverify, vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn resumable_restore_plan_samples_a_bounded_number_of_already_confirmed_keys() {
+        let all_keys: Vec<String> = (0..10).map(|i| format!("key-{i}")).collect();
+        let mut progress = RestoreProgress::default();
+        progress.record(&all_keys);
+
+        let plan = resumable_restore_plan(&all_keys, &progress, 3);
+
+        assert!(plan.to_confirm.is_empty());
+        assert!(plan.to_reverify.len() <= 3);
+        assert!(!plan.to_reverify.is_empty());
+    }
+
+    #[test]
+    fn restore_progress_falls_back_to_a_digest_past_the_tracked_key_cap() {
+        let many_keys: Vec<String> = (0..(MAX_TRACKED_KEYS + 1)).map(|i| format!("key-{i}")).collect();
+        let mut progress = RestoreProgress::default();
+        progress.record(&many_keys);
+
+        assert!(!progress.is_confirmed(&many_keys[0]));
+        assert_eq!(progress.confirmed_keys.len(), 0);
+        assert!(progress.digest.is_some());
+    }
+
+    #[test]
+    fn restore_progress_recognizes_a_previously_confirmed_key() {
+        let mut progress = RestoreProgress::default();
+        progress.record(&["a".to_string(), "b".to_string()]);
+
+        assert!(progress.is_confirmed("a"));
+        assert!(!progress.is_confirmed("z"));
+    }
+}
@@ -0,0 +1,1914 @@
+//! Halley's configuration file and the per-repository knobs it holds.
+//!
+//! This module grows alongside the engine: each engine feature that needs a
+//! user-facing switch gets a field here, and a matching entry in the
+//! example config.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::HalleyError;
+use crate::restic::{CompressionLevel, RetentionPolicy, SymlinkPolicy};
+use crate::s3::RetrievalTier;
+use crate::secret::SecretProvider;
+
+/// A ready-to-adapt example config: one valid, minimal repo plus commented
+/// alternatives for the optional knobs (S3 cold storage, retention,
+/// excludes). Kept as an on-disk asset rather than inline TOML so it's the
+/// same file a user would copy from the repo, and checked by
+/// [`tests::example_config_parses_and_validates_cleanly`] so it can't drift
+/// into something that no longer parses or validates.
+pub const EXAMPLE_CONFIG: &str = include_str!("../config.example.toml");
+
+/// The top-level configuration file: global settings plus one entry per
+/// managed repository.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    /// Path (or bare name, resolved via `PATH`) to the restic binary.
+    /// Defaults to `"restic"`.
+    pub restic_binary: Option<String>,
+    /// The tag applied to every snapshot Halley creates, so its own
+    /// `forget` runs never touch snapshots made by hand. Defaults to
+    /// `"halley"`.
+    pub snapshot_tag: Option<String>,
+    /// Default restic cache directory, used by any repo that doesn't set
+    /// its own `cache_dir`. May start with `~/` to refer to the user's home
+    /// directory. Restic defaults to `~/.cache/restic` on its own, which is
+    /// worth overriding on a host with a small root partition.
+    #[serde(default)]
+    pub cache_dir: Option<PathBuf>,
+    /// Kills a `restic` invocation (and its process group) if it runs
+    /// longer than this many minutes, e.g. after an NFS stall wedges it
+    /// indefinitely. `None` disables the timeout, restic's own default.
+    #[serde(default)]
+    pub command_timeout_minutes: Option<u64>,
+    #[serde(default)]
+    pub repos: Vec<RepoConfig>,
+    /// Default for any repo that doesn't set its own
+    /// `min_backup_interval_hours`. See
+    /// [`RepoConfig::resolved_min_backup_interval_hours`].
+    #[serde(default)]
+    pub default_min_backup_interval_hours: Option<u32>,
+    /// Default for any repo that doesn't set its own
+    /// `max_backup_interval_days`. See
+    /// [`RepoConfig::resolved_max_backup_interval_days`].
+    #[serde(default)]
+    pub default_max_backup_interval_days: Option<u32>,
+    /// Retention caps for Halley's own on-disk artifacts under the state
+    /// dir (see [`crate::janitor`]), enforced at the end of every
+    /// invocation and on demand via `halley state clean`.
+    #[serde(default)]
+    pub janitor: JanitorConfig,
+    /// Delivers a summary of each `halley backup` run through
+    /// [`crate::notify`]. `None` disables run-report notifications
+    /// entirely.
+    #[serde(default)]
+    pub notify: Option<NotifyConfig>,
+    /// Pings a dead man's switch (e.g. healthchecks.io) around each
+    /// `halley backup` run through [`crate::healthcheck`]. `None` disables
+    /// pinging entirely.
+    #[serde(default)]
+    pub healthcheck: Option<HealthcheckConfig>,
+    /// Base of the exponential per-repo failure backoff (see
+    /// [`crate::state::RepoState::failure_backoff_active`]): after N
+    /// consecutive failures, a repo is skipped for this many hours times
+    /// `2^N`, capped at `failure_backoff_max_hours`. Defaults to 1 hour.
+    #[serde(default)]
+    pub failure_backoff_base_hours: Option<u32>,
+    /// Caps [`Config::failure_backoff_base_hours`]'s exponential growth,
+    /// regardless of how many consecutive failures have piled up. Defaults
+    /// to 168 hours (one week), so a repo broken for a long time is
+    /// revisited at least weekly rather than essentially forgotten.
+    #[serde(default)]
+    pub failure_backoff_max_hours: Option<u32>,
+    /// How to react when the restic binary itself isn't installed, e.g. on
+    /// a fleet-shared config also deployed to hosts that only run
+    /// `validate`/`state show`/monitoring roles. Defaults to
+    /// [`MissingResticPolicy::Fail`].
+    #[serde(default)]
+    pub missing_restic: MissingResticPolicy,
+}
+
+/// How a restic-dependent command should react to
+/// [`crate::error::HalleyError::ResticNotAvailable`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingResticPolicy {
+    /// Treat a missing restic binary like any other failure. The safe
+    /// default: a scheduled backup that silently does nothing looks
+    /// identical to one that's working, until the data is needed.
+    #[default]
+    Fail,
+    /// Let restic-independent functionality (`validate`, `state show`,
+    /// `s3` commands, ...) keep working regardless, and treat a missing
+    /// restic binary as a clean no-op rather than a failure wherever it
+    /// would otherwise block a restic-dependent command -- for a host
+    /// that was never going to run backups in the first place.
+    SkipBackends,
+}
+
+/// Delivers a [`crate::engine::RunReport`] after every `halley backup`
+/// invocation. Halley has no HTTP client of its own -- like
+/// `s3.on_archive_complete`/`s3.on_restore_complete`, delivery is a shell
+/// command the user supplies (typically `curl` against a webhook such as
+/// ntfy or Slack's incoming-webhooks endpoint), with the report handed to
+/// it as JSON on `HALLEY_NOTIFICATION_BODY` rather than piped over stdin
+/// (`[`crate::util::run_hook`]` doesn't support stdin). A failed delivery is
+/// queued and retried on the next run rather than failing the backup; see
+/// [`crate::notify::NotificationQueue`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct NotifyConfig {
+    /// e.g. `curl -fsS -X POST -H 'Content-Type: application/json' -d
+    /// "$HALLEY_NOTIFICATION_BODY" https://ntfy.sh/my-topic`.
+    pub command: String,
+    /// Which runs to notify about. Defaults to [`NotifyOn::Always`].
+    #[serde(default)]
+    pub notify_on: NotifyOn,
+}
+
+/// Which [`crate::engine::RunReport`] outcomes trigger a [`NotifyConfig`]
+/// delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotifyOn {
+    /// Notify after every run, success or failure.
+    #[default]
+    Always,
+    /// Only notify when at least one repo in the run failed.
+    Failure,
+}
+
+/// Pings a dead man's switch around a `halley backup` run, so a run that
+/// stops happening at all -- not a cron job removed, a host that's down, a
+/// hung process -- gets noticed the same way a failed run does. Halley has
+/// no HTTP client of its own -- like [`NotifyConfig`], pinging is a shell
+/// command the user supplies (typically `curl` against a service like
+/// healthchecks.io), run once at the start of the run and once at the end;
+/// see [`crate::healthcheck`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct HealthcheckConfig {
+    /// e.g. `curl -fsS "https://hc-ping.com/my-uuid$HALLEY_HEALTHCHECK_SUFFIX"`.
+    /// `HALLEY_HEALTHCHECK_PHASE` is also set, to "start", "success", or
+    /// "fail"; `HALLEY_DURATION_SECS` too, on "success"/"fail".
+    pub command: String,
+    /// Whether a run that found no due repos (nothing to back up) still
+    /// pings success. Defaults to `true`, since from the switch's
+    /// perspective a run that checked in and found nothing to do is exactly
+    /// as reassuring as one that backed something up -- silence is the
+    /// thing to catch, not an empty run.
+    #[serde(default = "default_ping_on_nothing_to_do")]
+    pub ping_on_nothing_to_do: bool,
+}
+
+fn default_ping_on_nothing_to_do() -> bool {
+    true
+}
+
+/// Retention caps for artifact classes [`crate::janitor`] manages. Halley
+/// only has one such class today — [`crate::notify::NotificationQueue`]'s
+/// queued notifications — so this stays a flat struct rather than a map;
+/// it'll grow a field per class the way [`RepoConfig`] grows a field per
+/// feature, if more classes show up.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct JanitorConfig {
+    /// Caps the queued-notifications directory's total size, in bytes.
+    /// `None` (the default) leaves it uncapped; notify already drops
+    /// anything older than 14 days regardless of this setting.
+    #[serde(default)]
+    pub notifications_max_bytes: Option<u64>,
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HalleyError> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| HalleyError::Parse(format!("invalid config: {e}")))
+    }
+
+    /// The restic binary to invoke, falling back to `"restic"` on `PATH`.
+    pub fn restic_binary(&self) -> &str {
+        self.restic_binary.as_deref().unwrap_or("restic")
+    }
+
+    /// The base tag stamped on every snapshot Halley creates.
+    pub fn snapshot_tag(&self) -> &str {
+        self.snapshot_tag.as_deref().unwrap_or("halley")
+    }
+
+    pub fn repo(&self, name: &str) -> Option<&RepoConfig> {
+        self.repos.iter().find(|r| r.name == name)
+    }
+
+    /// The configured command timeout, as a [`Duration`], or `None` if
+    /// invocations should be allowed to run indefinitely.
+    pub fn command_timeout(&self) -> Option<std::time::Duration> {
+        self.command_timeout_minutes.map(|m| std::time::Duration::from_secs(m * 60))
+    }
+
+    /// The effective base of the per-repo failure backoff (see
+    /// [`Config::failure_backoff_base_hours`]). Defaults to 1 hour.
+    pub fn failure_backoff_base_hours(&self) -> u32 {
+        self.failure_backoff_base_hours.unwrap_or(1)
+    }
+
+    /// The effective cap on the per-repo failure backoff (see
+    /// [`Config::failure_backoff_max_hours`]). Defaults to 168 hours (one
+    /// week).
+    pub fn failure_backoff_max_hours(&self) -> u32 {
+        self.failure_backoff_max_hours.unwrap_or(168)
+    }
+}
+
+/// How the backup phase should react if the retry mechanism would otherwise
+/// leave the source tree in a half-backed-up state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangedDuringBackup {
+    /// Never auto-retry the backup phase, even on a transient failure.
+    #[default]
+    Ignore,
+    /// Retry the backup phase once, same as archive/restore.
+    RetryOnce,
+}
+
+/// Configuration for a single repository Halley manages.
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct RepoConfig {
+    pub name: String,
+    pub sources: Vec<PathBuf>,
+    pub repo: String,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    /// Whether to run `restic prune` after `forget` on every cycle.
+    #[serde(default)]
+    pub prune: bool,
+    #[serde(default)]
+    pub changed_during_backup: ChangedDuringBackup,
+    /// Warn at run end if this repo's last verification is older than this
+    /// many days. `None` disables the warning.
+    #[serde(default)]
+    pub max_verify_age_days: Option<u32>,
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+    /// `--compression` level (restic 0.14+ only). `None` leaves restic's
+    /// own default in place. Dropped with a warning against an older
+    /// restic; see [`crate::engine::compression_warnings`].
+    #[serde(default)]
+    pub compression: Option<CompressionLevel>,
+    /// Skip restic's pre-backup scan pass. Useful for very large trees where
+    /// the up-front walk costs more than the progress estimate is worth.
+    #[serde(default)]
+    pub no_scan: bool,
+    /// Concurrent file-reading goroutines restic should use during backup.
+    #[serde(default)]
+    pub read_concurrency: Option<u32>,
+    /// Glob patterns passed to restic as `--exclude`, in order.
+    #[serde(default)]
+    pub excludes: Vec<String>,
+    /// A file of newline-separated exclude patterns, passed as
+    /// `--exclude-file`. May start with `~/` to refer to the user's home
+    /// directory.
+    #[serde(default)]
+    pub exclude_file: Option<PathBuf>,
+    /// Glob patterns excluded from a source path's change digest (see
+    /// [`crate::digest::needs_update`]), so churn restic never backs up
+    /// anyway -- a `.cache` directory, `node_modules` -- doesn't make every
+    /// run look changed. Defaults to [`excludes`] when empty, since that's
+    /// usually exactly what should be ignored here too; set this instead of
+    /// `excludes` for a pattern that should affect the digest but not the
+    /// backup itself. See [`RepoConfig::resolved_digest_ignore`].
+    ///
+    /// [`excludes`]: RepoConfig::excludes
+    #[serde(default)]
+    pub digest_ignore: Vec<String>,
+    /// A [`digest_ignore`] pattern matching none of a source's files is
+    /// almost always a typo or a path that moved, not something worth
+    /// silently ignoring -- by default it's just logged. Set this to fail
+    /// the run instead (see [`crate::digest::needs_update`]).
+    ///
+    /// [`digest_ignore`]: RepoConfig::digest_ignore
+    #[serde(default)]
+    pub strict_paths: bool,
+    /// Extra tags applied to this repo's snapshots, alongside the global
+    /// `snapshot_tag`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Run `restic check` before backing up. Useful after thawing a
+    /// Glacier-tiered repo, to catch corruption before piling new snapshots
+    /// on top of it.
+    #[serde(default)]
+    pub check_before_backup: bool,
+    /// Caps restic's memory use for this repo, in megabytes, by setting
+    /// `GOGC`/`GOMEMLIMIT` on the child process and skipping its local
+    /// cache when the budget is tight. Useful on small VPSes where restic
+    /// gets OOM-killed on large repos.
+    #[serde(default)]
+    pub restic_memory_limit_mb: Option<u64>,
+    /// Run `restic init` before the first backup if the repository doesn't
+    /// exist yet. Safe to enable on more than one host backing up to the
+    /// same repo: a host that loses the init race treats restic's "config
+    /// file already exists" as success once it's confirmed the winner used
+    /// the same password. Defaults to `true`: a repository that doesn't
+    /// exist yet is far more common than one Halley should refuse to
+    /// create, and [`crate::restic::init`] already treats a lost
+    /// initialization race safely.
+    #[serde(default = "default_auto_init")]
+    pub auto_init: bool,
+    /// Inline repository password. Avoid where possible: `RESTIC_PASSWORD`
+    /// stays visible in `/proc/<pid>/environ` for the whole restic run.
+    /// Prefer `password_file` or `password_command`.
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Path to a file holding the repository password, passed to restic as
+    /// `RESTIC_PASSWORD_FILE`.
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+    /// A shell command that prints the repository password on stdout,
+    /// passed to restic as `RESTIC_PASSWORD_COMMAND`.
+    #[serde(default)]
+    pub password_command: Option<String>,
+    /// A [`crate::secret::SecretSource`], as a fourth alternative to
+    /// `password`/`password_file`/`password_command` covering a source
+    /// those can't express, e.g. `age` (there's no restic-native way to
+    /// hand it an encrypted password file). `file` and `command` variants
+    /// are passed through to restic as `RESTIC_PASSWORD_FILE`/
+    /// `RESTIC_PASSWORD_COMMAND` unresolved, same as the dedicated fields
+    /// above; `inline`, `env` and `age` are resolved by halley itself (see
+    /// [`crate::secret::RealSecretProvider`]) and passed as
+    /// `RESTIC_PASSWORD`. See [`RepoConfig::password_env`].
+    #[serde(default)]
+    pub password_source: Option<crate::secret::SecretSource>,
+    /// Caps restic's upload rate, in KiB/s, via `--limit-upload`. Must be
+    /// positive; see [`RepoConfig::validate`].
+    #[serde(default)]
+    pub limit_upload: Option<i64>,
+    /// Caps restic's download rate, in KiB/s, via `--limit-download`. Must
+    /// be positive; see [`RepoConfig::validate`].
+    #[serde(default)]
+    pub limit_download: Option<i64>,
+    /// Skip the first-backup confirmation gate (see
+    /// [`crate::engine::first_backup::first_backup_gate`]) even when the
+    /// estimated upload size crosses `first_backup_size_threshold_mb`.
+    #[serde(default)]
+    pub allow_initial_backup: bool,
+    /// Above this estimated upload size, in megabytes, a repository's very
+    /// first backup is refused unless `allow_initial_backup` is set.
+    /// `None` disables the check regardless of size.
+    #[serde(default)]
+    pub first_backup_size_threshold_mb: Option<u64>,
+    /// Overrides [`Config::cache_dir`] for this repo specifically. May start
+    /// with `~/` to refer to the user's home directory.
+    #[serde(default)]
+    pub cache_dir: Option<PathBuf>,
+    /// Skip restic's local cache entirely, via `--no-cache`. Takes
+    /// precedence over `cache_dir` when both are set.
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Write restored files sparsely, via `--sparse`. Only applies to
+    /// `restic restore`; backup has no equivalent flag.
+    #[serde(default)]
+    pub restore_sparse: bool,
+    /// Extra raw flags passed through to `restic restore` verbatim (e.g.
+    /// restic's platform-specific ACL restore flags). Each entry must start
+    /// with `--`; see [`RepoConfig::validate`]. Never applied to backup.
+    #[serde(default)]
+    pub restore_flags: Vec<String>,
+    /// Overrides restic's implicit hostname, via `--host` on both `backup`
+    /// and `forget`. Useful when the same source is backed up from more
+    /// than one machine and should share one snapshot lineage. Must not be
+    /// empty; see [`RepoConfig::validate`].
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Don't cross filesystem boundaries under a source path, via
+    /// `--one-file-system`. Useful for backing up `/` without descending
+    /// into mounted media.
+    #[serde(default)]
+    pub one_file_system: bool,
+    /// If a backup fails because restic reports the repository is already
+    /// locked (e.g. a previous run was killed mid-backup and left a stale
+    /// lock behind), run `restic unlock` and retry the backup once instead
+    /// of failing outright. Halley doesn't track lock age itself, so this
+    /// always unlocks on the first "already locked" failure rather than
+    /// waiting out some staleness window; see
+    /// [`crate::engine::backup_cycle`].
+    #[serde(default)]
+    pub auto_unlock_stale: bool,
+    /// Above this many snapshots, a `forget` preview (`restic forget
+    /// --dry-run`) is required to confirm before the real `forget` runs.
+    /// `None` (the default) never requires confirmation, no matter how many
+    /// snapshots a run would remove. Guards against a mis-edited retention
+    /// policy silently wiping history; see
+    /// [`crate::engine::forget_decision`].
+    #[serde(default)]
+    pub max_auto_forget: Option<u32>,
+    /// Above this many categorized restic warnings in a single backup (see
+    /// [`crate::restic::warnings::summarize`]), the run's outcome is reported
+    /// as [`crate::engine::RunStatus::Partial`] instead of `Success`, even
+    /// though the backup itself didn't fail. `None` (the default) never
+    /// downgrades a successful run, no matter how many warnings it logged.
+    #[serde(default)]
+    pub warning_threshold: Option<u32>,
+    /// Present only for repositories backed by S3 Glacier-tiered storage.
+    #[serde(default)]
+    pub s3: Option<S3RepoConfig>,
+    /// Present for repositories whose cold storage is driven by arbitrary
+    /// shell commands instead of S3 (e.g. `rclone move` to a cloud drive on
+    /// a NAS). Meant to be mutually exclusive with `s3`; [`RepoConfig::validate`]
+    /// warns if both are set, but doesn't refuse to load the config over it. See
+    /// [`crate::cold_storage::ColdStorageBackend`].
+    #[serde(default)]
+    pub cold_storage_command: Option<CommandColdStorageConfig>,
+    /// A shell command run before this repo's backup starts, e.g. dumping a
+    /// database that restic should back up a consistent snapshot of. A
+    /// nonzero exit aborts the backup for this repo; see
+    /// [`crate::engine::backup_cycle`]. Run with `HALLEY_REPO_ID` set.
+    #[serde(default)]
+    pub pre_hook: Option<String>,
+    /// A shell command run after this repo's backup finishes, whether it
+    /// succeeded or not, e.g. deleting a dump `pre_hook` made. Its exit
+    /// status is recorded but never turns a successful backup into a failed
+    /// one; see [`crate::engine::run_post_hook`]. Run with `HALLEY_REPO_ID`
+    /// and `HALLEY_RESULT` (`"success"` or `"failure"`) set.
+    #[serde(default)]
+    pub post_hook: Option<String>,
+    /// Whether to re-archive (see [`crate::cold_storage::ColdStorageBackend::archive_all`])
+    /// after a failed backup for a repo with cold storage configured (`s3`
+    /// or `cold_storage_command`). Defaults to `false`: a failed backup
+    /// leaves the repo thawed, so its data stays reachable for whoever's
+    /// investigating the failure instead of immediately going back into
+    /// cold storage. See [`crate::engine::backup_cycle`].
+    #[serde(default)]
+    pub archive_after_failed_backup: bool,
+    /// Delays re-archiving a cold-storage repo (see
+    /// [`crate::cold_storage::ColdStorageBackend::archive_all`]) until this
+    /// many hours after the backup that thawed it, so it stays hot for a
+    /// verification or sample-restore run right afterward instead of going
+    /// straight back into Glacier. `None` or `0` (the default) archives
+    /// immediately, same as before this existed. `halley s3 archive` bypasses
+    /// the delay for a repo that's ready sooner than planned. See
+    /// [`crate::state::RepoState::archive_due_at`].
+    #[serde(default)]
+    pub archive_delay_hours: Option<u32>,
+    /// When [`max_verify_age_days`] is set and this repo's verification is
+    /// overdue, re-archiving (see
+    /// [`crate::cold_storage::ColdStorageBackend::archive_all`]) is deferred
+    /// the same way an [`archive_delay_hours`] due-time not yet reached is:
+    /// the repo stays thawed so a `halley verify` run can check it without
+    /// paying for another thaw, and the next run's archive phase tries
+    /// again. Set this to restore the old behaviour and archive regardless.
+    /// See [`crate::engine::backup_cycle`].
+    ///
+    /// [`max_verify_age_days`]: RepoConfig::max_verify_age_days
+    /// [`archive_delay_hours`]: RepoConfig::archive_delay_hours
+    #[serde(default)]
+    pub archive_unverified: bool,
+    /// This repo isn't due for a backup until at least this many hours have
+    /// passed since the last one, even if [`crate::engine::due_repos`]
+    /// would otherwise consider it overdue. Overrides
+    /// [`Config::default_min_backup_interval_hours`] when set. Doesn't
+    /// apply to a repo whose last backup failed -- a failure should be
+    /// retried, not waited out. See
+    /// [`RepoConfig::resolved_min_backup_interval_hours`].
+    #[serde(default)]
+    pub min_backup_interval_hours: Option<u32>,
+    /// This repo is forced due for a backup once this many days have passed
+    /// since the last one, regardless of anything else, so its restic
+    /// retention windows stay meaningful even if nothing has changed.
+    /// Overrides [`Config::default_max_backup_interval_days`] when set. See
+    /// [`RepoConfig::resolved_max_backup_interval_days`].
+    #[serde(default)]
+    pub max_backup_interval_days: Option<u32>,
+    /// Extra environment variable names passed through verbatim from
+    /// halley's own environment into every restic invocation for this repo,
+    /// beyond the minimal base restic needs to run
+    /// (`crate::restic::BASE_ENV_VARS`) and whatever halley itself sets
+    /// (e.g. `RESTIC_PASSWORD*`). For a `password_command`/backend that
+    /// needs something like `SSH_AUTH_SOCK` or a cloud CLI's own credential
+    /// variables. See [`crate::restic::RealCall::extra_env_passthrough`].
+    #[serde(default)]
+    pub extra_env_passthrough: Vec<String>,
+    /// Silences the warning (an error under strict `halley validate`, a log
+    /// line during a real run) that one of `sources` resolves to a network
+    /// filesystem (NFS, CIFS, ...) per [`crate::mounts`]. Backing up a
+    /// network mount is usually unintentional -- the data is someone
+    /// else's responsibility -- so this defaults to `false`.
+    #[serde(default)]
+    pub allow_network_sources: bool,
+}
+
+/// Redacts `password`/`password_command` so an incidental `{:?}` print (a
+/// `dbg!`, a panic message, an over-eager log line) can't leak a secret the
+/// way the derived impl would. [`RepoConfig::password_env`] remains the way
+/// to get at the raw value when a restic invocation genuinely needs it.
+impl std::fmt::Debug for RepoConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RepoConfig")
+            .field("name", &self.name)
+            .field("sources", &self.sources)
+            .field("repo", &self.repo)
+            .field("retention", &self.retention)
+            .field("prune", &self.prune)
+            .field("changed_during_backup", &self.changed_during_backup)
+            .field("max_verify_age_days", &self.max_verify_age_days)
+            .field("symlinks", &self.symlinks)
+            .field("compression", &self.compression)
+            .field("no_scan", &self.no_scan)
+            .field("read_concurrency", &self.read_concurrency)
+            .field("excludes", &self.excludes)
+            .field("exclude_file", &self.exclude_file)
+            .field("digest_ignore", &self.digest_ignore)
+            .field("strict_paths", &self.strict_paths)
+            .field("tags", &self.tags)
+            .field("check_before_backup", &self.check_before_backup)
+            .field("restic_memory_limit_mb", &self.restic_memory_limit_mb)
+            .field("auto_init", &self.auto_init)
+            .field("password", &self.password.as_ref().map(|_| "REDACTED"))
+            .field("password_file", &self.password_file)
+            .field(
+                "password_command",
+                &self.password_command.as_ref().map(|_| "REDACTED"),
+            )
+            .field("password_source", &self.password_source)
+            .field("limit_upload", &self.limit_upload)
+            .field("limit_download", &self.limit_download)
+            .field("allow_initial_backup", &self.allow_initial_backup)
+            .field(
+                "first_backup_size_threshold_mb",
+                &self.first_backup_size_threshold_mb,
+            )
+            .field("cache_dir", &self.cache_dir)
+            .field("no_cache", &self.no_cache)
+            .field("restore_sparse", &self.restore_sparse)
+            .field("restore_flags", &self.restore_flags)
+            .field("hostname", &self.hostname)
+            .field("one_file_system", &self.one_file_system)
+            .field("auto_unlock_stale", &self.auto_unlock_stale)
+            .field("max_auto_forget", &self.max_auto_forget)
+            .field("warning_threshold", &self.warning_threshold)
+            .field("s3", &self.s3)
+            .field("cold_storage_command", &self.cold_storage_command)
+            .field("pre_hook", &self.pre_hook)
+            .field("post_hook", &self.post_hook)
+            .field("archive_after_failed_backup", &self.archive_after_failed_backup)
+            .field("archive_delay_hours", &self.archive_delay_hours)
+            .field("archive_unverified", &self.archive_unverified)
+            .field("min_backup_interval_hours", &self.min_backup_interval_hours)
+            .field("max_backup_interval_days", &self.max_backup_interval_days)
+            .field("extra_env_passthrough", &self.extra_env_passthrough)
+            .field("allow_network_sources", &self.allow_network_sources)
+            .finish()
+    }
+}
+
+impl RepoConfig {
+    /// The `exclude_file` path with a leading `~/` expanded against `$HOME`,
+    /// ready to hand to restic.
+    pub fn resolved_exclude_file(&self) -> Option<PathBuf> {
+        self.exclude_file.as_deref().map(expand_tilde)
+    }
+
+    /// This repo's effective change-digest ignore patterns: its own
+    /// `digest_ignore` if set, otherwise `excludes` -- see
+    /// [`RepoConfig::digest_ignore`].
+    pub fn resolved_digest_ignore(&self) -> &[String] {
+        if self.digest_ignore.is_empty() {
+            &self.excludes
+        } else {
+            &self.digest_ignore
+        }
+    }
+
+    /// This repo's effective cache directory: its own `cache_dir` if set,
+    /// otherwise `global_cache_dir`, with a leading `~/` expanded against
+    /// `$HOME`. `None` if neither is set, leaving restic's own default in
+    /// place.
+    pub fn resolved_cache_dir(&self, global_cache_dir: Option<&Path>) -> Option<PathBuf> {
+        self.cache_dir
+            .as_deref()
+            .or(global_cache_dir)
+            .map(expand_tilde)
+    }
+
+    /// This repo's effective minimum backup interval: its own
+    /// `min_backup_interval_hours` if set, otherwise
+    /// `global_default_hours` (usually [`Config::default_min_backup_interval_hours`]).
+    /// `None` if neither is set, leaving the repo due as soon as
+    /// [`crate::engine::due_repos`] considers it overdue.
+    pub fn resolved_min_backup_interval_hours(&self, global_default_hours: Option<u32>) -> Option<u32> {
+        self.min_backup_interval_hours.or(global_default_hours)
+    }
+
+    /// This repo's effective maximum backup interval: its own
+    /// `max_backup_interval_days` if set, otherwise
+    /// `global_default_days` (usually [`Config::default_max_backup_interval_days`]).
+    /// `None` if neither is set, so the repo is never forced due on age
+    /// alone.
+    pub fn resolved_max_backup_interval_days(&self, global_default_days: Option<u32>) -> Option<u32> {
+        self.max_backup_interval_days.or(global_default_days)
+    }
+
+    /// The `RESTIC_PASSWORD*` environment variable for whichever password
+    /// source is configured, as a `(name, value)` pair ready to hand to
+    /// [`crate::restic::RealCall::env`]. `Ok(None)` if zero or more than one
+    /// of `password`/`password_file`/`password_command`/`password_source`
+    /// is set — see [`RepoConfig::validate`]. `password_source`'s `file`
+    /// and `command` variants pass their pointer straight through, same as
+    /// the dedicated fields; its `inline`, `env` and `age` variants are
+    /// resolved right here via [`crate::secret::RealSecretProvider`], so
+    /// this can fail where the three dedicated fields never do (e.g. an
+    /// unset environment variable, a failed `age` decrypt).
+    pub fn password_env(&self) -> Result<Option<(String, String)>, HalleyError> {
+        let sources_set = [
+            self.password.is_some(),
+            self.password_file.is_some(),
+            self.password_command.is_some(),
+            self.password_source.is_some(),
+        ]
+        .into_iter()
+        .filter(|set| *set)
+        .count();
+        if sources_set != 1 {
+            return Ok(None);
+        }
+        if let Some(password) = &self.password {
+            return Ok(Some(("RESTIC_PASSWORD".to_string(), password.clone())));
+        }
+        if let Some(path) = &self.password_file {
+            return Ok(Some((
+                "RESTIC_PASSWORD_FILE".to_string(),
+                path.display().to_string(),
+            )));
+        }
+        if let Some(command) = &self.password_command {
+            return Ok(Some(("RESTIC_PASSWORD_COMMAND".to_string(), command.clone())));
+        }
+        Ok(Some(match self.password_source.as_ref().expect("sources_set == 1") {
+            crate::secret::SecretSource::File(path) => {
+                ("RESTIC_PASSWORD_FILE".to_string(), path.display().to_string())
+            }
+            crate::secret::SecretSource::Command(command) => {
+                ("RESTIC_PASSWORD_COMMAND".to_string(), command.clone())
+            }
+            resolved => {
+                let secret = crate::secret::RealSecretProvider.resolve(resolved)?;
+                ("RESTIC_PASSWORD".to_string(), secret.expose().to_string())
+            }
+        }))
+    }
+
+    /// This repo's [`crate::cold_storage::CommandBackend`], if it's
+    /// configured with `cold_storage_command`. `None` for repos with no
+    /// cold storage backend configured, including S3 repos, which have no
+    /// concrete backend to construct here yet — see
+    /// [`crate::cold_storage::ColdStorageBackend`].
+    pub fn cold_storage_backend(&self) -> Option<crate::cold_storage::CommandBackend> {
+        self.cold_storage_command
+            .as_ref()
+            .map(|c| crate::cold_storage::CommandBackend {
+                list_command: c.list_command.clone(),
+                archive_command: c.archive_command.clone(),
+                restore_command: c.restore_command.clone(),
+            })
+    }
+
+    /// Warnings about this repo's configuration that don't warrant a hard
+    /// failure, e.g. a configured file that isn't there yet.
+    ///
+    /// `global_cache_dir` is [`Config::cache_dir`], consulted here (via
+    /// [`RepoConfig::resolved_cache_dir`]) to warn if this repo's effective
+    /// cache directory can't be created. `global_min_interval_hours` and
+    /// `global_max_interval_days` are [`Config::default_min_backup_interval_hours`]/
+    /// [`Config::default_max_backup_interval_days`], consulted (via
+    /// [`RepoConfig::resolved_min_backup_interval_hours`]/
+    /// [`RepoConfig::resolved_max_backup_interval_days`]) to warn if this
+    /// repo's effective max interval doesn't actually exceed its effective
+    /// min interval.
+    pub fn validate(
+        &self,
+        global_cache_dir: Option<&Path>,
+        global_min_interval_hours: Option<u32>,
+        global_max_interval_days: Option<u32>,
+    ) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let password_sources = [
+            self.password.is_some(),
+            self.password_file.is_some(),
+            self.password_command.is_some(),
+            self.password_source.is_some(),
+        ]
+        .into_iter()
+        .filter(|set| *set)
+        .count();
+        if password_sources != 1 {
+            warnings.push(format!(
+                "repo '{}': exactly one of password, password_file, password_command, or password_source must be set (found {})",
+                self.name, password_sources
+            ));
+        }
+        if let Err(e) = crate::excludes::expand(&self.excludes) {
+            warnings.push(format!("repo '{}': {e}", self.name));
+        }
+        // Validate whatever `record_changed_sources` will actually compile
+        // at backup time -- `digest_ignore` if set, otherwise the
+        // `excludes` fallback (see `resolved_digest_ignore`) -- not just
+        // the raw `digest_ignore` field, or a bad pattern in `excludes`
+        // alone would slip past validate and only surface as a silently
+        // disabled digest at backup time.
+        match crate::excludes::expand(self.resolved_digest_ignore()) {
+            Err(e) => warnings.push(format!("repo '{}': {e}", self.name)),
+            Ok(expanded) => {
+                if let Err(e) = crate::globset::GlobSet::compile(&expanded) {
+                    warnings.push(format!("repo '{}': {e}", self.name));
+                }
+            }
+        }
+        if let Some(path) = self.resolved_exclude_file() {
+            if !path.exists() {
+                warnings.push(format!(
+                    "repo '{}': exclude_file '{}' does not exist",
+                    self.name,
+                    path.display()
+                ));
+            }
+        }
+        if let Some(limit) = self.limit_upload {
+            if limit <= 0 {
+                warnings.push(format!(
+                    "repo '{}': limit_upload must be a positive number of KiB/s, got {limit}",
+                    self.name
+                ));
+            }
+        }
+        if let Some(limit) = self.limit_download {
+            if limit <= 0 {
+                warnings.push(format!(
+                    "repo '{}': limit_download must be a positive number of KiB/s, got {limit}",
+                    self.name
+                ));
+            }
+        }
+        for flag in &self.restore_flags {
+            if !flag.starts_with("--") {
+                warnings.push(format!(
+                    "repo '{}': restore_flags entry '{flag}' must start with '--'",
+                    self.name
+                ));
+            }
+        }
+        if let Some(dir) = self.resolved_cache_dir(global_cache_dir) {
+            if fs::create_dir_all(&dir).is_err() {
+                warnings.push(format!(
+                    "repo '{}': cache_dir '{}' does not exist and could not be created",
+                    self.name,
+                    dir.display()
+                ));
+            }
+        }
+        if let Some(hostname) = &self.hostname {
+            if hostname.is_empty() {
+                warnings.push(format!("repo '{}': hostname must not be empty", self.name));
+            }
+        }
+        if self.s3.is_some() && self.cold_storage_command.is_some() {
+            warnings.push(format!(
+                "repo '{}': s3 and cold_storage_command are both set; only one cold storage backend can be active",
+                self.name
+            ));
+        }
+        if let Some(s3) = &self.s3 {
+            let inline_set = s3.access_key_id.is_some() || s3.secret_access_key.is_some();
+            let credential_sources = [inline_set, s3.credential_command.is_some(), s3.credential_source.is_some()]
+                .into_iter()
+                .filter(|set| *set)
+                .count();
+            if credential_sources > 1 {
+                warnings.push(format!(
+                    "repo '{}': s3 access_key_id/secret_access_key, credential_command, and credential_source are mutually exclusive; only one credential source can be active",
+                    self.name
+                ));
+            }
+            if s3.access_key_id.is_some() != s3.secret_access_key.is_some() {
+                warnings.push(format!(
+                    "repo '{}': s3 access_key_id and secret_access_key must be set together",
+                    self.name
+                ));
+            }
+        }
+        if let (Some(min_hours), Some(max_days)) = (
+            self.resolved_min_backup_interval_hours(global_min_interval_hours),
+            self.resolved_max_backup_interval_days(global_max_interval_days),
+        ) {
+            if u64::from(max_days) * 24 <= u64::from(min_hours) {
+                warnings.push(format!(
+                    "repo '{}': max_backup_interval_days ({max_days}d) must be greater than min_backup_interval_hours ({min_hours}h)",
+                    self.name
+                ));
+            }
+        }
+        if !self.allow_network_sources {
+            if let Ok(mounts) = crate::mounts::ProcMounts::load() {
+                for warning in crate::mounts::warn_network_sources(&mounts, &self.sources) {
+                    warnings.push(format!("repo '{}': {warning}", self.name));
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Online counterpart to [`RepoConfig::validate`]: for an S3 repo,
+    /// actually resolves its credentials (see
+    /// [`crate::s3::resolve_credentials`]) instead of just checking that the
+    /// config shape makes sense. Empty for a repo with no `s3` configured, or
+    /// one with neither inline keys nor `credential_command` set -- there's
+    /// nothing to resolve, and restic's own credential resolution (an
+    /// environment variable, an instance role) is left untested either way.
+    ///
+    /// Halley has no S3 client (see [`crate::s3`]), so this can only ever
+    /// confirm credentials resolve, not that the bucket itself is reachable.
+    pub fn validate_online(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(s3) = &self.s3 {
+            if let Err(e) = crate::s3::resolve_credentials(s3) {
+                warnings.push(format!(
+                    "repo '{}': s3 bucket '{}': {e}",
+                    self.name, s3.bucket
+                ));
+            }
+        }
+        warnings
+    }
+}
+
+/// The default for [`RepoConfig::auto_init`] when the config file omits it.
+fn default_auto_init() -> bool {
+    true
+}
+
+/// The default for [`S3RepoConfig::confirm_expedited`] when the config file
+/// omits it.
+fn default_confirm_expedited() -> bool {
+    true
+}
+
+/// Expands a leading `~/` (or bare `~`) against `$HOME`. Paths that don't
+/// start with `~` are returned unchanged.
+fn expand_tilde(path: &Path) -> PathBuf {
+    let Ok(rest) = path.strip_prefix("~") else {
+        return path.to_path_buf();
+    };
+    match std::env::var("HOME") {
+        Ok(home) => PathBuf::from(home).join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// The repositories that changed between two loads of the config file,
+/// identified by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// One human-readable line per change, for the reload log.
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for name in &self.added {
+            lines.push(format!("repo '{name}' added"));
+        }
+        for name in &self.removed {
+            lines.push(format!("repo '{name}' removed"));
+        }
+        for name in &self.changed {
+            lines.push(format!("repo '{name}' changed"));
+        }
+        lines
+    }
+}
+
+/// Diffs two configs by repo name, reporting additions, removals and
+/// per-repo field changes. Global settings (e.g. `restic_binary`) aren't
+/// currently tracked, since only the repo list needs to drive a reload.
+pub fn diff(old: &Config, new: &Config) -> ConfigDiff {
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    for repo in &new.repos {
+        match old.repo(&repo.name) {
+            None => added.push(repo.name.clone()),
+            Some(old_repo) if old_repo != repo => changed.push(repo.name.clone()),
+            Some(_) => {}
+        }
+    }
+    let removed = old
+        .repos
+        .iter()
+        .filter(|r| new.repo(&r.name).is_none())
+        .map(|r| r.name.clone())
+        .collect();
+    ConfigDiff {
+        added,
+        removed,
+        changed,
+    }
+}
+
+/// Configuration specific to an S3-backed repository.
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct S3RepoConfig {
+    pub bucket: String,
+    /// Maximum number of restore (thaw) requests to issue in a single run.
+    pub max_restore_requests_per_run: u32,
+    /// Glacier retrieval speed used for restore (thaw) requests. Defaults to
+    /// `Standard`; `Expedited` is roughly 10x the cost and gated by
+    /// `confirm_expedited`/`expedited_restore_confirm_above` (see
+    /// [`crate::s3::expedited_restore_decision`]).
+    #[serde(default)]
+    pub restore_tier: RetrievalTier,
+    /// Requires confirmation before an `Expedited` restore issues requests
+    /// for at least this many objects (see
+    /// [`crate::s3::expedited_restore_decision`]). `None` never requires
+    /// confirmation, no matter how many objects would be requested.
+    #[serde(default)]
+    pub expedited_restore_confirm_above: Option<u32>,
+    /// Disables the `expedited_restore_confirm_above` gate entirely when
+    /// `false`, e.g. for a repo whose `Expedited` restores are already
+    /// reviewed some other way. Defaults to `true`.
+    #[serde(default = "default_confirm_expedited")]
+    pub confirm_expedited: bool,
+    /// Shell command run once everything eligible has been moved into cold
+    /// storage, e.g. to trigger a billing snapshot. Sees the same
+    /// `HALLEY_REPO_ID` as `pre_hook`/`post_hook`, plus `HALLEY_OBJECT_COUNT`
+    /// and `HALLEY_DURATION_SECS` describing the transition. Same
+    /// timeout/failure semantics as `post_hook`: a failure is reported but
+    /// never fails the archive. See
+    /// [`crate::engine::run_on_archive_complete`].
+    #[serde(default)]
+    pub on_archive_complete: Option<String>,
+    /// Shell command run once a restore (thaw) has actually completed and
+    /// the data is usable, e.g. to announce that a manual restore window is
+    /// open. Same environment and failure semantics as
+    /// `on_archive_complete`. See [`crate::engine::run_on_restore_complete`].
+    #[serde(default)]
+    pub on_restore_complete: Option<String>,
+    /// Inline AWS access key id. Avoid where possible; prefer
+    /// `credential_command`. Mutually exclusive with `credential_command`;
+    /// see [`RepoConfig::validate`].
+    #[serde(default)]
+    pub access_key_id: Option<String>,
+    /// Inline AWS secret access key, paired with `access_key_id`.
+    #[serde(default)]
+    pub secret_access_key: Option<String>,
+    /// Inline AWS session token, for temporary credentials. Only meaningful
+    /// alongside `access_key_id`/`secret_access_key`.
+    #[serde(default)]
+    pub session_token: Option<String>,
+    /// A shell command that prints AWS credentials as JSON on stdout —
+    /// `{"id": ..., "secret": ..., "token": null}` — analogous to
+    /// `password_command` but for bucket access instead of the repository
+    /// password. See [`crate::s3::resolve_credentials`].
+    #[serde(default)]
+    pub credential_command: Option<String>,
+    /// A [`crate::secret::SecretSource`] whose resolved value is the same
+    /// `{"id": ..., "secret": ..., "token": null}` JSON shape
+    /// `credential_command` prints, resolved through halley's own secret
+    /// provider instead of always shelling out -- e.g. for credentials
+    /// sitting in an age-encrypted file. Mutually exclusive with inline
+    /// keys and `credential_command`; see [`RepoConfig::validate`]. See
+    /// [`crate::s3::resolve_credentials`].
+    #[serde(default)]
+    pub credential_source: Option<crate::secret::SecretSource>,
+}
+
+/// Redacts `access_key_id`/`secret_access_key`/`session_token`/
+/// `credential_command` for the same reason [`RepoConfig`]'s manual `Debug`
+/// impl redacts `password`/`password_command`: an incidental `{:?}` print
+/// must not be able to leak a secret.
+impl std::fmt::Debug for S3RepoConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3RepoConfig")
+            .field("bucket", &self.bucket)
+            .field("max_restore_requests_per_run", &self.max_restore_requests_per_run)
+            .field("restore_tier", &self.restore_tier)
+            .field("expedited_restore_confirm_above", &self.expedited_restore_confirm_above)
+            .field("confirm_expedited", &self.confirm_expedited)
+            .field("on_archive_complete", &self.on_archive_complete)
+            .field("on_restore_complete", &self.on_restore_complete)
+            .field("access_key_id", &self.access_key_id.as_ref().map(|_| "REDACTED"))
+            .field("secret_access_key", &self.secret_access_key.as_ref().map(|_| "REDACTED"))
+            .field("session_token", &self.session_token.as_ref().map(|_| "REDACTED"))
+            .field(
+                "credential_command",
+                &self.credential_command.as_ref().map(|_| "REDACTED"),
+            )
+            .field(
+                "credential_source",
+                &self.credential_source.as_ref().map(|_| "REDACTED"),
+            )
+            .finish()
+    }
+}
+
+/// Configuration for a [`crate::cold_storage::CommandBackend`]: shell
+/// commands that archive and restore a repository's data, for cold storage
+/// setups that don't speak S3. Each command's first element is the binary,
+/// the rest its arguments — no shell is invoked, so shell operators like
+/// pipes or redirection won't work here.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CommandColdStorageConfig {
+    /// Prints one archived key per line on stdout. `None` if this backend
+    /// has no way to list what's archived.
+    #[serde(default)]
+    pub list_command: Option<Vec<String>>,
+    pub archive_command: Vec<String>,
+    pub restore_command: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_a_minimal_config() {
+        let toml = r#"
+            restic_binary = "/opt/restic/bin/restic"
+
+            [[repos]]
+            name = "home"
+            sources = ["/home/user"]
+            repo = "/srv/backups/home"
+        "#;
+        let path = std::env::temp_dir().join("halley-config-test-minimal.toml");
+        fs::write(&path, toml).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.restic_binary(), "/opt/restic/bin/restic");
+        assert_eq!(config.repos.len(), 1);
+        assert_eq!(config.repo("home").unwrap().repo, "/srv/backups/home");
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn example_config_parses_and_validates_cleanly() {
+        let config: Config = toml::from_str(EXAMPLE_CONFIG).unwrap();
+        assert_eq!(config.repos.len(), 1);
+        for repo in &config.repos {
+            assert!(
+                repo.validate(
+                    config.cache_dir.as_deref(),
+                    config.default_min_backup_interval_hours,
+                    config.default_max_backup_interval_days,
+                )
+                .is_empty(),
+                "example config repo '{}' has validation warnings",
+                repo.name
+            );
+        }
+    }
+
+    #[test]
+    fn command_timeout_is_none_by_default() {
+        assert_eq!(Config::default().command_timeout(), None);
+    }
+
+    #[test]
+    fn command_timeout_converts_minutes_to_a_duration() {
+        let config = Config {
+            command_timeout_minutes: Some(90),
+            ..Default::default()
+        };
+        assert_eq!(config.command_timeout(), Some(std::time::Duration::from_secs(5400)));
+    }
+
+    #[test]
+    fn tilde_exclude_file_expands_against_home() {
+        std::env::set_var("HOME", "/home/user");
+        let repo = RepoConfig {
+            name: "home".into(),
+            sources: vec![],
+            repo: "/srv/backups/home".into(),
+            retention: RetentionPolicy::default(),
+            prune: false,
+            changed_during_backup: ChangedDuringBackup::Ignore,
+            max_verify_age_days: None,
+            symlinks: SymlinkPolicy::default(),
+            compression: None,
+            no_scan: false,
+            read_concurrency: None,
+            excludes: vec![],
+            exclude_file: Some(PathBuf::from("~/.halley-ignore")),
+            digest_ignore: Vec::new(),
+            strict_paths: false,
+            tags: vec![],
+            check_before_backup: false,
+            restic_memory_limit_mb: None,
+            auto_init: false,
+            password: Some("testpass".to_string()),
+            password_file: None,
+            password_command: None,
+            password_source: None,
+            limit_upload: None,
+            limit_download: None,
+            allow_initial_backup: false,
+            first_backup_size_threshold_mb: None,
+            cache_dir: None,
+            no_cache: false,
+            restore_sparse: false,
+            restore_flags: vec![],
+            hostname: None,
+            one_file_system: false,
+            auto_unlock_stale: false,
+            max_auto_forget: None,
+            warning_threshold: None,
+            s3: None,
+            cold_storage_command: None,
+            pre_hook: None,
+            post_hook: None,
+            archive_after_failed_backup: false,
+            archive_delay_hours: None,
+            archive_unverified: false,
+            min_backup_interval_hours: None,
+            max_backup_interval_days: None,
+            extra_env_passthrough: Vec::new(),
+            allow_network_sources: false,
+        };
+        assert_eq!(
+            repo.resolved_exclude_file().unwrap(),
+            PathBuf::from("/home/user/.halley-ignore")
+        );
+    }
+
+    #[test]
+    fn validate_warns_on_missing_exclude_file() {
+        let repo = RepoConfig {
+            name: "home".into(),
+            sources: vec![],
+            repo: "/srv/backups/home".into(),
+            retention: RetentionPolicy::default(),
+            prune: false,
+            changed_during_backup: ChangedDuringBackup::Ignore,
+            max_verify_age_days: None,
+            symlinks: SymlinkPolicy::default(),
+            compression: None,
+            no_scan: false,
+            read_concurrency: None,
+            excludes: vec![],
+            exclude_file: Some(PathBuf::from("/does/not/exist/.halley-ignore")),
+            digest_ignore: Vec::new(),
+            strict_paths: false,
+            tags: vec![],
+            check_before_backup: false,
+            restic_memory_limit_mb: None,
+            auto_init: false,
+            password: Some("testpass".to_string()),
+            password_file: None,
+            password_command: None,
+            password_source: None,
+            limit_upload: None,
+            limit_download: None,
+            allow_initial_backup: false,
+            first_backup_size_threshold_mb: None,
+            cache_dir: None,
+            no_cache: false,
+            restore_sparse: false,
+            restore_flags: vec![],
+            hostname: None,
+            one_file_system: false,
+            auto_unlock_stale: false,
+            max_auto_forget: None,
+            warning_threshold: None,
+            s3: None,
+            cold_storage_command: None,
+            pre_hook: None,
+            post_hook: None,
+            archive_after_failed_backup: false,
+            archive_delay_hours: None,
+            archive_unverified: false,
+            min_backup_interval_hours: None,
+            max_backup_interval_days: None,
+            extra_env_passthrough: Vec::new(),
+            allow_network_sources: false,
+        };
+        let warnings = repo.validate(None, None, None);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("does not exist"));
+    }
+
+    #[test]
+    fn resolved_cache_dir_prefers_the_repo_override_over_the_global_default() {
+        let mut repo = minimal_repo("home", false);
+        repo.cache_dir = Some(PathBuf::from("/var/cache/halley-home"));
+        assert_eq!(
+            repo.resolved_cache_dir(Some(&PathBuf::from("/var/cache/halley"))),
+            Some(PathBuf::from("/var/cache/halley-home"))
+        );
+    }
+
+    #[test]
+    fn resolved_cache_dir_falls_back_to_the_global_default() {
+        let repo = minimal_repo("home", false);
+        assert_eq!(
+            repo.resolved_cache_dir(Some(&PathBuf::from("/var/cache/halley"))),
+            Some(PathBuf::from("/var/cache/halley"))
+        );
+    }
+
+    #[test]
+    fn resolved_cache_dir_is_none_without_a_repo_or_global_setting() {
+        let repo = minimal_repo("home", false);
+        assert_eq!(repo.resolved_cache_dir(None), None);
+    }
+
+    #[test]
+    fn resolved_min_backup_interval_hours_prefers_the_repo_override_over_the_global_default() {
+        let mut repo = minimal_repo("home", false);
+        repo.min_backup_interval_hours = Some(2);
+        assert_eq!(repo.resolved_min_backup_interval_hours(Some(6)), Some(2));
+    }
+
+    #[test]
+    fn resolved_min_backup_interval_hours_falls_back_to_the_global_default() {
+        let repo = minimal_repo("home", false);
+        assert_eq!(repo.resolved_min_backup_interval_hours(Some(6)), Some(6));
+    }
+
+    #[test]
+    fn resolved_max_backup_interval_days_prefers_the_repo_override_over_the_global_default() {
+        let mut repo = minimal_repo("home", false);
+        repo.max_backup_interval_days = Some(7);
+        assert_eq!(repo.resolved_max_backup_interval_days(Some(30)), Some(7));
+    }
+
+    #[test]
+    fn resolved_max_backup_interval_days_falls_back_to_the_global_default() {
+        let repo = minimal_repo("home", false);
+        assert_eq!(repo.resolved_max_backup_interval_days(Some(30)), Some(30));
+    }
+
+    #[test]
+    fn resolved_digest_ignore_falls_back_to_excludes_when_unset() {
+        let mut repo = minimal_repo("home", false);
+        repo.excludes = vec!["*.tmp".to_string()];
+        assert_eq!(repo.resolved_digest_ignore(), &["*.tmp".to_string()]);
+    }
+
+    #[test]
+    fn resolved_digest_ignore_prefers_its_own_patterns_over_excludes() {
+        let mut repo = minimal_repo("home", false);
+        repo.excludes = vec!["*.tmp".to_string()];
+        repo.digest_ignore = vec!["*.log".to_string()];
+        assert_eq!(repo.resolved_digest_ignore(), &["*.log".to_string()]);
+    }
+
+    #[test]
+    fn validate_warns_on_an_invalid_digest_ignore_glob_pattern() {
+        let mut repo = minimal_repo("home", false);
+        repo.digest_ignore = vec!["[".to_string()];
+        let warnings = repo.validate(None, None, None);
+        assert!(warnings.iter().any(|w| w.contains("invalid glob pattern")));
+    }
+
+    #[test]
+    fn validate_is_silent_with_a_valid_digest_ignore_glob_pattern() {
+        let mut repo = minimal_repo("home", false);
+        repo.digest_ignore = vec!["*.log".to_string()];
+        let warnings = repo.validate(None, None, None);
+        assert!(warnings.iter().all(|w| !w.contains("glob")));
+    }
+
+    #[test]
+    fn validate_warns_on_an_invalid_pattern_in_excludes_when_digest_ignore_is_unset() {
+        let mut repo = minimal_repo("home", false);
+        repo.digest_ignore = Vec::new();
+        repo.excludes = vec!["[".to_string()];
+        let warnings = repo.validate(None, None, None);
+        assert!(warnings.iter().any(|w| w.contains("invalid glob pattern")));
+    }
+
+    #[test]
+    fn validate_creates_a_missing_cache_dir() {
+        let dir = std::env::temp_dir().join("halley-config-cache-dir-test");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut repo = minimal_repo("home", false);
+        repo.cache_dir = Some(dir.clone());
+        assert!(repo.validate(None, None, None).is_empty());
+        assert!(dir.is_dir());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn validate_warns_when_the_cache_dir_cannot_be_created() {
+        // A regular file can't be turned into a directory by
+        // `create_dir_all`, so pointing `cache_dir` at one is a reliable
+        // stand-in for a non-creatable path (e.g. a permission-denied
+        // parent) without needing to fiddle with real permissions.
+        let blocker = std::env::temp_dir().join("halley-config-cache-dir-blocker-test");
+        fs::write(&blocker, b"not a directory").unwrap();
+
+        let mut repo = minimal_repo("home", false);
+        repo.cache_dir = Some(blocker.join("cache"));
+        let warnings = repo.validate(None, None, None);
+        assert!(warnings.iter().any(|w| w.contains("could not be created")));
+
+        let _ = fs::remove_file(&blocker);
+    }
+
+    #[test]
+    fn validate_warns_on_a_restore_flag_missing_a_leading_dashdash() {
+        let mut repo = minimal_repo("home", false);
+        repo.restore_flags = vec!["--no-lock".to_string(), "sparse".to_string()];
+        let warnings = repo.validate(None, None, None);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("restore_flags entry 'sparse'"));
+    }
+
+    #[test]
+    fn validate_is_silent_when_all_restore_flags_start_with_dashdash() {
+        let mut repo = minimal_repo("home", false);
+        repo.restore_flags = vec!["--no-lock".to_string(), "--verify".to_string()];
+        assert!(repo.validate(None, None, None).is_empty());
+    }
+
+    #[test]
+    fn validate_warns_on_an_empty_hostname() {
+        let mut repo = minimal_repo("home", false);
+        repo.hostname = Some(String::new());
+        let warnings = repo.validate(None, None, None);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("hostname must not be empty"));
+    }
+
+    #[test]
+    fn validate_is_silent_with_a_non_empty_hostname() {
+        let mut repo = minimal_repo("home", false);
+        repo.hostname = Some("laptop".to_string());
+        assert!(repo.validate(None, None, None).is_empty());
+    }
+
+    #[test]
+    fn validate_warns_when_s3_and_cold_storage_command_are_both_set() {
+        let mut repo = minimal_repo("home", false);
+        repo.s3 = Some(S3RepoConfig {
+            bucket: "cold-bucket".to_string(),
+            max_restore_requests_per_run: 5,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: None,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            credential_command: None,
+            credential_source: None,
+        });
+        repo.cold_storage_command = Some(CommandColdStorageConfig {
+            list_command: None,
+            archive_command: vec!["rclone".to_string()],
+            restore_command: vec!["rclone".to_string()],
+        });
+        let warnings = repo.validate(None, None, None);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("only one cold storage backend can be active"));
+    }
+
+    #[test]
+    fn password_env_uses_inline_password() {
+        let mut repo = minimal_repo("home", false);
+        repo.password = Some("hunter2".to_string());
+        assert_eq!(
+            repo.password_env().unwrap(),
+            Some(("RESTIC_PASSWORD".to_string(), "hunter2".to_string()))
+        );
+    }
+
+    #[test]
+    fn password_env_uses_password_file() {
+        let mut repo = minimal_repo("home", false);
+        repo.password = None;
+        repo.password_file = Some(PathBuf::from("/etc/halley/home.pass"));
+        assert_eq!(
+            repo.password_env().unwrap(),
+            Some((
+                "RESTIC_PASSWORD_FILE".to_string(),
+                "/etc/halley/home.pass".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn password_env_uses_password_command() {
+        let mut repo = minimal_repo("home", false);
+        repo.password = None;
+        repo.password_command = Some("pass show halley/home".to_string());
+        assert_eq!(
+            repo.password_env().unwrap(),
+            Some((
+                "RESTIC_PASSWORD_COMMAND".to_string(),
+                "pass show halley/home".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn password_env_is_none_when_no_source_is_set() {
+        let mut repo = minimal_repo("home", false);
+        repo.password = None;
+        assert_eq!(repo.password_env().unwrap(), None);
+    }
+
+    #[test]
+    fn password_env_is_none_when_multiple_sources_are_set() {
+        let mut repo = minimal_repo("home", false);
+        repo.password_file = Some(PathBuf::from("/etc/halley/home.pass"));
+        assert_eq!(repo.password_env().unwrap(), None);
+    }
+
+    #[test]
+    fn password_env_resolves_an_inline_password_source() {
+        let mut repo = minimal_repo("home", false);
+        repo.password = None;
+        repo.password_source = Some(crate::secret::SecretSource::Inline("hunter2".to_string()));
+        assert_eq!(
+            repo.password_env().unwrap(),
+            Some(("RESTIC_PASSWORD".to_string(), "hunter2".to_string()))
+        );
+    }
+
+    #[test]
+    fn password_env_passes_a_file_password_source_through_unresolved() {
+        let mut repo = minimal_repo("home", false);
+        repo.password = None;
+        repo.password_source = Some(crate::secret::SecretSource::File(PathBuf::from(
+            "/etc/halley/home.pass",
+        )));
+        assert_eq!(
+            repo.password_env().unwrap(),
+            Some((
+                "RESTIC_PASSWORD_FILE".to_string(),
+                "/etc/halley/home.pass".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn password_env_surfaces_a_failed_password_source_resolution() {
+        let mut repo = minimal_repo("home", false);
+        repo.password = None;
+        repo.password_source = Some(crate::secret::SecretSource::Env(
+            "HALLEY_TEST_UNSET_PASSWORD_VAR".to_string(),
+        ));
+        std::env::remove_var("HALLEY_TEST_UNSET_PASSWORD_VAR");
+        assert!(repo.password_env().is_err());
+    }
+
+    #[test]
+    fn password_env_is_none_when_both_password_and_password_source_are_set() {
+        let mut repo = minimal_repo("home", false);
+        repo.password_source = Some(crate::secret::SecretSource::Inline("hunter2".to_string()));
+        assert_eq!(repo.password_env().unwrap(), None);
+    }
+
+    #[test]
+    fn debug_never_contains_the_inline_password() {
+        let mut repo = minimal_repo("home", false);
+        repo.password = Some("hunter2".to_string());
+        let debug = format!("{repo:?}");
+        assert!(!debug.contains("hunter2"));
+        assert!(debug.contains("REDACTED"));
+    }
+
+    #[test]
+    fn debug_never_contains_the_password_command() {
+        let mut repo = minimal_repo("home", false);
+        repo.password = None;
+        repo.password_command = Some("pass show halley/home".to_string());
+        let debug = format!("{repo:?}");
+        assert!(!debug.contains("pass show halley/home"));
+        assert!(debug.contains("REDACTED"));
+    }
+
+    #[test]
+    fn debug_still_shows_non_secret_fields() {
+        let repo = minimal_repo("home", false);
+        let debug = format!("{repo:?}");
+        assert!(debug.contains("home"));
+    }
+
+    #[test]
+    fn debug_never_contains_s3_inline_credentials_or_credential_command() {
+        let s3 = S3RepoConfig {
+            bucket: "cold-bucket".to_string(),
+            max_restore_requests_per_run: 5,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: None,
+            access_key_id: Some("AKIASECRET".to_string()),
+            secret_access_key: Some("shh-secret".to_string()),
+            session_token: Some("shh-token".to_string()),
+            credential_command: Some("my-helper s3 halley".to_string()),
+            credential_source: None,
+        };
+        let debug = format!("{s3:?}");
+        assert!(!debug.contains("AKIASECRET"));
+        assert!(!debug.contains("shh-secret"));
+        assert!(!debug.contains("shh-token"));
+        assert!(!debug.contains("my-helper s3 halley"));
+        assert!(debug.contains("cold-bucket"));
+    }
+
+    #[test]
+    fn validate_warns_when_s3_inline_keys_and_credential_command_are_both_set() {
+        let mut repo = minimal_repo("home", false);
+        repo.s3 = Some(S3RepoConfig {
+            bucket: "cold-bucket".to_string(),
+            max_restore_requests_per_run: 5,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: None,
+            access_key_id: Some("AKIASECRET".to_string()),
+            secret_access_key: Some("shh-secret".to_string()),
+            session_token: None,
+            credential_command: Some("my-helper s3 halley".to_string()),
+            credential_source: None,
+        });
+        let warnings = repo.validate(None, None, None);
+        assert!(warnings.iter().any(|w| w.contains("only one credential source")));
+    }
+
+    #[test]
+    fn validate_warns_when_credential_command_and_credential_source_are_both_set() {
+        let mut repo = minimal_repo("home", false);
+        repo.s3 = Some(S3RepoConfig {
+            bucket: "cold-bucket".to_string(),
+            max_restore_requests_per_run: 5,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: None,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            credential_command: Some("my-helper s3 halley".to_string()),
+            credential_source: Some(crate::secret::SecretSource::Inline("{}".to_string())),
+        });
+        let warnings = repo.validate(None, None, None);
+        assert!(warnings.iter().any(|w| w.contains("only one credential source")));
+    }
+
+    #[test]
+    fn validate_warns_when_only_one_half_of_an_inline_s3_key_pair_is_set() {
+        let mut repo = minimal_repo("home", false);
+        repo.s3 = Some(S3RepoConfig {
+            bucket: "cold-bucket".to_string(),
+            max_restore_requests_per_run: 5,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: None,
+            access_key_id: Some("AKIASECRET".to_string()),
+            secret_access_key: None,
+            session_token: None,
+            credential_command: None,
+            credential_source: None,
+        });
+        let warnings = repo.validate(None, None, None);
+        assert!(warnings.iter().any(|w| w.contains("must be set together")));
+    }
+
+    #[test]
+    fn validate_warns_when_max_interval_does_not_exceed_min_interval() {
+        let mut repo = minimal_repo("home", false);
+        repo.min_backup_interval_hours = Some(48);
+        repo.max_backup_interval_days = Some(1);
+        let warnings = repo.validate(None, None, None);
+        assert!(warnings
+            .iter()
+            .any(|w| w.contains("must be greater than min_backup_interval_hours")));
+    }
+
+    #[test]
+    fn validate_allows_a_max_interval_that_comfortably_exceeds_the_min_interval() {
+        let mut repo = minimal_repo("home", false);
+        repo.min_backup_interval_hours = Some(6);
+        repo.max_backup_interval_days = Some(30);
+        assert!(repo.validate(None, None, None).is_empty());
+    }
+
+    #[test]
+    fn validate_falls_back_to_global_interval_defaults() {
+        let repo = minimal_repo("home", false);
+        let warnings = repo.validate(None, Some(48), Some(1));
+        assert!(warnings
+            .iter()
+            .any(|w| w.contains("must be greater than min_backup_interval_hours")));
+    }
+
+    #[test]
+    fn validate_warns_when_no_password_source_is_set() {
+        let mut repo = minimal_repo("home", false);
+        repo.password = None;
+        let warnings = repo.validate(None, None, None);
+        assert!(warnings.iter().any(|w| w.contains("exactly one of password")));
+    }
+
+    #[test]
+    fn validate_warns_when_multiple_password_sources_are_set() {
+        let mut repo = minimal_repo("home", false);
+        repo.password_command = Some("pass show halley/home".to_string());
+        let warnings = repo.validate(None, None, None);
+        assert!(warnings.iter().any(|w| w.contains("exactly one of password")));
+    }
+
+    #[test]
+    fn validate_is_silent_when_exactly_one_password_source_is_set() {
+        let repo = minimal_repo("home", false);
+        assert!(repo.validate(None, None, None).is_empty());
+    }
+
+    #[test]
+    fn validate_warns_when_password_and_password_source_are_both_set() {
+        let mut repo = minimal_repo("home", false);
+        repo.password_source = Some(crate::secret::SecretSource::Inline("hunter2".to_string()));
+        let warnings = repo.validate(None, None, None);
+        assert!(warnings.iter().any(|w| w.contains("exactly one of password")));
+    }
+
+    #[test]
+    fn validate_is_silent_when_only_password_source_is_set() {
+        let mut repo = minimal_repo("home", false);
+        repo.password = None;
+        repo.password_source = Some(crate::secret::SecretSource::Inline("hunter2".to_string()));
+        assert!(repo.validate(None, None, None).is_empty());
+    }
+
+    #[test]
+    fn validate_online_is_silent_with_no_s3_configured() {
+        let repo = minimal_repo("home", false);
+        assert!(repo.validate_online().is_empty());
+    }
+
+    #[test]
+    fn validate_online_is_silent_when_s3_credentials_resolve() {
+        let mut repo = minimal_repo("home", false);
+        repo.s3 = Some(S3RepoConfig {
+            bucket: "cold-bucket".to_string(),
+            max_restore_requests_per_run: 5,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: None,
+            access_key_id: Some("AKIASECRET".to_string()),
+            secret_access_key: Some("shh-secret".to_string()),
+            session_token: None,
+            credential_command: None,
+            credential_source: None,
+        });
+        assert!(repo.validate_online().is_empty());
+    }
+
+    #[test]
+    fn validate_online_warns_naming_the_bucket_when_credentials_fail_to_resolve() {
+        let mut repo = minimal_repo("home", false);
+        repo.s3 = Some(S3RepoConfig {
+            bucket: "cold-bucket".to_string(),
+            max_restore_requests_per_run: 5,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: None,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            credential_command: Some("exit 1".to_string()),
+            credential_source: None,
+        });
+        let warnings = repo.validate_online();
+        assert!(warnings.iter().any(|w| w.contains("cold-bucket")));
+    }
+
+    #[test]
+    fn cold_storage_backend_is_none_when_unconfigured() {
+        let repo = minimal_repo("home", false);
+        assert!(repo.cold_storage_backend().is_none());
+    }
+
+    #[test]
+    fn cold_storage_backend_builds_a_command_backend_from_config() {
+        let mut repo = minimal_repo("home", false);
+        repo.cold_storage_command = Some(CommandColdStorageConfig {
+            list_command: Some(vec!["rclone".to_string(), "lsf".to_string(), "cold:home".to_string()]),
+            archive_command: vec!["rclone".to_string(), "move".to_string(), "home".to_string(), "cold:home".to_string()],
+            restore_command: vec!["rclone".to_string(), "move".to_string(), "cold:home".to_string(), "home".to_string()],
+        });
+        let backend = repo.cold_storage_backend().unwrap();
+        assert_eq!(backend.archive_command, vec!["rclone", "move", "home", "cold:home"]);
+        assert_eq!(backend.restore_command, vec!["rclone", "move", "cold:home", "home"]);
+    }
+
+    #[test]
+    fn defaults_restic_binary_when_unset() {
+        let toml = "repos = []";
+        let path = std::env::temp_dir().join("halley-config-test-default-binary.toml");
+        fs::write(&path, toml).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.restic_binary(), "restic");
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn defaults_auto_init_to_true_when_unset() {
+        let toml = concat!(
+            "[[repos]]\n",
+            "name = \"home\"\n",
+            "sources = []\n",
+            "repo = \"/srv/backups/home\"\n",
+        );
+        let path = std::env::temp_dir().join("halley-config-test-default-auto-init.toml");
+        fs::write(&path, toml).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert!(config.repos[0].auto_init);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn respects_an_explicit_auto_init_false() {
+        let toml = concat!(
+            "[[repos]]\n",
+            "name = \"home\"\n",
+            "sources = []\n",
+            "repo = \"/srv/backups/home\"\n",
+            "auto_init = false\n",
+        );
+        let path = std::env::temp_dir().join("halley-config-test-explicit-auto-init.toml");
+        fs::write(&path, toml).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert!(!config.repos[0].auto_init);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn s3_repo_config_defaults_confirm_expedited_to_true_when_unset() {
+        let toml = concat!(
+            "[[repos]]\n",
+            "name = \"cold\"\n",
+            "sources = []\n",
+            "repo = \"/srv/backups/cold\"\n",
+            "[repos.s3]\n",
+            "bucket = \"my-bucket\"\n",
+            "max_restore_requests_per_run = 5\n",
+        );
+        let path = std::env::temp_dir().join("halley-config-test-default-confirm-expedited.toml");
+        fs::write(&path, toml).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        let s3 = config.repos[0].s3.as_ref().unwrap();
+        assert!(s3.confirm_expedited);
+        assert_eq!(s3.restore_tier, RetrievalTier::Standard);
+        assert_eq!(s3.expedited_restore_confirm_above, None);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn s3_repo_config_respects_explicit_restore_tier_settings() {
+        let toml = concat!(
+            "[[repos]]\n",
+            "name = \"cold\"\n",
+            "sources = []\n",
+            "repo = \"/srv/backups/cold\"\n",
+            "[repos.s3]\n",
+            "bucket = \"my-bucket\"\n",
+            "max_restore_requests_per_run = 5\n",
+            "restore_tier = \"expedited\"\n",
+            "expedited_restore_confirm_above = 100\n",
+            "confirm_expedited = false\n",
+        );
+        let path = std::env::temp_dir().join("halley-config-test-explicit-restore-tier.toml");
+        fs::write(&path, toml).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        let s3 = config.repos[0].s3.as_ref().unwrap();
+        assert_eq!(s3.restore_tier, RetrievalTier::Expedited);
+        assert_eq!(s3.expedited_restore_confirm_above, Some(100));
+        assert!(!s3.confirm_expedited);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    fn minimal_repo(name: &str, prune: bool) -> RepoConfig {
+        RepoConfig {
+            name: name.into(),
+            sources: vec![],
+            repo: "/srv/backups".into(),
+            retention: RetentionPolicy::default(),
+            prune,
+            changed_during_backup: ChangedDuringBackup::Ignore,
+            max_verify_age_days: None,
+            symlinks: SymlinkPolicy::default(),
+            compression: None,
+            no_scan: false,
+            read_concurrency: None,
+            excludes: vec![],
+            exclude_file: None,
+            digest_ignore: Vec::new(),
+            strict_paths: false,
+            tags: vec![],
+            check_before_backup: false,
+            restic_memory_limit_mb: None,
+            auto_init: false,
+            password: Some("testpass".to_string()),
+            password_file: None,
+            password_command: None,
+            password_source: None,
+            limit_upload: None,
+            limit_download: None,
+            allow_initial_backup: false,
+            first_backup_size_threshold_mb: None,
+            cache_dir: None,
+            no_cache: false,
+            restore_sparse: false,
+            restore_flags: vec![],
+            hostname: None,
+            one_file_system: false,
+            auto_unlock_stale: false,
+            max_auto_forget: None,
+            warning_threshold: None,
+            s3: None,
+            cold_storage_command: None,
+            pre_hook: None,
+            post_hook: None,
+            archive_after_failed_backup: false,
+            archive_delay_hours: None,
+            archive_unverified: false,
+            min_backup_interval_hours: None,
+            max_backup_interval_days: None,
+            extra_env_passthrough: Vec::new(),
+            allow_network_sources: false,
+        }
+    }
+
+    #[test]
+    fn diff_detects_added_and_removed_repos() {
+        let old = Config {
+            repos: vec![minimal_repo("home", false)],
+            ..Default::default()
+        };
+        let new = Config {
+            repos: vec![minimal_repo("work", false)],
+            ..Default::default()
+        };
+        let d = diff(&old, &new);
+        assert_eq!(d.added, vec!["work".to_string()]);
+        assert_eq!(d.removed, vec!["home".to_string()]);
+        assert!(d.changed.is_empty());
+    }
+
+    #[test]
+    fn diff_detects_changed_repo_fields() {
+        let old = Config {
+            repos: vec![minimal_repo("home", false)],
+            ..Default::default()
+        };
+        let new = Config {
+            repos: vec![minimal_repo("home", true)],
+            ..Default::default()
+        };
+        let d = diff(&old, &new);
+        assert_eq!(d.changed, vec!["home".to_string()]);
+        assert!(d.added.is_empty());
+        assert!(d.removed.is_empty());
+    }
+
+    #[test]
+    fn diff_is_empty_for_identical_configs() {
+        let config = Config {
+            repos: vec![minimal_repo("home", false)],
+            ..Default::default()
+        };
+        assert!(diff(&config, &config).is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+//! Long-running (daemon) mode support.
+//!
+//! The actual SIGHUP wiring belongs to the daemon run loop, once one exists;
+//! this module owns the reload semantics on their own so they're testable
+//! without a live process: reload the config file, validate it, and only
+//! swap it in if it parses, leaving the previous config active otherwise.
+
+use crate::config::{self, Config, ConfigDiff};
+use crate::error::HalleyError;
+
+/// The config a running daemon is currently using, plus the path to re-read
+/// it from on reload.
+pub struct ReloadableConfig {
+    path: String,
+    active: Config,
+}
+
+impl ReloadableConfig {
+    pub fn new(path: String, active: Config) -> Self {
+        Self { path, active }
+    }
+
+    pub fn active(&self) -> &Config {
+        &self.active
+    }
+
+    /// Re-reads and parses the config file. On success, swaps it in and
+    /// returns a summary of what changed; on failure, the previously active
+    /// config is left untouched and the error is returned so the caller can
+    /// log it instead of losing the daemon's working configuration.
+    pub fn reload(&mut self) -> Result<ConfigDiff, HalleyError> {
+        let candidate = Config::load(&self.path)?;
+        let diff = config::diff(&self.active, &candidate);
+        self.active = candidate;
+        Ok(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RepoConfig;
+    use std::fs;
+
+    fn write_config(path: &std::path::Path, repo_names: &[&str]) {
+        let repos: String = repo_names
+            .iter()
+            .map(|name| {
+                format!(
+                    "[[repos]]\nname = \"{name}\"\nsources = []\nrepo = \"/srv/backups/{name}\"\n\n"
+                )
+            })
+            .collect();
+        fs::write(path, repos).unwrap();
+    }
+
+    #[test]
+    fn reload_swaps_in_valid_config_and_reports_diff() {
+        let path = std::env::temp_dir().join("halley-daemon-test-reload.toml");
+        write_config(&path, &["home"]);
+
+        let active = Config::load(&path).unwrap();
+        let mut reloadable = ReloadableConfig::new(path.display().to_string(), active);
+
+        write_config(&path, &["home", "work"]);
+        let diff = reloadable.reload().unwrap();
+
+        assert_eq!(diff.added, vec!["work".to_string()]);
+        assert_eq!(reloadable.active().repos.len(), 2);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reload_keeps_old_config_on_validation_failure() {
+        let path = std::env::temp_dir().join("halley-daemon-test-reload-invalid.toml");
+        write_config(&path, &["home"]);
+
+        let active = Config::load(&path).unwrap();
+        let mut reloadable = ReloadableConfig::new(path.display().to_string(), active);
+
+        fs::write(&path, "this is not valid toml [[[").unwrap();
+        let err = reloadable.reload();
+
+        assert!(err.is_err());
+        assert_eq!(reloadable.active().repos.len(), 1);
+        assert_eq!(
+            reloadable.active().repos[0],
+            RepoConfig {
+                name: "home".into(),
+                sources: vec![],
+                repo: "/srv/backups/home".into(),
+                retention: Default::default(),
+                prune: false,
+                changed_during_backup: Default::default(),
+                max_verify_age_days: None,
+                symlinks: Default::default(),
+                compression: None,
+                no_scan: false,
+                read_concurrency: None,
+                excludes: vec![],
+                exclude_file: None,
+                digest_ignore: Vec::new(),
+                strict_paths: false,
+                tags: vec![],
+                check_before_backup: false,
+                restic_memory_limit_mb: None,
+            auto_init: true,
+            password: None,
+            password_file: None,
+            password_command: None,
+            password_source: None,
+            limit_upload: None,
+            limit_download: None,
+            allow_initial_backup: false,
+            first_backup_size_threshold_mb: None,
+            cache_dir: None,
+            no_cache: false,
+            restore_sparse: false,
+            restore_flags: vec![],
+            hostname: None,
+            one_file_system: false,
+            auto_unlock_stale: false,
+            max_auto_forget: None,
+            warning_threshold: None,
+            s3: None,
+            cold_storage_command: None,
+            pre_hook: None,
+            post_hook: None,
+            archive_after_failed_backup: false,
+            archive_delay_hours: None,
+            archive_unverified: false,
+            min_backup_interval_hours: None,
+            max_backup_interval_days: None,
+            extra_env_passthrough: Vec::new(),
+            allow_network_sources: false,
+            }
+        );
+
+        let _ = fs::remove_file(&path);
+    }
+}
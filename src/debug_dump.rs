@@ -0,0 +1,340 @@
+//! `halley debug-dump`: gathers everything a maintainer needs to diagnose a
+//! bug report into one place, with secret redaction applied on the way out.
+//!
+//! Halley has no `tar`/`flate2` dependency yet, so this writes a plain
+//! directory of files (named after `--output`) rather than a compressed
+//! `.tar.gz`; a real archive can replace [`write_dump`]'s output step later
+//! without touching how entries are gathered or redacted.
+
+use std::fs;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::error::HalleyError;
+use crate::restic::{self, WrappedCall};
+use crate::state::StateFile;
+
+/// Key names that mark a `key = value` or `key: value` line as holding a
+/// secret, checked case-insensitively against the part before the
+/// separator.
+const SECRET_KEY_MARKERS: &[&str] = &["password", "secret", "token", "key", "credential"];
+
+/// One file included in a debug dump.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpEntry {
+    pub name: String,
+    pub contents: String,
+}
+
+/// Redacts anything on a `key = value` or `key: value` line whose key
+/// contains one of [`SECRET_KEY_MARKERS`], as a second line of defence
+/// behind whatever a given entry already scrubbed by hand.
+pub fn redact_secrets(text: &str) -> String {
+    text.lines()
+        .map(redact_line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn redact_line(line: &str) -> String {
+    let Some(sep_index) = line.find(['=', ':']) else {
+        return line.to_string();
+    };
+    let (key, rest) = line.split_at(sep_index);
+    let key_lower = key.to_lowercase();
+    if SECRET_KEY_MARKERS
+        .iter()
+        .any(|marker| key_lower.contains(marker))
+    {
+        let (sep, value) = rest.split_at(1);
+        let padding = &value[..value.len() - value.trim_start().len()];
+        format!("{key}{sep}{padding}REDACTED")
+    } else {
+        line.to_string()
+    }
+}
+
+/// A human-readable, secret-redacted rendering of the effective config.
+/// Inline passwords and password commands are always scrubbed up front,
+/// even though [`redact_secrets`] would also catch them by key name.
+pub fn sanitized_config(config: &Config) -> String {
+    let mut out = String::new();
+    out.push_str(&format!("restic_binary = {}\n", config.restic_binary()));
+    out.push_str(&format!("snapshot_tag = {}\n", config.snapshot_tag()));
+    if let Some(dir) = &config.cache_dir {
+        out.push_str(&format!("cache_dir = {}\n", dir.display()));
+    }
+    if let Some(minutes) = config.command_timeout_minutes {
+        out.push_str(&format!("command_timeout_minutes = {minutes}\n"));
+    }
+    for repo in &config.repos {
+        out.push_str(&format!("\n[[repos]]\nname = {}\n", repo.name));
+        out.push_str(&format!("repo = {}\n", repo.repo));
+        out.push_str(&format!("sources = {:?}\n", repo.sources));
+        if repo.password.is_some() {
+            out.push_str("password = REDACTED\n");
+        }
+        if let Some(path) = &repo.password_file {
+            out.push_str(&format!("password_file = {}\n", path.display()));
+        }
+        if repo.password_command.is_some() {
+            out.push_str("password_command = REDACTED\n");
+        }
+    }
+    redact_secrets(&out)
+}
+
+/// This host's platform, for ruling out OS-specific bugs at a glance.
+pub fn platform_info() -> String {
+    format!(
+        "os = {}\narch = {}\nfamily = {}\n",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        std::env::consts::FAMILY
+    )
+}
+
+/// A short restic version/capabilities probe. Never fails the whole dump:
+/// if restic can't be reached, the probe result says so instead.
+pub fn restic_probe<C: WrappedCall>(call: &C) -> String {
+    match restic::version::version(call) {
+        Ok(version) => {
+            let go = restic::version::go_version(call).ok().flatten();
+            match go {
+                Some(go) => format!(
+                    "restic {}.{}.{} (go{}.{})\n",
+                    version.major, version.minor, version.patch, go.major, go.minor
+                ),
+                None => format!(
+                    "restic {}.{}.{} (go version unknown)\n",
+                    version.major, version.minor, version.patch
+                ),
+            }
+        }
+        Err(e) => format!("restic probe failed: {e}\n"),
+    }
+}
+
+/// The statefile, redacted as a second line of defence even though it
+/// currently holds nothing secret.
+pub fn sanitized_statefile(state: &StateFile) -> String {
+    let json = serde_json::to_string_pretty(state).unwrap_or_default();
+    redact_secrets(&json)
+}
+
+/// Gathers every entry of a debug dump. `call` is only used for the restic
+/// probe; nothing here backs up or restores anything.
+pub fn build_dump<C: WrappedCall>(call: &C, config: &Config, state: &StateFile) -> Vec<DumpEntry> {
+    vec![
+        DumpEntry {
+            name: "config.txt".to_string(),
+            contents: sanitized_config(config),
+        },
+        DumpEntry {
+            name: "state.json".to_string(),
+            contents: sanitized_statefile(state),
+        },
+        DumpEntry {
+            name: "restic-version.txt".to_string(),
+            contents: restic_probe(call),
+        },
+        DumpEntry {
+            name: "platform.txt".to_string(),
+            contents: platform_info(),
+        },
+    ]
+}
+
+/// Writes `entries` into a directory at `output`, plus a `manifest.txt`
+/// listing what was included, so a maintainer can see at a glance whether
+/// anything was left out.
+pub fn write_dump(entries: &[DumpEntry], output: &Path) -> Result<(), HalleyError> {
+    fs::create_dir_all(output)?;
+    let mut manifest = String::new();
+    for entry in entries {
+        fs::write(output.join(&entry.name), &entry.contents)?;
+        manifest.push_str(&entry.name);
+        manifest.push('\n');
+    }
+    fs::write(output.join("manifest.txt"), manifest)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RepoConfig;
+    use crate::restic::mock::MockCall;
+    use crate::restic::CallOutput;
+    use std::path::PathBuf;
+
+    #[test]
+    fn redact_secrets_scrubs_password_like_keys() {
+        let text = "password = hunter2\nRESTIC_PASSWORD_FILE: /etc/halley/pw\nname = home";
+        let redacted = redact_secrets(text);
+        assert!(redacted.contains("password = REDACTED"));
+        assert!(redacted.contains("RESTIC_PASSWORD_FILE: REDACTED"));
+        assert!(redacted.contains("name = home"));
+    }
+
+    #[test]
+    fn redact_secrets_leaves_lines_without_a_separator_untouched() {
+        assert_eq!(redact_secrets("just some text"), "just some text");
+    }
+
+    fn repo_with_secrets() -> RepoConfig {
+        RepoConfig {
+            name: "home".into(),
+            sources: vec![PathBuf::from("/home/user")],
+            repo: "/srv/backups/home".into(),
+            retention: Default::default(),
+            prune: false,
+            changed_during_backup: Default::default(),
+            max_verify_age_days: None,
+            symlinks: Default::default(),
+            compression: None,
+            no_scan: false,
+            read_concurrency: None,
+            excludes: vec![],
+            exclude_file: None,
+            digest_ignore: Vec::new(),
+            strict_paths: false,
+            tags: vec![],
+            check_before_backup: false,
+            restic_memory_limit_mb: None,
+            auto_init: false,
+            password: Some("hunter2".to_string()),
+            password_file: None,
+            password_command: None,
+            password_source: None,
+            limit_upload: None,
+            limit_download: None,
+            allow_initial_backup: false,
+            first_backup_size_threshold_mb: None,
+            cache_dir: None,
+            no_cache: false,
+            restore_sparse: false,
+            restore_flags: vec![],
+            hostname: None,
+            one_file_system: false,
+            auto_unlock_stale: false,
+            max_auto_forget: None,
+            warning_threshold: None,
+            s3: None,
+            cold_storage_command: None,
+            pre_hook: None,
+            post_hook: None,
+            archive_after_failed_backup: false,
+            archive_delay_hours: None,
+            archive_unverified: false,
+            min_backup_interval_hours: None,
+            max_backup_interval_days: None,
+            extra_env_passthrough: Vec::new(),
+            allow_network_sources: false,
+        }
+    }
+
+    #[test]
+    fn sanitized_config_never_contains_the_inline_password() {
+        let config = Config {
+            repos: vec![repo_with_secrets()],
+            ..Default::default()
+        };
+        let dump = sanitized_config(&config);
+        assert!(!dump.contains("hunter2"));
+        assert!(dump.contains("password = REDACTED"));
+        assert!(dump.contains("name = home"));
+    }
+
+    #[test]
+    fn sanitized_config_includes_the_command_timeout_when_set() {
+        let config = Config {
+            command_timeout_minutes: Some(90),
+            ..Default::default()
+        };
+        let dump = sanitized_config(&config);
+        assert!(dump.contains("command_timeout_minutes = 90"));
+    }
+
+    #[test]
+    fn sanitized_config_redacts_a_password_command_too() {
+        let mut repo = repo_with_secrets();
+        repo.password = None;
+        repo.password_command = Some("pass show halley/home".to_string());
+        let config = Config {
+            repos: vec![repo],
+            ..Default::default()
+        };
+        let dump = sanitized_config(&config);
+        assert!(!dump.contains("pass show halley/home"));
+    }
+
+    #[test]
+    fn restic_probe_reports_version_and_go_runtime() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 0,
+                stdout: "restic 0.16.4 compiled with go1.21.5 on linux/amd64\n".to_string(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let probe = restic_probe(&call);
+        assert!(probe.contains("0.16.4"));
+        assert!(probe.contains("go1.21"));
+    }
+
+    #[test]
+    fn restic_probe_reports_failure_without_panicking() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 1,
+                stderr: "restic: command not found".to_string(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let probe = restic_probe(&call);
+        assert!(probe.contains("restic probe failed"));
+    }
+
+    #[test]
+    fn build_dump_includes_the_expected_entries() {
+        let config = Config::default();
+        let state = StateFile::default();
+        let call = MockCall::ok();
+        let entries = build_dump(&call, &config, &state);
+        let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["config.txt", "state.json", "restic-version.txt", "platform.txt"]
+        );
+    }
+
+    #[test]
+    fn write_dump_writes_every_entry_plus_a_manifest() {
+        let dir = std::env::temp_dir().join("halley-debug-dump-test");
+        let _ = fs::remove_dir_all(&dir);
+
+        let entries = vec![
+            DumpEntry {
+                name: "a.txt".to_string(),
+                contents: "alpha".to_string(),
+            },
+            DumpEntry {
+                name: "b.txt".to_string(),
+                contents: "beta".to_string(),
+            },
+        ];
+        write_dump(&entries, &dir).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "alpha");
+        assert_eq!(fs::read_to_string(dir.join("b.txt")).unwrap(), "beta");
+        let manifest = fs::read_to_string(dir.join("manifest.txt")).unwrap();
+        assert_eq!(manifest, "a.txt\nb.txt\n");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,304 @@
+//! Per-source-path content digests, used to tell the run report which of a
+//! repo's configured paths actually changed since the last backup attempt
+//! instead of just "something did somewhere" -- see
+//! [`crate::state::RepoState::digests`]. Hashing itself is delegated to the
+//! `directory-hasher` crate via [`DirectoryHasher`], the same small-trait
+//! seam used everywhere else a real dependency needs a fake standing in for
+//! it in tests (see [`crate::clock::Clock`], [`crate::healthcheck::HealthcheckPinger`]).
+//!
+//! There's no prior single-digest-per-repo mechanism this migrates away
+//! from -- nothing in this codebase computed a change digest before this
+//! module existed -- so a repo with no recorded digests for a path (an
+//! unadopted repo, or one backed up before this field existed) is simply
+//! treated as changed; see [`needs_update`].
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::HalleyError;
+use crate::globset::GlobSet;
+
+/// A directory content digest, hex-encoded. Opaque to Halley -- only ever
+/// compared for equality against a previous run's value, never decoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HexDigest(pub String);
+
+impl std::fmt::Display for HexDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Hashes a list of files down to one [`HexDigest`]. A trait rather than a
+/// bare function so tests can swap in a fake instead of hashing real files
+/// on disk. Takes an explicit file list, rather than a directory root it
+/// walks itself, so [`needs_update`] can drop ignored files (see
+/// [`crate::config::RepoConfig::digest_ignore`]) before they ever reach it.
+pub trait DirectoryHasher {
+    fn hash(&self, paths: &[PathBuf]) -> Result<HexDigest, HalleyError>;
+}
+
+/// The real hasher, backed by the `directory-hasher` crate.
+pub struct RealDirectoryHasher;
+
+impl DirectoryHasher for RealDirectoryHasher {
+    fn hash(&self, paths: &[PathBuf]) -> Result<HexDigest, HalleyError> {
+        let digest = directory_hasher::hash_files(paths)
+            .map_err(|e| HalleyError::Parse(format!("failed to hash source paths: {e}")))?;
+        Ok(HexDigest(digest))
+    }
+}
+
+/// Recursively lists every regular file under `root`, or `root` itself if
+/// it's a file rather than a directory. Order isn't meaningful on its own --
+/// [`needs_update`] sorts the result before hashing, so the digest doesn't
+/// change just because a directory walk happened to visit entries in a
+/// different order.
+fn walk_files(root: &Path) -> Result<Vec<PathBuf>, HalleyError> {
+    if !root.is_dir() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)
+            .map_err(|e| HalleyError::Parse(format!("failed to read '{}': {e}", dir.display())))?
+        {
+            let path = entry
+                .map_err(|e| HalleyError::Parse(format!("failed to read '{}': {e}", dir.display())))?
+                .path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// A `digest_ignore` pattern that matched none of a source's files --
+/// almost always a typo or a path that moved. Purely informational here;
+/// whether that's worth failing the run over is up to the caller (see
+/// [`crate::config::RepoConfig::strict_paths`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadPattern(pub String);
+
+/// Hashes every one of `sources` (the configured, post-glob-expansion roots
+/// -- see [`crate::engine::filter_existing_sources`]) with `hasher`,
+/// diffing the result against `previous` (a repo's last recorded
+/// [`crate::state::RepoState::digests`]).
+///
+/// Each source is walked into its individual files (see [`walk_files`]),
+/// any matching `ignore` (see
+/// [`crate::config::RepoConfig::resolved_digest_ignore`]) dropped, and the
+/// rest handed to `hasher` as one file list -- so a `.cache` or
+/// `node_modules` directory excluded from the backup doesn't also make
+/// every run look changed.
+///
+/// Returns the digests to record for next time, which of `sources` (by
+/// their string form, the same keys `previous` and the returned map use)
+/// changed since `previous` was recorded, and any [`DeadPattern`]s found
+/// among `ignore`. A source with no entry in `previous` counts as changed
+/// -- see the module doc for why that's the right default rather than an
+/// error.
+pub fn needs_update(
+    sources: &[PathBuf],
+    previous: &HashMap<String, HexDigest>,
+    ignore: &GlobSet,
+    hasher: &dyn DirectoryHasher,
+) -> Result<(HashMap<String, HexDigest>, Vec<String>, Vec<DeadPattern>), HalleyError> {
+    let mut digests = HashMap::with_capacity(sources.len());
+    let mut changed = Vec::new();
+    let mut all_files = Vec::new();
+    for source in sources {
+        let key = source.display().to_string();
+        let mut files = walk_files(source)?;
+        all_files.extend(files.iter().cloned());
+        files.retain(|f| !ignore.matches(f));
+        files.sort();
+        let digest = hasher.hash(&files)?;
+        if previous.get(&key) != Some(&digest) {
+            changed.push(key.clone());
+        }
+        digests.insert(key, digest);
+    }
+
+    let dead = ignore.dead_patterns(&all_files).into_iter().map(|p| DeadPattern(p.to_string())).collect();
+    Ok((digests, changed, dead))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeHasher;
+
+    impl DirectoryHasher for FakeHasher {
+        /// Joins the (already sorted, by [`needs_update`]) file names and
+        /// their contents into one string, standing in for a real content
+        /// hash -- good enough to tell these tests whether ignoring a file
+        /// changed what was handed to the hasher.
+        fn hash(&self, paths: &[PathBuf]) -> Result<HexDigest, HalleyError> {
+            Ok(HexDigest(
+                paths
+                    .iter()
+                    .map(|p| {
+                        let contents = std::fs::read(p).unwrap_or_default();
+                        format!("{}={}", p.display(), String::from_utf8_lossy(&contents))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ))
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("halley-digest-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_path_with_no_previous_entry_counts_as_changed() {
+        let dir = temp_dir("no-previous");
+        std::fs::write(dir.join("a"), b"x").unwrap();
+        let (digests, changed, dead) =
+            needs_update(&[dir.clone()], &HashMap::new(), &GlobSet::default(), &FakeHasher).unwrap();
+        let key = dir.display().to_string();
+        assert_eq!(changed, vec![key.clone()]);
+        assert!(digests.contains_key(&key));
+        assert!(dead.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// `needs_update` takes `previous` by shared reference and returns a
+    /// freshly built digest map rather than writing into it -- run twice
+    /// against the same `previous`, the second call must report the exact
+    /// same result as the first, and `previous` itself must be untouched.
+    /// This is what makes a caller safe to invoke speculatively (e.g. a
+    /// dry run) without corrupting the comparison a real run would later
+    /// make against the same state.
+    #[test]
+    fn needs_update_does_not_mutate_its_previous_digest_map_and_is_idempotent() {
+        let dir = temp_dir("purity");
+        std::fs::write(dir.join("a"), b"x").unwrap();
+        let previous = HashMap::from([(dir.display().to_string(), HexDigest("stale".to_string()))]);
+        let previous_before = previous.clone();
+
+        let (digests_first, changed_first, _) =
+            needs_update(&[dir.clone()], &previous, &GlobSet::default(), &FakeHasher).unwrap();
+        assert_eq!(previous, previous_before, "needs_update must not write into `previous`");
+
+        let (digests_second, changed_second, _) =
+            needs_update(&[dir.clone()], &previous, &GlobSet::default(), &FakeHasher).unwrap();
+        assert_eq!(previous, previous_before, "a second call must not write into `previous` either");
+        assert_eq!(digests_first, digests_second);
+        assert_eq!(changed_first, changed_second);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_path_whose_file_set_is_unchanged_is_not_reported() {
+        let dir = temp_dir("unchanged");
+        std::fs::write(dir.join("a"), b"x").unwrap();
+        let (digests, _, _) =
+            needs_update(&[dir.clone()], &HashMap::new(), &GlobSet::default(), &FakeHasher).unwrap();
+        let (_, changed, _) =
+            needs_update(&[dir.clone()], &digests, &GlobSet::default(), &FakeHasher).unwrap();
+        assert!(changed.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn adding_a_file_is_reported_as_changed() {
+        let dir = temp_dir("added-file");
+        std::fs::write(dir.join("a"), b"x").unwrap();
+        let (digests, _, _) =
+            needs_update(&[dir.clone()], &HashMap::new(), &GlobSet::default(), &FakeHasher).unwrap();
+        std::fs::write(dir.join("b"), b"y").unwrap();
+        let (_, changed, _) =
+            needs_update(&[dir.clone()], &digests, &GlobSet::default(), &FakeHasher).unwrap();
+        assert_eq!(changed, vec![dir.display().to_string()]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn an_ignored_file_s_changes_do_not_mark_the_source_as_changed() {
+        let dir = temp_dir("ignored");
+        std::fs::write(dir.join("keep"), b"x").unwrap();
+        std::fs::create_dir_all(dir.join("node_modules")).unwrap();
+        std::fs::write(dir.join("node_modules").join("churn"), b"1").unwrap();
+        let ignore = GlobSet::compile(&["**/node_modules".to_string()]).unwrap();
+
+        let (digests, _, _) =
+            needs_update(&[dir.clone()], &HashMap::new(), &ignore, &FakeHasher).unwrap();
+        std::fs::write(dir.join("node_modules").join("churn"), b"2").unwrap();
+        let (_, changed, _) = needs_update(&[dir.clone()], &digests, &ignore, &FakeHasher).unwrap();
+
+        assert!(changed.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn without_an_ignore_pattern_the_same_change_is_reported() {
+        let dir = temp_dir("not-ignored");
+        std::fs::write(dir.join("keep"), b"x").unwrap();
+        std::fs::create_dir_all(dir.join("node_modules")).unwrap();
+        std::fs::write(dir.join("node_modules").join("churn"), b"1").unwrap();
+
+        let (digests, _, _) =
+            needs_update(&[dir.clone()], &HashMap::new(), &GlobSet::default(), &FakeHasher).unwrap();
+        std::fs::write(dir.join("node_modules").join("churn"), b"2").unwrap();
+        let (_, changed, _) =
+            needs_update(&[dir.clone()], &digests, &GlobSet::default(), &FakeHasher).unwrap();
+
+        assert_eq!(changed, vec![dir.display().to_string()]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_failed_walk_aborts_without_reporting_any_changes() {
+        let missing = std::env::temp_dir().join("halley-digest-test-missing-does-not-exist");
+        let _ = std::fs::remove_dir_all(&missing);
+        // Not a directory, so walk_files treats it as a single (missing)
+        // file entry; the failure instead comes from hashing it -- a real
+        // hasher would error on a path that doesn't exist, same as the fake
+        // below standing in for one.
+        struct AlwaysFails;
+        impl DirectoryHasher for AlwaysFails {
+            fn hash(&self, _paths: &[PathBuf]) -> Result<HexDigest, HalleyError> {
+                Err(HalleyError::Parse("boom".to_string()))
+            }
+        }
+        assert!(needs_update(&[missing], &HashMap::new(), &GlobSet::default(), &AlwaysFails).is_err());
+    }
+
+    #[test]
+    fn an_ignore_pattern_matching_nothing_is_reported_as_dead() {
+        let dir = temp_dir("dead-pattern");
+        std::fs::write(dir.join("keep"), b"x").unwrap();
+        let ignore = GlobSet::compile(&["**/node_modules".to_string()]).unwrap();
+
+        let (_, _, dead) = needs_update(&[dir.clone()], &HashMap::new(), &ignore, &FakeHasher).unwrap();
+
+        assert_eq!(dead, vec![DeadPattern("**/node_modules".to_string())]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_dead_ignore_pattern_is_not_reported_once_it_matches_something() {
+        let dir = temp_dir("not-dead");
+        std::fs::create_dir_all(dir.join("node_modules")).unwrap();
+        std::fs::write(dir.join("node_modules").join("churn"), b"1").unwrap();
+        let ignore = GlobSet::compile(&["**/node_modules".to_string()]).unwrap();
+
+        let (_, _, dead) = needs_update(&[dir.clone()], &HashMap::new(), &ignore, &FakeHasher).unwrap();
+
+        assert!(dead.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
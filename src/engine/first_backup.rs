@@ -0,0 +1,249 @@
+//! Guards against an oversized, unreviewed first backup.
+//!
+//! The first backup of a repository is a very different event from a
+//! nightly incremental: it uploads the whole source tree instead of a
+//! handful of changed files, which on a large tree over a metered or
+//! Glacier-tiered link can be an expensive surprise. This module estimates
+//! that upload size and gates on it unless the repo has explicitly opted
+//! in via `allow_initial_backup`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::RepoConfig;
+use crate::error::HalleyError;
+use crate::restic::{self, WrappedCall};
+
+/// What [`first_backup_gate`] decided for a repository about to be backed
+/// up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirstBackupDecision {
+    /// The repository already has at least one snapshot; this isn't a
+    /// first backup and no size gate applies.
+    NotFirstBackup,
+    /// A first backup that's clear to proceed, either because it's under
+    /// the configured threshold or because the repo opted in.
+    Proceed { estimated_bytes: u64 },
+    /// A first backup whose estimated size crosses
+    /// `first_backup_size_threshold_mb` without `allow_initial_backup` set.
+    RequiresConfirmation { estimated_bytes: u64 },
+}
+
+/// Sums the size of every regular file reachable from `sources`, descending
+/// into directories. Unreadable entries (permission errors, paths that
+/// vanish mid-walk) are skipped rather than failing the estimate outright,
+/// since this is a best-effort figure for a warning, not an authoritative
+/// backup manifest.
+pub fn estimate_source_bytes(sources: &[PathBuf]) -> u64 {
+    sources.iter().map(|s| estimate_path_bytes(s)).sum()
+}
+
+fn estimate_path_bytes(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if metadata.is_file() {
+        return metadata.len();
+    }
+    if !metadata.is_dir() {
+        return 0;
+    }
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| estimate_path_bytes(&entry.path()))
+        .sum()
+}
+
+/// Decides whether a repository's next backup is its first, and if so,
+/// whether it's clear to proceed given its estimated size and
+/// `allow_initial_backup`/`first_backup_size_threshold_mb` configuration.
+///
+/// Callers that get [`FirstBackupDecision::RequiresConfirmation`] back and
+/// still want to proceed should either set `allow_initial_backup = true`
+/// in the repo's config, or (once a CLI entry point exists) pass an
+/// explicit `--yes`.
+pub fn first_backup_gate<C: WrappedCall>(
+    call: &C,
+    repo: &RepoConfig,
+) -> Result<FirstBackupDecision, HalleyError> {
+    let snapshots = restic::snapshots(call)?;
+    if !snapshots.is_empty() {
+        return Ok(FirstBackupDecision::NotFirstBackup);
+    }
+
+    let estimated_bytes = estimate_source_bytes(&repo.sources);
+    if repo.allow_initial_backup {
+        return Ok(FirstBackupDecision::Proceed { estimated_bytes });
+    }
+
+    match repo.first_backup_size_threshold_mb {
+        Some(threshold_mb) if estimated_bytes > threshold_mb.saturating_mul(1024 * 1024) => {
+            Ok(FirstBackupDecision::RequiresConfirmation { estimated_bytes })
+        }
+        _ => Ok(FirstBackupDecision::Proceed { estimated_bytes }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::restic::mock::MockCall;
+    use crate::restic::CallOutput;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn estimate_source_bytes_sums_files_across_a_nested_tree() {
+        let dir = temp_dir("halley-first-backup-estimate-test");
+        fs::write(dir.join("a.bin"), vec![0u8; 100]).unwrap();
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub").join("b.bin"), vec![0u8; 250]).unwrap();
+
+        assert_eq!(estimate_source_bytes(&[dir.clone()]), 350);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn estimate_source_bytes_ignores_missing_paths() {
+        let missing = std::env::temp_dir().join("halley-first-backup-does-not-exist");
+        let _ = fs::remove_dir_all(&missing);
+        assert_eq!(estimate_source_bytes(&[missing]), 0);
+    }
+
+    fn repo() -> RepoConfig {
+        RepoConfig {
+            name: "test".into(),
+            sources: vec![],
+            repo: "/tmp/repo".into(),
+            retention: restic::RetentionPolicy::default(),
+            prune: false,
+            changed_during_backup: crate::config::ChangedDuringBackup::Ignore,
+            max_verify_age_days: None,
+            symlinks: restic::SymlinkPolicy::default(),
+            compression: None,
+            no_scan: false,
+            read_concurrency: None,
+            excludes: vec![],
+            exclude_file: None,
+            digest_ignore: Vec::new(),
+            strict_paths: false,
+            tags: vec![],
+            check_before_backup: false,
+            restic_memory_limit_mb: None,
+            auto_init: false,
+            password: Some("testpass".to_string()),
+            password_file: None,
+            password_command: None,
+            password_source: None,
+            limit_upload: None,
+            limit_download: None,
+            allow_initial_backup: false,
+            first_backup_size_threshold_mb: None,
+            cache_dir: None,
+            no_cache: false,
+            restore_sparse: false,
+            restore_flags: vec![],
+            hostname: None,
+            one_file_system: false,
+            auto_unlock_stale: false,
+            max_auto_forget: None,
+            warning_threshold: None,
+            s3: None,
+            cold_storage_command: None,
+            pre_hook: None,
+            post_hook: None,
+            archive_after_failed_backup: false,
+            archive_delay_hours: None,
+            archive_unverified: false,
+            min_backup_interval_hours: None,
+            max_backup_interval_days: None,
+            extra_env_passthrough: Vec::new(),
+            allow_network_sources: false,
+        }
+    }
+
+    fn snapshots_result(stdout: &str) -> MockCall {
+        MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 0,
+                stdout: stdout.into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        }
+    }
+
+    #[test]
+    fn gate_reports_not_first_backup_when_snapshots_exist() {
+        let call = snapshots_result(
+            r#"[{"id":"abc","short_id":"abc","time":"2026-01-01T00:00:00Z"}]"#,
+        );
+        let decision = first_backup_gate(&call, &repo()).unwrap();
+        assert_eq!(decision, FirstBackupDecision::NotFirstBackup);
+    }
+
+    #[test]
+    fn gate_proceeds_when_no_threshold_is_configured() {
+        let call = snapshots_result("[]");
+        let decision = first_backup_gate(&call, &repo()).unwrap();
+        assert!(matches!(decision, FirstBackupDecision::Proceed { .. }));
+    }
+
+    #[test]
+    fn gate_proceeds_when_allow_initial_backup_is_set_regardless_of_size() {
+        let dir = temp_dir("halley-first-backup-allowed-test");
+        fs::write(dir.join("big.bin"), vec![0u8; 1024]).unwrap();
+        let mut repo = repo();
+        repo.sources = vec![dir.clone()];
+        repo.allow_initial_backup = true;
+        repo.first_backup_size_threshold_mb = Some(0);
+
+        let call = snapshots_result("[]");
+        let decision = first_backup_gate(&call, &repo).unwrap();
+        assert!(matches!(decision, FirstBackupDecision::Proceed { estimated_bytes: 1024 }));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn gate_requires_confirmation_when_estimate_exceeds_threshold() {
+        let dir = temp_dir("halley-first-backup-over-threshold-test");
+        fs::write(dir.join("big.bin"), vec![0u8; 1024]).unwrap();
+        let mut repo = repo();
+        repo.sources = vec![dir.clone()];
+        repo.first_backup_size_threshold_mb = Some(0);
+
+        let call = snapshots_result("[]");
+        let decision = first_backup_gate(&call, &repo).unwrap();
+        assert_eq!(
+            decision,
+            FirstBackupDecision::RequiresConfirmation { estimated_bytes: 1024 }
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn gate_proceeds_when_estimate_is_under_threshold() {
+        let dir = temp_dir("halley-first-backup-under-threshold-test");
+        fs::write(dir.join("small.bin"), vec![0u8; 10]).unwrap();
+        let mut repo = repo();
+        repo.sources = vec![dir.clone()];
+        repo.first_backup_size_threshold_mb = Some(1);
+
+        let call = snapshots_result("[]");
+        let decision = first_backup_gate(&call, &repo).unwrap();
+        assert_eq!(
+            decision,
+            FirstBackupDecision::Proceed { estimated_bytes: 10 }
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,3799 @@
+//! Orchestration of Halley's backup, archive and restore phases.
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::cancel::CancellationToken;
+use crate::cold_storage::ColdStorageBackend;
+use crate::config::{ChangedDuringBackup, RepoConfig};
+use crate::error::{HalleyError, ResticErrorKind, Severity};
+use crate::restic::{self, WrappedCall};
+use crate::s3::RetrievalTier;
+use crate::scheduler::{Candidate, Scheduler};
+use crate::state::{PendingAction, RepoState, VerifyMethod};
+use crate::util;
+
+pub mod first_backup;
+use first_backup::FirstBackupDecision;
+
+pub mod plan;
+
+/// The phases a repository run can go through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Backup,
+    Archive,
+    Restore,
+}
+
+/// Governs whether a failed phase gets a second attempt.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub changed_during_backup: ChangedDuringBackup,
+    /// How long to wait before the retry attempt.
+    pub pause: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            changed_during_backup: ChangedDuringBackup::default(),
+            pause: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A single recorded attempt at running a phase, for the run report.
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    pub phase: Phase,
+    pub attempt_no: u32,
+    pub error: Option<String>,
+}
+
+/// Runs `op`, retrying once after `policy.pause` if it fails with a
+/// [`Severity::Transient`] error and the phase is eligible for retry.
+///
+/// Archive and restore always retry once on a transient failure. The backup
+/// phase only retries when `policy.changed_during_backup` is
+/// [`ChangedDuringBackup::RetryOnce`], since silently re-running a backup can
+/// pick up a partially-written source tree.
+///
+/// Built on [`util::retry::Policy`]; this function's job is turning that
+/// generic attempt-count-and-backoff loop into the phase-specific
+/// [`Attempt`] records the run report wants, not the retry loop itself.
+pub fn run_phase_with_retry<F>(
+    phase: Phase,
+    policy: &RetryPolicy,
+    mut op: F,
+) -> (Vec<Attempt>, Result<(), HalleyError>)
+where
+    F: FnMut() -> Result<(), HalleyError>,
+{
+    let eligible = match phase {
+        Phase::Backup => policy.changed_during_backup == ChangedDuringBackup::RetryOnce,
+        Phase::Archive | Phase::Restore => true,
+    };
+
+    let attempts = std::cell::RefCell::new(Vec::new());
+    let retry_policy = util::retry::Policy::once(policy.pause);
+    let (_, result) = retry_policy.run(
+        &util::retry::RealSleeper,
+        |attempt_no| {
+            let result = op();
+            attempts.borrow_mut().push(Attempt {
+                phase,
+                attempt_no,
+                error: result.as_ref().err().map(|e| e.to_string()),
+            });
+            result
+        },
+        |e| eligible && e.severity() == Severity::Transient,
+    );
+    (attempts.into_inner(), result)
+}
+
+/// Runs a repository's backup cycle: `backup`, then `forget`, then
+/// (if `repo.prune` is set) `prune`, then the archive phase.
+///
+/// The `?` chain deliberately guards the archive phase behind a successful
+/// retention step: if `prune` is configured but fails (e.g. a locked
+/// repository), archiving a repo that's still holding space it thinks it
+/// freed would be wasted work at best, so the cycle stops there instead.
+///
+/// This grows towards a full end-to-end cycle as the restore phase and its
+/// orchestration land.
+///
+/// `snapshot_tag` is the global tag (see [`crate::config::Config::snapshot_tag`])
+/// stamped on every snapshot Halley creates, and the one `forget` filters
+/// on, so retention never touches snapshots made by hand.
+///
+/// If `repo.auto_init` is set, `restic init` runs first; a lost race
+/// against another host initializing the same repository is treated as
+/// success once [`restic::init`] has confirmed the password matches.
+///
+/// If `repo.check_before_backup` is set, a `restic check` runs next and
+/// aborts the cycle on failure, so a repo that came back corrupted from
+/// Glacier doesn't get new snapshots piled on top of it.
+///
+/// If `repo.auto_unlock_stale` is set and the backup fails because the
+/// repository is already locked, [`run_backup_with_unlock_retry`] runs
+/// `restic unlock` and retries the backup once.
+///
+/// If `repo.max_auto_forget` is set, [`run_forget_with_confirmation_gate`]
+/// previews the forget first and refuses to run it automatically once the
+/// preview crosses that cap.
+///
+/// If this repo has never been backed up, [`first_backup::first_backup_gate`]
+/// estimates the upload size and, unless `repo.allow_initial_backup` is set,
+/// refuses to proceed once that estimate crosses
+/// `repo.first_backup_size_threshold_mb`, returning
+/// [`HalleyError::FirstBackupNotConfirmed`].
+///
+/// Source paths that no longer exist are dropped before the backup runs
+/// (see [`filter_existing_sources`]); the cycle only fails outright, with
+/// [`HalleyError::NoBackupSources`], once every configured source is gone.
+///
+/// `cold_storage`, if this repo has a [`ColdStorageBackend`] configured
+/// (e.g. [`crate::config::RepoConfig::cold_storage_backend`]), is handed to
+/// the archive phase in place of the built-in (currently unimplemented) S3
+/// archiving. `None` for repos with no cold storage backend at all.
+///
+/// `memory_limit`, if the repo is configured with `restic_memory_limit_mb`,
+/// is expected to already have its `env()` applied to `call` (e.g. via
+/// [`crate::restic::RealCall::env`]); here it's only consulted for the
+/// `--no-cache` cache decision. See [`memory_limit_warnings`] for surfacing
+/// `GOMEMLIMIT` capability gaps in the run report.
+///
+/// `global_cache_dir` is [`crate::config::Config::cache_dir`], the fallback
+/// used when `repo.cache_dir` isn't set (see
+/// [`crate::config::RepoConfig::resolved_cache_dir`]). A memory-limit-driven
+/// `--no-cache` or `repo.no_cache` both take precedence over any cache
+/// directory.
+///
+/// `restic_version`, if known (e.g. via [`restic::ensure_supported_version`]),
+/// gates `repo.compression`: it's only passed through to `--compression`
+/// against a restic new enough to understand the flag
+/// ([`restic::MIN_COMPRESSION_VERSION`]), and silently dropped otherwise —
+/// see [`compression_warnings`] for surfacing that in the run report.
+/// `None` (version unknown) is treated the same as "too old". When known,
+/// it's also embedded verbatim in the snapshot's own tags as
+/// `halley-restic-<version>`, so it's recoverable from the repo itself even
+/// if the statefile that also records it (see
+/// [`crate::state::BackupRecord::restic_version`]) is lost.
+///
+/// Returns the snapshot id restic reported for this backup, if any, so the
+/// caller can record it in the statefile for the next run's
+/// [`restic::diff`] against it.
+///
+/// A repo with [`ColdStorageBackend`] configured gets thawed (see
+/// [`restore_phase`]) before restic touches it at all, and re-archived (see
+/// [`archive_phase`]) afterward — cold storage should look, from the rest of
+/// this function's perspective, no different from a repo that was reachable
+/// the whole time. A failed backup skips the re-archive by default, leaving
+/// the repo thawed for whoever's investigating; set
+/// `repo.archive_after_failed_backup` to re-freeze it anyway.
+///
+/// `state.pending_action` tracks which of those two transitions, if any, is
+/// currently in flight, so a run killed partway through leaves a record for
+/// [`resume_pending_cold_storage_action`] to finish on the next one, instead
+/// of the repo silently sitting thawed (and billing for it) until whoever
+/// notices.
+///
+/// `cancel`, if given, is checked between every phase below (see
+/// [`crate::cancel::CancellationToken`]) so a SIGINT/SIGTERM delivered
+/// mid-cycle stops at the next boundary with [`HalleyError::Cancelled`]
+/// rather than the process just dying wherever it happened to be. A cycle
+/// cancelled after thaw but before archive leaves `state.pending_action` set
+/// to [`PendingAction::Freeze`], the same marker an ordinary failed backup
+/// leaves, so [`resume_pending_cold_storage_action`] re-archives it on the
+/// next run exactly as it would for any other interruption.
+pub fn backup_cycle<C: WrappedCall>(
+    call: &C,
+    repo: &RepoConfig,
+    state: &mut RepoState,
+    snapshot_tag: &str,
+    memory_limit: Option<&restic::MemoryLimit>,
+    global_cache_dir: Option<&std::path::Path>,
+    cold_storage: Option<&dyn ColdStorageBackend>,
+    restic_version: Option<restic::Version>,
+    cancel: Option<&CancellationToken>,
+) -> Result<BackupCycleOutcome, HalleyError> {
+    let _repo_span = tracing::info_span!("repo", id = %repo.name).entered();
+    tracing::info!("starting backup cycle");
+
+    let check_cancelled = || -> Result<(), HalleyError> {
+        match cancel {
+            Some(token) => token.check(),
+            None => Ok(()),
+        }
+    };
+
+    check_cancelled()?;
+    tracing::debug_span!("pre_hook").in_scope(|| run_pre_hook(repo))?;
+    check_cancelled()?;
+    tracing::debug_span!("restore").in_scope(|| restore_phase(repo, state, cold_storage))?;
+    check_cancelled()?;
+    if repo.auto_init {
+        tracing::debug_span!("init").in_scope(|| restic::init(call))?;
+    }
+    if repo.check_before_backup {
+        tracing::debug_span!("check").in_scope(|| restic::check(call, None))?;
+    }
+    if let FirstBackupDecision::RequiresConfirmation { estimated_bytes } =
+        first_backup::first_backup_gate(call, repo)?
+    {
+        return Err(HalleyError::FirstBackupNotConfirmed { estimated_bytes });
+    }
+
+    let (sources, _missing_source_warnings) = filter_existing_sources(&repo.sources);
+    if sources.is_empty() && !repo.sources.is_empty() {
+        return Err(HalleyError::NoBackupSources { repo: repo.name.clone() });
+    }
+    warn_network_sources(repo, &sources);
+    record_changed_sources(repo, state, &sources)?;
+
+    let mut tags = vec![snapshot_tag.to_string()];
+    tags.extend(repo.tags.iter().cloned());
+    if let Some(version) = restic_version {
+        tags.push(format!("halley-restic-{version}"));
+    }
+    let compression_supported = restic_version
+        .map(|v| v >= restic::MIN_COMPRESSION_VERSION)
+        .unwrap_or(false);
+    let backup_options = restic::BackupOptions {
+        symlinks: repo.symlinks,
+        compression: repo.compression.filter(|_| compression_supported),
+        no_scan: repo.no_scan,
+        read_concurrency: repo.read_concurrency,
+        excludes: crate::excludes::expand(&repo.excludes)?,
+        exclude_file: repo.resolved_exclude_file(),
+        tags,
+        no_cache: repo.no_cache || memory_limit.map(|m| m.no_cache).unwrap_or(false),
+        limit_upload: repo.limit_upload,
+        limit_download: repo.limit_download,
+        cache_dir: repo.resolved_cache_dir(global_cache_dir),
+        hostname: repo.hostname.clone(),
+        one_file_system: repo.one_file_system,
+        dry_run: false,
+    };
+    check_cancelled()?;
+    let backup_result = match tracing::info_span!("backup").in_scope(|| {
+        run_backup_with_unlock_retry(call, repo, &sources, &backup_options)
+    }) {
+        Ok(result) => result,
+        Err(e) => {
+            tracing::warn!(error = %e, "backup failed");
+            if repo.archive_after_failed_backup {
+                let _ = archive_phase(call, repo, state, cold_storage, false);
+            }
+            return Err(e);
+        }
+    };
+    let warnings = restic::warnings::summarize(&backup_result.events);
+    let throughput =
+        restic::backup::summarize_throughput(&backup_result.events, restic::backup::DEFAULT_THROUGHPUT_WINDOW);
+    check_cancelled()?;
+    tracing::debug_span!("forget").in_scope(|| run_forget_with_confirmation_gate(call, repo, snapshot_tag))?;
+    if repo.prune {
+        tracing::debug_span!("prune").in_scope(|| restic::prune(call))?;
+    }
+    check_cancelled()?;
+    tracing::debug_span!("archive").in_scope(|| archive_phase(call, repo, state, cold_storage, false))?;
+    tracing::info!("backup cycle finished");
+    Ok(BackupCycleOutcome {
+        snapshot_id: backup_result.summary.map(|s| s.snapshot_id),
+        warnings,
+        throughput,
+    })
+}
+
+/// [`backup_cycle`]'s result: the snapshot it produced, if any, plus the
+/// categorized warnings restic emitted along the way (see
+/// [`restic::warnings::summarize`]) and the throughput/ETA smoothed from its
+/// status lines (see [`restic::backup::summarize_throughput`]).
+#[derive(Debug, Clone, Default)]
+pub struct BackupCycleOutcome {
+    pub snapshot_id: Option<String>,
+    pub warnings: restic::WarningSummary,
+    pub throughput: restic::backup::ThroughputSummary,
+}
+
+/// [`dry_run_backup_cycle`]'s preview of what a real [`backup_cycle`] would
+/// do, with concrete numbers instead of "some files, somewhere": what
+/// `restic backup --dry-run` reports it would add, and what `restic forget
+/// --dry-run` reports it would remove under the repo's retention policy.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DryRunPlan {
+    pub files_new: u64,
+    pub files_changed: u64,
+    pub total_bytes_processed: u64,
+    pub snapshots_would_forget: Vec<String>,
+    pub would_prune: bool,
+}
+
+/// One repo's [`DryRunPlan`] from a `halley backup --dry` run, or the error
+/// that kept it from being previewed. Mirrors [`RunOutcome`]'s shape for the
+/// same reason: one line per repo when printed, or serialized wholesale with
+/// `--json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRunReportEntry {
+    pub repo: String,
+    pub plan: Result<DryRunPlan, String>,
+}
+
+/// Previews [`backup_cycle`] for a local repository: runs `restic backup
+/// --dry-run` for real change/size numbers and `restic forget --dry-run`
+/// against the repo's retention policy, all without mutating the repository
+/// or `state` -- restic itself guarantees the former for both dry-run
+/// subcommands; the latter is simply never touched -- this signature takes
+/// no `&mut RepoState` at all, so there's nothing for a dry run to
+/// accidentally commit (see [`record_changed_sources`]'s doc for the
+/// digest-tracking side of that same guarantee).
+///
+/// Cold-storage-backed repos (`cold_storage.is_some()`) aren't supported: a
+/// dry run has no safe way to preview a thaw, which is why this takes
+/// `cold_storage` at all -- only to reject it, with
+/// [`HalleyError::ColdStorageDryRunUnsupported`]. Use `halley verify --dry`
+/// to check on those without side effects instead.
+///
+/// `repo.prune` is reported back as-is rather than previewed: `restic
+/// prune` has no `--dry-run` mode to ask.
+pub fn dry_run_backup_cycle<C: WrappedCall>(
+    call: &C,
+    repo: &RepoConfig,
+    snapshot_tag: &str,
+    memory_limit: Option<&restic::MemoryLimit>,
+    global_cache_dir: Option<&std::path::Path>,
+    cold_storage: Option<&dyn ColdStorageBackend>,
+) -> Result<DryRunPlan, HalleyError> {
+    if cold_storage.is_some() {
+        return Err(HalleyError::ColdStorageDryRunUnsupported { repo: repo.name.clone() });
+    }
+
+    let (sources, _missing_source_warnings) = filter_existing_sources(&repo.sources);
+    if sources.is_empty() && !repo.sources.is_empty() {
+        return Err(HalleyError::NoBackupSources { repo: repo.name.clone() });
+    }
+    warn_network_sources(repo, &sources);
+
+    let mut tags = vec![snapshot_tag.to_string()];
+    tags.extend(repo.tags.iter().cloned());
+    let backup_options = restic::BackupOptions {
+        symlinks: repo.symlinks,
+        compression: None,
+        no_scan: repo.no_scan,
+        read_concurrency: repo.read_concurrency,
+        excludes: crate::excludes::expand(&repo.excludes)?,
+        exclude_file: repo.resolved_exclude_file(),
+        tags,
+        no_cache: repo.no_cache || memory_limit.map(|m| m.no_cache).unwrap_or(false),
+        limit_upload: repo.limit_upload,
+        limit_download: repo.limit_download,
+        cache_dir: repo.resolved_cache_dir(global_cache_dir),
+        hostname: repo.hostname.clone(),
+        one_file_system: repo.one_file_system,
+        dry_run: true,
+    };
+    let backup_result = restic::backup(call, &sources, &backup_options)?;
+    let summary = backup_result.summary.unwrap_or_default();
+
+    let snapshots_would_forget =
+        restic::forget_dry_run(call, &repo.retention, snapshot_tag, repo.hostname.as_deref())?
+            .into_iter()
+            .map(|s| s.short_id)
+            .collect();
+
+    Ok(DryRunPlan {
+        files_new: summary.files_new,
+        files_changed: summary.files_changed,
+        total_bytes_processed: summary.total_bytes_processed,
+        snapshots_would_forget,
+        would_prune: repo.prune,
+    })
+}
+
+/// The thaw phase of the cycle, mirroring [`archive_phase`] at the other
+/// end: a repo with no [`ColdStorageBackend`] configured has nothing to
+/// thaw; one that has gets `state.pending_action` set to
+/// [`PendingAction::Thaw`] and then [`ColdStorageBackend::restore_all`] then
+/// [`ColdStorageBackend::restore_blocking`] called on it, so its data is
+/// reachable before [`backup_cycle`] does anything else with the repo,
+/// followed by `repo.s3.on_restore_complete` (see
+/// [`run_on_restore_complete`]) if configured. Once thawed,
+/// `state.pending_action` becomes [`PendingAction::Freeze`]: the repo needs
+/// re-archiving before this cycle is done, whether or not it gets there.
+fn restore_phase(
+    repo: &RepoConfig,
+    state: &mut RepoState,
+    cold_storage: Option<&dyn ColdStorageBackend>,
+) -> Result<(), HalleyError> {
+    let Some(backend) = cold_storage else {
+        return Ok(());
+    };
+    state.pending_action = Some(PendingAction::Thaw);
+    backend.restore_all()?;
+    let report = backend.restore_blocking()?;
+    state.pending_action = Some(PendingAction::Freeze);
+    let _ = run_on_restore_complete(repo, &report);
+    Ok(())
+}
+
+/// Runs [`backup_cycle`] and records its outcome in `state`, so a repo
+/// whose backup just failed (e.g. [`HalleyError::Timeout`] after a hung
+/// restic process was killed) shows that failure in the statefile instead
+/// of `last_backup` silently keeping whatever it was left at by an earlier,
+/// successful run.
+///
+/// Before any of that, [`resume_pending_cold_storage_action`] finishes
+/// whatever cold-storage transition an earlier, interrupted run left
+/// half-done, so `backup_cycle`'s own thaw/re-archive isn't started against
+/// a repo that's already partway through one.
+pub fn run_backup_cycle<C: WrappedCall>(
+    call: &C,
+    repo: &RepoConfig,
+    state: &mut RepoState,
+    snapshot_tag: &str,
+    memory_limit: Option<&restic::MemoryLimit>,
+    global_cache_dir: Option<&std::path::Path>,
+    cold_storage: Option<&dyn ColdStorageBackend>,
+    restic_version: Option<restic::Version>,
+    cancel: Option<&CancellationToken>,
+) -> Result<(), HalleyError> {
+    resume_pending_cold_storage_action(call, repo, state, cold_storage)?;
+    let previous_snapshot_id = state.last_backup.as_ref().and_then(|b| b.snapshot_id.clone());
+    let started = Instant::now();
+    let result = backup_cycle(
+        call,
+        repo,
+        state,
+        snapshot_tag,
+        memory_limit,
+        global_cache_dir,
+        cold_storage,
+        restic_version,
+        cancel,
+    );
+    match &result {
+        Ok(outcome) => {
+            let bytes_added = outcome.snapshot_id.as_deref().and_then(|new_snapshot_id| {
+                backup_diff_report(call, previous_snapshot_id.as_deref(), new_snapshot_id)?
+                    .ok()
+                    .map(|diff| diff.added_bytes)
+            });
+            state.record_backup_success(
+                outcome.snapshot_id.clone(),
+                started.elapsed().as_secs(),
+                bytes_added,
+                outcome.warnings.clone(),
+                restic_version.map(|v| v.to_string()),
+                outcome.throughput.average_bytes_per_sec,
+                outcome.throughput.peak_bytes_per_sec,
+            );
+        }
+        Err(e) => state.record_backup_failure(e.to_string(), restic_version.map(|v| v.to_string())),
+    }
+    let _post_hook_result = run_post_hook(repo, result.is_ok());
+    result.map(|_| ())
+}
+
+/// How long a `pre_hook`/`post_hook` is allowed to run before it's killed,
+/// same as [`restic::RealCall`]'s own timeout mechanism. Not yet threaded
+/// through from [`crate::config::Config::command_timeout`]; a hook always
+/// gets this fixed budget regardless of the global restic timeout setting.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// The environment set on every `pre_hook`/`post_hook` invocation.
+/// `HALLEY_RESULT` is only meaningful for a post-hook, since a pre-hook
+/// runs before the backup it gates has an outcome.
+fn hook_env(repo: &RepoConfig, backup_succeeded: Option<bool>) -> Vec<(String, String)> {
+    let mut env = vec![("HALLEY_REPO_ID".to_string(), repo.name.clone())];
+    if let Some(succeeded) = backup_succeeded {
+        let result = if succeeded { "success" } else { "failure" };
+        env.push(("HALLEY_RESULT".to_string(), result.to_string()));
+    }
+    env
+}
+
+/// Runs `repo.pre_hook`, if any, before anything else in [`backup_cycle`].
+/// A nonzero exit (or the hook itself failing to run) aborts the backup for
+/// this repo, reported the same way a failed restic invocation would be —
+/// there's no dedicated hook-failure error variant, since from the caller's
+/// perspective it's just another reason the backup didn't happen.
+fn run_pre_hook(repo: &RepoConfig) -> Result<(), HalleyError> {
+    let Some(command) = &repo.pre_hook else {
+        return Ok(());
+    };
+    let output = util::run_hook(command, &hook_env(repo, None), Some(HOOK_TIMEOUT))?;
+    if !output.success() {
+        return Err(HalleyError::Restic {
+            status: output.status,
+            stderr: output.stderr,
+        });
+    }
+    Ok(())
+}
+
+/// Runs `repo.post_hook`, if any, after [`backup_cycle`] finishes — whether
+/// it succeeded or not, since a hook cleaning up a `pre_hook`'s work (e.g.
+/// deleting a database dump) needs to run either way. `backup_succeeded`
+/// becomes `HALLEY_RESULT`.
+///
+/// Unlike [`run_pre_hook`], a failure here is never surfaced as a backup
+/// failure — `backup_succeeded` already reflects the actual outcome of the
+/// backup itself, and a failed cleanup step shouldn't retroactively change
+/// that. Returns `None` when no `post_hook` is configured, `Some(Err(_))`
+/// when it failed to run or exited nonzero. [`run_backup_cycle`] discards
+/// that result without logging it.
+pub fn run_post_hook(repo: &RepoConfig, backup_succeeded: bool) -> Option<Result<util::HookOutput, HalleyError>> {
+    let command = repo.post_hook.as_ref()?;
+    let env = hook_env(repo, Some(backup_succeeded));
+    Some(run_hook_reporting_failure(command, &env))
+}
+
+/// Runs `command` with the given environment and [`HOOK_TIMEOUT`], turning a
+/// nonzero exit into a [`HalleyError::Restic`] the same way [`run_pre_hook`]
+/// and [`run_post_hook`] do — the shared bit of "run a configured hook and
+/// treat a bad exit as a failure" that all of Halley's hooks reduce to.
+fn run_hook_reporting_failure(command: &str, env: &[(String, String)]) -> Result<util::HookOutput, HalleyError> {
+    util::run_hook(command, env, Some(HOOK_TIMEOUT)).and_then(|output| {
+        if output.success() {
+            Ok(output)
+        } else {
+            Err(HalleyError::Restic {
+                status: output.status,
+                stderr: output.stderr.clone(),
+            })
+        }
+    })
+}
+
+/// The environment set on an `on_archive_complete`/`on_restore_complete`
+/// invocation, summarizing the [`crate::cold_storage::TransitionReport`] the
+/// milestone just produced. There's no `HALLEY_TOTAL_BYTES`: nothing in the
+/// cold storage layer tracks transferred bytes yet (see
+/// [`crate::cold_storage::TransitionReport`]).
+fn transition_hook_env(
+    repo: &RepoConfig,
+    report: &crate::cold_storage::TransitionReport,
+) -> Vec<(String, String)> {
+    vec![
+        ("HALLEY_REPO_ID".to_string(), repo.name.clone()),
+        ("HALLEY_OBJECT_COUNT".to_string(), report.object_count.to_string()),
+        ("HALLEY_DURATION_SECS".to_string(), report.duration.as_secs().to_string()),
+    ]
+}
+
+/// Runs `repo.s3.on_archive_complete`, if configured, once
+/// [`crate::cold_storage::ColdStorageBackend::archive_all`] has finished for
+/// an S3-backed repo. Like [`run_post_hook`], a failure here is never
+/// surfaced as an archive failure: the archive itself already succeeded by
+/// the time this runs, and the hook is only a notification about it.
+pub fn run_on_archive_complete(
+    repo: &RepoConfig,
+    report: &crate::cold_storage::TransitionReport,
+) -> Option<Result<util::HookOutput, HalleyError>> {
+    let command = repo.s3.as_ref()?.on_archive_complete.as_ref()?;
+    Some(run_hook_reporting_failure(command, &transition_hook_env(repo, report)))
+}
+
+/// Runs `repo.s3.on_restore_complete`, if configured, once
+/// [`crate::cold_storage::ColdStorageBackend::restore_blocking`] has
+/// finished for an S3-backed repo. Same non-fatal failure handling as
+/// [`run_on_archive_complete`].
+pub fn run_on_restore_complete(
+    repo: &RepoConfig,
+    report: &crate::cold_storage::TransitionReport,
+) -> Option<Result<util::HookOutput, HalleyError>> {
+    let command = repo.s3.as_ref()?.on_restore_complete.as_ref()?;
+    Some(run_hook_reporting_failure(command, &transition_hook_env(repo, report)))
+}
+
+/// Summarizes what changed since the previous backup, via `restic diff`
+/// between `old_snapshot_id` and `new_snapshot_id`, for [`RunOutcome::bytes_added`].
+/// Skipped (returns `None`) when there's no previous snapshot to diff
+/// against, e.g. a repo's first successful backup. A `restic diff` failure
+/// is returned as `Some(Err(_))` rather than failing the backup outright:
+/// the backup itself already succeeded, and the diff is only diagnostic.
+pub fn backup_diff_report<C: WrappedCall>(
+    call: &C,
+    old_snapshot_id: Option<&str>,
+    new_snapshot_id: &str,
+) -> Option<Result<restic::DiffSummary, HalleyError>> {
+    let old_snapshot_id = old_snapshot_id?;
+    Some(restic::diff(call, old_snapshot_id, new_snapshot_id))
+}
+
+/// What [`run_forget_with_confirmation_gate`] decided, given a dry-run
+/// preview and `repo.max_auto_forget`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForgetDecision {
+    /// At or below the configured cap (or no cap configured): proceed with a
+    /// real forget.
+    Proceed,
+    /// Above `max_auto_forget`: the real forget is skipped until a human
+    /// confirms it.
+    RequiresConfirmation { would_remove: usize },
+}
+
+/// Decides whether a forget that would remove `would_remove` snapshots is
+/// clear to run automatically, given `max_auto_forget`. `None` never
+/// requires confirmation, no matter how many snapshots would be removed.
+pub fn forget_decision(would_remove: usize, max_auto_forget: Option<u32>) -> ForgetDecision {
+    match max_auto_forget {
+        Some(max) if would_remove > max as usize => {
+            ForgetDecision::RequiresConfirmation { would_remove }
+        }
+        _ => ForgetDecision::Proceed,
+    }
+}
+
+/// Runs `restic forget`, previewing it first via `restic forget --dry-run`
+/// when `repo.max_auto_forget` is set. If the preview would remove more
+/// snapshots than the cap allows, the real forget is skipped and
+/// [`HalleyError::ForgetRequiresConfirmation`] is returned instead — a
+/// mis-edited retention policy shouldn't get to silently wipe history.
+///
+/// `halley forget <repo> --confirm` applies a forget that tripped this gate.
+fn run_forget_with_confirmation_gate<C: WrappedCall>(
+    call: &C,
+    repo: &RepoConfig,
+    snapshot_tag: &str,
+) -> Result<(), HalleyError> {
+    if let Some(max_auto_forget) = repo.max_auto_forget {
+        let would_remove =
+            restic::forget_dry_run(call, &repo.retention, snapshot_tag, repo.hostname.as_deref())?;
+        if let ForgetDecision::RequiresConfirmation { would_remove } =
+            forget_decision(would_remove.len(), Some(max_auto_forget))
+        {
+            return Err(HalleyError::ForgetRequiresConfirmation {
+                repo: repo.name.clone(),
+                would_remove,
+            });
+        }
+    }
+    restic::forget(call, &repo.retention, snapshot_tag, repo.hostname.as_deref())
+}
+
+/// Runs `restic backup`, and if it fails because the repository is already
+/// locked and `repo.auto_unlock_stale` is set, runs `restic unlock` and
+/// retries the backup once.
+///
+/// Halley doesn't track lock age, so this doesn't wait out any staleness
+/// window before unlocking — enabling `auto_unlock_stale` means trusting
+/// that nothing else is genuinely still holding the lock.
+fn run_backup_with_unlock_retry<C: WrappedCall>(
+    call: &C,
+    repo: &RepoConfig,
+    sources: &[std::path::PathBuf],
+    backup_options: &restic::BackupOptions,
+) -> Result<restic::backup::BackupResult, HalleyError> {
+    let result = restic::backup::backup(call, sources, backup_options);
+    match &result {
+        Err(e)
+            if repo.auto_unlock_stale && e.restic_error_kind() == Some(ResticErrorKind::LockHeld) =>
+        {
+            restic::unlock(call)?;
+            restic::backup::backup(call, sources, backup_options)
+        }
+        _ => result,
+    }
+}
+
+/// Filters `sources` down to paths that still exist on disk, so one deleted
+/// source (e.g. a glob target removed since the config was written) doesn't
+/// fail the whole backup while the rest are fine. Returns the surviving
+/// subset alongside a warning per path that was dropped, for the run report
+/// (see [`memory_limit_warnings`]); [`backup_cycle`] fails outright only
+/// when every source is missing.
+pub fn filter_existing_sources(sources: &[std::path::PathBuf]) -> (Vec<std::path::PathBuf>, Vec<String>) {
+    let mut existing = Vec::new();
+    let mut warnings = Vec::new();
+    for source in sources {
+        if source.exists() {
+            existing.push(source.clone());
+        } else {
+            warnings.push(format!("source path '{}' does not exist, skipping", source.display()));
+        }
+    }
+    (existing, warnings)
+}
+
+/// Logs a warning for each of `sources` that resolves to a network
+/// filesystem (see [`crate::mounts`]), unless `repo.allow_network_sources`
+/// is set. Best-effort: silent if `/proc/mounts` can't be read, since that's
+/// far more likely to mean a non-Linux host than an actual network source.
+fn warn_network_sources(repo: &RepoConfig, sources: &[std::path::PathBuf]) {
+    if repo.allow_network_sources {
+        return;
+    }
+    let Ok(mounts) = crate::mounts::ProcMounts::load() else {
+        return;
+    };
+    for warning in crate::mounts::warn_network_sources(&mounts, sources) {
+        eprintln!("repo '{}': {warning}", repo.name);
+    }
+}
+
+/// Hashes `sources` (see [`crate::digest::needs_update`]), ignoring files
+/// matching `repo`'s [`RepoConfig::resolved_digest_ignore`] patterns, and
+/// logs which of them changed since `state.digests` was last recorded, then
+/// updates `state.digests` regardless -- this is purely informational for
+/// now (see [`crate::digest`]'s module doc); nothing yet skips a backup on
+/// the strength of an unchanged digest. A hashing failure (e.g. a source
+/// vanishing mid-run) is logged and otherwise ignored, the same way
+/// [`warn_network_sources`] treats a failed `/proc/mounts` read: this is an
+/// observability aid, not something worth failing the backup over.
+///
+/// A `digest_ignore` pattern matching none of `sources`' files is always
+/// logged; with [`RepoConfig::strict_paths`] set, it also fails the run --
+/// unlike a hashing failure, a dead pattern is a config mistake, not flaky
+/// environment noise, so `strict_paths` exists for repos where that should
+/// be caught rather than quietly tolerated.
+///
+/// This only ever runs from [`backup_cycle`], which has no dry-run mode of
+/// its own: a `halley backup --dry` goes through [`dry_run_backup_cycle`]
+/// instead, which never takes a `&mut RepoState` at all, so there's no path
+/// by which a dry run reaches this function and commits a digest to
+/// `state`. [`crate::digest::needs_update`] itself is pure on top of
+/// that -- it computes a fresh digest map from `previous` without writing
+/// into it -- so calling it speculatively can never desync the comparison
+/// a later real run makes against the same state.
+fn record_changed_sources(
+    repo: &RepoConfig,
+    state: &mut RepoState,
+    sources: &[std::path::PathBuf],
+) -> Result<(), HalleyError> {
+    let ignore = match crate::excludes::expand(repo.resolved_digest_ignore())
+        .and_then(|patterns| crate::globset::GlobSet::compile(&patterns))
+    {
+        Ok(ignore) => ignore,
+        Err(e) => {
+            eprintln!("repo '{}': failed to hash source paths: {e}", repo.name);
+            return Ok(());
+        }
+    };
+    match crate::digest::needs_update(sources, &state.digests, &ignore, &crate::digest::RealDirectoryHasher) {
+        Ok((digests, changed, dead)) => {
+            if !changed.is_empty() {
+                eprintln!("repo '{}': changed paths since last backup: {}", repo.name, changed.join(", "));
+            }
+            for crate::digest::DeadPattern(pattern) in &dead {
+                eprintln!(
+                    "repo '{}': digest_ignore pattern '{pattern}' matched none of the source files",
+                    repo.name
+                );
+            }
+            state.digests = digests;
+            if repo.strict_paths && !dead.is_empty() {
+                return Err(HalleyError::Parse(format!(
+                    "repo '{}': digest_ignore pattern(s) matched nothing and strict_paths is set",
+                    repo.name
+                )));
+            }
+        }
+        Err(e) => eprintln!("repo '{}': failed to hash source paths: {e}", repo.name),
+    }
+    Ok(())
+}
+
+/// The archive phase of the cycle. A repo with no [`ColdStorageBackend`]
+/// configured has nothing to archive; one that has gets
+/// [`ColdStorageBackend::archive_all`] called on it, followed by
+/// `repo.s3.on_archive_complete` (see [`run_on_archive_complete`]) if
+/// configured, and `state.pending_action` cleared once that's confirmed
+/// done. Built-in S3 archiving (thaw/freeze orchestration for
+/// [`crate::config::RepoConfig::s3`] without a backend of its own) still
+/// doesn't exist.
+///
+/// If `repo.max_verify_age_days` is set and `force` is `false`, and this
+/// repo's verification is overdue (see [`RepoState::verify_is_stale`]),
+/// archiving is deferred the same way: it returns without archiving,
+/// leaving `state.pending_action` at [`PendingAction::Freeze`] so a
+/// `halley verify` run can check the repo while it's still hot, and a later
+/// [`resume_pending_cold_storage_action`]/[`backup_cycle`] call re-archives
+/// it. Set `repo.archive_unverified` to restore the old behaviour and
+/// archive regardless.
+///
+/// If `repo.archive_delay_hours` is set and `force` is `false`, the actual
+/// archive is deferred: the first call after a thaw records a due-time on
+/// `state.archive_due_at` (see [`RepoState::defer_archive`]) and returns
+/// without archiving, leaving `state.pending_action` at
+/// [`PendingAction::Freeze`] so [`resume_pending_cold_storage_action`] tries
+/// again on a later run; once the due-time has passed, that later call
+/// archives for real. `force` (set by [`force_archive`], for
+/// `halley s3 archive`) skips the delay entirely.
+fn archive_phase<C: WrappedCall>(
+    _call: &C,
+    repo: &RepoConfig,
+    state: &mut RepoState,
+    cold_storage: Option<&dyn ColdStorageBackend>,
+    force: bool,
+) -> Result<(), HalleyError> {
+    match cold_storage {
+        Some(backend) => {
+            if !force {
+                if !repo.archive_unverified {
+                    if let Some(max_age) = repo.max_verify_age_days {
+                        if state.verify_is_stale(max_age) {
+                            eprintln!(
+                                "repo '{}': deferring archive -- verification is overdue (not within the last {max_age} day(s)); set archive_unverified to re-freeze anyway",
+                                repo.name
+                            );
+                            return Ok(());
+                        }
+                    }
+                }
+                if let Some(delay_hours) = repo.archive_delay_hours.filter(|hours| *hours > 0) {
+                    state.defer_archive(delay_hours);
+                    if !state.archive_is_due() {
+                        return Ok(());
+                    }
+                }
+            }
+            let report = backend.archive_all()?;
+            state.pending_action = None;
+            state.clear_archive_due();
+            let _ = run_on_archive_complete(repo, &report);
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Forces the archive phase to run now for `repo`, bypassing any
+/// `archive_delay_hours` due-time still in the future. Used by
+/// `halley s3 archive` to let an operator archive early once they're done
+/// with a repo a delayed archive left hot. A no-op, not an error, for a repo
+/// with no cold storage backend configured.
+pub fn force_archive<C: WrappedCall>(
+    call: &C,
+    repo: &RepoConfig,
+    state: &mut RepoState,
+    cold_storage: Option<&dyn ColdStorageBackend>,
+) -> Result<(), HalleyError> {
+    archive_phase(call, repo, state, cold_storage, true)
+}
+
+/// Finishes a cold-storage transition left in flight by a run that was
+/// killed partway through (see [`restore_phase`]/[`archive_phase`]), before
+/// [`run_backup_cycle`] does anything else. [`PendingAction::Thaw`] means
+/// it's unclear whether the last thaw actually completed, so this just
+/// re-issues it; [`PendingAction::Freeze`] means the repo is definitely
+/// thawed and only needs re-archiving.
+///
+/// A no-op when `state.pending_action` is `None`, or when the repo has no
+/// [`ColdStorageBackend`] configured (a pending action from a repo whose
+/// cold storage was since removed from config can't be resolved, so it's
+/// left as-is rather than silently dropped).
+pub fn resume_pending_cold_storage_action<C: WrappedCall>(
+    call: &C,
+    repo: &RepoConfig,
+    state: &mut RepoState,
+    cold_storage: Option<&dyn ColdStorageBackend>,
+) -> Result<(), HalleyError> {
+    let Some(pending) = state.pending_action else {
+        return Ok(());
+    };
+    let Some(backend) = cold_storage else {
+        return Ok(());
+    };
+    match pending {
+        PendingAction::Thaw => restore_phase(repo, state, Some(backend)),
+        PendingAction::Freeze => archive_phase(call, repo, state, Some(backend), false),
+    }
+}
+
+/// Restores `snapshot` (or the latest one) from `repo` into `target`,
+/// honoring `repo.restore_sparse` and `repo.restore_flags`.
+///
+/// A repo with a [`ColdStorageBackend`] configured has
+/// [`ColdStorageBackend::restore_all`] and then
+/// [`ColdStorageBackend::restore_blocking`] run first, so its data is back
+/// in place before restic tries to read it, followed by
+/// `repo.s3.on_restore_complete` (see [`run_on_restore_complete`]) if
+/// configured. An S3 repo (see [`crate::config::RepoConfig::s3`]) with no
+/// backend configured has no way to thaw its Glacier-tiered objects yet, so
+/// this refuses it outright rather than silently attempting a restore restic
+/// can't actually service.
+pub fn restore_cycle<C: WrappedCall>(
+    call: &C,
+    repo: &RepoConfig,
+    snapshot: Option<&str>,
+    target: &std::path::Path,
+    include: &[String],
+    cold_storage: Option<&dyn ColdStorageBackend>,
+) -> Result<(), HalleyError> {
+    match cold_storage {
+        Some(backend) => {
+            backend.restore_all()?;
+            let report = backend.restore_blocking()?;
+            let _ = run_on_restore_complete(repo, &report);
+        }
+        None if repo.s3.is_some() => {
+            return Err(HalleyError::S3(format!(
+                "repo '{}': restoring from an S3 repository needs its Glacier objects thawed first, which isn't wired up yet",
+                repo.name
+            )));
+        }
+        None => {}
+    }
+    let options = restic::RestoreOptions {
+        sparse: repo.restore_sparse,
+        extra_flags: repo.restore_flags.clone(),
+    };
+    restic::restore::restore(call, snapshot, target, include, &options)
+}
+
+/// Runs a repository integrity verification and records the result in
+/// `state` on success. When `dry` is set, the check still runs against the
+/// real repository but `state` is left untouched, so a dry run can't leave
+/// the in-memory state (or, via a later save, the statefile) out of sync
+/// with what actually happened.
+pub fn run_verify<C: WrappedCall>(
+    call: &C,
+    state: &mut RepoState,
+    method: VerifyMethod,
+    dry: bool,
+) -> Result<(), HalleyError> {
+    let read_data_subset = match method {
+        VerifyMethod::CheckMetadata => None,
+        VerifyMethod::CheckReadData { percent } => Some(format!("{percent}%")),
+        // A full sample-restore check needs a real restore target; until
+        // that's wired in, it still runs a metadata check so state doesn't
+        // record a verification that never happened.
+        VerifyMethod::SampleRestore => None,
+    };
+    restic::check(call, read_data_subset.as_deref())?;
+    if !dry {
+        state.record_verify(method);
+    }
+    Ok(())
+}
+
+/// One repo's outcome from a read-only verification pass (see
+/// [`verify_repo`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoVerificationOutcome {
+    pub repo: String,
+    pub check_result: Result<(), String>,
+    /// The configured retention policy, echoed back for a human to
+    /// eyeball. Not a live check: nothing here runs `forget --dry-run` or
+    /// otherwise inspects real snapshots against it.
+    pub retention: restic::RetentionPolicy,
+    /// A cold storage listing summary, for repos with `s3` or
+    /// `cold_storage_command` configured. `None` for a repo with neither.
+    pub cold_storage_report: Option<String>,
+    /// Whether this was a dry run (see [`run_verify`]): `check_result`
+    /// reflects a real `restic check`, but `state`'s `last_verified` was
+    /// left untouched. Echoed back so a caller printing outcomes (e.g.
+    /// [`crate::print_verification_outcome`]) can mark a rehearsal as one,
+    /// instead of it looking indistinguishable from a recorded verification.
+    pub dry: bool,
+}
+
+impl RepoVerificationOutcome {
+    pub fn passed(&self) -> bool {
+        self.check_result.is_ok()
+    }
+}
+
+/// The result of verifying one or more repos read-only, e.g. `halley
+/// verify` run without a specific repo.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerificationReport {
+    pub outcomes: Vec<RepoVerificationOutcome>,
+}
+
+impl VerificationReport {
+    pub fn all_passed(&self) -> bool {
+        self.outcomes.iter().all(RepoVerificationOutcome::passed)
+    }
+}
+
+/// Verifies one repo read-only: a restic integrity check (see
+/// [`run_verify`]) plus, if `cold_storage` is configured, a listing of its
+/// contents.
+///
+/// Guaranteed not to write to the repository, the cold storage backend, or
+/// the statefile beyond the `last_verify` timestamp [`run_verify`] already
+/// records: there's no `forget`, `prune`, `archive_all`, `restore_all`, or
+/// `restore_blocking` call anywhere in this path, only `restic check` and
+/// [`ColdStorageBackend::list`]/[`ColdStorageBackend::report`]. Pass `dry`
+/// to skip even that `last_verify` update.
+pub fn verify_repo<C: WrappedCall>(
+    call: &C,
+    repo: &RepoConfig,
+    state: &mut RepoState,
+    method: VerifyMethod,
+    cold_storage: Option<&dyn ColdStorageBackend>,
+    dry: bool,
+) -> RepoVerificationOutcome {
+    let check_result = run_verify(call, state, method, dry).map_err(|e| e.to_string());
+    let cold_storage_report = cold_storage.map(|backend| match backend.list() {
+        Ok(objects) => format!("{} ({} objects)", backend.report(), objects.len()),
+        Err(e) => format!("{} (listing failed: {e})", backend.report()),
+    });
+    RepoVerificationOutcome {
+        repo: repo.name.clone(),
+        check_result,
+        retention: repo.retention,
+        cold_storage_report,
+        dry,
+    }
+}
+
+/// A warning for the run report when a repo's memory limit is configured
+/// but the installed restic can't honor `GOMEMLIMIT`, so only `GOGC` (and,
+/// if the budget is tight, `--no-cache`) actually apply.
+pub fn memory_limit_warnings(repo: &RepoConfig, limit: &restic::MemoryLimit) -> Vec<String> {
+    if limit.gomemlimit_unsupported {
+        vec![format!(
+            "repo '{}': restic doesn't support GOMEMLIMIT (needs Go 1.19+), falling back to GOGC only",
+            repo.name
+        )]
+    } else {
+        vec![]
+    }
+}
+
+/// A warning for the run report when a repo has `compression` configured
+/// but the installed restic (or an unknown one) is too old to understand
+/// `--compression`, so [`backup_cycle`] silently drops it.
+pub fn compression_warnings(repo: &RepoConfig, restic_version: Option<restic::Version>) -> Vec<String> {
+    if repo.compression.is_none() {
+        return vec![];
+    }
+    let supported = restic_version.map(|v| v >= restic::MIN_COMPRESSION_VERSION).unwrap_or(false);
+    if supported {
+        vec![]
+    } else {
+        vec![format!(
+            "repo '{}': compression is configured but the installed restic doesn't support --compression (needs {}.{}.{}+), dropping it",
+            repo.name,
+            restic::MIN_COMPRESSION_VERSION.major,
+            restic::MIN_COMPRESSION_VERSION.minor,
+            restic::MIN_COMPRESSION_VERSION.patch,
+        )]
+    }
+}
+
+/// Returns a warning line for every repo whose last verification is older
+/// than its configured `max_verify_age_days`, for inclusion in the run
+/// report.
+pub fn verify_staleness_warnings(
+    repos: &[RepoConfig],
+    states: &std::collections::BTreeMap<String, RepoState>,
+) -> Vec<String> {
+    repos
+        .iter()
+        .filter_map(|repo| {
+            let max_age = repo.max_verify_age_days?;
+            let stale = states
+                .get(&repo.name)
+                .map(|s| s.verify_is_stale(max_age))
+                .unwrap_or(true);
+            stale.then(|| {
+                format!(
+                    "repo '{}' has not been verified within the last {} day(s)",
+                    repo.name, max_age
+                )
+            })
+        })
+        .collect()
+}
+
+/// Picks which repos are due for a backup and the order to run them in (see
+/// [`Scheduler::next_up`]), oldest last-backup first. Used by `halley
+/// backup` when run without a specific repo, so a single invocation catches
+/// up every repo that needs it instead of requiring one invocation per
+/// repo. `default_min_interval_hours`/`default_max_interval_days` are
+/// [`crate::config::Config::default_min_backup_interval_hours`]/
+/// [`crate::config::Config::default_max_backup_interval_days`], used for any
+/// repo that doesn't set its own (see
+/// [`RepoConfig::resolved_min_backup_interval_hours`]/
+/// [`RepoConfig::resolved_max_backup_interval_days`]); `failure_backoff_base_hours`/
+/// `failure_backoff_max_hours` are [`crate::config::Config::failure_backoff_base_hours`]/
+/// [`crate::config::Config::failure_backoff_max_hours`].
+///
+/// A repo with no recorded backup sorts ahead of every repo that has one. A
+/// repo whose last backup is younger than its effective min interval isn't
+/// due yet, unless that last backup failed -- a failure should be retried,
+/// not waited out. A repo whose last backup is older than its effective max
+/// interval is forced due, the same as [`RepoState::last_backup_failed`],
+/// so restic retention windows stay meaningful even if nothing has changed.
+///
+/// A repo still serving out its [`RepoState::failure_backoff_active`]
+/// window after repeated failures is excluded entirely rather than forced,
+/// so a repo that's been broken for a while doesn't get retried (and starve
+/// the healthy repos of their turn) on every single invocation.
+pub fn due_repos(
+    repos: &[RepoConfig],
+    states: &std::collections::BTreeMap<String, RepoState>,
+    default_min_interval_hours: Option<u32>,
+    default_max_interval_days: Option<u32>,
+    failure_backoff_base_hours: u32,
+    failure_backoff_max_hours: u32,
+) -> Vec<String> {
+    let candidates: Vec<Candidate> = repos
+        .iter()
+        .map(|repo| {
+            let state = states.get(&repo.name);
+            let age = state.map(RepoState::backup_age_secs).unwrap_or(u64::MAX);
+            let failed = state.is_some_and(RepoState::last_backup_failed);
+            let backing_off = state.is_some_and(|state| {
+                state.failure_backoff_active(failure_backoff_base_hours, failure_backoff_max_hours)
+            });
+            if backing_off {
+                eprintln!(
+                    "repo '{}': skipping this run, backing off after {} consecutive failure(s)",
+                    repo.name,
+                    state.map(|s| s.consecutive_failures).unwrap_or(0)
+                );
+            }
+
+            let min_interval_secs = repo
+                .resolved_min_backup_interval_hours(default_min_interval_hours)
+                .map(|hours| u64::from(hours) * 3600);
+            let too_soon = !failed && min_interval_secs.is_some_and(|min| age < min);
+
+            let max_interval_secs = repo
+                .resolved_max_backup_interval_days(default_max_interval_days)
+                .map(|days| u64::from(days) * 24 * 3600);
+            let overdue_past_max = max_interval_secs.is_some_and(|max| age >= max);
+
+            let mut candidate = Candidate::new(repo.name.clone(), !too_soon && !backing_off, age);
+            candidate.forced = failed || overdue_past_max;
+            candidate
+        })
+        .collect();
+    Scheduler::next_up(&candidates)
+}
+
+/// One repo's due-or-not verdict from [`check_due`], with a one-line reason
+/// a human (or a monitoring script) can act on without re-deriving
+/// [`due_repos`]'s own logic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RepoDueStatus {
+    pub repo: String,
+    pub due: bool,
+    pub reason: String,
+}
+
+/// [`due_repos`]'s eligibility logic, reported for every repo instead of
+/// just the ones due, and without picking an order among them -- for
+/// answering "would `halley backup` do anything right now?" from a
+/// monitoring script with no side effects: no statefile write, no restic or
+/// S3 call. A repo with no entry in `states` is reported due ("never backed
+/// up") rather than erroring, unlike a real backup run against a statefile
+/// that's missing a repo it expects -- there's nothing wrong with a
+/// statefile that simply hasn't seen this repo yet.
+pub fn check_due(
+    repos: &[RepoConfig],
+    states: &std::collections::BTreeMap<String, RepoState>,
+    default_min_interval_hours: Option<u32>,
+    default_max_interval_days: Option<u32>,
+    failure_backoff_base_hours: u32,
+    failure_backoff_max_hours: u32,
+) -> Vec<RepoDueStatus> {
+    repos
+        .iter()
+        .map(|repo| {
+            let Some(state) = states.get(&repo.name) else {
+                return RepoDueStatus {
+                    repo: repo.name.clone(),
+                    due: true,
+                    reason: "never backed up".to_string(),
+                };
+            };
+            if state.failure_backoff_active(failure_backoff_base_hours, failure_backoff_max_hours) {
+                return RepoDueStatus {
+                    repo: repo.name.clone(),
+                    due: false,
+                    reason: format!(
+                        "backing off after {} consecutive failure(s)",
+                        state.consecutive_failures
+                    ),
+                };
+            }
+            let failed = state.last_backup_failed();
+            if failed {
+                return RepoDueStatus {
+                    repo: repo.name.clone(),
+                    due: true,
+                    reason: "last backup failed".to_string(),
+                };
+            }
+            let age = state.backup_age_secs();
+            let max_interval_secs = repo
+                .resolved_max_backup_interval_days(default_max_interval_days)
+                .map(|days| u64::from(days) * 24 * 3600);
+            if max_interval_secs.is_some_and(|max| age >= max) {
+                return RepoDueStatus {
+                    repo: repo.name.clone(),
+                    due: true,
+                    reason: "past max_backup_interval_days".to_string(),
+                };
+            }
+            let min_interval_secs = repo
+                .resolved_min_backup_interval_hours(default_min_interval_hours)
+                .map(|hours| u64::from(hours) * 3600);
+            if min_interval_secs.is_some_and(|min| age < min) {
+                return RepoDueStatus {
+                    repo: repo.name.clone(),
+                    due: false,
+                    reason: "backed up within min_backup_interval_hours".to_string(),
+                };
+            }
+            RepoDueStatus { repo: repo.name.clone(), due: true, reason: "overdue".to_string() }
+        })
+        .collect()
+}
+
+/// One repo's outcome from a `halley backup` run, whether against a single
+/// named repo or over every repo that's due (see [`due_repos`]). Built from
+/// `repo_state` right after [`run_backup_cycle`] returns (see
+/// [`RunOutcome::from_repo_state`]), for `main` to print as the final
+/// cron-friendly summary line or, with `--json`, serialize wholesale.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunOutcome {
+    pub repo: String,
+    pub result: Result<(), String>,
+    /// The snapshot restic produced, if the backup got far enough to make
+    /// one.
+    pub snapshot_id: Option<String>,
+    /// Bytes added since the previous snapshot; see
+    /// [`backup_diff_report`]. `None` when there was nothing to diff
+    /// against, or the backup didn't succeed.
+    pub bytes_added: Option<u64>,
+    pub duration_secs: u64,
+    /// `false` if a cold-storage thaw or freeze was still in flight when
+    /// this outcome was recorded, i.e. `repo_state.pending_action` was
+    /// `Some(_)` — see [`resume_pending_cold_storage_action`].
+    pub cold_storage_settled: bool,
+    /// Categorized restic warnings from the backup, e.g. permission errors
+    /// or files that changed mid-read. See [`restic::warnings::summarize`].
+    pub warnings: restic::WarningSummary,
+    /// [`RunStatus::Partial`] if `warnings` exceeded the repo's
+    /// `warning_threshold`, even though the backup itself succeeded.
+    pub status: RunStatus,
+    /// The restic version this backup ran against, if it was known. See
+    /// [`crate::state::BackupRecord::restic_version`].
+    pub restic_version: Option<String>,
+    /// Halley's own version at the time of this backup. See
+    /// [`crate::state::BackupRecord::halley_version`].
+    pub halley_version: Option<String>,
+    /// Bytes/sec smoothed over the whole backup. See
+    /// [`restic::backup::summarize_throughput`].
+    pub average_throughput_bytes_per_sec: Option<u64>,
+    /// The highest smoothed rate seen at any point during the backup.
+    pub peak_throughput_bytes_per_sec: Option<u64>,
+}
+
+impl RunOutcome {
+    /// Builds a `RunOutcome` from `repo_state` right after
+    /// [`run_backup_cycle`] returns. `warning_threshold` is the repo's own
+    /// [`crate::config::RepoConfig::warning_threshold`], used to decide
+    /// whether this run's warnings are enough to downgrade `status` to
+    /// [`RunStatus::Partial`].
+    pub fn from_repo_state(
+        repo: impl Into<String>,
+        result: Result<(), String>,
+        repo_state: &RepoState,
+        warning_threshold: Option<u32>,
+    ) -> Self {
+        let (
+            snapshot_id,
+            bytes_added,
+            duration_secs,
+            warnings,
+            restic_version,
+            halley_version,
+            average_throughput_bytes_per_sec,
+            peak_throughput_bytes_per_sec,
+        ) = match &repo_state.last_backup {
+            Some(record) => {
+                let (duration_secs, bytes_added, warnings, average_throughput, peak_throughput) =
+                    match &record.outcome {
+                        crate::state::BackupOutcome::Success {
+                            duration_secs,
+                            bytes_added,
+                            warnings,
+                            average_throughput_bytes_per_sec,
+                            peak_throughput_bytes_per_sec,
+                        } => (
+                            *duration_secs,
+                            *bytes_added,
+                            warnings.clone(),
+                            *average_throughput_bytes_per_sec,
+                            *peak_throughput_bytes_per_sec,
+                        ),
+                        crate::state::BackupOutcome::Failed { .. } => {
+                            (0, None, restic::WarningSummary::default(), None, None)
+                        }
+                    };
+                (
+                    record.snapshot_id.clone(),
+                    bytes_added,
+                    duration_secs,
+                    warnings,
+                    record.restic_version.clone(),
+                    record.halley_version.clone(),
+                    average_throughput,
+                    peak_throughput,
+                )
+            }
+            None => (None, None, 0, restic::WarningSummary::default(), None, None, None, None),
+        };
+        let status = if result.is_err() {
+            RunStatus::Failed
+        } else if warning_threshold.is_some_and(|threshold| warnings.total() as u32 > threshold) {
+            RunStatus::Partial
+        } else {
+            RunStatus::Success
+        };
+        Self {
+            repo: repo.into(),
+            result,
+            snapshot_id,
+            bytes_added,
+            duration_secs,
+            cold_storage_settled: repo_state.pending_action.is_none(),
+            warnings,
+            status,
+            restic_version,
+            halley_version,
+            average_throughput_bytes_per_sec,
+            peak_throughput_bytes_per_sec,
+        }
+    }
+}
+
+/// Coarse outcome classification for a [`RunOutcome`], for `main` to print
+/// and for a caller scripting around `--json` output to branch on without
+/// re-deriving the warning-threshold logic itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    /// The backup succeeded with no warnings past the repo's threshold.
+    Success,
+    /// The backup succeeded, but restic logged more warnings than the
+    /// repo's `warning_threshold` allows.
+    Partial,
+    /// The backup itself failed.
+    Failed,
+}
+
+/// The result of a `halley backup` run, whether over one named repo or every
+/// repo that's due. A failure in one repo doesn't stop the rest from being
+/// attempted, so an empty `outcomes` means there was nothing due to back up
+/// (see [`due_repos`]), not that the run failed.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunReport {
+    pub outcomes: Vec<RunOutcome>,
+}
+
+impl RunReport {
+    pub fn succeeded(&self) -> usize {
+        self.outcomes.iter().filter(|o| o.result.is_ok()).count()
+    }
+
+    pub fn all_succeeded(&self) -> bool {
+        self.outcomes.iter().all(|o| o.result.is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn policy(changed_during_backup: ChangedDuringBackup) -> RetryPolicy {
+        RetryPolicy {
+            changed_during_backup,
+            pause: Duration::from_millis(0),
+        }
+    }
+
+    fn transient() -> HalleyError {
+        HalleyError::S3("connection reset by peer".into())
+    }
+
+    fn permanent() -> HalleyError {
+        HalleyError::S3("access denied".into())
+    }
+
+    #[test]
+    fn archive_retries_once_on_transient_failure() {
+        let calls = Cell::new(0);
+        let (attempts, result) = run_phase_with_retry(Phase::Archive, &policy(ChangedDuringBackup::Ignore), || {
+            calls.set(calls.get() + 1);
+            if calls.get() == 1 {
+                Err(transient())
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(calls.get(), 2);
+        assert!(result.is_ok());
+        assert_eq!(attempts.len(), 2);
+    }
+
+    #[test]
+    fn archive_does_not_retry_permanent_failure() {
+        let calls = Cell::new(0);
+        let (attempts, result) = run_phase_with_retry(Phase::Archive, &policy(ChangedDuringBackup::Ignore), || {
+            calls.set(calls.get() + 1);
+            Err(permanent())
+        });
+        assert_eq!(calls.get(), 1);
+        assert!(result.is_err());
+        assert_eq!(attempts.len(), 1);
+    }
+
+    #[test]
+    fn backup_ignores_transient_failure_by_default() {
+        let calls = Cell::new(0);
+        let (_, result) = run_phase_with_retry(Phase::Backup, &policy(ChangedDuringBackup::Ignore), || {
+            calls.set(calls.get() + 1);
+            Err(transient())
+        });
+        assert_eq!(calls.get(), 1);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn backup_retries_when_configured() {
+        let calls = Cell::new(0);
+        let (_, result) = run_phase_with_retry(Phase::Backup, &policy(ChangedDuringBackup::RetryOnce), || {
+            calls.set(calls.get() + 1);
+            if calls.get() == 1 {
+                Err(transient())
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(calls.get(), 2);
+        assert!(result.is_ok());
+    }
+
+    fn repo_config(prune: bool) -> RepoConfig {
+        RepoConfig {
+            name: "test".into(),
+            sources: vec![],
+            repo: "/tmp/repo".into(),
+            retention: restic::RetentionPolicy::default(),
+            prune,
+            changed_during_backup: ChangedDuringBackup::Ignore,
+            max_verify_age_days: None,
+            symlinks: restic::SymlinkPolicy::default(),
+            compression: None,
+            no_scan: false,
+            read_concurrency: None,
+            excludes: vec![],
+            exclude_file: None,
+            digest_ignore: Vec::new(),
+            strict_paths: false,
+            tags: vec![],
+            check_before_backup: false,
+            restic_memory_limit_mb: None,
+            auto_init: false,
+            password: Some("testpass".to_string()),
+            password_file: None,
+            password_command: None,
+            password_source: None,
+            limit_upload: None,
+            limit_download: None,
+            allow_initial_backup: false,
+            first_backup_size_threshold_mb: None,
+            cache_dir: None,
+            no_cache: false,
+            restore_sparse: false,
+            restore_flags: vec![],
+            hostname: None,
+            one_file_system: false,
+            auto_unlock_stale: false,
+            max_auto_forget: None,
+            warning_threshold: None,
+            s3: None,
+            cold_storage_command: None,
+            pre_hook: None,
+            post_hook: None,
+            archive_after_failed_backup: false,
+            archive_delay_hours: None,
+            archive_unverified: false,
+            min_backup_interval_hours: None,
+            max_backup_interval_days: None,
+            extra_env_passthrough: Vec::new(),
+            allow_network_sources: false,
+        }
+    }
+
+    #[test]
+    fn backup_cycle_runs_the_pre_hook_before_the_backup() {
+        let call = restic::mock::MockCall::ok();
+        let dir = std::env::temp_dir().join(format!("halley-pre-hook-test-{}", std::process::id()));
+        let marker = dir.join("marker");
+        std::fs::create_dir_all(&dir).unwrap();
+        let _ = std::fs::remove_file(&marker);
+
+        let mut repo = repo_config(false);
+        repo.pre_hook = Some(format!("touch {}", marker.display()));
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+
+        assert!(marker.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn backup_cycle_aborts_when_the_pre_hook_fails() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.pre_hook = Some("exit 1".to_string());
+        let err = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { status: 1, .. }));
+        // The backup itself must never have been attempted.
+        assert!(call.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn backup_cycle_stops_at_the_next_phase_boundary_once_cancelled() {
+        let call = restic::mock::MockCall::ok();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let err = backup_cycle(
+            &call,
+            &repo_config(false),
+            &mut RepoState::default(),
+            "halley",
+            None,
+            None,
+            None,
+            None,
+            Some(&cancel),
+        )
+        .unwrap_err();
+        assert!(matches!(err, HalleyError::Cancelled));
+        // Cancellation is checked before the pre-hook even runs.
+        assert!(call.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn backup_cycle_ignores_an_uncancelled_token() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput { status: 0, stdout: "[]".to_string(), ..Default::default() },
+            restic::CallOutput {
+                status: 0,
+                stdout: r#"{"message_type":"summary","files_new":0,"files_changed":0,"total_bytes_processed":0,"snapshot_id":"deadbeef"}"#.to_string(),
+                ..Default::default()
+            },
+        ]);
+        let cancel = CancellationToken::new();
+        let outcome = backup_cycle(
+            &call,
+            &repo_config(false),
+            &mut RepoState::default(),
+            "halley",
+            None,
+            None,
+            None,
+            None,
+            Some(&cancel),
+        )
+        .unwrap();
+        assert_eq!(outcome.snapshot_id.as_deref(), Some("deadbeef"));
+    }
+
+    #[test]
+    fn pre_hook_sees_the_repo_id() {
+        let dir = std::env::temp_dir().join(format!("halley-pre-hook-env-test-{}", std::process::id()));
+        let marker = dir.join("marker");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut repo = repo_config(false);
+        repo.name = "home".to_string();
+        repo.pre_hook = Some(format!("echo -n \"$HALLEY_REPO_ID\" > {}", marker.display()));
+        let call = restic::mock::MockCall::ok();
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "home");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn run_post_hook_is_none_without_one_configured() {
+        let repo = repo_config(false);
+        assert!(run_post_hook(&repo, true).is_none());
+    }
+
+    #[test]
+    fn run_post_hook_reports_the_backup_result() {
+        let dir = std::env::temp_dir().join(format!("halley-post-hook-test-{}", std::process::id()));
+        let marker = dir.join("marker");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut repo = repo_config(false);
+        repo.post_hook = Some(format!("echo -n \"$HALLEY_RESULT\" > {}", marker.display()));
+        let outcome = run_post_hook(&repo, true).unwrap();
+        assert!(outcome.unwrap().success());
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "success");
+
+        run_post_hook(&repo, false).unwrap().unwrap();
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "failure");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn run_post_hook_failure_is_returned_but_not_panicked() {
+        let mut repo = repo_config(false);
+        repo.post_hook = Some("exit 2".to_string());
+        let err = run_post_hook(&repo, true).unwrap().unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { status: 2, .. }));
+    }
+
+    #[test]
+    fn transition_hook_env_reports_the_repo_object_count_and_duration() {
+        let repo = repo_config(false);
+        let report = crate::cold_storage::TransitionReport {
+            object_count: 42,
+            duration: Duration::from_secs(7),
+        };
+        let env = transition_hook_env(&repo, &report);
+        assert!(env.contains(&("HALLEY_REPO_ID".to_string(), repo.name.clone())));
+        assert!(env.contains(&("HALLEY_OBJECT_COUNT".to_string(), "42".to_string())));
+        assert!(env.contains(&("HALLEY_DURATION_SECS".to_string(), "7".to_string())));
+    }
+
+    #[test]
+    fn run_on_archive_complete_is_none_without_s3_configured() {
+        let repo = repo_config(false);
+        let report = crate::cold_storage::TransitionReport::default();
+        assert!(run_on_archive_complete(&repo, &report).is_none());
+    }
+
+    #[test]
+    fn run_on_archive_complete_is_none_without_the_command_configured() {
+        let mut repo = repo_config(false);
+        repo.s3 = Some(crate::config::S3RepoConfig {
+            bucket: "backups".into(),
+            max_restore_requests_per_run: 10,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: None,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            credential_command: None,
+            credential_source: None,
+        });
+        let report = crate::cold_storage::TransitionReport::default();
+        assert!(run_on_archive_complete(&repo, &report).is_none());
+    }
+
+    #[test]
+    fn run_on_archive_complete_sees_the_transition_report() {
+        let dir = std::env::temp_dir().join(format!("halley-archive-complete-test-{}", std::process::id()));
+        let marker = dir.join("marker");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut repo = repo_config(false);
+        repo.s3 = Some(crate::config::S3RepoConfig {
+            bucket: "backups".into(),
+            max_restore_requests_per_run: 10,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: Some(format!(
+                "echo -n \"$HALLEY_OBJECT_COUNT:$HALLEY_DURATION_SECS\" > {}",
+                marker.display()
+            )),
+            on_restore_complete: None,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            credential_command: None,
+            credential_source: None,
+        });
+        let report = crate::cold_storage::TransitionReport {
+            object_count: 3,
+            duration: Duration::from_secs(5),
+        };
+        run_on_archive_complete(&repo, &report).unwrap().unwrap();
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "3:5");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn run_on_restore_complete_sees_the_transition_report() {
+        let dir = std::env::temp_dir().join(format!("halley-restore-complete-test-{}", std::process::id()));
+        let marker = dir.join("marker");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut repo = repo_config(false);
+        repo.s3 = Some(crate::config::S3RepoConfig {
+            bucket: "backups".into(),
+            max_restore_requests_per_run: 10,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: Some(format!("echo -n \"$HALLEY_OBJECT_COUNT\" > {}", marker.display())),
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            credential_command: None,
+            credential_source: None,
+        });
+        let report = crate::cold_storage::TransitionReport {
+            object_count: 9,
+            duration: Duration::ZERO,
+        };
+        run_on_restore_complete(&repo, &report).unwrap().unwrap();
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "9");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn archive_phase_runs_on_archive_complete_only_after_the_archive_succeeds() {
+        let call = restic::mock::MockCall::ok();
+        let dir = std::env::temp_dir().join(format!("halley-archive-phase-hook-test-{}", std::process::id()));
+        let marker = dir.join("marker");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut repo = repo_config(false);
+        repo.s3 = Some(crate::config::S3RepoConfig {
+            bucket: "backups".into(),
+            max_restore_requests_per_run: 10,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: Some(format!("touch {}", marker.display())),
+            on_restore_complete: None,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            credential_command: None,
+            credential_source: None,
+        });
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+
+        archive_phase(&call, &repo, &mut RepoState::default(), Some(&backend), false).unwrap();
+        assert_eq!(*backend.calls.borrow(), vec!["archive_all"]);
+        assert!(marker.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn archive_phase_does_not_run_on_archive_complete_when_the_archive_fails() {
+        let call = restic::mock::MockCall::ok();
+        let dir = std::env::temp_dir().join(format!("halley-archive-phase-hook-failure-test-{}", std::process::id()));
+        let marker = dir.join("marker");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut repo = repo_config(false);
+        repo.s3 = Some(crate::config::S3RepoConfig {
+            bucket: "backups".into(),
+            max_restore_requests_per_run: 10,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: Some(format!("touch {}", marker.display())),
+            on_restore_complete: None,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            credential_command: None,
+            credential_source: None,
+        });
+        let backend = crate::cold_storage::mock::ScriptedBackend {
+            archive_result: Err(()),
+            ..crate::cold_storage::mock::ScriptedBackend::ok()
+        };
+
+        assert!(archive_phase(&call, &repo, &mut RepoState::default(), Some(&backend), false).is_err());
+        assert!(!marker.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn archive_phase_defers_the_archive_when_verification_is_overdue() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.max_verify_age_days = Some(30);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+
+        archive_phase(&call, &repo, &mut state, Some(&backend), false).unwrap();
+
+        assert!(backend.calls.borrow().is_empty());
+        assert!(state.archive_due_at.is_none());
+    }
+
+    #[test]
+    fn archive_phase_archives_when_verification_is_fresh() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.max_verify_age_days = Some(30);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+        state.record_verify(VerifyMethod::CheckMetadata);
+
+        archive_phase(&call, &repo, &mut state, Some(&backend), false).unwrap();
+
+        assert_eq!(*backend.calls.borrow(), vec!["archive_all"]);
+    }
+
+    #[test]
+    fn archive_unverified_skips_the_overdue_verification_check() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.max_verify_age_days = Some(30);
+        repo.archive_unverified = true;
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+
+        archive_phase(&call, &repo, &mut state, Some(&backend), false).unwrap();
+
+        assert_eq!(*backend.calls.borrow(), vec!["archive_all"]);
+    }
+
+    #[test]
+    fn force_archive_bypasses_an_overdue_verification_check() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.max_verify_age_days = Some(30);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+
+        force_archive(&call, &repo, &mut state, Some(&backend)).unwrap();
+
+        assert_eq!(*backend.calls.borrow(), vec!["archive_all"]);
+    }
+
+    #[test]
+    fn archive_phase_with_a_delay_defers_the_archive_instead_of_running_it() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.archive_delay_hours = Some(4);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+
+        archive_phase(&call, &repo, &mut state, Some(&backend), false).unwrap();
+
+        assert!(backend.calls.borrow().is_empty());
+        assert!(state.archive_due_at.is_some());
+    }
+
+    #[test]
+    fn archive_phase_archives_once_the_delay_has_passed() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.archive_delay_hours = Some(4);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+        state.archive_due_at = Some(0);
+
+        archive_phase(&call, &repo, &mut state, Some(&backend), false).unwrap();
+
+        assert_eq!(*backend.calls.borrow(), vec!["archive_all"]);
+        assert!(state.archive_due_at.is_none());
+    }
+
+    #[test]
+    fn force_archive_bypasses_a_delay_still_in_the_future() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.archive_delay_hours = Some(4);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+
+        force_archive(&call, &repo, &mut state, Some(&backend)).unwrap();
+
+        assert_eq!(*backend.calls.borrow(), vec!["archive_all"]);
+        assert!(state.archive_due_at.is_none());
+    }
+
+    #[test]
+    fn resume_pending_cold_storage_action_does_not_archive_before_a_deferred_delay_is_due() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.archive_delay_hours = Some(4);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+        state.pending_action = Some(PendingAction::Freeze);
+        state.archive_due_at = Some(u64::MAX);
+
+        resume_pending_cold_storage_action(&call, &repo, &mut state, Some(&backend)).unwrap();
+
+        assert!(backend.calls.borrow().is_empty());
+        assert_eq!(state.pending_action, Some(PendingAction::Freeze));
+    }
+
+    #[test]
+    fn resume_pending_cold_storage_action_archives_once_a_deferred_delay_has_elapsed() {
+        // Simulates a later run finding a repo left thawed by an earlier
+        // backup whose archive_delay_hours grace period has since passed.
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.archive_delay_hours = Some(4);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+        state.pending_action = Some(PendingAction::Freeze);
+        state.archive_due_at = Some(0);
+
+        resume_pending_cold_storage_action(&call, &repo, &mut state, Some(&backend)).unwrap();
+
+        assert_eq!(*backend.calls.borrow(), vec!["archive_all"]);
+        assert_eq!(state.pending_action, None);
+        assert!(state.archive_due_at.is_none());
+    }
+
+    #[test]
+    fn run_backup_cycle_runs_the_post_hook_even_after_a_failed_backup() {
+        let call = restic::mock::MockCall {
+            calls: Default::default(),
+            result: restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: repository locked".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let dir = std::env::temp_dir().join(format!("halley-post-hook-failure-test-{}", std::process::id()));
+        let marker = dir.join("marker");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut repo = repo_config(false);
+        repo.post_hook = Some(format!("echo -n \"$HALLEY_RESULT\" > {}", marker.display()));
+        let mut state = RepoState::default();
+        let result = run_backup_cycle(&call, &repo, &mut state, "halley", None, None, None, None, None);
+
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "failure");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn backup_cycle_prunes_only_when_configured() {
+        let call = restic::mock::MockCall::ok();
+        backup_cycle(&call, &repo_config(true), &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls[0][0], "snapshots");
+        assert_eq!(calls[1][0], "backup");
+        assert_eq!(calls[2][0], "forget");
+        assert_eq!(calls[3][0], "prune");
+    }
+
+    #[test]
+    fn backup_cycle_tags_backup_and_forget_with_snapshot_tag() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.tags = vec!["laptop".to_string()];
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[1].windows(2).any(|w| w == ["--tag", "halley"]));
+        assert!(calls[1].windows(2).any(|w| w == ["--tag", "laptop"]));
+        assert!(calls[2].windows(2).any(|w| w == ["--tag", "halley"]));
+    }
+
+    #[test]
+    fn backup_cycle_runs_check_before_backup_when_configured() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.check_before_backup = true;
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert_eq!(calls[0][0], "check");
+        assert_eq!(calls[1][0], "snapshots");
+        assert_eq!(calls[2][0], "backup");
+    }
+
+    #[test]
+    fn backup_cycle_stops_before_backup_when_check_fails() {
+        let call = restic::mock::MockCall {
+            calls: Default::default(),
+            result: restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: pack file corrupt".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let mut repo = repo_config(false);
+        repo.check_before_backup = true;
+        let err = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { .. }));
+        assert_eq!(call.calls.borrow().len(), 1);
+    }
+
+    #[test]
+    fn backup_cycle_skips_prune_when_not_configured() {
+        let call = restic::mock::MockCall::ok();
+        backup_cycle(&call, &repo_config(false), &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        assert_eq!(call.calls.borrow().len(), 3);
+    }
+
+    #[test]
+    fn backup_cycle_stops_before_archive_when_prune_fails() {
+        // snapshots (not a first backup), backup and forget succeed, prune fails.
+        let ok = restic::CallOutput {
+            status: 0,
+            stdout: "[]".into(),
+            ..Default::default()
+        };
+        let call = restic::mock::MockCall::sequence(vec![
+            ok.clone(),
+            ok.clone(),
+            ok,
+            restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: repository locked".into(),
+                ..Default::default()
+            },
+        ]);
+        let err = backup_cycle(&call, &repo_config(true), &mut RepoState::default(), "halley", None, None, None, None, None).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { .. }));
+        // snapshots, backup, forget and the failing prune ran; nothing beyond
+        // that (i.e. no archive-phase calls) should have gone out.
+        assert_eq!(call.calls.borrow().len(), 4);
+        assert_eq!(call.calls.borrow()[3][0], "prune");
+    }
+
+    #[test]
+    fn backup_cycle_applies_no_cache_from_a_tight_memory_limit() {
+        let call = restic::mock::MockCall::ok();
+        let limit = restic::MemoryLimit::for_budget(256, None);
+        backup_cycle(&call, &repo_config(false), &mut RepoState::default(), "halley", Some(&limit), None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[1].iter().any(|a| a == "--no-cache"));
+    }
+
+    #[test]
+    fn backup_cycle_omits_no_cache_from_a_generous_memory_limit() {
+        let call = restic::mock::MockCall::ok();
+        let limit = restic::MemoryLimit::for_budget(4096, None);
+        backup_cycle(&call, &repo_config(false), &mut RepoState::default(), "halley", Some(&limit), None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(!calls[1].iter().any(|a| a == "--no-cache"));
+    }
+
+    #[test]
+    fn backup_cycle_falls_back_to_the_global_cache_dir() {
+        let call = restic::mock::MockCall::ok();
+        let global = std::path::PathBuf::from("/var/cache/halley");
+        backup_cycle(&call, &repo_config(false), &mut RepoState::default(), "halley", None, Some(&global), None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[1]
+            .windows(2)
+            .any(|w| w == ["--cache-dir", "/var/cache/halley"]));
+    }
+
+    #[test]
+    fn backup_cycle_prefers_the_repo_cache_dir_over_the_global_one() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.cache_dir = Some(std::path::PathBuf::from("/var/cache/halley-test"));
+        let global = std::path::PathBuf::from("/var/cache/halley");
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, Some(&global), None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[1]
+            .windows(2)
+            .any(|w| w == ["--cache-dir", "/var/cache/halley-test"]));
+    }
+
+    #[test]
+    fn backup_cycle_repo_no_cache_overrides_a_configured_cache_dir() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.cache_dir = Some(std::path::PathBuf::from("/var/cache/halley"));
+        repo.no_cache = true;
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[1].iter().any(|a| a == "--no-cache"));
+        assert!(!calls[1].iter().any(|a| a == "--cache-dir"));
+    }
+
+    #[test]
+    fn memory_limit_warnings_flags_unsupported_gomemlimit() {
+        let repo = repo_config(false);
+        let limit = restic::MemoryLimit::for_budget(1024, None);
+        let warnings = memory_limit_warnings(&repo, &limit);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("GOMEMLIMIT"));
+    }
+
+    #[test]
+    fn compression_warnings_is_silent_when_unconfigured() {
+        let repo = repo_config(false);
+        assert!(compression_warnings(&repo, None).is_empty());
+    }
+
+    #[test]
+    fn compression_warnings_is_silent_against_a_new_enough_restic() {
+        let mut repo = repo_config(false);
+        repo.compression = Some(restic::CompressionLevel::Max);
+        let version = restic::Version { major: 0, minor: 17, patch: 0 };
+        assert!(compression_warnings(&repo, Some(version)).is_empty());
+    }
+
+    #[test]
+    fn compression_warnings_flags_an_old_restic() {
+        let mut repo = repo_config(false);
+        repo.compression = Some(restic::CompressionLevel::Max);
+        let version = restic::Version { major: 0, minor: 12, patch: 0 };
+        let warnings = compression_warnings(&repo, Some(version));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("--compression"));
+    }
+
+    #[test]
+    fn compression_warnings_flags_an_unknown_restic_version() {
+        let mut repo = repo_config(false);
+        repo.compression = Some(restic::CompressionLevel::Auto);
+        let warnings = compression_warnings(&repo, None);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn backup_cycle_passes_compression_through_to_a_new_enough_restic() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.compression = Some(restic::CompressionLevel::Max);
+        let version = restic::Version { major: 0, minor: 17, patch: 0 };
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, Some(version), None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[1].windows(2).any(|w| w == ["--compression", "max"]));
+    }
+
+    #[test]
+    fn backup_cycle_tags_the_snapshot_with_the_restic_version() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let version = restic::Version { major: 0, minor: 17, patch: 0 };
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, Some(version), None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[1].windows(2).any(|w| w == ["--tag", "halley-restic-0.17.0"]));
+    }
+
+    #[test]
+    fn backup_cycle_omits_the_restic_version_tag_when_the_version_is_unknown() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(!calls[1].iter().any(|arg| arg.starts_with("halley-restic-")));
+    }
+
+    #[test]
+    fn dry_run_backup_cycle_rejects_cold_storage_backed_repos() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let err = dry_run_backup_cycle(&call, &repo, "halley", None, None, Some(&backend)).unwrap_err();
+        assert!(matches!(err, HalleyError::ColdStorageDryRunUnsupported { .. }));
+        assert!(call.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn dry_run_backup_cycle_passes_dry_run_through_to_the_backup_call() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        dry_run_backup_cycle(&call, &repo, "halley", None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[0].contains(&"--dry-run".to_string()));
+    }
+
+    #[test]
+    fn dry_run_backup_cycle_previews_forget_instead_of_running_it() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        dry_run_backup_cycle(&call, &repo, "halley", None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[1].contains(&"forget".to_string()));
+        assert!(calls[1].contains(&"--dry-run".to_string()));
+    }
+
+    /// Regression test for the dry-run digest-mutation bug: running a dry
+    /// preview twice back to back must leave a repo's recorded state
+    /// untouched, both in memory and on disk, the same way running `halley
+    /// backup --dry` twice in a row shouldn't slowly desync the statefile
+    /// from reality. `dry_run_backup_cycle` takes no `&mut RepoState` at
+    /// all, so this mostly guards against a future refactor reintroducing
+    /// one without also keeping it out of the dry path.
+    #[test]
+    fn dry_run_backup_cycle_run_twice_leaves_state_unchanged_in_memory_and_on_disk() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+
+        let mut state_file = crate::state::StateFile::default();
+        let repo_state = state_file.repos.entry(repo.name.clone()).or_default();
+        repo_state.digests.insert(
+            "/some/source".to_string(),
+            crate::digest::HexDigest("unchanged".to_string()),
+        );
+        let serialized_before = serde_json::to_string(&state_file).unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "halley-dry-run-state-unchanged-test-{}.json",
+            std::process::id()
+        ));
+        state_file.save(&path).unwrap();
+        let bytes_before = std::fs::read(&path).unwrap();
+
+        dry_run_backup_cycle(&call, &repo, "halley", None, None, None).unwrap();
+        dry_run_backup_cycle(&call, &repo, "halley", None, None, None).unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&state_file).unwrap(),
+            serialized_before,
+            "dry_run_backup_cycle must not mutate in-memory state"
+        );
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            bytes_before,
+            "dry_run_backup_cycle must not touch the statefile on disk"
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn dry_run_backup_cycle_reports_the_snapshots_that_would_be_forgotten() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput { status: 0, stdout: "[]".to_string(), ..Default::default() },
+            restic::CallOutput {
+                status: 0,
+                stdout: r#"[{"keep":[],"remove":[{"id":"abc123","short_id":"abc123","time":"2024-01-01T00:00:00Z"}]}]"#
+                    .to_string(),
+                ..Default::default()
+            },
+        ]);
+        let repo = repo_config(true);
+        let plan = dry_run_backup_cycle(&call, &repo, "halley", None, None, None).unwrap();
+        assert_eq!(plan.snapshots_would_forget, vec!["abc123".to_string()]);
+        assert!(plan.would_prune);
+    }
+
+    #[test]
+    fn backup_cycle_drops_compression_against_an_old_restic() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.compression = Some(restic::CompressionLevel::Max);
+        let version = restic::Version { major: 0, minor: 12, patch: 0 };
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, Some(version), None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(!calls[1].contains(&"--compression".to_string()));
+    }
+
+    #[test]
+    fn backup_cycle_drops_compression_when_the_restic_version_is_unknown() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.compression = Some(restic::CompressionLevel::Max);
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(!calls[1].contains(&"--compression".to_string()));
+    }
+
+    #[test]
+    fn backup_cycle_returns_the_new_snapshot_id() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput {
+                status: 0,
+                stdout: "[]".to_string(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 0,
+                stdout: r#"{"message_type":"summary","files_new":1,"files_changed":0,"total_bytes_processed":10,"snapshot_id":"deadbeef"}"#
+                    .to_string(),
+                ..Default::default()
+            },
+        ]);
+        let outcome = backup_cycle(&call, &repo_config(false), &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        assert_eq!(outcome.snapshot_id.as_deref(), Some("deadbeef"));
+    }
+
+    #[test]
+    fn backup_cycle_span_carries_the_repo_id_onto_nested_events() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct BufWriter(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for BufWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for BufWriter {
+            type Writer = BufWriter;
+            fn make_writer(&'a self) -> Self::Writer {
+                self.clone()
+            }
+        }
+
+        let buf = BufWriter::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(buf.clone())
+            .with_ansi(false)
+            .finish();
+
+        let call = restic::mock::MockCall::ok();
+        tracing::subscriber::with_default(subscriber, || {
+            let _ = backup_cycle(&call, &repo_config(false), &mut RepoState::default(), "halley", None, None, None, None, None);
+        });
+
+        let output = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            output.contains("backup cycle finished") && output.contains("id=test"),
+            "expected nested events to carry the repo span's id field, got:\n{output}"
+        );
+    }
+
+    #[test]
+    fn backup_cycle_summarizes_warnings_from_the_backup_stream() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput {
+                status: 0,
+                stdout: "[]".to_string(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 0,
+                stdout: [
+                    r#"{"message_type":"error","item":"/mnt/foo","error":"lstat /mnt/foo: permission denied"}"#,
+                    r#"{"message_type":"summary","files_new":1,"files_changed":0,"total_bytes_processed":10,"snapshot_id":"deadbeef"}"#,
+                ]
+                .join("\n"),
+                ..Default::default()
+            },
+        ]);
+        let outcome = backup_cycle(&call, &repo_config(false), &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        assert_eq!(outcome.warnings.permission, 1);
+        assert_eq!(outcome.warnings.total(), 1);
+    }
+
+    #[test]
+    fn backup_diff_report_is_none_without_a_previous_snapshot() {
+        let call = restic::mock::MockCall::ok();
+        assert!(backup_diff_report(&call, None, "new-snapshot").is_none());
+    }
+
+    #[test]
+    fn backup_diff_report_summarizes_against_a_previous_snapshot() {
+        let call = restic::mock::MockCall::sequence(vec![restic::CallOutput {
+            status: 0,
+            stdout: "{\"message_type\":\"statistics\",\"changed_files\":3,\"added\":{\"bytes\":2048},\"removed\":{\"bytes\":1024}}\n".to_string(),
+            ..Default::default()
+        }]);
+        let report = backup_diff_report(&call, Some("old-snapshot"), "new-snapshot").unwrap().unwrap();
+        assert_eq!(report.added_bytes, 2048);
+        assert_eq!(report.removed_bytes, 1024);
+        assert_eq!(report.changed_files, 3);
+        assert_eq!(call.calls.borrow()[0], vec!["diff", "--json", "old-snapshot", "new-snapshot"]);
+    }
+
+    #[test]
+    fn run_backup_cycle_stores_the_snapshot_id_and_diffs_against_the_previous_one() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput {
+                status: 0,
+                stdout: "[]".to_string(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 0,
+                stdout: r#"{"message_type":"summary","files_new":1,"files_changed":0,"total_bytes_processed":10,"snapshot_id":"new-snap"}"#
+                    .to_string(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 0,
+                stdout: "[]".to_string(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 0,
+                stdout: "{\"message_type\":\"statistics\",\"changed_files\":1,\"added\":{\"bytes\":10},\"removed\":{\"bytes\":0}}\n".to_string(),
+                ..Default::default()
+            },
+        ]);
+        let mut state = RepoState::default();
+        state.record_backup_success(Some("old-snap".to_string()), 0, None, crate::restic::WarningSummary::default(), None, None, None);
+
+        run_backup_cycle(&call, &repo_config(false), &mut state, "halley", None, None, None, None, None).unwrap();
+
+        let last_backup = state.last_backup.unwrap();
+        assert_eq!(last_backup.snapshot_id.as_deref(), Some("new-snap"));
+        match last_backup.outcome {
+            crate::state::BackupOutcome::Success { bytes_added, .. } => assert_eq!(bytes_added, Some(10)),
+            crate::state::BackupOutcome::Failed { .. } => panic!("expected a Success outcome"),
+        }
+        let calls = call.calls.borrow();
+        assert!(calls.iter().any(|c| c[0] == "diff" && c[2] == "old-snap" && c[3] == "new-snap"));
+    }
+
+    #[test]
+    fn run_backup_cycle_persists_warnings_alongside_the_backup_outcome() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput {
+                status: 0,
+                stdout: "[]".to_string(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 0,
+                stdout: [
+                    r#"{"message_type":"error","item":"/mnt/foo","error":"lstat /mnt/foo: permission denied"}"#,
+                    r#"{"message_type":"summary","files_new":1,"files_changed":0,"total_bytes_processed":10,"snapshot_id":"new-snap"}"#,
+                ]
+                .join("\n"),
+                ..Default::default()
+            },
+        ]);
+        let mut state = RepoState::default();
+
+        run_backup_cycle(&call, &repo_config(false), &mut state, "halley", None, None, None, None, None).unwrap();
+
+        let last_backup = state.last_backup.unwrap();
+        match last_backup.outcome {
+            crate::state::BackupOutcome::Success { warnings, .. } => assert_eq!(warnings.permission, 1),
+            crate::state::BackupOutcome::Failed { .. } => panic!("expected a Success outcome"),
+        }
+    }
+
+    #[test]
+    fn backup_cycle_retries_once_after_unlocking_a_stale_lock() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput {
+                status: 0,
+                stdout: "[]".to_string(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: unable to create lock in backend: repository is already locked exclusively".into(),
+                ..Default::default()
+            },
+        ]);
+        let mut repo = repo_config(false);
+        repo.auto_unlock_stale = true;
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert_eq!(calls.len(), 5);
+        assert_eq!(calls[0][0], "snapshots");
+        assert_eq!(calls[1][0], "backup");
+        assert_eq!(calls[2][0], "unlock");
+        assert_eq!(calls[3][0], "backup");
+        assert_eq!(calls[4][0], "forget");
+    }
+
+    #[test]
+    fn backup_cycle_does_not_unlock_when_auto_unlock_stale_is_off() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput {
+                status: 0,
+                stdout: "[]".to_string(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: unable to create lock in backend: repository is already locked exclusively".into(),
+                ..Default::default()
+            },
+        ]);
+        let repo = repo_config(false);
+        let err = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { .. }));
+        let calls = call.calls.borrow();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1][0], "backup");
+    }
+
+    #[test]
+    fn backup_cycle_does_not_unlock_on_an_unrelated_backup_failure() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput {
+                status: 0,
+                stdout: "[]".to_string(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: unable to open bucket: access denied".into(),
+                ..Default::default()
+            },
+        ]);
+        let mut repo = repo_config(false);
+        repo.auto_unlock_stale = true;
+        let err = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { .. }));
+        let calls = call.calls.borrow();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1][0], "backup");
+    }
+
+    #[test]
+    fn forget_decision_proceeds_when_no_cap_is_configured() {
+        assert_eq!(forget_decision(1000, None), ForgetDecision::Proceed);
+    }
+
+    #[test]
+    fn forget_decision_proceeds_at_or_under_the_cap() {
+        assert_eq!(forget_decision(5, Some(5)), ForgetDecision::Proceed);
+    }
+
+    #[test]
+    fn forget_decision_requires_confirmation_over_the_cap() {
+        assert_eq!(
+            forget_decision(6, Some(5)),
+            ForgetDecision::RequiresConfirmation { would_remove: 6 }
+        );
+    }
+
+    #[test]
+    fn backup_cycle_previews_forget_when_max_auto_forget_is_set_and_proceeds_under_the_cap() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput {
+                status: 0,
+                stdout: "[]".to_string(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 0,
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 0,
+                stdout: r#"[{"keep": [], "remove": [{"id": "a", "short_id": "a", "time": "2026-01-01T00:00:00Z"}]}]"#
+                    .to_string(),
+                ..Default::default()
+            },
+        ]);
+        let mut repo = repo_config(false);
+        repo.max_auto_forget = Some(5);
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls[0][0], "snapshots");
+        assert_eq!(calls[1][0], "backup");
+        assert_eq!(calls[2][0], "forget");
+        assert!(calls[2].contains(&"--dry-run".to_string()));
+        assert_eq!(calls[3][0], "forget");
+        assert!(!calls[3].contains(&"--dry-run".to_string()));
+    }
+
+    #[test]
+    fn backup_cycle_skips_the_real_forget_when_the_preview_exceeds_the_cap() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput {
+                status: 0,
+                stdout: "[]".to_string(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 0,
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 0,
+                stdout: r#"[{"keep": [], "remove": [
+                    {"id": "a", "short_id": "a", "time": "2026-01-01T00:00:00Z"},
+                    {"id": "b", "short_id": "b", "time": "2026-01-02T00:00:00Z"}
+                ]}]"#
+                    .to_string(),
+                ..Default::default()
+            },
+        ]);
+        let mut repo = repo_config(false);
+        repo.max_auto_forget = Some(1);
+        let err = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap_err();
+        match err {
+            HalleyError::ForgetRequiresConfirmation { repo, would_remove } => {
+                assert_eq!(repo, "test");
+                assert_eq!(would_remove, 2);
+            }
+            other => panic!("expected ForgetRequiresConfirmation, got {other:?}"),
+        }
+        let calls = call.calls.borrow();
+        assert_eq!(calls.len(), 3);
+        assert!(calls[2].contains(&"--dry-run".to_string()));
+    }
+
+    #[test]
+    fn backup_cycle_runs_init_before_check_when_auto_init_is_configured() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.auto_init = true;
+        repo.check_before_backup = true;
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert_eq!(calls[0][0], "init");
+        assert_eq!(calls[1][0], "check");
+        assert_eq!(calls[2][0], "snapshots");
+        assert_eq!(calls[3][0], "backup");
+    }
+
+    #[test]
+    fn backup_cycle_continues_after_a_lost_init_race_with_a_matching_password() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: config file already exists".into(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 0,
+                ..Default::default()
+            },
+        ]);
+        let mut repo = repo_config(false);
+        repo.auto_init = true;
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert_eq!(calls[0], vec!["init"]);
+        assert_eq!(calls[1], vec!["cat", "config"]);
+        assert_eq!(calls[2][0], "snapshots");
+        assert_eq!(calls[3][0], "backup");
+    }
+
+    #[test]
+    fn backup_cycle_stops_on_a_lost_init_race_with_a_different_password() {
+        let call = restic::mock::MockCall::sequence(vec![
+            restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: config file already exists".into(),
+                ..Default::default()
+            },
+            restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: wrong password or no key found".into(),
+                ..Default::default()
+            },
+        ]);
+        let mut repo = repo_config(false);
+        repo.auto_init = true;
+        let err = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { .. }));
+        assert_eq!(call.calls.borrow().len(), 2);
+    }
+
+    #[test]
+    fn backup_cycle_stops_on_an_unconfirmed_first_backup() {
+        let dir = std::env::temp_dir().join("halley-backup-cycle-first-backup-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("big.bin"), vec![0u8; 1024]).unwrap();
+
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.sources = vec![dir.clone()];
+        repo.first_backup_size_threshold_mb = Some(0);
+
+        let err = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap_err();
+        assert!(matches!(
+            err,
+            HalleyError::FirstBackupNotConfirmed { estimated_bytes: 1024 }
+        ));
+        assert_eq!(call.calls.borrow().len(), 1);
+        assert_eq!(call.calls.borrow()[0][0], "snapshots");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn backup_cycle_proceeds_on_first_backup_when_allow_initial_backup_is_set() {
+        let dir = std::env::temp_dir().join("halley-backup-cycle-first-backup-allowed-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("big.bin"), vec![0u8; 1024]).unwrap();
+
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.sources = vec![dir.clone()];
+        repo.first_backup_size_threshold_mb = Some(0);
+        repo.allow_initial_backup = true;
+
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0][0], "snapshots");
+        assert_eq!(calls[1][0], "backup");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn filter_existing_sources_keeps_only_paths_that_exist() {
+        let dir = std::env::temp_dir().join("halley-filter-existing-sources-mixed-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let missing = std::env::temp_dir().join("halley-filter-existing-sources-missing");
+        let _ = std::fs::remove_dir_all(&missing);
+
+        let (existing, warnings) = filter_existing_sources(&[dir.clone(), missing.clone()]);
+        assert_eq!(existing, vec![dir.clone()]);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains(&missing.display().to_string()));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn filter_existing_sources_drops_everything_missing() {
+        let missing = std::env::temp_dir().join("halley-filter-existing-sources-all-missing");
+        let _ = std::fs::remove_dir_all(&missing);
+
+        let (existing, warnings) = filter_existing_sources(&[missing]);
+        assert!(existing.is_empty());
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn filter_existing_sources_on_an_empty_list_warns_about_nothing() {
+        let (existing, warnings) = filter_existing_sources(&[]);
+        assert!(existing.is_empty());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn backup_cycle_fails_when_every_configured_source_is_missing() {
+        let missing = std::env::temp_dir().join("halley-backup-cycle-all-sources-missing");
+        let _ = std::fs::remove_dir_all(&missing);
+
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.sources = vec![missing];
+
+        let err = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap_err();
+        assert!(matches!(err, HalleyError::NoBackupSources { .. }));
+        assert!(call.calls.borrow().iter().all(|c| c[0] != "backup"));
+    }
+
+    #[test]
+    fn backup_cycle_fails_on_a_dead_digest_ignore_pattern_when_strict_paths_is_set() {
+        let dir = std::env::temp_dir().join("halley-backup-cycle-strict-paths-dead-pattern");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("keep"), b"x").unwrap();
+
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.sources = vec![dir.clone()];
+        repo.digest_ignore = vec!["**/node_modules".to_string()];
+        repo.strict_paths = true;
+
+        let err = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap_err();
+        assert!(matches!(err, HalleyError::Parse(_)));
+        assert!(call.calls.borrow().iter().all(|c| c[0] != "backup"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn backup_cycle_tolerates_a_dead_digest_ignore_pattern_by_default() {
+        let dir = std::env::temp_dir().join("halley-backup-cycle-dead-pattern-not-strict");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("keep"), b"x").unwrap();
+
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.sources = vec![dir.clone()];
+        repo.digest_ignore = vec!["**/node_modules".to_string()];
+
+        let result = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None);
+        assert!(result.is_ok());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn backup_cycle_skips_a_missing_source_but_backs_up_the_rest() {
+        let dir = std::env::temp_dir().join("halley-backup-cycle-partial-sources-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let missing = std::env::temp_dir().join("halley-backup-cycle-one-missing-source");
+        let _ = std::fs::remove_dir_all(&missing);
+
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.sources = vec![dir.clone(), missing];
+
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, None, None, None).unwrap();
+        let calls = call.calls.borrow();
+        let backup_call = calls.iter().find(|c| c[0] == "backup").unwrap();
+        assert!(backup_call.contains(&dir.display().to_string()));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restore_cycle_restores_a_local_repo_into_a_temp_dir() {
+        let dir = std::env::temp_dir().join("halley-restore-cycle-local-test");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+
+        restore_cycle(&call, &repo, Some("abc123"), &dir, &[], None).unwrap();
+        assert!(dir.is_dir());
+        assert_eq!(call.calls.borrow()[0][0], "restore");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restore_cycle_refuses_s3_repos_until_thaw_is_wired_up() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.s3 = Some(crate::config::S3RepoConfig {
+            bucket: "backups".into(),
+            max_restore_requests_per_run: 10,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: None,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            credential_command: None,
+            credential_source: None,
+        });
+
+        let err = restore_cycle(&call, &repo, None, &std::env::temp_dir(), &[], None).unwrap_err();
+        assert!(matches!(err, HalleyError::S3(_)));
+        assert!(call.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn restore_cycle_uses_a_configured_cold_storage_backend_instead_of_the_s3_refusal() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.s3 = Some(crate::config::S3RepoConfig {
+            bucket: "backups".into(),
+            max_restore_requests_per_run: 10,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: None,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            credential_command: None,
+            credential_source: None,
+        });
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+
+        restore_cycle(&call, &repo, None, &std::env::temp_dir(), &[], Some(&backend)).unwrap();
+        assert_eq!(*backend.calls.borrow(), vec!["restore_all", "restore_blocking"]);
+        assert_eq!(call.calls.borrow()[0][0], "restore");
+    }
+
+    #[test]
+    fn restore_cycle_surfaces_a_cold_storage_backend_failure_before_touching_restic() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend {
+            restore_all_result: Err(()),
+            ..crate::cold_storage::mock::ScriptedBackend::ok()
+        };
+
+        let err = restore_cycle(&call, &repo, None, &std::env::temp_dir(), &[], Some(&backend)).unwrap_err();
+        assert!(matches!(err, HalleyError::S3(_)));
+        assert!(call.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn backup_cycle_thaws_before_backing_up_and_re_archives_after() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+
+        backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, Some(&backend), None, None).unwrap();
+        assert_eq!(
+            *backend.calls.borrow(),
+            vec!["restore_all", "restore_blocking", "archive_all"]
+        );
+        // calls[0] is the first-backup gate's `snapshots` lookup.
+        assert_eq!(call.calls.borrow()[1][0], "backup");
+    }
+
+    /// End-to-end orchestration test for a cold-storage-backed repo: no real
+    /// restic binary or AWS access needed, since [`restic::mock::MockCall`]
+    /// stands in for [`WrappedCall`] and [`crate::cold_storage::mock::ScriptedBackend`]
+    /// stands in for [`ColdStorageBackend`] -- the same dependency-injection
+    /// seams [`run_backup_cycle`] is always driven through, in production and
+    /// in tests alike. Asserts the full thaw -> check -> backup -> freeze ->
+    /// state-write sequence of a cold-storage backup, without a
+    /// separate `Engine`/`Backend`/`ColdStorage` layer duplicating what
+    /// `WrappedCall`/`ColdStorageBackend` already provide.
+    #[test]
+    fn run_backup_cycle_orchestrates_thaw_check_backup_freeze_and_state_write_in_order() {
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.check_before_backup = true;
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+        assert!(state.last_backup.is_none());
+
+        run_backup_cycle(&call, &repo, &mut state, "halley", None, None, Some(&backend), None, None).unwrap();
+
+        // Thaw happens before restic ever touches the repo.
+        assert_eq!(*backend.calls.borrow(), vec!["restore_all", "restore_blocking", "archive_all"]);
+        // check, then backup, in that order (with the first-backup gate's
+        // `snapshots` lookup between them).
+        let restic_calls = call.calls.borrow();
+        assert_eq!(restic_calls[0][0], "check");
+        assert_eq!(restic_calls[2][0], "backup");
+        drop(restic_calls);
+        // The state write: a successful cycle records a backup.
+        assert!(state.last_backup.is_some());
+    }
+
+    #[test]
+    fn backup_cycle_fails_when_the_cold_storage_backend_fails_to_archive() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend {
+            archive_result: Err(()),
+            ..crate::cold_storage::mock::ScriptedBackend::ok()
+        };
+
+        let err = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, Some(&backend), None, None).unwrap_err();
+        assert!(matches!(err, HalleyError::S3(_)));
+    }
+
+    #[test]
+    fn backup_cycle_aborts_without_backing_up_when_the_thaw_fails() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend {
+            restore_all_result: Err(()),
+            ..crate::cold_storage::mock::ScriptedBackend::ok()
+        };
+
+        let err = backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, Some(&backend), None, None).unwrap_err();
+        assert!(matches!(err, HalleyError::S3(_)));
+        assert_eq!(*backend.calls.borrow(), vec!["restore_all"]);
+        assert!(call.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn backup_cycle_clears_pending_action_once_the_archive_completes() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+
+        backup_cycle(&call, &repo, &mut state, "halley", None, None, Some(&backend), None, None).unwrap();
+
+        assert_eq!(state.pending_action, None);
+    }
+
+    #[test]
+    fn backup_cycle_leaves_pending_action_set_to_freeze_when_the_archive_never_runs() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend {
+            archive_result: Err(()),
+            ..crate::cold_storage::mock::ScriptedBackend::ok()
+        };
+        let mut state = RepoState::default();
+
+        let err = backup_cycle(&call, &repo, &mut state, "halley", None, None, Some(&backend), None, None).unwrap_err();
+        assert!(matches!(err, HalleyError::S3(_)));
+        assert_eq!(state.pending_action, Some(PendingAction::Freeze));
+    }
+
+    #[test]
+    fn resume_pending_cold_storage_action_is_a_no_op_when_nothing_is_pending() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+
+        resume_pending_cold_storage_action(&call, &repo, &mut state, Some(&backend)).unwrap();
+
+        assert!(backend.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn resume_pending_cold_storage_action_finishes_an_interrupted_freeze() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+        // Simulates a crash after a previous run thawed the bucket, ran its
+        // backup, but was killed before re-archiving.
+        state.pending_action = Some(PendingAction::Freeze);
+
+        resume_pending_cold_storage_action(&call, &repo, &mut state, Some(&backend)).unwrap();
+
+        assert_eq!(*backend.calls.borrow(), vec!["archive_all"]);
+        assert_eq!(state.pending_action, None);
+    }
+
+    #[test]
+    fn resume_pending_cold_storage_action_finishes_an_interrupted_thaw() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+        // Simulates a crash while the previous run was still waiting on a
+        // thaw, before it could even confirm the data was usable.
+        state.pending_action = Some(PendingAction::Thaw);
+
+        resume_pending_cold_storage_action(&call, &repo, &mut state, Some(&backend)).unwrap();
+
+        assert_eq!(*backend.calls.borrow(), vec!["restore_all", "restore_blocking"]);
+        // Thawed, but not yet re-archived: still pending, now as a freeze.
+        assert_eq!(state.pending_action, Some(PendingAction::Freeze));
+    }
+
+    #[test]
+    fn resume_pending_cold_storage_action_leaves_pending_state_alone_without_a_backend() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let mut state = RepoState::default();
+        state.pending_action = Some(PendingAction::Freeze);
+
+        resume_pending_cold_storage_action(&call, &repo, &mut state, None).unwrap();
+
+        assert_eq!(state.pending_action, Some(PendingAction::Freeze));
+    }
+
+    #[test]
+    fn run_backup_cycle_resumes_a_pending_freeze_before_starting_its_own_cycle() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let mut state = RepoState::default();
+        state.pending_action = Some(PendingAction::Freeze);
+
+        run_backup_cycle(&call, &repo, &mut state, "halley", None, None, Some(&backend), None, None).unwrap();
+
+        // The resumed freeze, then the backup cycle's own thaw/re-archive.
+        assert_eq!(
+            *backend.calls.borrow(),
+            vec!["archive_all", "restore_all", "restore_blocking", "archive_all"]
+        );
+        assert_eq!(state.pending_action, None);
+    }
+
+    #[test]
+    fn backup_cycle_does_not_re_archive_after_a_failed_backup_by_default() {
+        let call = restic::mock::MockCall {
+            calls: Default::default(),
+            result: restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: repository locked".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let repo = repo_config(false);
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+
+        assert!(backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, Some(&backend), None, None).is_err());
+        assert_eq!(*backend.calls.borrow(), vec!["restore_all", "restore_blocking"]);
+    }
+
+    #[test]
+    fn backup_cycle_re_archives_after_a_failed_backup_when_configured() {
+        let call = restic::mock::MockCall {
+            calls: Default::default(),
+            result: restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: repository locked".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let mut repo = repo_config(false);
+        repo.archive_after_failed_backup = true;
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+
+        assert!(backup_cycle(&call, &repo, &mut RepoState::default(), "halley", None, None, Some(&backend), None, None).is_err());
+        assert_eq!(
+            *backend.calls.borrow(),
+            vec!["restore_all", "restore_blocking", "archive_all"]
+        );
+    }
+
+    #[test]
+    fn restore_cycle_passes_sparse_and_restore_flags_through() {
+        let dir = std::env::temp_dir().join("halley-restore-cycle-sparse-test");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let call = restic::mock::MockCall::ok();
+        let mut repo = repo_config(false);
+        repo.restore_sparse = true;
+        repo.restore_flags = vec!["--no-lock".to_string()];
+
+        restore_cycle(&call, &repo, None, &dir, &[], None).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[0].iter().any(|a| a == "--sparse"));
+        assert!(calls[0].iter().any(|a| a == "--no-lock"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn memory_limit_warnings_is_empty_when_supported() {
+        let repo = repo_config(false);
+        let limit = restic::MemoryLimit::for_budget(
+            1024,
+            Some(restic::GoVersion { major: 1, minor: 21 }),
+        );
+        assert!(memory_limit_warnings(&repo, &limit).is_empty());
+    }
+
+    #[test]
+    fn run_backup_cycle_records_success() {
+        let call = restic::mock::MockCall::ok();
+        let mut state = RepoState::default();
+        run_backup_cycle(&call, &repo_config(false), &mut state, "halley", None, None, None, None, None).unwrap();
+        assert!(matches!(
+            state.last_backup.unwrap().outcome,
+            crate::state::BackupOutcome::Success { .. }
+        ));
+    }
+
+    #[test]
+    fn run_backup_cycle_records_failure_instead_of_leaving_state_untouched() {
+        let call = restic::mock::MockCall {
+            calls: Default::default(),
+            result: restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: restic command exceeded the 60 minute timeout and was killed".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let mut state = RepoState::default();
+        state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+
+        let err = run_backup_cycle(&call, &repo_config(false), &mut state, "halley", None, None, None, None, None).unwrap_err();
+        assert!(err.to_string().contains("timeout"));
+        match state.last_backup.unwrap().outcome {
+            crate::state::BackupOutcome::Failed { error } => assert!(error.contains("timeout")),
+            crate::state::BackupOutcome::Success { .. } => panic!("expected the failure to overwrite state"),
+        }
+    }
+
+    #[test]
+    fn run_verify_records_method_on_success() {
+        let call = restic::mock::MockCall::ok();
+        let mut state = RepoState::default();
+        run_verify(&call, &mut state, VerifyMethod::CheckReadData { percent: 5 }, false).unwrap();
+        assert!(matches!(
+            state.last_verified.unwrap().method,
+            VerifyMethod::CheckReadData { percent: 5 }
+        ));
+    }
+
+    #[test]
+    fn run_verify_does_not_record_on_failure() {
+        let call = restic::mock::MockCall {
+            calls: Default::default(),
+            result: restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: pack file corrupt".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let mut state = RepoState::default();
+        assert!(run_verify(&call, &mut state, VerifyMethod::CheckMetadata, false).is_err());
+        assert!(state.last_verified.is_none());
+    }
+
+    /// Running `check` with `dry=true` twice in a row must leave `state`
+    /// untouched both times, in memory and, once saved, on disk -- a dry
+    /// run should never make later decisions in the same run (or a later
+    /// run) see a verification that didn't actually get recorded.
+    #[test]
+    fn run_verify_leaves_state_unchanged_across_repeated_dry_runs() {
+        let call = restic::mock::MockCall::ok();
+        let mut state = RepoState::default();
+
+        run_verify(&call, &mut state, VerifyMethod::CheckMetadata, true).unwrap();
+        assert!(state.last_verified.is_none());
+
+        run_verify(&call, &mut state, VerifyMethod::CheckReadData { percent: 5 }, true).unwrap();
+        assert!(state.last_verified.is_none());
+
+        let scratch = std::env::temp_dir().join(format!(
+            "halley-dry-verify-test-{}-{}",
+            std::process::id(),
+            line!()
+        ));
+        let mut state_file = crate::state::StateFile::default();
+        state_file.repos.insert("home".to_string(), state.clone());
+        state_file.save(&scratch).unwrap();
+        let reloaded = crate::state::StateFile::load(&scratch).unwrap();
+        std::fs::remove_file(&scratch).ok();
+        assert!(reloaded.repos.get("home").unwrap().last_verified.is_none());
+    }
+
+    #[test]
+    fn verify_repo_reports_success_and_the_repo_s_retention() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let mut state = RepoState::default();
+        let outcome = verify_repo(&call, &repo, &mut state, VerifyMethod::CheckMetadata, None, false);
+        assert!(outcome.passed());
+        assert_eq!(outcome.retention, repo.retention);
+        assert!(outcome.cold_storage_report.is_none());
+    }
+
+    #[test]
+    fn verify_repo_echoes_back_whether_it_was_a_dry_run() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+
+        let mut state = RepoState::default();
+        let real = verify_repo(&call, &repo, &mut state, VerifyMethod::CheckMetadata, None, false);
+        assert!(!real.dry);
+        assert!(state.last_verified.is_some());
+
+        let mut state = RepoState::default();
+        let dry = verify_repo(&call, &repo, &mut state, VerifyMethod::CheckMetadata, None, true);
+        assert!(dry.dry);
+        assert!(state.last_verified.is_none());
+    }
+
+    #[test]
+    fn verify_repo_never_issues_a_write_restic_subcommand() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let mut state = RepoState::default();
+        verify_repo(&call, &repo, &mut state, VerifyMethod::CheckMetadata, None, false);
+        for invocation in call.calls.borrow().iter() {
+            assert_eq!(invocation[0], "check");
+        }
+    }
+
+    #[test]
+    fn verify_repo_only_lists_cold_storage_never_archives_or_restores() {
+        let call = restic::mock::MockCall::ok();
+        let repo = repo_config(false);
+        let mut state = RepoState::default();
+        let backend = crate::cold_storage::mock::ScriptedBackend::ok();
+        let outcome = verify_repo(&call, &repo, &mut state, VerifyMethod::CheckMetadata, Some(&backend), false);
+        assert!(outcome.cold_storage_report.unwrap().contains("scripted backend"));
+        assert_eq!(*backend.calls.borrow(), vec!["list"]);
+    }
+
+    #[test]
+    fn verify_repo_fails_but_still_reports_when_the_check_fails() {
+        let call = restic::mock::MockCall {
+            calls: Default::default(),
+            result: restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: pack file corrupt".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let repo = repo_config(false);
+        let mut state = RepoState::default();
+        let outcome = verify_repo(&call, &repo, &mut state, VerifyMethod::CheckMetadata, None, false);
+        assert!(!outcome.passed());
+        assert!(outcome.check_result.unwrap_err().contains("pack file corrupt"));
+    }
+
+    #[test]
+    fn verification_report_all_passed_is_false_if_any_repo_failed() {
+        let mut report = VerificationReport::default();
+        report.outcomes.push(RepoVerificationOutcome {
+            repo: "a".into(),
+            check_result: Ok(()),
+            retention: restic::RetentionPolicy::default(),
+            cold_storage_report: None,
+            dry: false,
+        });
+        report.outcomes.push(RepoVerificationOutcome {
+            repo: "b".into(),
+            check_result: Err("boom".into()),
+            retention: restic::RetentionPolicy::default(),
+            cold_storage_report: None,
+            dry: false,
+        });
+        assert!(!report.all_passed());
+    }
+
+    #[test]
+    fn verify_staleness_warnings_only_flags_configured_stale_repos() {
+        let mut fresh = repo_config(false);
+        fresh.name = "fresh".into();
+        fresh.max_verify_age_days = Some(7);
+        let mut stale = repo_config(false);
+        stale.name = "stale".into();
+        stale.max_verify_age_days = Some(7);
+        let mut unconfigured = repo_config(false);
+        unconfigured.name = "unconfigured".into();
+
+        let mut states = std::collections::BTreeMap::new();
+        let mut fresh_state = RepoState::default();
+        fresh_state.record_verify(VerifyMethod::CheckMetadata);
+        states.insert("fresh".to_string(), fresh_state);
+        states.insert("stale".to_string(), RepoState::default());
+
+        let warnings =
+            verify_staleness_warnings(&[fresh, stale, unconfigured], &states);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("stale"));
+    }
+
+    #[test]
+    fn due_repos_puts_the_never_backed_up_repo_first() {
+        let mut never = repo_config(false);
+        never.name = "never".into();
+        let mut recent = repo_config(false);
+        recent.name = "recent".into();
+
+        let mut states = std::collections::BTreeMap::new();
+        let mut recent_state = RepoState::default();
+        recent_state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        states.insert("recent".to_string(), recent_state);
+
+        assert_eq!(
+            due_repos(&[recent, never], &states, None, None, 1, 168),
+            vec!["never".to_string(), "recent".to_string()]
+        );
+    }
+
+    #[test]
+    fn due_repos_orders_by_backup_age_oldest_first() {
+        let mut a = repo_config(false);
+        a.name = "a".into();
+        let mut b = repo_config(false);
+        b.name = "b".into();
+
+        let mut states = std::collections::BTreeMap::new();
+        let mut a_state = RepoState::default();
+        a_state.last_backup = Some(crate::state::BackupRecord {
+            at: 1_000,
+            outcome: crate::state::BackupOutcome::Success { duration_secs: 0, bytes_added: None, warnings: crate::restic::WarningSummary::default(), average_throughput_bytes_per_sec: None, peak_throughput_bytes_per_sec: None },
+            snapshot_id: None,
+            restic_version: None,
+            halley_version: None,
+        });
+        states.insert("a".to_string(), a_state);
+        let mut b_state = RepoState::default();
+        b_state.last_backup = Some(crate::state::BackupRecord {
+            at: 2_000,
+            outcome: crate::state::BackupOutcome::Success { duration_secs: 0, bytes_added: None, warnings: crate::restic::WarningSummary::default(), average_throughput_bytes_per_sec: None, peak_throughput_bytes_per_sec: None },
+            snapshot_id: None,
+            restic_version: None,
+            halley_version: None,
+        });
+        states.insert("b".to_string(), b_state);
+
+        assert_eq!(due_repos(&[a, b], &states, None, None, 1, 168), vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn due_repos_forces_a_repo_whose_backoff_has_elapsed_ahead_of_a_stale_success() {
+        let mut just_failed = repo_config(false);
+        just_failed.name = "just-failed".into();
+        let mut long_overdue_success = repo_config(false);
+        long_overdue_success.name = "long-overdue-success".into();
+
+        let mut states = std::collections::BTreeMap::new();
+        let mut failed_state = RepoState::default();
+        failed_state.record_backup_failure("boom", None);
+        // 1h base * 2^1 = 2h backoff; a failure 3h ago has cleared it.
+        failed_state.last_backup.as_mut().unwrap().at = now_secs() - 3 * 3600;
+        states.insert("just-failed".to_string(), failed_state);
+        let mut success_state = RepoState::default();
+        success_state.last_backup = Some(crate::state::BackupRecord {
+            at: 1,
+            outcome: crate::state::BackupOutcome::Success { duration_secs: 0, bytes_added: None, warnings: crate::restic::WarningSummary::default(), average_throughput_bytes_per_sec: None, peak_throughput_bytes_per_sec: None },
+            snapshot_id: None,
+            restic_version: None,
+            halley_version: None,
+        });
+        states.insert("long-overdue-success".to_string(), success_state);
+
+        assert_eq!(
+            due_repos(&[long_overdue_success, just_failed], &states, None, None, 1, 168),
+            vec!["just-failed".to_string(), "long-overdue-success".to_string()]
+        );
+    }
+
+    #[test]
+    fn due_repos_excludes_a_repo_backed_up_more_recently_than_its_min_interval() {
+        let mut too_soon = repo_config(false);
+        too_soon.name = "too-soon".into();
+        too_soon.min_backup_interval_hours = Some(6);
+        let mut overdue = repo_config(false);
+        overdue.name = "overdue".into();
+
+        let mut states = std::collections::BTreeMap::new();
+        let mut too_soon_state = RepoState::default();
+        too_soon_state.last_backup = Some(crate::state::BackupRecord {
+            at: now_secs() - 60, // one minute ago, well under the 6h minimum
+            outcome: crate::state::BackupOutcome::Success { duration_secs: 0, bytes_added: None, warnings: crate::restic::WarningSummary::default(), average_throughput_bytes_per_sec: None, peak_throughput_bytes_per_sec: None },
+            snapshot_id: None,
+            restic_version: None,
+            halley_version: None,
+        });
+        states.insert("too-soon".to_string(), too_soon_state);
+
+        assert_eq!(
+            due_repos(&[too_soon, overdue], &states, None, None, 1, 168),
+            vec!["overdue".to_string()]
+        );
+    }
+
+    #[test]
+    fn due_repos_min_interval_does_not_hold_back_a_repo_whose_last_backup_failed() {
+        let mut just_failed = repo_config(false);
+        just_failed.name = "just-failed".into();
+        just_failed.min_backup_interval_hours = Some(6);
+
+        let mut states = std::collections::BTreeMap::new();
+        let mut failed_state = RepoState::default();
+        failed_state.record_backup_failure("boom", None);
+        states.insert("just-failed".to_string(), failed_state);
+
+        assert_eq!(
+            due_repos(&[just_failed], &states, None, None, 1, 168),
+            vec!["just-failed".to_string()]
+        );
+    }
+
+    #[test]
+    fn due_repos_uses_the_global_default_min_interval_when_the_repo_sets_none() {
+        let mut too_soon = repo_config(false);
+        too_soon.name = "too-soon".into();
+
+        let mut states = std::collections::BTreeMap::new();
+        let mut too_soon_state = RepoState::default();
+        too_soon_state.last_backup = Some(crate::state::BackupRecord {
+            at: now_secs() - 60,
+            outcome: crate::state::BackupOutcome::Success { duration_secs: 0, bytes_added: None, warnings: crate::restic::WarningSummary::default(), average_throughput_bytes_per_sec: None, peak_throughput_bytes_per_sec: None },
+            snapshot_id: None,
+            restic_version: None,
+            halley_version: None,
+        });
+        states.insert("too-soon".to_string(), too_soon_state);
+
+        assert_eq!(due_repos(&[too_soon], &states, Some(6), None, 1, 168), Vec::<String>::new());
+    }
+
+    #[test]
+    fn due_repos_forces_a_repo_past_its_max_interval_even_without_any_changes() {
+        let mut forced = repo_config(false);
+        forced.name = "forced".into();
+        forced.max_backup_interval_days = Some(30);
+        let mut recent = repo_config(false);
+        recent.name = "recent".into();
+
+        let mut states = std::collections::BTreeMap::new();
+        let mut forced_state = RepoState::default();
+        forced_state.last_backup = Some(crate::state::BackupRecord {
+            at: now_secs() - 31 * 24 * 3600,
+            outcome: crate::state::BackupOutcome::Success { duration_secs: 0, bytes_added: None, warnings: crate::restic::WarningSummary::default(), average_throughput_bytes_per_sec: None, peak_throughput_bytes_per_sec: None },
+            snapshot_id: None,
+            restic_version: None,
+            halley_version: None,
+        });
+        states.insert("forced".to_string(), forced_state);
+        let mut recent_state = RepoState::default();
+        recent_state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        states.insert("recent".to_string(), recent_state);
+
+        assert_eq!(
+            due_repos(&[recent, forced], &states, None, None, 1, 168),
+            vec!["forced".to_string(), "recent".to_string()]
+        );
+    }
+
+    #[test]
+    fn due_repos_excludes_a_repo_still_backing_off_from_repeated_failures() {
+        let mut broken = repo_config(false);
+        broken.name = "broken".into();
+        let mut healthy = repo_config(false);
+        healthy.name = "healthy".into();
+
+        let mut states = std::collections::BTreeMap::new();
+        let mut broken_state = RepoState::default();
+        for _ in 0..3 {
+            broken_state.record_backup_failure("boom", None);
+        }
+        // 1h base * 2^3 = 8h backoff; a failure 1h ago is still within it.
+        broken_state.last_backup.as_mut().unwrap().at = now_secs() - 3600;
+        states.insert("broken".to_string(), broken_state);
+
+        assert_eq!(
+            due_repos(&[broken, healthy], &states, None, None, 1, 168),
+            vec!["healthy".to_string()]
+        );
+    }
+
+    #[test]
+    fn due_repos_retries_a_repo_once_its_backoff_window_has_elapsed() {
+        let mut broken = repo_config(false);
+        broken.name = "broken".into();
+
+        let mut states = std::collections::BTreeMap::new();
+        let mut broken_state = RepoState::default();
+        broken_state.record_backup_failure("boom", None);
+        // 1h base * 2^1 = 2h backoff; a failure 3h ago is past it.
+        broken_state.last_backup.as_mut().unwrap().at = now_secs() - 3 * 3600;
+        states.insert("broken".to_string(), broken_state);
+
+        assert_eq!(due_repos(&[broken], &states, None, None, 1, 168), vec!["broken".to_string()]);
+    }
+
+    #[test]
+    fn check_due_reports_a_repo_with_no_state_as_never_backed_up() {
+        let mut never = repo_config(false);
+        never.name = "never".into();
+        let states = std::collections::BTreeMap::new();
+
+        let statuses = check_due(&[never], &states, None, None, 1, 168);
+
+        assert_eq!(
+            statuses,
+            vec![RepoDueStatus {
+                repo: "never".to_string(),
+                due: true,
+                reason: "never backed up".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn check_due_reports_a_recently_backed_up_repo_as_not_due() {
+        let mut recent = repo_config(false);
+        recent.name = "recent".into();
+        recent.min_backup_interval_hours = Some(6);
+        let mut states = std::collections::BTreeMap::new();
+        let mut recent_state = RepoState::default();
+        recent_state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        states.insert("recent".to_string(), recent_state);
+
+        let statuses = check_due(&[recent], &states, None, None, 1, 168);
+
+        assert_eq!(statuses[0].due, false);
+        assert_eq!(statuses[0].reason, "backed up within min_backup_interval_hours");
+    }
+
+    #[test]
+    fn check_due_reports_a_failed_repo_as_due() {
+        let mut failed = repo_config(false);
+        failed.name = "failed".into();
+        let mut states = std::collections::BTreeMap::new();
+        let mut failed_state = RepoState::default();
+        failed_state.record_backup_failure("boom", None);
+        // 1h base * 2^1 = 2h backoff; a failure 3h ago has cleared it.
+        failed_state.last_backup.as_mut().unwrap().at = now_secs() - 3 * 3600;
+        states.insert("failed".to_string(), failed_state);
+
+        let statuses = check_due(&[failed], &states, None, None, 1, 168);
+
+        assert_eq!(statuses[0].due, true);
+        assert_eq!(statuses[0].reason, "last backup failed");
+    }
+
+    #[test]
+    fn check_due_reports_a_backing_off_repo_as_not_due() {
+        let mut broken = repo_config(false);
+        broken.name = "broken".into();
+        let mut states = std::collections::BTreeMap::new();
+        let mut broken_state = RepoState::default();
+        broken_state.record_backup_failure("boom", None);
+        states.insert("broken".to_string(), broken_state);
+
+        let statuses = check_due(&[broken], &states, None, None, 1, 168);
+
+        assert_eq!(statuses[0].due, false);
+        assert!(statuses[0].reason.contains("backing off"));
+    }
+
+    #[test]
+    fn check_due_reports_a_repo_past_its_max_interval_as_due() {
+        let mut forced = repo_config(false);
+        forced.name = "forced".into();
+        forced.max_backup_interval_days = Some(30);
+        let mut states = std::collections::BTreeMap::new();
+        let mut forced_state = RepoState::default();
+        forced_state.last_backup = Some(crate::state::BackupRecord {
+            at: now_secs() - 31 * 24 * 3600,
+            outcome: crate::state::BackupOutcome::Success { duration_secs: 0, bytes_added: None, warnings: crate::restic::WarningSummary::default(), average_throughput_bytes_per_sec: None, peak_throughput_bytes_per_sec: None },
+            snapshot_id: None,
+            restic_version: None,
+            halley_version: None,
+        });
+        states.insert("forced".to_string(), forced_state);
+
+        let statuses = check_due(&[forced], &states, None, None, 1, 168);
+
+        assert_eq!(statuses[0].due, true);
+        assert_eq!(statuses[0].reason, "past max_backup_interval_days");
+    }
+
+    #[test]
+    fn check_due_never_touches_the_states_map() {
+        let mut repo = repo_config(false);
+        repo.name = "home".into();
+        let states = std::collections::BTreeMap::new();
+
+        let before = states.clone();
+        check_due(&[repo.clone()], &states, None, None, 1, 168);
+        assert_eq!(states, before);
+    }
+
+    fn now_secs() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    fn run_outcome(repo: &str, result: Result<(), String>) -> RunOutcome {
+        let status = if result.is_err() { RunStatus::Failed } else { RunStatus::Success };
+        RunOutcome {
+            repo: repo.to_string(),
+            result,
+            snapshot_id: None,
+            bytes_added: None,
+            duration_secs: 0,
+            cold_storage_settled: true,
+            warnings: crate::restic::WarningSummary::default(),
+            status,
+            restic_version: None,
+            halley_version: None,
+            average_throughput_bytes_per_sec: None,
+            peak_throughput_bytes_per_sec: None,
+        }
+    }
+
+    #[test]
+    fn run_report_counts_successes_and_flags_partial_failure() {
+        let mut report = RunReport::default();
+        report.outcomes.push(run_outcome("a", Ok(())));
+        report.outcomes.push(run_outcome("b", Err("boom".into())));
+        assert_eq!(report.succeeded(), 1);
+        assert!(!report.all_succeeded());
+    }
+
+    #[test]
+    fn run_report_serializes_the_nothing_to_do_case_as_an_empty_but_valid_report() {
+        let report = RunReport::default();
+        assert_eq!(serde_json::to_string(&report).unwrap(), r#"{"outcomes":[]}"#);
+    }
+
+    #[test]
+    fn run_report_serializes_a_populated_outcome_to_json() {
+        let mut report = RunReport::default();
+        report.outcomes.push(RunOutcome {
+            repo: "home".into(),
+            result: Ok(()),
+            snapshot_id: Some("abc123".into()),
+            bytes_added: Some(2048),
+            duration_secs: 12,
+            cold_storage_settled: true,
+            warnings: crate::restic::WarningSummary::default(),
+            status: RunStatus::Success,
+            restic_version: Some("0.16.4".into()),
+            halley_version: Some("0.3.0".into()),
+            average_throughput_bytes_per_sec: Some(1_048_576),
+            peak_throughput_bytes_per_sec: Some(2_097_152),
+        });
+        let json: serde_json::Value = serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+        assert_eq!(json["outcomes"][0]["repo"], "home");
+        assert_eq!(json["outcomes"][0]["snapshot_id"], "abc123");
+        assert_eq!(json["outcomes"][0]["bytes_added"], 2048);
+        assert_eq!(json["outcomes"][0]["duration_secs"], 12);
+        assert_eq!(json["outcomes"][0]["cold_storage_settled"], true);
+        assert_eq!(json["outcomes"][0]["restic_version"], "0.16.4");
+        assert_eq!(json["outcomes"][0]["halley_version"], "0.3.0");
+        assert_eq!(json["outcomes"][0]["average_throughput_bytes_per_sec"], 1_048_576);
+        assert_eq!(json["outcomes"][0]["peak_throughput_bytes_per_sec"], 2_097_152);
+    }
+
+    #[test]
+    fn run_outcome_from_repo_state_pulls_the_last_backup_and_pending_action() {
+        let mut state = RepoState::default();
+        state.record_backup_success(Some("snap-1".to_string()), 7, Some(4096), crate::restic::WarningSummary::default(), None, None, None);
+
+        let outcome = RunOutcome::from_repo_state("home", Ok(()), &state, None);
+
+        assert_eq!(outcome.snapshot_id.as_deref(), Some("snap-1"));
+        assert_eq!(outcome.bytes_added, Some(4096));
+        assert_eq!(outcome.duration_secs, 7);
+        assert!(outcome.cold_storage_settled);
+        assert_eq!(outcome.status, RunStatus::Success);
+    }
+
+    #[test]
+    fn run_outcome_from_repo_state_pulls_the_restic_and_halley_versions() {
+        let mut state = RepoState::default();
+        state.record_backup_success(
+            Some("snap-1".to_string()),
+            7,
+            None,
+            crate::restic::WarningSummary::default(),
+            Some("0.16.4".to_string()),
+            None,
+            None,
+        );
+
+        let outcome = RunOutcome::from_repo_state("home", Ok(()), &state, None);
+
+        assert_eq!(outcome.restic_version.as_deref(), Some("0.16.4"));
+        assert_eq!(outcome.halley_version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn run_outcome_from_repo_state_is_unsettled_while_a_freeze_is_pending() {
+        let mut state = RepoState::default();
+        state.pending_action = Some(PendingAction::Freeze);
+
+        let outcome = RunOutcome::from_repo_state("cold", Ok(()), &state, None);
+
+        assert!(!outcome.cold_storage_settled);
+    }
+
+    #[test]
+    fn run_outcome_from_repo_state_has_no_snapshot_or_bytes_without_a_backup() {
+        let state = RepoState::default();
+
+        let outcome = RunOutcome::from_repo_state("fresh", Ok(()), &state, None);
+
+        assert_eq!(outcome.snapshot_id, None);
+        assert_eq!(outcome.bytes_added, None);
+        assert_eq!(outcome.duration_secs, 0);
+    }
+
+    #[test]
+    fn run_outcome_from_repo_state_is_partial_when_warnings_exceed_the_threshold() {
+        let mut state = RepoState::default();
+        let mut warnings = crate::restic::WarningSummary::default();
+        warnings.permission = 3;
+        state.record_backup_success(Some("snap-1".to_string()), 7, None, warnings, None, None, None);
+
+        let outcome = RunOutcome::from_repo_state("home", Ok(()), &state, Some(2));
+
+        assert_eq!(outcome.status, RunStatus::Partial);
+    }
+
+    #[test]
+    fn run_outcome_from_repo_state_stays_success_within_the_warning_threshold() {
+        let mut state = RepoState::default();
+        let mut warnings = crate::restic::WarningSummary::default();
+        warnings.permission = 2;
+        state.record_backup_success(Some("snap-1".to_string()), 7, None, warnings, None, None, None);
+
+        let outcome = RunOutcome::from_repo_state("home", Ok(()), &state, Some(2));
+
+        assert_eq!(outcome.status, RunStatus::Success);
+    }
+
+    #[test]
+    fn run_outcome_from_repo_state_is_failed_even_with_warnings_under_threshold() {
+        let state = RepoState::default();
+
+        let outcome = RunOutcome::from_repo_state("home", Err("boom".to_string()), &state, Some(2));
+
+        assert_eq!(outcome.status, RunStatus::Failed);
+    }
+}
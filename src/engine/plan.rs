@@ -0,0 +1,205 @@
+//! Grouping repositories into a run plan so repos with overlapping source
+//! paths never run concurrently.
+//!
+//! Two repos can intentionally share (or nest) source paths, e.g. different
+//! retention tiers over the same tree. Hashing and reading that tree twice
+//! at once thrashes disks for no benefit, so any such repos are placed on
+//! the same lane, serializing them against each other; unrelated repos land
+//! on their own lanes and are free to run in parallel once something
+//! actually executes lanes concurrently (that executor doesn't exist yet).
+
+use std::path::Path;
+
+use crate::config::RepoConfig;
+
+/// A run plan: each lane is a list of repo names, in config order, that
+/// must run one after another. Different lanes have no source overlap with
+/// each other and may run concurrently.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunPlan {
+    pub lanes: Vec<Vec<String>>,
+}
+
+/// True if any source path in `a` is equal to, or an ancestor/descendant
+/// of, any source path in `b`.
+fn sources_overlap(a: &[std::path::PathBuf], b: &[std::path::PathBuf]) -> bool {
+    a.iter()
+        .any(|pa| b.iter().any(|pb| paths_overlap(pa, pb)))
+}
+
+fn paths_overlap(a: &Path, b: &Path) -> bool {
+    a == b || a.starts_with(b) || b.starts_with(a)
+}
+
+/// Groups `repos` into lanes via the connected components of the "shares an
+/// overlapping source path" relation, so two repos land on the same lane
+/// whenever they overlap directly or transitively (A overlaps B, B overlaps
+/// C: all three share a lane, even if A and C don't overlap directly).
+/// Lanes are ordered by the config position of their first member; repo
+/// names within a lane keep their original config order.
+pub fn build_run_plan(repos: &[RepoConfig]) -> RunPlan {
+    let mut parent: Vec<usize> = (0..repos.len()).collect();
+
+    fn find(parent: &mut [usize], i: usize) -> usize {
+        if parent[i] != i {
+            parent[i] = find(parent, parent[i]);
+        }
+        parent[i]
+    }
+
+    fn union(parent: &mut [usize], a: usize, b: usize) {
+        let (ra, rb) = (find(parent, a), find(parent, b));
+        if ra != rb {
+            parent[ra] = rb;
+        }
+    }
+
+    for i in 0..repos.len() {
+        for j in (i + 1)..repos.len() {
+            if sources_overlap(&repos[i].sources, &repos[j].sources) {
+                union(&mut parent, i, j);
+            }
+        }
+    }
+
+    let mut lanes: Vec<(usize, Vec<String>)> = Vec::new();
+    for i in 0..repos.len() {
+        let root = find(&mut parent, i);
+        match lanes.iter_mut().find(|(r, _)| *r == root) {
+            Some((_, lane)) => lane.push(repos[i].name.clone()),
+            None => lanes.push((root, vec![repos[i].name.clone()])),
+        }
+    }
+
+    RunPlan {
+        lanes: lanes.into_iter().map(|(_, lane)| lane).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChangedDuringBackup, RetentionPolicy};
+    use crate::restic::SymlinkPolicy;
+    use std::path::PathBuf;
+
+    fn repo(name: &str, sources: &[&str]) -> RepoConfig {
+        RepoConfig {
+            name: name.into(),
+            sources: sources.iter().map(PathBuf::from).collect(),
+            repo: format!("/srv/backups/{name}"),
+            retention: RetentionPolicy::default(),
+            prune: false,
+            changed_during_backup: ChangedDuringBackup::Ignore,
+            max_verify_age_days: None,
+            symlinks: SymlinkPolicy::default(),
+            compression: None,
+            no_scan: false,
+            read_concurrency: None,
+            excludes: vec![],
+            exclude_file: None,
+            digest_ignore: Vec::new(),
+            strict_paths: false,
+            tags: vec![],
+            check_before_backup: false,
+            restic_memory_limit_mb: None,
+            auto_init: false,
+            password: Some("testpass".to_string()),
+            password_file: None,
+            password_command: None,
+            password_source: None,
+            limit_upload: None,
+            limit_download: None,
+            allow_initial_backup: false,
+            first_backup_size_threshold_mb: None,
+            cache_dir: None,
+            no_cache: false,
+            restore_sparse: false,
+            restore_flags: vec![],
+            hostname: None,
+            one_file_system: false,
+            auto_unlock_stale: false,
+            max_auto_forget: None,
+            warning_threshold: None,
+            s3: None,
+            cold_storage_command: None,
+            pre_hook: None,
+            post_hook: None,
+            archive_after_failed_backup: false,
+            archive_delay_hours: None,
+            archive_unverified: false,
+            min_backup_interval_hours: None,
+            max_backup_interval_days: None,
+            extra_env_passthrough: Vec::new(),
+            allow_network_sources: false,
+        }
+    }
+
+    #[test]
+    fn disjoint_repos_each_get_their_own_lane() {
+        let repos = vec![repo("home", &["/home"]), repo("etc", &["/etc"])];
+        let plan = build_run_plan(&repos);
+        assert_eq!(
+            plan.lanes,
+            vec![vec!["home".to_string()], vec!["etc".to_string()]]
+        );
+    }
+
+    #[test]
+    fn repos_with_identical_sources_share_a_lane() {
+        let repos = vec![repo("daily", &["/data"]), repo("archive", &["/data"])];
+        let plan = build_run_plan(&repos);
+        assert_eq!(plan.lanes, vec![vec!["daily".to_string(), "archive".to_string()]]);
+    }
+
+    #[test]
+    fn a_source_nested_under_another_counts_as_overlap() {
+        let repos = vec![repo("root", &["/srv"]), repo("nested", &["/srv/app/data"])];
+        let plan = build_run_plan(&repos);
+        assert_eq!(plan.lanes, vec![vec!["root".to_string(), "nested".to_string()]]);
+    }
+
+    #[test]
+    fn overlap_is_transitive_across_three_repos() {
+        let repos = vec![
+            repo("a", &["/srv/a"]),
+            repo("b", &["/srv/a/sub", "/srv/b"]),
+            repo("c", &["/srv/b/sub"]),
+        ];
+        let plan = build_run_plan(&repos);
+        assert_eq!(plan.lanes.len(), 1);
+        assert_eq!(plan.lanes[0], vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+    }
+
+    #[test]
+    fn unrelated_repos_stay_separate_even_with_an_overlapping_pair_between_them() {
+        let repos = vec![
+            repo("home", &["/home"]),
+            repo("daily", &["/data"]),
+            repo("archive", &["/data"]),
+        ];
+        let plan = build_run_plan(&repos);
+        assert_eq!(
+            plan.lanes,
+            vec![
+                vec!["home".to_string()],
+                vec!["daily".to_string(), "archive".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_repo_list_produces_no_lanes() {
+        assert_eq!(build_run_plan(&[]), RunPlan::default());
+    }
+
+    #[test]
+    fn a_repo_with_no_sources_never_overlaps_anything() {
+        let repos = vec![repo("empty", &[]), repo("home", &["/home"])];
+        let plan = build_run_plan(&repos);
+        assert_eq!(
+            plan.lanes,
+            vec![vec!["empty".to_string()], vec!["home".to_string()]]
+        );
+    }
+}
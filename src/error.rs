@@ -0,0 +1,488 @@
+//! Error types shared across Halley's restic and S3 layers.
+//!
+//! Every fallible operation the engine can retry ends up as a
+//! [`HalleyError`], which knows how to classify itself as
+//! [`Severity::Transient`] (worth another attempt) or
+//! [`Severity::Permanent`] (retrying would just waste time).
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum HalleyError {
+    #[error("restic exited with status {status}: {stderr}")]
+    Restic { status: i32, stderr: String },
+
+    #[error("S3 operation failed: {0}")]
+    S3(String),
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error(
+        "restic binary '{binary}' not found; install restic, fix `restic_binary`, or set \
+         missing_restic = \"skip-backends\" on hosts that intentionally don't run backups"
+    )]
+    ResticNotAvailable { binary: String },
+
+    #[error("failed to parse restic output: {0}")]
+    Parse(String),
+
+    #[error("state directory unusable: {0}")]
+    StateDir(String),
+
+    #[error(
+        "first backup would upload an estimated {estimated_bytes} bytes; set \
+         allow_initial_backup = true (or confirm interactively, once supported) to proceed"
+    )]
+    FirstBackupNotConfirmed { estimated_bytes: u64 },
+
+    #[error(
+        "forget would remove {would_remove} snapshot(s) from '{repo}', above max_auto_forget; \
+         run `halley forget {repo} --confirm` to apply it"
+    )]
+    ForgetRequiresConfirmation { repo: String, would_remove: usize },
+
+    #[error(
+        "expedited restore of '{repo}' would issue {object_count} request(s), at roughly 10x \
+         Standard's cost, above expedited_restore_confirm_above; confirm interactively (once \
+         supported) or set confirm_expedited = false to proceed automatically"
+    )]
+    ExpeditedRestoreRequiresConfirmation { repo: String, object_count: u64 },
+
+    #[error("restic command exceeded the {minutes} minute timeout and was killed")]
+    Timeout { minutes: u64 },
+
+    #[error("none of the configured source paths for '{repo}' exist; refusing to run an empty backup")]
+    NoBackupSources { repo: String },
+
+    #[error(
+        "'{repo}' is backed by cold storage; a dry run has no safe way to preview a thaw, \
+         so --dry only supports local repositories"
+    )]
+    ColdStorageDryRunUnsupported { repo: String },
+
+    #[error("{0}")]
+    Locked(String),
+
+    #[error("run interrupted by signal before it could finish")]
+    Cancelled,
+}
+
+/// Whether an error is worth retrying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Likely to succeed on a second attempt (network blips, throttling, ...).
+    Transient,
+    /// Retrying won't help (bad config, corrupt repo, auth failure, ...).
+    Permanent,
+}
+
+impl HalleyError {
+    pub fn severity(&self) -> Severity {
+        match self {
+            HalleyError::Restic { stderr, .. } => classify_message(stderr),
+            HalleyError::S3(msg) => classify_message(msg),
+            HalleyError::Io(_) => Severity::Transient,
+            // Won't clear up on its own between attempts within the same
+            // run; needs a human to install restic, fix the config, or set
+            // `missing_restic = "skip-backends"`.
+            HalleyError::ResticNotAvailable { .. } => Severity::Permanent,
+            HalleyError::Parse(_) => Severity::Permanent,
+            // A full or read-only disk won't clear up by the time a retry
+            // within the same run would fire.
+            HalleyError::StateDir(_) => Severity::Permanent,
+            // Nothing about the estimate changes on a retry; this needs a
+            // human (or a config change), not another attempt.
+            HalleyError::FirstBackupNotConfirmed { .. } => Severity::Permanent,
+            // Same reasoning as above: needs a human to run `--confirm` or
+            // adjust the retention policy, not another automatic attempt.
+            HalleyError::ForgetRequiresConfirmation { .. } => Severity::Permanent,
+            // Same again: the object count and tier won't change on a
+            // retry, only a human (or a config change) can clear this.
+            HalleyError::ExpeditedRestoreRequiresConfirmation { .. } => Severity::Permanent,
+            // Whatever stalled the process (an NFS hang, a wedged network
+            // mount) may well have cleared up by the next attempt.
+            HalleyError::Timeout { .. } => Severity::Transient,
+            // A missing source is a config/filesystem problem that won't
+            // fix itself between attempts.
+            HalleyError::NoBackupSources { .. } => Severity::Permanent,
+            // A repo's cold-storage backend doesn't change on a retry;
+            // this needs a human to run without --dry, or against a
+            // different repo.
+            HalleyError::ColdStorageDryRunUnsupported { .. } => Severity::Permanent,
+            // The other instance holding the lock might finish before a
+            // retry within the same run would fire, but that's exactly the
+            // overlap this error exists to prevent -- don't paper over it
+            // with an automatic retry.
+            HalleyError::Locked(_) => Severity::Permanent,
+            // The user asked for this run to stop; retrying within the same
+            // run would just fight the signal that's still pending.
+            HalleyError::Cancelled => Severity::Permanent,
+        }
+    }
+}
+
+/// Markers that show up in restic/S3 error text when the failure is
+/// transient. This is necessarily a heuristic: restic and the S3 API don't
+/// give us a structured way to tell the two apart.
+const TRANSIENT_MARKERS: &[&str] = &[
+    "connection reset",
+    "connection refused",
+    "timeout",
+    "timed out",
+    "temporary failure",
+    "slow down",
+    "throttl",
+    "503",
+    "500",
+];
+
+fn classify_message(msg: &str) -> Severity {
+    let lower = msg.to_lowercase();
+    if TRANSIENT_MARKERS.iter().any(|m| lower.contains(m)) {
+        Severity::Transient
+    } else {
+        Severity::Permanent
+    }
+}
+
+/// A structured classification of a `restic` failure, inferred from its
+/// exit status and stderr.
+///
+/// This is distinct from [`Severity`]: severity says whether a failure is
+/// worth retrying, this says *what kind* of failure it was. Callers that
+/// need to react to a specific failure mode — like [`crate::restic::init`]
+/// treating a lost `auto_init` race as success — match on this instead of
+/// re-deriving their own substring checks on `stderr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResticErrorKind {
+    /// The `restic` binary couldn't be found or executed.
+    NotInstalled,
+    /// `restic init` found a repository already there.
+    RepoAlreadyExists,
+    /// The configured password doesn't match the repository's key.
+    WrongPassword,
+    /// Another restic process is holding the repository lock.
+    LockHeld,
+    /// Anything else, identified only by its exit code and message.
+    ExitCode(i32, String),
+}
+
+/// Markers restic's stderr uses for failure modes worth telling apart from
+/// a generic exit code, in the order they're checked.
+const NOT_INSTALLED_MARKERS: &[&str] = &["no such file or directory", "command not found"];
+const ALREADY_EXISTS_MARKERS: &[&str] = &["config file already exists"];
+const WRONG_PASSWORD_MARKERS: &[&str] = &["wrong password"];
+const LOCK_HELD_MARKERS: &[&str] = &["repository is already locked", "unable to create lock"];
+
+fn classify_restic_error(status: i32, stderr: &str) -> ResticErrorKind {
+    let lower = stderr.to_lowercase();
+    if ALREADY_EXISTS_MARKERS.iter().any(|m| lower.contains(m)) {
+        ResticErrorKind::RepoAlreadyExists
+    } else if WRONG_PASSWORD_MARKERS.iter().any(|m| lower.contains(m)) {
+        ResticErrorKind::WrongPassword
+    } else if LOCK_HELD_MARKERS.iter().any(|m| lower.contains(m)) {
+        ResticErrorKind::LockHeld
+    } else if NOT_INSTALLED_MARKERS.iter().any(|m| lower.contains(m)) {
+        ResticErrorKind::NotInstalled
+    } else {
+        ResticErrorKind::ExitCode(status, stderr.to_string())
+    }
+}
+
+/// A structured classification of an S3 failure, inferred from its message
+/// text. Mirrors [`ResticErrorKind`]: [`Severity`] says whether a failure is
+/// worth retrying, this says *what kind* of failure it was, so a caller can
+/// tell a typo'd endpoint (never going to work) apart from a VPN being
+/// temporarily down (might clear up on its own).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum S3ErrorKind {
+    /// The endpoint hostname couldn't be resolved at all — almost always a
+    /// typo in the configured endpoint/region, not something a retry fixes.
+    DnsResolutionFailed,
+    /// The endpoint resolved, but nothing answered on the other end — e.g. a
+    /// VPN or proxy required to reach it is down.
+    ConnectionRefused,
+    /// Anything else, identified only by its raw message.
+    Other,
+}
+
+/// Markers seen in S3 SDK error text for a hostname that couldn't be
+/// resolved at all, in the order they're checked.
+const DNS_FAILURE_MARKERS: &[&str] = &[
+    "nxdomain",
+    "could not resolve",
+    "name or service not known",
+    "no such host",
+    "dns error",
+];
+
+fn classify_s3_error(msg: &str) -> S3ErrorKind {
+    let lower = msg.to_lowercase();
+    if DNS_FAILURE_MARKERS.iter().any(|m| lower.contains(m)) {
+        S3ErrorKind::DnsResolutionFailed
+    } else if lower.contains("connection refused") {
+        S3ErrorKind::ConnectionRefused
+    } else {
+        S3ErrorKind::Other
+    }
+}
+
+impl HalleyError {
+    /// The structured failure kind for a [`HalleyError::Restic`], or `None`
+    /// for error variants that don't come from a restic invocation.
+    pub fn restic_error_kind(&self) -> Option<ResticErrorKind> {
+        match self {
+            HalleyError::Restic { status, stderr } => Some(classify_restic_error(*status, stderr)),
+            _ => None,
+        }
+    }
+
+    /// The structured failure kind for a [`HalleyError::S3`], or `None` for
+    /// error variants that didn't come from an S3 operation.
+    pub fn s3_error_kind(&self) -> Option<S3ErrorKind> {
+        match self {
+            HalleyError::S3(msg) => Some(classify_s3_error(msg)),
+            _ => None,
+        }
+    }
+
+    /// Builds a [`HalleyError::S3`] naming `bucket` and, based on `raw`'s
+    /// classification, the likely cause — so a typo'd endpoint doesn't just
+    /// surface as a generic "operation failed" after exhausting retries.
+    ///
+    /// Not yet wired into a real S3 client (Halley has none; see
+    /// [`crate::s3`]), but ready for the caller that eventually detects a
+    /// dispatch failure on the first attempt.
+    pub fn s3_endpoint_unreachable(bucket: &str, raw: &str) -> HalleyError {
+        let hint = match classify_s3_error(raw) {
+            S3ErrorKind::DnsResolutionFailed => {
+                "could not resolve the endpoint hostname; check for a typo in the configured endpoint or region"
+            }
+            S3ErrorKind::ConnectionRefused => {
+                "connection to the endpoint was refused; check whether a VPN or proxy required to reach it is up"
+            }
+            S3ErrorKind::Other => "the endpoint could not be reached",
+        };
+        HalleyError::S3(format!("bucket '{bucket}' unreachable: {hint} ({raw})"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restic_timeout_is_transient() {
+        let err = HalleyError::Restic {
+            status: 1,
+            stderr: "Fatal: unable to open repository: Get \"...\": timeout".into(),
+        };
+        assert_eq!(err.severity(), Severity::Transient);
+    }
+
+    #[test]
+    fn restic_bad_password_is_permanent() {
+        let err = HalleyError::Restic {
+            status: 1,
+            stderr: "Fatal: wrong password or no key found".into(),
+        };
+        assert_eq!(err.severity(), Severity::Permanent);
+    }
+
+    #[test]
+    fn s3_throttling_is_transient() {
+        let err = HalleyError::S3("SlowDown: please reduce your request rate".into());
+        assert_eq!(err.severity(), Severity::Transient);
+    }
+
+    #[test]
+    fn state_dir_unusable_is_permanent() {
+        let err = HalleyError::StateDir("disk full".into());
+        assert_eq!(err.severity(), Severity::Permanent);
+    }
+
+    #[test]
+    fn classifies_repo_already_exists() {
+        let err = HalleyError::Restic {
+            status: 1,
+            stderr: "Fatal: create repository at s3:bucket/repo failed: config file already exists".into(),
+        };
+        assert_eq!(err.restic_error_kind(), Some(ResticErrorKind::RepoAlreadyExists));
+    }
+
+    #[test]
+    fn classifies_wrong_password() {
+        let err = HalleyError::Restic {
+            status: 1,
+            stderr: "Fatal: wrong password or no key found".into(),
+        };
+        assert_eq!(err.restic_error_kind(), Some(ResticErrorKind::WrongPassword));
+    }
+
+    #[test]
+    fn classifies_lock_held() {
+        let err = HalleyError::Restic {
+            status: 1,
+            stderr: "unable to create lock in backend: repository is already locked by PID 123 by user on host".into(),
+        };
+        assert_eq!(err.restic_error_kind(), Some(ResticErrorKind::LockHeld));
+    }
+
+    #[test]
+    fn classifies_not_installed() {
+        let err = HalleyError::Restic {
+            status: 127,
+            stderr: "sh: restic: command not found".into(),
+        };
+        assert_eq!(err.restic_error_kind(), Some(ResticErrorKind::NotInstalled));
+    }
+
+    #[test]
+    fn falls_back_to_exit_code_for_unrecognized_failures() {
+        let err = HalleyError::Restic {
+            status: 3,
+            stderr: "Fatal: unable to open bucket: access denied".into(),
+        };
+        assert_eq!(
+            err.restic_error_kind(),
+            Some(ResticErrorKind::ExitCode(
+                3,
+                "Fatal: unable to open bucket: access denied".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn non_restic_errors_have_no_restic_error_kind() {
+        let err = HalleyError::S3("SlowDown".into());
+        assert_eq!(err.restic_error_kind(), None);
+    }
+
+    #[test]
+    fn forget_requiring_confirmation_is_permanent() {
+        let err = HalleyError::ForgetRequiresConfirmation {
+            repo: "home".into(),
+            would_remove: 42,
+        };
+        assert_eq!(err.severity(), Severity::Permanent);
+    }
+
+    #[test]
+    fn expedited_restore_requiring_confirmation_is_permanent() {
+        let err = HalleyError::ExpeditedRestoreRequiresConfirmation {
+            repo: "home".into(),
+            object_count: 10_000,
+        };
+        assert_eq!(err.severity(), Severity::Permanent);
+    }
+
+    #[test]
+    fn timeout_is_transient() {
+        let err = HalleyError::Timeout { minutes: 60 };
+        assert_eq!(err.severity(), Severity::Transient);
+    }
+
+    #[test]
+    fn no_backup_sources_is_permanent() {
+        let err = HalleyError::NoBackupSources { repo: "home".into() };
+        assert_eq!(err.severity(), Severity::Permanent);
+    }
+
+    #[test]
+    fn cold_storage_dry_run_unsupported_is_permanent() {
+        let err = HalleyError::ColdStorageDryRunUnsupported { repo: "home".into() };
+        assert_eq!(err.severity(), Severity::Permanent);
+    }
+
+    #[test]
+    fn locked_is_permanent() {
+        let err = HalleyError::Locked("another Halley process (pid 123) is already running".into());
+        assert_eq!(err.severity(), Severity::Permanent);
+    }
+
+    #[test]
+    fn missing_restic_binary_is_permanent() {
+        let err = HalleyError::ResticNotAvailable {
+            binary: "restic".into(),
+        };
+        assert_eq!(err.severity(), Severity::Permanent);
+    }
+
+    #[test]
+    fn unconfirmed_first_backup_is_permanent() {
+        let err = HalleyError::FirstBackupNotConfirmed {
+            estimated_bytes: 1024,
+        };
+        assert_eq!(err.severity(), Severity::Permanent);
+    }
+
+    #[test]
+    fn classifies_dns_resolution_failure() {
+        let err = HalleyError::S3(
+            "dispatch failure: failed to lookup address information: Name or service not known".into(),
+        );
+        assert_eq!(err.s3_error_kind(), Some(S3ErrorKind::DnsResolutionFailed));
+    }
+
+    #[test]
+    fn classifies_nxdomain_as_dns_resolution_failure() {
+        let err = HalleyError::S3("dns error: NXDOMAIN".into());
+        assert_eq!(err.s3_error_kind(), Some(S3ErrorKind::DnsResolutionFailed));
+    }
+
+    #[test]
+    fn classifies_s3_connection_refused() {
+        let err = HalleyError::S3("dispatch failure: connection refused".into());
+        assert_eq!(err.s3_error_kind(), Some(S3ErrorKind::ConnectionRefused));
+    }
+
+    #[test]
+    fn classifies_unrecognized_s3_failure_as_other() {
+        let err = HalleyError::S3("access denied".into());
+        assert_eq!(err.s3_error_kind(), Some(S3ErrorKind::Other));
+    }
+
+    #[test]
+    fn non_s3_errors_have_no_s3_error_kind() {
+        let err = HalleyError::Timeout { minutes: 5 };
+        assert_eq!(err.s3_error_kind(), None);
+    }
+
+    #[test]
+    fn dns_resolution_failure_is_permanent_even_though_its_untimed() {
+        // A typo'd hostname will never resolve; retrying wastes the retry
+        // budget instead of short-circuiting it.
+        let err = HalleyError::S3("failed to lookup address information: nxdomain".into());
+        assert_eq!(err.severity(), Severity::Permanent);
+    }
+
+    #[test]
+    fn s3_connection_refused_is_still_transient() {
+        // Unlike DNS failures, a refused connection (VPN/proxy down) may
+        // clear up on its own, so it stays worth retrying.
+        let err = HalleyError::S3("dispatch failure: connection refused".into());
+        assert_eq!(err.severity(), Severity::Transient);
+    }
+
+    #[test]
+    fn s3_endpoint_unreachable_names_the_bucket_and_cause_for_dns_failures() {
+        let err = HalleyError::s3_endpoint_unreachable(
+            "my-backup-bucket",
+            "failed to lookup address information: Name or service not known",
+        );
+        let message = err.to_string();
+        assert!(message.contains("my-backup-bucket"));
+        assert!(message.contains("typo"));
+        assert_eq!(err.severity(), Severity::Permanent);
+    }
+
+    #[test]
+    fn s3_endpoint_unreachable_names_the_bucket_and_cause_for_connection_refused() {
+        let err = HalleyError::s3_endpoint_unreachable("my-backup-bucket", "connection refused");
+        let message = err.to_string();
+        assert!(message.contains("my-backup-bucket"));
+        assert!(message.contains("VPN"));
+        assert_eq!(err.severity(), Severity::Transient);
+    }
+}
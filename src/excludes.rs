@@ -0,0 +1,94 @@
+//! Named exclude presets: bundles of glob patterns a repo can pull in with
+//! `preset:name` instead of retyping the same boring excludes (trash
+//! folders, thumbnail caches, `node_modules`) in every config.
+//!
+//! Presets only ever expand into ordinary patterns before they reach restic
+//! (see [`crate::engine`]) -- there's no separate preset-aware code path
+//! downstream, so a repo mixing `excludes = ["preset:developer", "*.iso"]`
+//! behaves exactly as if the expanded list had been typed out by hand.
+
+use crate::error::HalleyError;
+
+/// Trash, thumbnail and lock-file clutter common to Linux desktops.
+const DESKTOP_LINUX: &[&str] = &[
+    "**/.Trash-*",
+    "**/.local/share/Trash",
+    "**/.cache/thumbnails",
+    "**/.thumbnails",
+    "**/lost+found",
+];
+
+/// Build artifacts and dependency caches common to software projects.
+const DEVELOPER: &[&str] = &[
+    "**/node_modules",
+    "**/.venv",
+    "**/target",
+    "**/.tox",
+    "**/__pycache__",
+    "**/*.pyc",
+];
+
+/// Trash and thumbnail clutter common to macOS.
+const MACOS: &[&str] = &["**/.Trash", "**/.DS_Store", "**/.Spotlight-V100", "**/.fseventsd"];
+
+/// Looks up a preset by the name after its `preset:` prefix.
+fn lookup(name: &str) -> Option<&'static [&'static str]> {
+    match name {
+        "desktop-linux" => Some(DESKTOP_LINUX),
+        "developer" => Some(DEVELOPER),
+        "macos" => Some(MACOS),
+        _ => None,
+    }
+}
+
+/// Expands any `preset:name` entries in `excludes` into their concrete glob
+/// patterns, leaving ordinary patterns untouched. Fails on the first
+/// unrecognized preset name rather than silently dropping it.
+pub fn expand(excludes: &[String]) -> Result<Vec<String>, HalleyError> {
+    let mut expanded = Vec::with_capacity(excludes.len());
+    for entry in excludes {
+        match entry.strip_prefix("preset:") {
+            Some(name) => match lookup(name) {
+                Some(patterns) => expanded.extend(patterns.iter().map(|p| p.to_string())),
+                None => {
+                    return Err(HalleyError::Parse(format!(
+                        "unknown exclude preset '{name}' (known presets: desktop-linux, developer, macos)"
+                    )))
+                }
+            },
+            None => expanded.push(entry.clone()),
+        }
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_patterns_pass_through_unchanged() {
+        let expanded = expand(&["*.iso".to_string(), "/home/user/.cache".to_string()]).unwrap();
+        assert_eq!(expanded, vec!["*.iso".to_string(), "/home/user/.cache".to_string()]);
+    }
+
+    #[test]
+    fn a_preset_expands_to_its_patterns() {
+        let expanded = expand(&["preset:macos".to_string()]).unwrap();
+        assert_eq!(expanded, MACOS.iter().map(|p| p.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn presets_and_plain_patterns_mix_in_order() {
+        let expanded = expand(&["preset:developer".to_string(), "*.iso".to_string()]).unwrap();
+        assert_eq!(expanded.len(), DEVELOPER.len() + 1);
+        assert_eq!(expanded.first().unwrap(), DEVELOPER.first().unwrap());
+        assert_eq!(expanded.last().unwrap(), "*.iso");
+    }
+
+    #[test]
+    fn an_unknown_preset_is_an_error() {
+        let err = expand(&["preset:windows".to_string()]).unwrap_err();
+        assert!(matches!(err, HalleyError::Parse(_)));
+    }
+}
@@ -0,0 +1,108 @@
+//! Shared glob-pattern matching, used by both the state layer (deciding
+//! what counts towards a source's change digest) and the restic layer
+//! (building `--exclude` arguments). Centralized so the two only ever
+//! disagree about *which* patterns apply, never about what a pattern means.
+
+use std::path::{Path, PathBuf};
+
+use glob::Pattern;
+
+use crate::error::HalleyError;
+
+/// A compiled set of glob patterns that can be tested against paths. Keeps
+/// each pattern's original source string alongside its compiled form so
+/// [`GlobSet::dead_patterns`] can name one that never matched anything,
+/// rather than just reporting that "some pattern" didn't.
+#[derive(Debug, Clone, Default)]
+pub struct GlobSet {
+    patterns: Vec<(String, Pattern)>,
+}
+
+impl GlobSet {
+    /// Compiles `patterns`, failing on the first invalid one.
+    pub fn compile(patterns: &[String]) -> Result<Self, HalleyError> {
+        let patterns = patterns
+            .iter()
+            .map(|p| {
+                Pattern::new(p)
+                    .map(|compiled| (p.clone(), compiled))
+                    .map_err(|e| HalleyError::Parse(format!("invalid glob pattern '{p}': {e}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// True if `path`, or one of its parent directories, matches any
+    /// pattern in the set -- so excluding a directory (`**/node_modules`)
+    /// excludes everything under it too, the same way restic's own
+    /// `--exclude` treats a directory pattern.
+    pub fn matches(&self, path: &Path) -> bool {
+        self.patterns
+            .iter()
+            .any(|(_, p)| path.ancestors().any(|ancestor| p.matches(&ancestor.to_string_lossy())))
+    }
+
+    /// The patterns in this set that matched none of `paths` (or their
+    /// parent directories) -- almost always a typo or a path that moved,
+    /// not a pattern that's simply never needed yet (see
+    /// [`crate::config::RepoConfig::strict_paths`]).
+    pub fn dead_patterns(&self, paths: &[PathBuf]) -> Vec<&str> {
+        self.patterns
+            .iter()
+            .filter(|(_, p)| {
+                !paths.iter().any(|path| path.ancestors().any(|a| p.matches(&a.to_string_lossy())))
+            })
+            .map(|(s, _)| s.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_any_pattern_in_the_set() {
+        let set = GlobSet::compile(&["*.log".into(), "target/*".into()]).unwrap();
+        assert!(set.matches(&PathBuf::from("debug.log")));
+        assert!(set.matches(&PathBuf::from("target/debug")));
+        assert!(!set.matches(&PathBuf::from("src/main.rs")));
+    }
+
+    #[test]
+    fn a_directory_pattern_matches_everything_beneath_it() {
+        let set = GlobSet::compile(&["**/node_modules".into()]).unwrap();
+        assert!(set.matches(&PathBuf::from("project/node_modules/left-pad/index.js")));
+        assert!(!set.matches(&PathBuf::from("project/src/main.rs")));
+    }
+
+    #[test]
+    fn empty_set_matches_nothing() {
+        let set = GlobSet::compile(&[]).unwrap();
+        assert!(set.is_empty());
+        assert!(!set.matches(&PathBuf::from("anything")));
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        assert!(GlobSet::compile(&["[".into()]).is_err());
+    }
+
+    #[test]
+    fn dead_patterns_names_a_pattern_that_matched_nothing() {
+        let set = GlobSet::compile(&["*.log".into(), "*.tmp".into()]).unwrap();
+        let paths = vec![PathBuf::from("debug.log")];
+        assert_eq!(set.dead_patterns(&paths), vec!["*.tmp"]);
+    }
+
+    #[test]
+    fn dead_patterns_is_empty_when_every_pattern_matched_something() {
+        let set = GlobSet::compile(&["*.log".into()]).unwrap();
+        let paths = vec![PathBuf::from("debug.log")];
+        assert!(set.dead_patterns(&paths).is_empty());
+    }
+}
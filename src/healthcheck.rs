@@ -0,0 +1,152 @@
+//! Dead man's switch pings around a `halley backup` run.
+//!
+//! A failed backup shows up in the run report; a backup that never ran at
+//! all -- a removed cron entry, a host that's down -- doesn't. Pinging a
+//! service like healthchecks.io at the start and end of every run closes
+//! that gap: the switch alerts if a ping is late, regardless of what
+//! Halley itself reports. See [`crate::config::HealthcheckConfig`] for why
+//! this is a user-supplied command rather than an HTTP call Halley makes
+//! itself.
+
+use std::time::Duration;
+
+use crate::error::HalleyError;
+use crate::util;
+
+/// How long a [`CommandHealthcheckPinger`]'s command is allowed to run
+/// before it's killed. Short, unlike [`crate::notify`]'s send timeout --
+/// a ping is meant to be a cheap, fire-and-forget check-in, not something
+/// worth waiting out a slow endpoint for.
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Which point in a run a [`HealthcheckPinger::ping`] call is reporting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// The run is starting.
+    Start,
+    /// The run finished with every repo succeeding (or nothing due, if
+    /// [`crate::config::HealthcheckConfig::ping_on_nothing_to_do`] allows it).
+    Success,
+    /// The run finished with at least one repo failing.
+    Fail,
+}
+
+impl Phase {
+    fn as_str(self) -> &'static str {
+        match self {
+            Phase::Start => "start",
+            Phase::Success => "success",
+            Phase::Fail => "fail",
+        }
+    }
+
+    /// The healthchecks.io-style URL suffix for this phase, for a command
+    /// built around appending `$HALLEY_HEALTHCHECK_SUFFIX` to a base ping
+    /// URL.
+    fn suffix(self) -> &'static str {
+        match self {
+            Phase::Start => "/start",
+            Phase::Success => "",
+            Phase::Fail => "/fail",
+        }
+    }
+}
+
+/// Pings a dead man's switch for one phase of a run. Implemented by the
+/// real command-driven pinger and by test doubles.
+pub trait HealthcheckPinger {
+    fn ping(&self, phase: Phase, duration: Option<Duration>) -> Result<(), HalleyError>;
+}
+
+/// Pings by running a user-supplied shell command, e.g. `curl` against a
+/// healthchecks.io check -- see [`crate::config::HealthcheckConfig`].
+/// `phase` is passed as both `HALLEY_HEALTHCHECK_PHASE` ("start", "success",
+/// or "fail") and `HALLEY_HEALTHCHECK_SUFFIX` (the matching healthchecks.io
+/// URL suffix); `duration`, when given, as `HALLEY_DURATION_SECS`.
+pub struct CommandHealthcheckPinger {
+    pub command: String,
+}
+
+impl HealthcheckPinger for CommandHealthcheckPinger {
+    fn ping(&self, phase: Phase, duration: Option<Duration>) -> Result<(), HalleyError> {
+        let mut env = vec![
+            ("HALLEY_HEALTHCHECK_PHASE".to_string(), phase.as_str().to_string()),
+            ("HALLEY_HEALTHCHECK_SUFFIX".to_string(), phase.suffix().to_string()),
+        ];
+        if let Some(duration) = duration {
+            env.push(("HALLEY_DURATION_SECS".to_string(), duration.as_secs().to_string()));
+        }
+        let output = util::run_hook(&self.command, &env, Some(PING_TIMEOUT))?;
+        if !output.success() {
+            return Err(HalleyError::Restic {
+                status: output.status,
+                stderr: output.stderr,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Pings `pinger` for `phase`, swallowing any error -- a broken healthcheck
+/// command is worth a warning on stderr, never a reason to fail the backup
+/// run it's reporting on.
+pub fn ping_quietly(pinger: &impl HealthcheckPinger, phase: Phase, duration: Option<Duration>) {
+    if let Err(e) = pinger.ping(phase, duration) {
+        eprintln!("healthcheck ping ({}) failed: {e}", phase.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn command_pinger_passes_the_phase_and_suffix_as_environment_variables() {
+        let dir = std::env::temp_dir().join(format!("halley-healthcheck-test-{}", std::process::id()));
+        let marker = dir.join("marker");
+        fs::create_dir_all(&dir).unwrap();
+
+        let pinger = CommandHealthcheckPinger {
+            command: format!(
+                "echo -n \"$HALLEY_HEALTHCHECK_PHASE:$HALLEY_HEALTHCHECK_SUFFIX\" > {}",
+                marker.display()
+            ),
+        };
+        pinger.ping(Phase::Start, None).unwrap();
+
+        assert_eq!(fs::read_to_string(&marker).unwrap(), "start:/start");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn command_pinger_passes_the_duration_only_when_given() {
+        let dir = std::env::temp_dir().join(format!("halley-healthcheck-duration-test-{}", std::process::id()));
+        let marker = dir.join("marker");
+        fs::create_dir_all(&dir).unwrap();
+
+        let pinger = CommandHealthcheckPinger {
+            command: format!("echo -n \"${{HALLEY_DURATION_SECS:-unset}}\" > {}", marker.display()),
+        };
+
+        pinger.ping(Phase::Success, Some(Duration::from_secs(42))).unwrap();
+        assert_eq!(fs::read_to_string(&marker).unwrap(), "42");
+
+        pinger.ping(Phase::Start, None).unwrap();
+        assert_eq!(fs::read_to_string(&marker).unwrap(), "unset");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn command_pinger_reports_a_nonzero_exit_as_a_failure() {
+        let pinger = CommandHealthcheckPinger { command: "exit 1".to_string() };
+        assert!(pinger.ping(Phase::Fail, None).is_err());
+    }
+
+    #[test]
+    fn ping_quietly_never_panics_on_a_failing_command() {
+        let pinger = CommandHealthcheckPinger { command: "exit 1".to_string() };
+        ping_quietly(&pinger, Phase::Start, None);
+    }
+}
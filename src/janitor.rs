@@ -0,0 +1,235 @@
+//! Enforces retention caps on artifacts Halley leaves behind under the
+//! state dir, so an unattended host doesn't slowly fill its disk with
+//! things that were written but never explicitly cleaned up.
+//!
+//! Halley doesn't have file logging, run-report history, or manifests
+//! accumulating on disk yet — the only such artifact today is
+//! [`crate::notify::NotificationQueue`]'s queued-but-undelivered
+//! notifications. [`ArtifactClass`] is generic over any directory of
+//! disposable files, though, so a future artifact class only needs a new
+//! entry, not a new cleanup mechanism.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::error::HalleyError;
+
+/// A directory of same-shaped, disposable files with its own retention
+/// caps.
+#[derive(Debug, Clone)]
+pub struct ArtifactClass {
+    pub name: String,
+    pub dir: PathBuf,
+    /// Delete oldest-first once the class's total size exceeds this many
+    /// bytes. `None` means no size cap.
+    pub max_total_bytes: Option<u64>,
+    /// Delete anything last modified longer ago than this. `None` means no
+    /// age cap.
+    pub max_age: Option<Duration>,
+}
+
+/// One file the janitor removed, or — in a dry run — would have removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemovedArtifact {
+    pub class: String,
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// Applies `class`'s caps against its directory, oldest-first by last
+/// modification time, and returns what was removed. With `dry_run` set,
+/// computes the same list but deletes nothing.
+///
+/// A missing directory is treated as already empty rather than an error,
+/// since a fresh install won't have created every artifact class's
+/// directory yet.
+pub fn clean_class(
+    class: &ArtifactClass,
+    now: SystemTime,
+    dry_run: bool,
+) -> Result<Vec<RemovedArtifact>, HalleyError> {
+    if !class.dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(&class.dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let modified = metadata.modified().unwrap_or(now);
+        entries.push((entry.path(), metadata.len(), modified));
+    }
+    entries.sort_by_key(|(_, _, modified)| *modified);
+
+    let mut removed = Vec::new();
+    let mut survivors = Vec::new();
+    let mut total_bytes: u64 = entries.iter().map(|(_, bytes, _)| bytes).sum();
+    for (path, bytes, modified) in entries {
+        let age = now.duration_since(modified).unwrap_or(Duration::ZERO);
+        if class.max_age.is_some_and(|max_age| age > max_age) {
+            total_bytes -= bytes;
+            removed.push(RemovedArtifact {
+                class: class.name.clone(),
+                path,
+                bytes,
+            });
+        } else {
+            survivors.push((path, bytes));
+        }
+    }
+
+    if let Some(max_total_bytes) = class.max_total_bytes {
+        for (path, bytes) in survivors {
+            if total_bytes <= max_total_bytes {
+                break;
+            }
+            total_bytes -= bytes;
+            removed.push(RemovedArtifact {
+                class: class.name.clone(),
+                path,
+                bytes,
+            });
+        }
+    }
+
+    if !dry_run {
+        for artifact in &removed {
+            fs::remove_file(&artifact.path)?;
+        }
+    }
+    Ok(removed)
+}
+
+/// Runs [`clean_class`] over every class in turn, in the order given,
+/// concatenating what each removed.
+pub fn clean(
+    classes: &[ArtifactClass],
+    now: SystemTime,
+    dry_run: bool,
+) -> Result<Vec<RemovedArtifact>, HalleyError> {
+    let mut removed = Vec::new();
+    for class in classes {
+        removed.extend(clean_class(class, now, dry_run)?);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("halley-janitor-test-{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_file_with_age(dir: &PathBuf, name: &str, bytes: &[u8], age: Duration, now: SystemTime) {
+        let path = dir.join(name);
+        fs::write(&path, bytes).unwrap();
+        let file = File::open(&path).unwrap();
+        file.set_modified(now - age).unwrap();
+    }
+
+    #[test]
+    fn clean_class_on_a_missing_directory_removes_nothing() {
+        let class = ArtifactClass {
+            name: "notifications".into(),
+            dir: std::env::temp_dir().join("halley-janitor-test-does-not-exist"),
+            max_total_bytes: Some(10),
+            max_age: None,
+        };
+        let removed = clean_class(&class, SystemTime::now(), false).unwrap();
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn clean_class_deletes_oldest_first_once_over_the_size_cap() {
+        let now = SystemTime::now();
+        let dir = temp_dir("size-cap");
+        write_file_with_age(&dir, "oldest.json", b"1234567890", Duration::from_secs(300), now);
+        write_file_with_age(&dir, "middle.json", b"1234567890", Duration::from_secs(200), now);
+        write_file_with_age(&dir, "newest.json", b"1234567890", Duration::from_secs(100), now);
+
+        let class = ArtifactClass {
+            name: "notifications".into(),
+            dir: dir.clone(),
+            max_total_bytes: Some(20),
+            max_age: None,
+        };
+        let removed = clean_class(&class, now, false).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].path, dir.join("oldest.json"));
+        assert!(!dir.join("oldest.json").exists());
+        assert!(dir.join("middle.json").exists());
+        assert!(dir.join("newest.json").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn clean_class_deletes_anything_past_the_age_cap() {
+        let now = SystemTime::now();
+        let dir = temp_dir("age-cap");
+        write_file_with_age(&dir, "expired.json", b"x", Duration::from_secs(3600), now);
+        write_file_with_age(&dir, "fresh.json", b"x", Duration::from_secs(10), now);
+
+        let class = ArtifactClass {
+            name: "notifications".into(),
+            dir: dir.clone(),
+            max_total_bytes: None,
+            max_age: Some(Duration::from_secs(1800)),
+        };
+        let removed = clean_class(&class, now, false).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].path, dir.join("expired.json"));
+        assert!(!dir.join("expired.json").exists());
+        assert!(dir.join("fresh.json").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn dry_run_reports_removals_without_deleting_anything() {
+        let now = SystemTime::now();
+        let dir = temp_dir("dry-run");
+        write_file_with_age(&dir, "expired.json", b"x", Duration::from_secs(3600), now);
+
+        let class = ArtifactClass {
+            name: "notifications".into(),
+            dir: dir.clone(),
+            max_total_bytes: None,
+            max_age: Some(Duration::from_secs(1800)),
+        };
+        let removed = clean_class(&class, now, true).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert!(dir.join("expired.json").exists(), "dry run must not delete");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn within_both_caps_nothing_is_removed() {
+        let now = SystemTime::now();
+        let dir = temp_dir("within-caps");
+        write_file_with_age(&dir, "a.json", b"x", Duration::from_secs(10), now);
+
+        let class = ArtifactClass {
+            name: "notifications".into(),
+            dir: dir.clone(),
+            max_total_bytes: Some(1000),
+            max_age: Some(Duration::from_secs(3600)),
+        };
+        let removed = clean_class(&class, now, false).unwrap();
+        assert!(removed.is_empty());
+        assert!(dir.join("a.json").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,215 @@
+//! A PID-file lock preventing two Halley invocations from touching the
+//! same state directory concurrently, e.g. a long Glacier thaw still
+//! running under cron when the next scheduled run starts and both end up
+//! racing to run restic against the same repository.
+//!
+//! [`LockGuard::acquire`] is meant to be held for the lifetime of `main`:
+//! its [`Drop`] impl removes the lock file, so it's released on every exit
+//! path -- a normal return, an early `return`, or a panic -- without every
+//! caller having to remember to clean up by hand.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::HalleyError;
+
+/// Holds Halley's lock file for as long as it's alive; dropping it removes
+/// the file.
+pub struct LockGuard {
+    path: PathBuf,
+}
+
+impl LockGuard {
+    /// Acquires the lock file at `dir.join("halley.lock")`, held for the
+    /// whole run. See [`LockGuard::acquire_named`] for a lock scoped more
+    /// narrowly than a full run, e.g. [`crate::state::StateFile::lock`].
+    pub fn acquire(dir: &Path) -> Result<Self, HalleyError> {
+        Self::acquire_named(dir, "halley.lock")
+    }
+
+    /// Acquires the lock file at `dir.join(name)`.
+    ///
+    /// The actual acquire is an atomic `create_new` -- two instances
+    /// launched at the same instant (overlapping cron/systemd timers, the
+    /// exact scenario this lock exists for) can't both win it, unlike a
+    /// read-then-write that leaves a window for both to pass. Only once
+    /// that create fails with "already exists" does this fall back to
+    /// checking whether the recorded PID is still alive: still alive fails
+    /// with [`HalleyError::Locked`]; gone is treated as stale (the previous
+    /// instance evidently didn't exit cleanly enough to remove it itself).
+    ///
+    /// Reclaiming a stale lock removes it and loops back to the same
+    /// `create_new`, rather than truncating the existing file in place --
+    /// truncate-in-place is exactly the check-then-write race this function
+    /// exists to avoid, and two instances recovering the same stale lock at
+    /// once would otherwise both believe they'd won it. With `remove` then
+    /// `create_new`, only one instance's `create_new` can succeed; the
+    /// other loops back, sees the winner's now-live PID, and correctly
+    /// backs off with [`HalleyError::Locked`]. Bounded by
+    /// [`MAX_STALE_RECOVERY_ATTEMPTS`] so a pathological loop (e.g. some
+    /// third party repeatedly recreating the file) fails loudly instead of
+    /// spinning forever.
+    pub fn acquire_named(dir: &Path, name: &str) -> Result<Self, HalleyError> {
+        let path = dir.join(name);
+        for _ in 0..MAX_STALE_RECOVERY_ATTEMPTS {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(std::process::id().to_string().as_bytes())?;
+                    return Ok(Self { path });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if let Some(holder) = read_pid(&path)? {
+                        if process_is_alive(holder) {
+                            return Err(HalleyError::Locked(format!(
+                                "another Halley process (pid {holder}) is already running (lock file '{}')",
+                                path.display()
+                            )));
+                        }
+                    }
+                    let _ = fs::remove_file(&path);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(HalleyError::Locked(format!(
+            "gave up recovering stale lock file '{}' after {MAX_STALE_RECOVERY_ATTEMPTS} attempts",
+            path.display()
+        )))
+    }
+}
+
+/// How many times [`LockGuard::acquire_named`] retries its `create_new`
+/// after reclaiming a stale lock before giving up -- covers a couple of
+/// instances racing to recover the same stale lock, not an unbounded
+/// contention scenario.
+const MAX_STALE_RECOVERY_ATTEMPTS: u32 = 5;
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn read_pid(path: &Path) -> Result<Option<u32>, HalleyError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents.trim().parse().ok()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether `pid` names a live process, checked via the `kill` binary
+/// (`kill -0`) rather than a `libc` dependency this crate doesn't otherwise
+/// need -- the same approach `util::kill_hook_process_group` uses to kill a
+/// timed-out hook's process group.
+fn process_is_alive(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("halley-lock-test-{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn acquire_creates_a_lock_file_naming_this_process() {
+        let dir = temp_dir("fresh");
+        let guard = LockGuard::acquire(&dir).unwrap();
+        let contents = fs::read_to_string(dir.join("halley.lock")).unwrap();
+        assert_eq!(contents, std::process::id().to_string());
+        drop(guard);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn dropping_the_guard_removes_the_lock_file() {
+        let dir = temp_dir("drop-cleanup");
+        let guard = LockGuard::acquire(&dir).unwrap();
+        assert!(dir.join("halley.lock").exists());
+        drop(guard);
+        assert!(!dir.join("halley.lock").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn acquire_refuses_when_the_recorded_pid_is_still_alive() {
+        let dir = temp_dir("live-lock");
+        fs::write(dir.join("halley.lock"), std::process::id().to_string()).unwrap();
+        let err = LockGuard::acquire(&dir).unwrap_err();
+        assert!(matches!(err, HalleyError::Locked(_)));
+        assert!(err.to_string().contains(&std::process::id().to_string()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn acquire_overwrites_a_stale_lock_from_a_dead_pid() {
+        let dir = temp_dir("stale-lock");
+        // Comfortably above any real PID (Linux's default pid_max tops out
+        // around 4 million), so this is never mistaken for a live process.
+        fs::write(dir.join("halley.lock"), "999999999").unwrap();
+        let guard = LockGuard::acquire(&dir).unwrap();
+        let contents = fs::read_to_string(dir.join("halley.lock")).unwrap();
+        assert_eq!(contents, std::process::id().to_string());
+        drop(guard);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_second_acquire_in_the_same_process_fails_fast_and_names_the_holder() {
+        let dir = temp_dir("second-handle");
+        let first = LockGuard::acquire(&dir).unwrap();
+        let err = LockGuard::acquire(&dir).unwrap_err();
+        assert!(matches!(err, HalleyError::Locked(_)));
+        assert!(err.to_string().contains(&std::process::id().to_string()));
+        drop(first);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn acquire_named_lets_two_differently_named_locks_coexist_in_the_same_dir() {
+        let dir = temp_dir("named-coexist");
+        let run_lock = LockGuard::acquire_named(&dir, "halley.lock").unwrap();
+        let state_lock = LockGuard::acquire_named(&dir, "statefile.lock").unwrap();
+        assert!(dir.join("halley.lock").exists());
+        assert!(dir.join("statefile.lock").exists());
+        drop(run_lock);
+        drop(state_lock);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn acquire_treats_a_garbage_lock_file_as_stale() {
+        let dir = temp_dir("garbage-lock");
+        fs::write(dir.join("halley.lock"), "not-a-pid").unwrap();
+        let guard = LockGuard::acquire(&dir).unwrap();
+        drop(guard);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn acquire_refuses_a_stale_lock_someone_else_already_recovered() {
+        // Simulates the race the recovery loop guards against: by the time
+        // this instance gets around to recreating the file, another
+        // instance already reclaimed it and is alive, so this one must
+        // back off instead of believing it won too.
+        let dir = temp_dir("raced-recovery");
+        fs::write(dir.join("halley.lock"), "999999999").unwrap();
+        let _ = fs::remove_file(dir.join("halley.lock"));
+        fs::write(dir.join("halley.lock"), std::process::id().to_string()).unwrap();
+        let err = LockGuard::acquire(&dir).unwrap_err();
+        assert!(matches!(err, HalleyError::Locked(_)));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,3 +1,1463 @@
-fn main() {
-    println!("Hello, world!");
+mod cancel;
+mod cli;
+mod clock;
+mod cold_storage;
+mod config;
+mod daemon;
+mod debug_dump;
+mod digest;
+mod engine;
+mod error;
+mod excludes;
+mod globset;
+mod healthcheck;
+mod janitor;
+mod lock;
+mod mounts;
+mod notify;
+mod report;
+mod restic;
+mod s3;
+mod scheduler;
+mod secret;
+mod state;
+mod util;
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+
+use cli::{Cli, Command, S3Command, StateCommand};
+use cold_storage::ColdStorageBackend;
+use config::{Config, RepoConfig};
+use error::HalleyError;
+use restic::RealCall;
+use state::{StateFile, VerifyMethod};
+
+/// Exit code for a run aborted because the state directory can't be
+/// durably written to (sysexits.h `EX_IOERR`). Distinct from the generic
+/// failure code so scripts wrapping Halley can tell "this run found a
+/// problem" from "this host can't run Halley at all" apart.
+const EXIT_STATE_DIR_UNWRITABLE: u8 = 74;
+
+/// Exit code for a run aborted because another Halley instance already
+/// holds the lock (sysexits.h `EX_TEMPFAIL`) -- worth a cron alert of its
+/// own, distinct from a run that started and found a real problem.
+const EXIT_ALREADY_RUNNING: u8 = 75;
+
+/// Builds a [`RealCall`] for `repo`, with its configured password source
+/// applied as the matching `RESTIC_PASSWORD*` environment variable.
+fn build_call(config: &Config, repo: &RepoConfig) -> Result<RealCall, String> {
+    let password_env = repo
+        .password_env()
+        .map_err(|e| format!("repo '{}': {e}", repo.name))?;
+    let Some(password_env) = password_env else {
+        return Err(format!(
+            "repo '{}': exactly one of password, password_file, password_command, or password_source must be set",
+            repo.name
+        ));
+    };
+    Ok(RealCall {
+        binary: config.restic_binary().to_string(),
+        env: vec![password_env],
+        timeout: config.command_timeout(),
+        extra_env_passthrough: repo.extra_env_passthrough.clone(),
+    })
+}
+
+fn default_state_path() -> PathBuf {
+    PathBuf::from("/var/lib/halley/state.json")
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if cli.verbose {
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .with_ansi(false)
+            .with_max_level(tracing::Level::INFO)
+            .init();
+    }
+    let config = match Config::load(&cli.config) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("failed to load config '{}': {e}", cli.config);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let state_dir = default_state_path()
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("."));
+    if let Err(e) = state::ensure_state_dir_writable(&state_dir) {
+        eprintln!("refusing to start: {e}");
+        return ExitCode::from(EXIT_STATE_DIR_UNWRITABLE);
+    }
+    let _lock = match lock::LockGuard::acquire(&state_dir) {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(EXIT_ALREADY_RUNNING);
+        }
+    };
+
+    let exit_code = match cli.command {
+        Command::Backup { repo, json, dry } if dry => run_backup_dry_command(&config, repo.as_deref(), json),
+        Command::Backup { repo, json, .. } => run_backup_command(&config, repo.as_deref(), json),
+        Command::Check { json } => run_check_command(&config, json),
+        Command::Verify {
+            repo,
+            read_data,
+            sample_restore,
+            dry,
+        } => run_verify_command(&config, repo.as_deref(), read_data, sample_restore, dry),
+        Command::Report => run_report_command(&config),
+        Command::Stats { repo } => run_stats_command(&config, &repo),
+        Command::Migrate { repo } => run_migrate_command(&config, &repo),
+        Command::State {
+            command: StateCommand::Show { repo },
+        } => run_state_show_command(repo.as_deref()),
+        Command::State {
+            command: StateCommand::Adopt { from, to },
+        } => run_state_adopt_command(&from, &to),
+        Command::State {
+            command: StateCommand::Clean { dry },
+        } => run_state_clean_command(&config, dry),
+        Command::State {
+            command: StateCommand::Fsck { repair },
+        } => run_state_fsck_command(&config, repair),
+        Command::DebugDump { output } => run_debug_dump_command(&config, &output),
+        Command::S3 {
+            command: S3Command::Clone {
+                repo,
+                to_bucket,
+                prefix,
+            },
+        } => run_s3_clone_command(&config, &repo, &to_bucket, prefix.as_deref()),
+        Command::S3 {
+            command: S3Command::Archive { repo },
+        } => run_s3_archive_command(&config, &repo),
+        Command::Validate { repo, online, show_effective } => {
+            run_validate_command(&config, repo.as_deref(), online, show_effective)
+        }
+        Command::Forget { repo, confirm } => run_forget_command(&config, &repo, confirm),
+    };
+    run_janitor_quietly(&config);
+    exit_code
+}
+
+/// Whether a restic failure should be treated as a clean no-op rather than
+/// a hard failure, per [`config::MissingResticPolicy`]. Only
+/// [`HalleyError::ResticNotAvailable`] is ever skippable this way -- a
+/// policy of `skip-backends` exists for a host that was never going to run
+/// backups in the first place, not to paper over a real restic failure
+/// (wrong password, corrupt repo, ...).
+fn restic_unavailable_is_skippable(config: &Config, error: &HalleyError) -> bool {
+    matches!(error, HalleyError::ResticNotAvailable { .. }) && config.missing_restic == config::MissingResticPolicy::SkipBackends
+}
+
+/// Checks the installed restic is recent enough before doing anything with
+/// it, so a mismatch fails with a clear message instead of a confusing
+/// mid-run flag-parsing error. `Ok(None)` means restic isn't installed at
+/// all and `missing_restic = "skip-backends"` lets the caller treat that as
+/// a clean no-op instead of a failure (see
+/// [`restic_unavailable_is_skippable`]).
+fn check_restic_version(config: &Config, call: &RealCall) -> Result<Option<restic::Version>, ExitCode> {
+    match restic::ensure_supported_version(call) {
+        Ok(version) => Ok(Some(version)),
+        Err(e) if restic_unavailable_is_skippable(config, &e) => {
+            eprintln!("restic not installed on this host; skipping (missing_restic = \"skip-backends\")");
+            Ok(None)
+        }
+        Err(e) => {
+            eprintln!("restic version check failed: {e}");
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Runs one repo's full backup cycle (see [`engine::run_backup_cycle`]),
+/// building the [`RealCall`], memory limit and cold storage backend it
+/// needs along the way. Shared by [`run_backup_command`]'s single-repo and
+/// backup-everything-due paths. A missing restic binary under
+/// `missing_restic = "skip-backends"` (see
+/// [`restic_unavailable_is_skippable`]) is reported as a clean no-op rather
+/// than a failure.
+fn backup_one_repo(
+    config: &Config,
+    repo_config: &RepoConfig,
+    repo_state: &mut state::RepoState,
+    cancel: Option<&cancel::CancellationToken>,
+) -> Result<(), String> {
+    let call = build_call(config, repo_config)?;
+    let restic_version = match restic::ensure_supported_version(&call) {
+        Ok(version) => version,
+        Err(e) if restic_unavailable_is_skippable(config, &e) => {
+            eprintln!(
+                "repo '{}': restic not installed on this host; skipping (missing_restic = \"skip-backends\")",
+                repo_config.name
+            );
+            return Ok(());
+        }
+        Err(e) => return Err(format!("repo '{}': restic version check failed: {e}", repo_config.name)),
+    };
+
+    let go_version = restic::version::go_version(&call).ok().flatten();
+    let memory_limit = repo_config
+        .restic_memory_limit_mb
+        .map(|limit_mb| restic::MemoryLimit::for_budget(limit_mb, go_version));
+    let cold_storage = repo_config.cold_storage_backend();
+
+    engine::run_backup_cycle(
+        &call,
+        repo_config,
+        repo_state,
+        config.snapshot_tag(),
+        memory_limit.as_ref(),
+        config.cache_dir.as_deref(),
+        cold_storage.as_ref().map(|backend| backend as &dyn ColdStorageBackend),
+        Some(restic_version),
+        cancel,
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Finishes any repo's deferred re-archive (see
+/// [`RepoConfig::archive_delay_hours`]) that isn't already covered by this
+/// invocation's own [`backup_one_repo`] calls (`already_processed`) --
+/// [`engine::run_backup_cycle`] resolves those on its way in via
+/// [`engine::resume_pending_cold_storage_action`]. Without this, a repo left
+/// thawed after a past backup would only get re-archived once it next comes
+/// due for a new backup, potentially long after `archive_delay_hours` has
+/// passed and well past the point of costing money for nothing -- so this
+/// runs on every `halley backup` invocation, including one where
+/// `due_repos` found nothing to back up.
+fn process_deferred_freezes(config: &Config, state_file: &mut StateFile, already_processed: &[String]) {
+    for repo_config in &config.repos {
+        if already_processed.iter().any(|name| name == &repo_config.name) {
+            continue;
+        }
+        let Some(repo_state) = state_file.repos.get_mut(&repo_config.name) else {
+            continue;
+        };
+        if repo_state.pending_action != Some(state::PendingAction::Freeze) {
+            continue;
+        }
+        let call = match build_call(config, repo_config) {
+            Ok(call) => call,
+            Err(e) => {
+                eprintln!("repo '{}': deferred freeze skipped: {e}", repo_config.name);
+                continue;
+            }
+        };
+        let cold_storage = repo_config.cold_storage_backend();
+        if let Err(e) = engine::resume_pending_cold_storage_action(
+            &call,
+            repo_config,
+            repo_state,
+            cold_storage.as_ref().map(|backend| backend as &dyn ColdStorageBackend),
+        ) {
+            eprintln!("repo '{}': deferred freeze failed: {e}", repo_config.name);
+        }
+    }
+}
+
+/// Announces which repo is about to run, printed to stderr right before
+/// [`backup_one_repo`] in a multi-repo `halley backup` so any diagnostic
+/// output that repo's cycle produces (restic warnings, hook failures) can be
+/// attributed to it without waiting for the final [`format_run_outcome_line`]
+/// summary. This eprintln! is a plain line, not a tracing span nested
+/// events inherit -- it's always on, while `backup_cycle`'s spans only
+/// reach anywhere with `--verbose` -- so the repo id is threaded through
+/// explicitly wherever a message needs it, same as
+/// [`RepoConfig::validate`]'s warnings do.
+fn format_repo_starting_line(repo_id: &str) -> String {
+    format!("repo '{repo_id}': starting")
+}
+
+/// Renders one [`engine::RunOutcome`] as a single human-readable summary
+/// line: which repo ran, its snapshot id, bytes added, duration, and
+/// whether its cold-storage transition (if any) fully settled. Printed once
+/// per repo instead of the aggregate `--json` report when `--json` isn't
+/// passed.
+fn format_run_outcome_line(outcome: &engine::RunOutcome) -> String {
+    match &outcome.result {
+        Ok(()) => {
+            let snapshot = outcome.snapshot_id.as_deref().unwrap_or("none");
+            let bytes_added = outcome
+                .bytes_added
+                .map(util::human_bytes)
+                .unwrap_or_else(|| "n/a".to_string());
+            let duration = util::human_duration(Duration::from_secs(outcome.duration_secs));
+            let cold_storage = if outcome.cold_storage_settled {
+                "settled"
+            } else {
+                "NOT settled, resumes next run"
+            };
+            let warnings = if outcome.status == engine::RunStatus::Partial {
+                format!(", PARTIAL: {} restic warnings", outcome.warnings.total())
+            } else if outcome.warnings.total() > 0 {
+                format!(", {} restic warnings", outcome.warnings.total())
+            } else {
+                String::new()
+            };
+            let throughput = match outcome.average_throughput_bytes_per_sec {
+                Some(average) => {
+                    let peak = outcome
+                        .peak_throughput_bytes_per_sec
+                        .map(|peak| format!(", peak {}/s", util::human_bytes(peak)))
+                        .unwrap_or_default();
+                    format!(", avg {}/s{peak}", util::human_bytes(average))
+                }
+                None => String::new(),
+            };
+            format!(
+                "backed up '{}': snapshot {snapshot}, +{bytes_added} added, {duration}, cold storage {cold_storage}{warnings}{throughput}",
+                outcome.repo
+            )
+        }
+        Err(e) => format!("backup of '{}' failed: {e}", outcome.repo),
+    }
+}
+
+/// Prints `report`: one [`format_run_outcome_line`] per repo followed by
+/// the aggregate success count, or, when `json` is set, the whole report
+/// serialized for machine consumption instead.
+fn print_run_report(report: &engine::RunReport, json: bool) {
+    if json {
+        match serde_json::to_string(report) {
+            Ok(rendered) => println!("{rendered}"),
+            Err(e) => eprintln!("failed to serialize run report: {e}"),
+        }
+        return;
+    }
+    for outcome in &report.outcomes {
+        println!("{}", format_run_outcome_line(outcome));
+    }
+    println!("{}/{} repos backed up successfully", report.succeeded(), report.outcomes.len());
+}
+
+/// Runs `halley backup`, either against one named repo or, when `repo` is
+/// omitted, every repo that's due: failed or past-max-interval repos first,
+/// then oldest last-backup first (see [`engine::due_repos`]). Persists the statefile whether the run succeeded
+/// or failed, so a failed run leaves a visible trace instead of silently
+/// keeping whatever `last_backup` it had before this run started. Prints a
+/// [`engine::RunReport`] summarizing what happened, human-readable or, with
+/// `json`, as JSON (see [`print_run_report`]).
+///
+/// Installs a [`cancel::CancellationToken`] for the run so a SIGINT/SIGTERM
+/// (Ctrl-C, `systemctl stop`) is noticed at the next phase boundary inside
+/// [`backup_one_repo`] instead of killing the process wherever it happens to
+/// be; the multi-repo path also stops before starting the next due repo
+/// once cancelled. A failure to install the handler is reported and the run
+/// proceeds without one -- the same as before this existed -- rather than
+/// refusing to back anything up over a best-effort safety feature.
+fn run_backup_command(config: &Config, repo: Option<&str>, json: bool) -> ExitCode {
+    let state_path = default_state_path();
+    if let Some(state_dir) = state_path.parent() {
+        if let Err(e) = state::ensure_state_dir_writable(state_dir) {
+            eprintln!("refusing to start: {e}");
+            return ExitCode::from(EXIT_STATE_DIR_UNWRITABLE);
+        }
+    }
+    let _state_lock = match state_path.parent().map(StateFile::lock) {
+        Some(Ok(guard)) => Some(guard),
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+    let mut state_file = match StateFile::load(&state_path) {
+        Ok(state_file) => state_file,
+        Err(e) => {
+            eprintln!("failed to load statefile: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    ping_healthcheck_start(config);
+    let started = Instant::now();
+    let cancel = match cancel::CancellationToken::install() {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("failed to install signal handler for graceful shutdown: {e}");
+            cancel::CancellationToken::new()
+        }
+    };
+
+    match repo {
+        Some(repo) => {
+            let Some(repo_config) = config.repo(repo) else {
+                eprintln!("no repo named '{repo}' in config");
+                return ExitCode::FAILURE;
+            };
+            let repo_state = state_file.repos.entry(repo.to_string()).or_default();
+            let result = backup_one_repo(config, repo_config, repo_state, Some(&cancel));
+            let outcome = engine::RunOutcome::from_repo_state(repo, result, repo_state, repo_config.warning_threshold);
+            let report = engine::RunReport { outcomes: vec![outcome] };
+            print_run_report(&report, json);
+            deliver_run_report_notification(config, &state_path, &report);
+            ping_healthcheck_finish(config, &report, false, started.elapsed());
+            process_deferred_freezes(config, &mut state_file, &[repo.to_string()]);
+
+            if let Err(e) = state_file.save(&state_path) {
+                eprintln!("failed to persist statefile: {e}");
+                return ExitCode::FAILURE;
+            }
+            if report.all_succeeded() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        None => {
+            let order = engine::due_repos(
+                &config.repos,
+                &state_file.repos,
+                config.default_min_backup_interval_hours,
+                config.default_max_backup_interval_days,
+                config.failure_backoff_base_hours(),
+                config.failure_backoff_max_hours(),
+            );
+            let nothing_to_do = order.is_empty();
+            let mut report = engine::RunReport::default();
+            for name in &order {
+                if cancel.is_cancelled() {
+                    eprintln!("run interrupted; stopping before starting '{name}'");
+                    break;
+                }
+                let Some(repo_config) = config.repo(name) else {
+                    continue;
+                };
+                eprintln!("{}", format_repo_starting_line(name));
+                let repo_state = state_file.repos.entry(name.clone()).or_default();
+                let result = backup_one_repo(config, repo_config, repo_state, Some(&cancel));
+                report.outcomes.push(engine::RunOutcome::from_repo_state(
+                    name.clone(),
+                    result,
+                    repo_state,
+                    repo_config.warning_threshold,
+                ));
+            }
+            print_run_report(&report, json);
+            deliver_run_report_notification(config, &state_path, &report);
+            ping_healthcheck_finish(config, &report, nothing_to_do, started.elapsed());
+            process_deferred_freezes(config, &mut state_file, &order);
+
+            if let Err(e) = state_file.save(&state_path) {
+                eprintln!("failed to persist statefile: {e}");
+                return ExitCode::FAILURE;
+            }
+            if report.all_succeeded() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+/// Builds the same [`RealCall`], memory limit and cold storage backend
+/// [`backup_one_repo`] would, then previews the cycle instead of running it
+/// (see [`engine::dry_run_backup_cycle`]). Read-only: no statefile write, no
+/// healthcheck ping, no run-report notification.
+fn dry_run_one_repo(config: &Config, repo_config: &RepoConfig) -> Result<engine::DryRunPlan, String> {
+    let call = build_call(config, repo_config)?;
+    let go_version = restic::version::go_version(&call).ok().flatten();
+    let memory_limit = repo_config
+        .restic_memory_limit_mb
+        .map(|limit_mb| restic::MemoryLimit::for_budget(limit_mb, go_version));
+    let cold_storage = repo_config.cold_storage_backend();
+
+    engine::dry_run_backup_cycle(
+        &call,
+        repo_config,
+        config.snapshot_tag(),
+        memory_limit.as_ref(),
+        config.cache_dir.as_deref(),
+        cold_storage.as_ref().map(|backend| backend as &dyn ColdStorageBackend),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Renders one repo's [`engine::DryRunPlan`], or the error that kept it from
+/// being previewed (e.g. [`error::HalleyError::ColdStorageDryRunUnsupported`]),
+/// as a single human-readable line.
+fn format_dry_run_line(repo: &str, plan: &Result<engine::DryRunPlan, String>) -> String {
+    match plan {
+        Ok(plan) => {
+            let bytes = util::human_bytes(plan.total_bytes_processed);
+            let forget = if plan.snapshots_would_forget.is_empty() {
+                "nothing".to_string()
+            } else {
+                format!("{} snapshot(s)", plan.snapshots_would_forget.len())
+            };
+            let prune = if plan.would_prune { ", would prune" } else { "" };
+            format!(
+                "'{repo}': would add {} new file(s), {} changed file(s), {bytes} processed; would forget {forget}{prune}",
+                plan.files_new, plan.files_changed
+            )
+        }
+        Err(e) => format!("'{repo}': dry run failed: {e}"),
+    }
+}
+
+/// Runs `halley backup --dry`: previews one named repo's backup cycle, or
+/// every configured repo when `repo` is omitted, instead of running it.
+/// Entirely read-only -- no statefile read or write, no healthcheck ping, no
+/// notification -- so it previews every repo rather than filtering by
+/// [`engine::due_repos`], which needs the statefile to decide.
+fn run_backup_dry_command(config: &Config, repo: Option<&str>, json: bool) -> ExitCode {
+    let repo_configs: Vec<&RepoConfig> = match repo {
+        Some(repo) => match config.repo(repo) {
+            Some(repo_config) => vec![repo_config],
+            None => {
+                eprintln!("no repo named '{repo}' in config");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => config.repos.iter().collect(),
+    };
+
+    let entries: Vec<engine::DryRunReportEntry> = repo_configs
+        .into_iter()
+        .map(|repo_config| engine::DryRunReportEntry {
+            repo: repo_config.name.clone(),
+            plan: dry_run_one_repo(config, repo_config),
+        })
+        .collect();
+
+    if json {
+        match serde_json::to_string(&entries) {
+            Ok(rendered) => println!("{rendered}"),
+            Err(e) => eprintln!("failed to serialize dry run report: {e}"),
+        }
+    } else {
+        for entry in &entries {
+            println!("{}", format_dry_run_line(&entry.repo, &entry.plan));
+        }
+    }
+
+    if entries.iter().all(|entry| entry.plan.is_ok()) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Pings [`Config::healthcheck`]'s command marking the start of a
+/// `halley backup` run, if configured. A no-op otherwise.
+fn ping_healthcheck_start(config: &Config) {
+    let Some(healthcheck_config) = &config.healthcheck else {
+        return;
+    };
+    let pinger = healthcheck::CommandHealthcheckPinger {
+        command: healthcheck_config.command.clone(),
+    };
+    healthcheck::ping_quietly(&pinger, healthcheck::Phase::Start, None);
+}
+
+/// Pings [`Config::healthcheck`]'s command marking the end of a
+/// `halley backup` run, if configured: [`healthcheck::Phase::Success`] if
+/// every repo in `report` succeeded, [`healthcheck::Phase::Fail`]
+/// otherwise. `nothing_to_do` (only meaningful when `report` has no
+/// outcomes at all) is gated on
+/// [`config::HealthcheckConfig::ping_on_nothing_to_do`].
+fn ping_healthcheck_finish(config: &Config, report: &engine::RunReport, nothing_to_do: bool, elapsed: Duration) {
+    let Some(healthcheck_config) = &config.healthcheck else {
+        return;
+    };
+    if nothing_to_do && !healthcheck_config.ping_on_nothing_to_do {
+        return;
+    }
+    let phase = if report.all_succeeded() {
+        healthcheck::Phase::Success
+    } else {
+        healthcheck::Phase::Fail
+    };
+    let pinger = healthcheck::CommandHealthcheckPinger {
+        command: healthcheck_config.command.clone(),
+    };
+    healthcheck::ping_quietly(&pinger, phase, Some(elapsed));
+}
+
+/// Queues and immediately attempts delivery of `report` through
+/// [`Config::notify`], if configured, gated on [`config::NotifyOn`]. Never
+/// affects the caller's exit code -- a failed or misconfigured notification
+/// is worth a warning on stderr, not a reason to report the backup itself
+/// as failed. See [`notify::NotificationQueue`] for how a delivery failure
+/// here gets retried on the next run.
+fn deliver_run_report_notification(config: &Config, state_path: &Path, report: &engine::RunReport) {
+    let Some(notify_config) = &config.notify else {
+        return;
+    };
+    let should_notify = match notify_config.notify_on {
+        config::NotifyOn::Always => true,
+        config::NotifyOn::Failure => !report.all_succeeded(),
+    };
+    if !should_notify {
+        return;
+    }
+    let body = match serde_json::to_string(report) {
+        Ok(body) => body,
+        Err(e) => {
+            eprintln!("failed to serialize run report for notification: {e}");
+            return;
+        }
+    };
+    let Some(state_dir) = state_path.parent() else {
+        return;
+    };
+    let queue = notify::NotificationQueue::new(state_dir);
+    if let Err(e) = queue.enqueue("run-report", body) {
+        eprintln!("failed to queue run report notification: {e}");
+        return;
+    }
+    let sender = notify::CommandNotificationSender {
+        command: notify_config.command.clone(),
+    };
+    if let Err(e) = queue.flush(&sender) {
+        eprintln!("failed to deliver queued notifications: {e}");
+    }
+}
+
+fn run_stats_command(config: &Config, repo: &str) -> ExitCode {
+    let Some(repo_config) = config.repo(repo) else {
+        eprintln!("no repo named '{repo}' in config");
+        return ExitCode::FAILURE;
+    };
+    let call = match build_call(config, repo_config) {
+        Ok(call) => call,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match check_restic_version(config, &call) {
+        Ok(Some(_)) => {}
+        Ok(None) => return ExitCode::SUCCESS,
+        Err(code) => return code,
+    }
+    match restic::stats(&call) {
+        Ok(stats) => {
+            println!(
+                "{repo}: {} files, {} across {} snapshot(s)",
+                stats.total_file_count,
+                util::human_bytes(stats.total_size),
+                stats.snapshots_count
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to get stats for '{repo}': {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_migrate_command(config: &Config, repo: &str) -> ExitCode {
+    let Some(repo_config) = config.repo(repo) else {
+        eprintln!("no repo named '{repo}' in config");
+        return ExitCode::FAILURE;
+    };
+    let call = match build_call(config, repo_config) {
+        Ok(call) => call,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match check_restic_version(config, &call) {
+        Ok(Some(_)) => {}
+        Ok(None) => return ExitCode::SUCCESS,
+        Err(code) => return code,
+    }
+    match restic::migrate(&call, restic::Migration::UpgradeRepoV2) {
+        Ok(()) => {
+            println!("migrated '{repo}' to repository format v2");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to migrate '{repo}': {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs `halley report`: loads the statefile and prints a Markdown table
+/// of every configured repo's current state (see
+/// [`report::render_markdown`]).
+fn run_report_command(config: &Config) -> ExitCode {
+    let state_path = default_state_path();
+    let state_file = match StateFile::load(&state_path) {
+        Ok(state_file) => state_file,
+        Err(e) => {
+            eprintln!("failed to load statefile: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    print!("{}", report::render_markdown(&config.repos, &state_file));
+    ExitCode::SUCCESS
+}
+
+/// Runs `halley check`: reports which repos [`engine::check_due`] would
+/// pick up right now and why, without running a backup -- no restic/S3
+/// call, and the statefile is only read, never written. A missing
+/// statefile is treated as "no repo has ever been backed up" rather than an
+/// error, since there's nothing to fail on.
+fn run_check_command(config: &Config, json: bool) -> ExitCode {
+    let state_file = match StateFile::load(default_state_path()) {
+        Ok(state_file) => state_file,
+        Err(e) => {
+            eprintln!("failed to load statefile: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let statuses = engine::check_due(
+        &config.repos,
+        &state_file.repos,
+        config.default_min_backup_interval_hours,
+        config.default_max_backup_interval_days,
+        config.failure_backoff_base_hours(),
+        config.failure_backoff_max_hours(),
+    );
+    if json {
+        match serde_json::to_string(&statuses) {
+            Ok(rendered) => println!("{rendered}"),
+            Err(e) => eprintln!("failed to serialize check report: {e}"),
+        }
+    } else {
+        for status in &statuses {
+            let due = if status.due { "due" } else { "not due" };
+            println!("{}: {due} ({})", status.repo, status.reason);
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Runs `halley validate`, either against one named repo or, when `repo` is
+/// omitted, every repo in the config (see [`cli::Command::Validate`]).
+/// Doesn't touch the statefile or run anything against a repo itself --
+/// [`RepoConfig::validate`] and, with `online` set,
+/// [`RepoConfig::validate_online`] are pure checks against config already in
+/// memory.
+fn run_validate_command(config: &Config, repo: Option<&str>, online: bool, show_effective: bool) -> ExitCode {
+    let repos: Vec<&RepoConfig> = match repo {
+        Some(repo) => match config.repo(repo) {
+            Some(repo_config) => vec![repo_config],
+            None => {
+                eprintln!("no repo named '{repo}' in config");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => config.repos.iter().collect(),
+    };
+
+    let mut any_warnings = false;
+    for repo_config in repos {
+        let mut warnings = repo_config.validate(
+            config.cache_dir.as_deref(),
+            config.default_min_backup_interval_hours,
+            config.default_max_backup_interval_days,
+        );
+        if online {
+            warnings.extend(repo_config.validate_online());
+        }
+        if warnings.is_empty() {
+            println!("'{}': ok", repo_config.name);
+        } else {
+            any_warnings = true;
+            for warning in &warnings {
+                println!("{warning}");
+            }
+        }
+        if show_effective {
+            match excludes::expand(&repo_config.excludes) {
+                Ok(effective) => println!("'{}': effective excludes: {effective:?}", repo_config.name),
+                Err(e) => println!("'{}': effective excludes: <{e}>", repo_config.name),
+            }
+        }
+    }
+
+    if any_warnings {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn run_state_show_command(repo: Option<&str>) -> ExitCode {
+    let state_path = default_state_path();
+    let state_file = match StateFile::load(&state_path) {
+        Ok(state_file) => state_file,
+        Err(e) => {
+            eprintln!("failed to load statefile: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let entries: Vec<_> = match repo {
+        Some(name) => match state_file.repos.get(name) {
+            Some(state) => vec![(name.to_string(), state)],
+            None => {
+                eprintln!("no recorded state for '{name}'");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => state_file
+            .repos
+            .iter()
+            .map(|(name, state)| (name.clone(), state))
+            .collect(),
+    };
+
+    for (name, state) in entries {
+        match &state.last_verified {
+            Some(record) => println!(
+                "{name}: last verified at {} via {:?}",
+                record.at, record.method
+            ),
+            None => println!("{name}: never verified"),
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Moves a statefile entry from `from` to `to`, e.g. after renaming a repo
+/// in config, so its backup/verify history isn't orphaned under the old
+/// name. Refuses to overwrite state already recorded under `to`.
+fn run_state_adopt_command(from: &str, to: &str) -> ExitCode {
+    let state_path = default_state_path();
+    let _state_lock = match state_path.parent().map(StateFile::lock) {
+        Some(Ok(guard)) => Some(guard),
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+    let mut state_file = match StateFile::load(&state_path) {
+        Ok(state_file) => state_file,
+        Err(e) => {
+            eprintln!("failed to load statefile: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if !state_file.adopt(from, to) {
+        eprintln!(
+            "could not adopt '{from}' as '{to}': either '{from}' has no recorded state, or '{to}' already does"
+        );
+        return ExitCode::FAILURE;
+    }
+
+    match state_file.save(&state_path) {
+        Ok(()) => {
+            println!("moved state from '{from}' to '{to}'");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to save statefile: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Reports (and, with `repair`, fixes) statefile problems [`state::fsck`]
+/// finds against `config`'s current repos. A repair backs up the original
+/// statefile to `<path>.bak` first, since the fixes it applies (resetting
+/// timestamps, dropping a corrupt fingerprint or archival flag) are safe
+/// individually but not something to apply without a way back if they turn
+/// out to be wrong for a particular entry.
+fn run_state_fsck_command(config: &Config, repair: bool) -> ExitCode {
+    let state_path = default_state_path();
+    let _state_lock = match state_path.parent().map(StateFile::lock) {
+        Some(Ok(guard)) => Some(guard),
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+    let mut state_file = match StateFile::load(&state_path) {
+        Ok(state_file) => state_file,
+        Err(e) => {
+            eprintln!("failed to load statefile: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let issues = state::fsck(&state_file, &config.repos);
+    if issues.is_empty() {
+        println!("no problems found");
+        return ExitCode::SUCCESS;
+    }
+    for issue in &issues {
+        println!("{}: {} ({})", issue.repo, issue.problem, issue.field);
+    }
+
+    if !repair {
+        return ExitCode::FAILURE;
+    }
+
+    let backup_path = PathBuf::from(format!("{}.bak", state_path.display()));
+    if let Err(e) = std::fs::copy(&state_path, &backup_path) {
+        eprintln!(
+            "refusing to repair: could not back up statefile to '{}': {e}",
+            backup_path.display()
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let repaired = state::repair(&mut state_file, &issues);
+    match state_file.save(&state_path) {
+        Ok(()) => {
+            println!(
+                "repaired {repaired} problem(s); original backed up to {}",
+                backup_path.display()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to save repaired statefile: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Builds every artifact class [`janitor`] should manage under `state_dir`,
+/// with caps sourced from `config`.
+fn janitor_classes(config: &Config, state_dir: &std::path::Path) -> Vec<janitor::ArtifactClass> {
+    vec![janitor::ArtifactClass {
+        name: "notifications".to_string(),
+        dir: state_dir.join("notifications"),
+        max_total_bytes: config.janitor.notifications_max_bytes,
+        max_age: None,
+    }]
+}
+
+/// Runs the janitor at the end of every invocation, whatever command ran.
+/// Never fails the run over it: a housekeeping step going wrong shouldn't
+/// turn an otherwise-successful backup into a failed one, so problems are
+/// only logged.
+fn run_janitor_quietly(config: &Config) {
+    let Some(state_dir) = default_state_path().parent().map(|p| p.to_path_buf()) else {
+        return;
+    };
+    let classes = janitor_classes(config, &state_dir);
+    match janitor::clean(&classes, std::time::SystemTime::now(), false) {
+        Ok(removed) => {
+            for artifact in removed {
+                eprintln!(
+                    "janitor: removed {} ({} bytes, class '{}')",
+                    artifact.path.display(),
+                    artifact.bytes,
+                    artifact.class
+                );
+            }
+        }
+        Err(e) => eprintln!("janitor: cleanup failed: {e}"),
+    }
+}
+
+/// Enforces the janitor's caps immediately, printing what it removed (or,
+/// with `--dry`, what it would remove) instead of waiting for the next
+/// invocation's automatic run.
+fn run_state_clean_command(config: &Config, dry: bool) -> ExitCode {
+    let Some(state_dir) = default_state_path().parent().map(|p| p.to_path_buf()) else {
+        eprintln!("could not determine the state directory");
+        return ExitCode::FAILURE;
+    };
+    let classes = janitor_classes(config, &state_dir);
+    match janitor::clean(&classes, std::time::SystemTime::now(), dry) {
+        Ok(removed) if removed.is_empty() => {
+            println!("nothing to clean");
+            ExitCode::SUCCESS
+        }
+        Ok(removed) => {
+            let verb = if dry { "would remove" } else { "removed" };
+            for artifact in removed {
+                println!(
+                    "{verb} {} ({} bytes, class '{}')",
+                    artifact.path.display(),
+                    artifact.bytes,
+                    artifact.class
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("cleanup failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Gathers a redacted config/state/restic-probe bundle for bug reports. No
+/// repository password is needed for `restic version`, so this uses a
+/// [`RealCall`] with no environment rather than [`build_call`], which would
+/// otherwise fail on repos with no password source configured yet.
+fn run_debug_dump_command(config: &Config, output: &std::path::Path) -> ExitCode {
+    let call = RealCall {
+        binary: config.restic_binary().to_string(),
+        env: Vec::new(),
+        timeout: config.command_timeout(),
+        extra_env_passthrough: Vec::new(),
+    };
+
+    let state_path = default_state_path();
+    let state_file = match StateFile::load(&state_path) {
+        Ok(state_file) => state_file,
+        Err(e) => {
+            eprintln!("failed to load statefile: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let entries = debug_dump::build_dump(&call, config, &state_file);
+    match debug_dump::write_dump(&entries, output) {
+        Ok(()) => {
+            println!("wrote debug dump to {}", output.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to write debug dump: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Prints a scratch repo definition for cloning `repo`'s objects into
+/// `to_bucket`, e.g. for a disaster-recovery drill (see
+/// [`s3::generate_clone_repo_config`]). Refuses repos with no `s3` config,
+/// since there's no source bucket to clone from otherwise. Only emits the
+/// config: the object copy itself isn't wired up yet, since Halley has no
+/// S3 client to drive it with.
+fn run_s3_clone_command(
+    config: &Config,
+    repo: &str,
+    to_bucket: &str,
+    prefix: Option<&str>,
+) -> ExitCode {
+    let Some(repo_config) = config.repo(repo) else {
+        eprintln!("no repo named '{repo}' in config");
+        return ExitCode::FAILURE;
+    };
+    let Some(s3) = &repo_config.s3 else {
+        eprintln!("repo '{repo}' has no [repos.s3] configured, so there's no bucket to clone from");
+        return ExitCode::FAILURE;
+    };
+    let request = s3::CloneRequest {
+        source_bucket: s3.bucket.clone(),
+        target_bucket: to_bucket.to_string(),
+        prefix: prefix.map(str::to_string),
+    };
+    let new_name = format!("{}-dr-clone", repo_config.name);
+    let toml = s3::generate_clone_repo_config(repo_config, &request, &new_name);
+    println!("{toml}");
+    eprintln!(
+        "note: this only generates the repo definition -- copy '{}' into '{to_bucket}' yourself \
+         (e.g. with `aws s3 sync`) before this repo will have anything to restore from",
+        s3.bucket
+    );
+    ExitCode::SUCCESS
+}
+
+/// Archives `repo`'s cold storage now (see [`engine::force_archive`]),
+/// bypassing any `archive_delay_hours` due-time still in the future.
+/// Refuses repos with no cold storage backend configured, since there's
+/// nothing to archive.
+fn run_s3_archive_command(config: &Config, repo: &str) -> ExitCode {
+    let Some(repo_config) = config.repo(repo) else {
+        eprintln!("no repo named '{repo}' in config");
+        return ExitCode::FAILURE;
+    };
+    let Some(cold_storage) = repo_config.cold_storage_backend() else {
+        eprintln!("repo '{repo}' has no cold storage backend configured, so there's nothing to archive");
+        return ExitCode::FAILURE;
+    };
+    let call = match build_call(config, repo_config) {
+        Ok(call) => call,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let state_path = default_state_path();
+    let _state_lock = match state_path.parent().map(StateFile::lock) {
+        Some(Ok(guard)) => Some(guard),
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+    let mut state_file = match StateFile::load(&state_path) {
+        Ok(state_file) => state_file,
+        Err(e) => {
+            eprintln!("failed to load statefile: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let repo_state = state_file.repos.entry(repo.to_string()).or_default();
+    if let Err(e) = engine::force_archive(
+        &call,
+        repo_config,
+        repo_state,
+        Some(&cold_storage as &dyn ColdStorageBackend),
+    ) {
+        eprintln!("failed to archive '{repo}': {e}");
+        return ExitCode::FAILURE;
+    }
+    if let Err(e) = state_file.save(&state_path) {
+        eprintln!("failed to persist statefile: {e}");
+        return ExitCode::FAILURE;
+    }
+    println!("archived '{repo}'");
+    ExitCode::SUCCESS
+}
+
+/// Runs `restic forget` for `repo` directly, bypassing `max_auto_forget`.
+/// The corresponding backup cycle already ran the same preview and refused
+/// to forget automatically; `--confirm` is required so this can't be run by
+/// accident in place of the gated path.
+fn run_forget_command(config: &Config, repo: &str, confirm: bool) -> ExitCode {
+    if !confirm {
+        eprintln!("refusing to forget '{repo}' without --confirm");
+        return ExitCode::FAILURE;
+    }
+    let Some(repo_config) = config.repo(repo) else {
+        eprintln!("no repo named '{repo}' in config");
+        return ExitCode::FAILURE;
+    };
+    let call = match build_call(config, repo_config) {
+        Ok(call) => call,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match check_restic_version(config, &call) {
+        Ok(Some(_)) => {}
+        Ok(None) => return ExitCode::SUCCESS,
+        Err(code) => return code,
+    }
+    match restic::forget(
+        &call,
+        &repo_config.retention,
+        config.snapshot_tag(),
+        repo_config.hostname.as_deref(),
+    ) {
+        Ok(()) => {
+            println!("forgot old snapshots for '{repo}'");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to forget for '{repo}': {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Verifies one repo read-only via [`engine::verify_repo`], building the
+/// [`RealCall`] and cold storage backend (if configured) it needs along the
+/// way. `Err(_)` covers a repo whose call couldn't even be built, e.g. a
+/// missing password source -- that's still recorded as a failed outcome by
+/// the caller rather than aborting the rest of the run.
+fn verify_one_repo(
+    config: &Config,
+    repo_config: &RepoConfig,
+    repo_state: &mut state::RepoState,
+    method: VerifyMethod,
+    dry: bool,
+) -> Result<engine::RepoVerificationOutcome, String> {
+    let call = build_call(config, repo_config)?;
+    restic::ensure_supported_version(&call).map_err(|e| format!("repo '{}': restic version check failed: {e}", repo_config.name))?;
+    let cold_storage = repo_config.cold_storage_backend();
+    Ok(engine::verify_repo(
+        &call,
+        repo_config,
+        repo_state,
+        method,
+        cold_storage.as_ref().map(|backend| backend as &dyn ColdStorageBackend),
+        dry,
+    ))
+}
+
+/// Prints one repo's verification result, marking a dry run as such so a
+/// string of `--dry` rehearsals in a log can't be mistaken for recorded
+/// verifications.
+fn print_verification_outcome(outcome: &engine::RepoVerificationOutcome) {
+    let dry_suffix = if outcome.dry { " (dry run, not recorded)" } else { "" };
+    match &outcome.check_result {
+        Ok(()) => println!("verified '{}'{dry_suffix}", outcome.repo),
+        Err(e) => eprintln!("verification of '{}' failed: {e}{dry_suffix}", outcome.repo),
+    }
+    if let Some(report) = &outcome.cold_storage_report {
+        println!("  cold storage: {report}");
+    }
+}
+
+/// Runs `halley verify`, either against one named repo or, when `repo` is
+/// omitted, every repo in the config (see [`cli::Command::Verify`]). Purely
+/// read-only beyond the `last_verify` timestamp [`engine::verify_repo`]
+/// already records -- and with `dry` set, not even that: the statefile is
+/// still loaded and saved, but no repo's `last_verified` changes.
+fn run_verify_command(config: &Config, repo: Option<&str>, read_data: Option<u8>, sample_restore: bool, dry: bool) -> ExitCode {
+    let method = match (read_data, sample_restore) {
+        (Some(percent), _) => VerifyMethod::CheckReadData { percent },
+        (None, true) => VerifyMethod::SampleRestore,
+        (None, false) => VerifyMethod::CheckMetadata,
+    };
+
+    let state_path = default_state_path();
+    if let Some(state_dir) = state_path.parent() {
+        if let Err(e) = state::ensure_state_dir_writable(state_dir) {
+            eprintln!("refusing to start: {e}");
+            return ExitCode::from(EXIT_STATE_DIR_UNWRITABLE);
+        }
+    }
+    let _state_lock = match state_path.parent().map(StateFile::lock) {
+        Some(Ok(guard)) => Some(guard),
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+    let mut state_file = match StateFile::load(&state_path) {
+        Ok(state_file) => state_file,
+        Err(e) => {
+            eprintln!("failed to load statefile: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut report = engine::VerificationReport::default();
+    let mut any_build_failure = false;
+    match repo {
+        Some(repo) => {
+            let Some(repo_config) = config.repo(repo) else {
+                eprintln!("no repo named '{repo}' in config");
+                return ExitCode::FAILURE;
+            };
+            let repo_state = state_file.repos.entry(repo.to_string()).or_default();
+            match verify_one_repo(config, repo_config, repo_state, method, dry) {
+                Ok(outcome) => report.outcomes.push(outcome),
+                Err(e) => {
+                    eprintln!("{e}");
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        None => {
+            for repo_config in &config.repos {
+                let repo_state = state_file.repos.entry(repo_config.name.clone()).or_default();
+                match verify_one_repo(config, repo_config, repo_state, method, dry) {
+                    Ok(outcome) => report.outcomes.push(outcome),
+                    Err(e) => {
+                        eprintln!("{e}");
+                        any_build_failure = true;
+                    }
+                }
+            }
+        }
+    }
+
+    for outcome in &report.outcomes {
+        print_verification_outcome(outcome);
+    }
+
+    if let Err(e) = state_file.save(&state_path) {
+        eprintln!("failed to persist statefile: {e}");
+        return ExitCode::FAILURE;
+    }
+    if any_build_failure || !report.all_passed() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_with_missing_binary() -> RealCall {
+        RealCall {
+            binary: "halley-test-binary-that-does-not-exist".to_string(),
+            env: Vec::new(),
+            timeout: None,
+            extra_env_passthrough: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn missing_restic_is_not_skippable_under_the_default_fail_policy() {
+        let config = Config::default();
+        let err = HalleyError::ResticNotAvailable {
+            binary: "restic".into(),
+        };
+        assert!(!restic_unavailable_is_skippable(&config, &err));
+    }
+
+    #[test]
+    fn missing_restic_is_skippable_under_skip_backends_policy() {
+        let config = Config {
+            missing_restic: config::MissingResticPolicy::SkipBackends,
+            ..Default::default()
+        };
+        let err = HalleyError::ResticNotAvailable {
+            binary: "restic".into(),
+        };
+        assert!(restic_unavailable_is_skippable(&config, &err));
+    }
+
+    #[test]
+    fn a_real_restic_failure_is_never_skippable_even_under_skip_backends_policy() {
+        let config = Config {
+            missing_restic: config::MissingResticPolicy::SkipBackends,
+            ..Default::default()
+        };
+        let err = HalleyError::Restic {
+            status: 1,
+            stderr: "wrong password".into(),
+        };
+        assert!(!restic_unavailable_is_skippable(&config, &err));
+    }
+
+    #[test]
+    fn check_restic_version_fails_under_the_default_policy_when_restic_is_missing() {
+        let config = Config::default();
+        let call = call_with_missing_binary();
+        assert!(check_restic_version(&config, &call).is_err());
+    }
+
+    #[test]
+    fn check_restic_version_skips_cleanly_under_skip_backends_policy_when_restic_is_missing() {
+        let config = Config {
+            missing_restic: config::MissingResticPolicy::SkipBackends,
+            ..Default::default()
+        };
+        let call = call_with_missing_binary();
+        assert_eq!(check_restic_version(&config, &call), Ok(None));
+    }
+
+    /// Exercises [`run_backup_command`] end to end against a real `restic`
+    /// binary and a scratch local repository: init, backup, and persisting
+    /// the outcome (including the new snapshot id) into a temp statefile.
+    /// Ignored by default since it needs a real `restic` on `PATH`, unlike
+    /// the rest of the suite, which drives the restic layer through
+    /// [`restic::mock::MockCall`].
+    #[test]
+    #[ignore = "requires a real restic binary on PATH"]
+    fn backup_command_initializes_backs_up_and_records_the_snapshot() {
+        let scratch = std::env::temp_dir().join(format!("halley-backup-command-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&scratch);
+        let repo_dir = scratch.join("repo");
+        let source_dir = scratch.join("source");
+        let state_path = scratch.join("state.json");
+        std::fs::create_dir_all(&source_dir).unwrap();
+        std::fs::write(source_dir.join("file.txt"), b"hello").unwrap();
+
+        let config = Config {
+            restic_binary: None,
+            snapshot_tag: None,
+            cache_dir: None,
+            command_timeout_minutes: None,
+            repos: vec![RepoConfig {
+                name: "scratch".into(),
+                sources: vec![source_dir.clone()],
+                repo: repo_dir.display().to_string(),
+                retention: Default::default(),
+                prune: false,
+                changed_during_backup: Default::default(),
+                max_verify_age_days: None,
+                symlinks: Default::default(),
+                compression: None,
+                no_scan: false,
+                read_concurrency: None,
+                excludes: vec![],
+                exclude_file: None,
+                digest_ignore: Vec::new(),
+                strict_paths: false,
+                tags: vec![],
+                check_before_backup: false,
+                restic_memory_limit_mb: None,
+                auto_init: true,
+                password: Some("test-password".into()),
+                password_file: None,
+                password_command: None,
+                password_source: None,
+                limit_upload: None,
+                limit_download: None,
+                allow_initial_backup: true,
+                first_backup_size_threshold_mb: None,
+                cache_dir: None,
+                no_cache: false,
+                restore_sparse: false,
+                restore_flags: vec![],
+                hostname: None,
+                one_file_system: false,
+                auto_unlock_stale: false,
+                max_auto_forget: None,
+                warning_threshold: None,
+                s3: None,
+                cold_storage_command: None,
+                pre_hook: None,
+                post_hook: None,
+                archive_after_failed_backup: false,
+                archive_delay_hours: None,
+                archive_unverified: false,
+                min_backup_interval_hours: None,
+                max_backup_interval_days: None,
+                extra_env_passthrough: Vec::new(),
+                allow_network_sources: false,
+            }],
+            default_min_backup_interval_hours: None,
+            default_max_backup_interval_days: None,
+            janitor: Default::default(),
+            notify: None,
+            healthcheck: None,
+            failure_backoff_base_hours: None,
+            failure_backoff_max_hours: None,
+            missing_restic: Default::default(),
+        };
+
+        let original_state_path = default_state_path();
+        let _ = original_state_path;
+        // `run_backup_command` always reads/writes the compiled-in default
+        // statefile path, so this test can't isolate it that way; it drives
+        // the same steps directly instead, using a scratch statefile.
+        let repo_config = config.repo("scratch").unwrap();
+        let call = build_call(&config, repo_config).unwrap();
+        let restic_version = check_restic_version(&config, &call).unwrap().unwrap();
+
+        let mut state_file = StateFile::default();
+        let repo_state = state_file.repos.entry("scratch".to_string()).or_default();
+        engine::run_backup_cycle(
+            &call,
+            repo_config,
+            repo_state,
+            config.snapshot_tag(),
+            None,
+            None,
+            None,
+            Some(restic_version),
+            None,
+        )
+        .unwrap();
+        state_file.save(&state_path).unwrap();
+
+        let loaded = StateFile::load(&state_path).unwrap();
+        let record = loaded.repos["scratch"].last_backup.as_ref().unwrap();
+        assert!(matches!(record.outcome, state::BackupOutcome::Success { .. }));
+        assert!(record.snapshot_id.is_some());
+
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
 }
@@ -0,0 +1,167 @@
+//! Detects a backup source that resides on a network filesystem.
+//!
+//! Backing up an NFS/CIFS mount is often unintentional -- the data is
+//! someone else's responsibility, and pulling it over the network is much
+//! slower than a local disk besides. [`warn_network_sources`] flags that so
+//! it shows up as a warning during [`crate::config::RepoConfig::validate`]
+//! and a log line during the actual backup, instead of silently working
+//! (slowly) against someone's NFS export.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::HalleyError;
+
+/// Filesystem types treated as "network" -- anything backed by a remote
+/// server rather than local block storage. Not exhaustive, but covers the
+/// mounts most likely to show up as an accidental backup source.
+const NETWORK_FSTYPES: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb2",
+    "smbfs",
+    "fuse.sshfs",
+    "afs",
+    "ncpfs",
+    "glusterfs",
+    "ceph",
+    "9p",
+];
+
+/// Whether `fstype` (as reported by `/proc/mounts`) names a network
+/// filesystem, per [`NETWORK_FSTYPES`].
+pub fn is_network_fstype(fstype: &str) -> bool {
+    NETWORK_FSTYPES.contains(&fstype)
+}
+
+/// A source of mount-point filesystem types, abstracted so
+/// [`warn_network_sources`] can be driven by a fake mount table in tests
+/// instead of the real `/proc/mounts`.
+pub trait MountTable {
+    /// The filesystem type backing the mount point that `path` resolves
+    /// under, or `None` if it can't be determined (e.g. `path` doesn't
+    /// exist, or matches no entry in the table).
+    fn fstype(&self, path: &Path) -> Option<&str>;
+}
+
+/// The real mount table, read from `/proc/mounts`.
+pub struct ProcMounts {
+    /// Mount point -> filesystem type, so [`MountTable::fstype`] can find
+    /// the most specific (longest) mount point that's a prefix of a given
+    /// path.
+    by_mount_point: BTreeMap<PathBuf, String>,
+}
+
+impl ProcMounts {
+    /// Parses `/proc/mounts`. Halley has no non-Linux support elsewhere
+    /// (see e.g. [`crate::lock`]'s `kill -0` liveness check), so this
+    /// doesn't fall back to `statfs` for other platforms.
+    pub fn load() -> Result<Self, HalleyError> {
+        Self::parse(&fs::read_to_string("/proc/mounts")?)
+    }
+
+    fn parse(contents: &str) -> Result<Self, HalleyError> {
+        let mut by_mount_point = BTreeMap::new();
+        for line in contents.lines() {
+            let mut fields = line.split_whitespace();
+            let Some(_device) = fields.next() else { continue };
+            let Some(mount_point) = fields.next() else { continue };
+            let Some(fstype) = fields.next() else { continue };
+            by_mount_point.insert(PathBuf::from(mount_point), fstype.to_string());
+        }
+        Ok(Self { by_mount_point })
+    }
+}
+
+impl MountTable for ProcMounts {
+    fn fstype(&self, path: &Path) -> Option<&str> {
+        self.by_mount_point
+            .iter()
+            .filter(|(mount_point, _)| path.starts_with(mount_point))
+            .max_by_key(|(mount_point, _)| mount_point.as_os_str().len())
+            .map(|(_, fstype)| fstype.as_str())
+    }
+}
+
+/// Warns about each of `sources` that resolves to a network filesystem per
+/// `mounts`, naming the path and the filesystem type. Empty if `mounts`
+/// can't place a source under any known mount point.
+pub fn warn_network_sources<M: MountTable>(mounts: &M, sources: &[PathBuf]) -> Vec<String> {
+    sources
+        .iter()
+        .filter_map(|source| {
+            let fstype = mounts.fstype(source)?;
+            is_network_fstype(fstype).then(|| {
+                format!(
+                    "source path '{}' is on a network filesystem ('{fstype}'); set \
+                     allow_network_sources = true to silence this warning",
+                    source.display()
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeMountTable(BTreeMap<PathBuf, &'static str>);
+
+    impl MountTable for FakeMountTable {
+        fn fstype(&self, path: &Path) -> Option<&str> {
+            self.0
+                .iter()
+                .filter(|(mount_point, _)| path.starts_with(mount_point))
+                .max_by_key(|(mount_point, _)| mount_point.as_os_str().len())
+                .map(|(_, fstype)| *fstype)
+        }
+    }
+
+    #[test]
+    fn warns_on_a_source_under_an_nfs_mount() {
+        let mounts = FakeMountTable(BTreeMap::from([
+            (PathBuf::from("/"), "ext4"),
+            (PathBuf::from("/mnt/nfs"), "nfs4"),
+        ]));
+        let warnings = warn_network_sources(&mounts, &[PathBuf::from("/mnt/nfs/backups")]);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("/mnt/nfs/backups"));
+        assert!(warnings[0].contains("nfs4"));
+    }
+
+    #[test]
+    fn is_silent_about_a_source_on_a_local_filesystem() {
+        let mounts = FakeMountTable(BTreeMap::from([(PathBuf::from("/"), "ext4")]));
+        let warnings = warn_network_sources(&mounts, &[PathBuf::from("/home/user/docs")]);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn is_silent_about_a_source_under_no_known_mount_point() {
+        let mounts = FakeMountTable(BTreeMap::new());
+        let warnings = warn_network_sources(&mounts, &[PathBuf::from("/home/user/docs")]);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn picks_the_most_specific_mount_point_for_a_nested_path() {
+        let mounts = FakeMountTable(BTreeMap::from([
+            (PathBuf::from("/"), "ext4"),
+            (PathBuf::from("/mnt"), "cifs"),
+            (PathBuf::from("/mnt/local"), "ext4"),
+        ]));
+        let warnings = warn_network_sources(&mounts, &[PathBuf::from("/mnt/local/docs")]);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn proc_mounts_parses_a_real_looking_mounts_file() {
+        let contents = "rootfs / rootfs rw 0 0\nserver:/export /mnt/nfs nfs4 rw,relatime 0 0\n";
+        let mounts = ProcMounts::parse(contents).unwrap();
+        assert_eq!(mounts.fstype(Path::new("/mnt/nfs/data")), Some("nfs4"));
+        assert_eq!(mounts.fstype(Path::new("/")), Some("rootfs"));
+    }
+}
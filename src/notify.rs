@@ -0,0 +1,268 @@
+//! Notification delivery, with an on-disk queue for when delivery fails.
+//!
+//! Halley's typical host is offline except during backup windows, so a
+//! webhook or email send will often fail transiently. Rather than lose the
+//! notification, we persist it as a JSON file under the state dir. The queue
+//! is flushed each time `halley backup` queues a new run report, so an
+//! undelivered entry is retried on a later run that sends a report; a run
+//! that sends no report leaves the queue alone, and no command flushes it
+//! on its own.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::HalleyError;
+use crate::util;
+
+/// How long a [`CommandNotificationSender`]'s command is allowed to run
+/// before it's killed. Far shorter than [`crate::engine`]'s ten-minute hook
+/// timeout, but still long enough for a webhook endpoint on the far side of
+/// a flaky offsite link that's slow without actually being stuck.
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delivers a single notification. Implemented by the real webhook/email
+/// senders and by test doubles.
+pub trait NotificationSender {
+    fn send(&self, notification: &Notification) -> Result<(), HalleyError>;
+}
+
+/// A queued notification, persisted as one JSON file per entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: String,
+    pub body: String,
+    pub created_at: u64,
+    pub attempts: u32,
+    /// Unix timestamp before which redelivery shouldn't be attempted.
+    pub next_attempt_at: u64,
+}
+
+/// Delivers a [`Notification`] by running a user-supplied shell command,
+/// e.g. `curl` against a webhook -- see [`crate::config::NotifyConfig`] for
+/// why this is a command rather than an HTTP call Halley makes itself.
+/// `notification.body` is passed as `HALLEY_NOTIFICATION_BODY`; a nonzero
+/// exit is treated as a delivery failure and queued for retry, same as
+/// [`crate::engine::run_post_hook`] treats a failing hook.
+pub struct CommandNotificationSender {
+    pub command: String,
+}
+
+impl NotificationSender for CommandNotificationSender {
+    fn send(&self, notification: &Notification) -> Result<(), HalleyError> {
+        let env = [("HALLEY_NOTIFICATION_BODY".to_string(), notification.body.clone())];
+        let output = util::run_hook(&self.command, &env, Some(SEND_TIMEOUT))?;
+        if !output.success() {
+            return Err(HalleyError::Restic {
+                status: output.status,
+                stderr: output.stderr,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// How long a notification is kept before being given up on and dropped.
+const MAX_RETENTION_SECS: u64 = 14 * 24 * 60 * 60;
+/// Base of the exponential backoff applied between redelivery attempts.
+const BACKOFF_BASE_SECS: u64 = 60;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// A directory of queued, undelivered notifications.
+pub struct NotificationQueue {
+    dir: PathBuf,
+}
+
+impl NotificationQueue {
+    pub fn new(state_dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: state_dir.as_ref().join("notifications"),
+        }
+    }
+
+    fn path_for(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+
+    /// Persists `body` as a new queue entry ready for immediate delivery.
+    pub fn enqueue(&self, id: impl Into<String>, body: impl Into<String>) -> Result<(), HalleyError> {
+        fs::create_dir_all(&self.dir)?;
+        let notification = Notification {
+            id: id.into(),
+            body: body.into(),
+            created_at: now(),
+            attempts: 0,
+            next_attempt_at: now(),
+        };
+        let path = self.path_for(&notification.id);
+        let json = serde_json::to_string_pretty(&notification)
+            .expect("Notification serializes without error");
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    fn load_all(&self) -> Result<Vec<Notification>, HalleyError> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut queued = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.path().extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let contents = fs::read_to_string(entry.path())?;
+            if let Ok(notification) = serde_json::from_str::<Notification>(&contents) {
+                queued.push(notification);
+            }
+        }
+        queued.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        Ok(queued)
+    }
+
+    /// Attempts redelivery of every due entry, aging out anything past
+    /// [`MAX_RETENTION_SECS`]. Successful deliveries remove their file;
+    /// failures are rescheduled with exponential backoff.
+    pub fn flush(&self, sender: &impl NotificationSender) -> Result<(), HalleyError> {
+        let current = now();
+        for mut notification in self.load_all()? {
+            if current.saturating_sub(notification.created_at) > MAX_RETENTION_SECS {
+                let _ = fs::remove_file(self.path_for(&notification.id));
+                continue;
+            }
+            if notification.next_attempt_at > current {
+                continue;
+            }
+            match sender.send(&notification) {
+                Ok(()) => {
+                    let _ = fs::remove_file(self.path_for(&notification.id));
+                }
+                Err(_) => {
+                    notification.attempts += 1;
+                    let backoff = BACKOFF_BASE_SECS.saturating_mul(1 << notification.attempts.min(16));
+                    notification.next_attempt_at = current + backoff;
+                    let json = serde_json::to_string_pretty(&notification)
+                        .expect("Notification serializes without error");
+                    fs::write(self.path_for(&notification.id), json)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::path::PathBuf;
+
+    struct FailThenSucceed {
+        remaining_failures: Cell<u32>,
+        received: RefCell<Vec<String>>,
+    }
+
+    impl NotificationSender for FailThenSucceed {
+        fn send(&self, notification: &Notification) -> Result<(), HalleyError> {
+            if self.remaining_failures.get() > 0 {
+                self.remaining_failures.set(self.remaining_failures.get() - 1);
+                return Err(HalleyError::S3("delivery failed".into()));
+            }
+            self.received.borrow_mut().push(notification.id.clone());
+            Ok(())
+        }
+    }
+
+    fn temp_queue(name: &str) -> (NotificationQueue, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("halley-notify-test-{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        (NotificationQueue::new(&dir), dir)
+    }
+
+    #[test]
+    fn enqueue_persists_a_file_and_flush_removes_it_on_success() {
+        let (queue, dir) = temp_queue("success");
+        queue.enqueue("n1", "hello").unwrap();
+        assert!(queue.path_for("n1").exists());
+
+        let sender = FailThenSucceed {
+            remaining_failures: Cell::new(0),
+            received: RefCell::new(Vec::new()),
+        };
+        queue.flush(&sender).unwrap();
+
+        assert!(!queue.path_for("n1").exists());
+        assert_eq!(sender.received.borrow().as_slice(), ["n1"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn failed_delivery_is_rescheduled_not_lost() {
+        let (queue, dir) = temp_queue("retry");
+        queue.enqueue("n1", "hello").unwrap();
+
+        let sender = FailThenSucceed {
+            remaining_failures: Cell::new(1),
+            received: RefCell::new(Vec::new()),
+        };
+        queue.flush(&sender).unwrap();
+        assert!(queue.path_for("n1").exists(), "still queued after a failure");
+
+        let stored: Notification =
+            serde_json::from_str(&fs::read_to_string(queue.path_for("n1")).unwrap()).unwrap();
+        assert_eq!(stored.attempts, 1);
+        assert!(stored.next_attempt_at > stored.created_at);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ordering_is_oldest_first() {
+        let (queue, dir) = temp_queue("order");
+        queue.enqueue("first", "a").unwrap();
+        queue.enqueue("second", "b").unwrap();
+        let all = queue.load_all().unwrap();
+        assert_eq!(all[0].id, "first");
+        assert_eq!(all[1].id, "second");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn notification(body: &str) -> Notification {
+        Notification {
+            id: "run-report".to_string(),
+            body: body.to_string(),
+            created_at: 0,
+            attempts: 0,
+            next_attempt_at: 0,
+        }
+    }
+
+    #[test]
+    fn command_sender_passes_the_body_as_an_environment_variable() {
+        let dir = std::env::temp_dir().join(format!("halley-notify-command-test-{}", std::process::id()));
+        let marker = dir.join("marker");
+        fs::create_dir_all(&dir).unwrap();
+
+        let sender = CommandNotificationSender {
+            command: format!("echo -n \"$HALLEY_NOTIFICATION_BODY\" > {}", marker.display()),
+        };
+        sender.send(&notification(r#"{"outcomes":[]}"#)).unwrap();
+
+        assert_eq!(fs::read_to_string(&marker).unwrap(), r#"{"outcomes":[]}"#);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn command_sender_reports_a_nonzero_exit_as_a_delivery_failure() {
+        let sender = CommandNotificationSender { command: "exit 1".to_string() };
+        assert!(sender.send(&notification("body")).is_err());
+    }
+}
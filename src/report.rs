@@ -0,0 +1,188 @@
+//! `halley report`: a Markdown rendering of Halley's current state, for
+//! pasting into a ticket or piping to a notifier as a status digest.
+//!
+//! Halley keeps only the *latest* backup/verify outcome per repo (see
+//! [`crate::state::RepoState`]), not a log of every run, so there's no
+//! "runs this week" or "data added this week" to report on yet -- this
+//! renders a snapshot of the current state instead of a trend over a
+//! window. A `--since` filter and per-run history are future work once
+//! Halley actually persists a run log to filter.
+
+use crate::config::RepoConfig;
+use crate::state::{BackupOutcome, RepoState, StateFile};
+
+/// Renders `state` as a Markdown table, one row per repo in `repos`
+/// (config order), plus a "notable warnings" line under each row that has
+/// something worth flagging (a failed last backup, or a stale/missing
+/// verification per `max_verify_age_days`).
+///
+/// Pure over its inputs, so it's tested with plain fixtures rather than a
+/// real statefile on disk.
+pub fn render_markdown(repos: &[RepoConfig], state: &StateFile) -> String {
+    let mut out = String::new();
+    out.push_str("| Repo | Last Backup | Last Verify | Notes |\n");
+    out.push_str("| --- | --- | --- | --- |\n");
+    for repo in repos {
+        let repo_state = state.repos.get(&repo.name);
+        out.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            repo.name,
+            last_backup_cell(repo_state),
+            last_verify_cell(repo_state),
+            notes_cell(repo, repo_state),
+        ));
+    }
+    out
+}
+
+fn last_backup_cell(state: Option<&RepoState>) -> String {
+    match state.and_then(|state| state.last_backup.as_ref()) {
+        None => "never".to_string(),
+        Some(record) => match &record.outcome {
+            BackupOutcome::Success { duration_secs, .. } => {
+                format!("succeeded at {} ({duration_secs}s)", record.at)
+            }
+            BackupOutcome::Failed { error } => format!("**failed** at {}: {error}", record.at),
+        },
+    }
+}
+
+fn last_verify_cell(state: Option<&RepoState>) -> String {
+    match state.and_then(|state| state.last_verified.as_ref()) {
+        None => "never".to_string(),
+        Some(record) => format!("{} via {:?}", record.at, record.method),
+    }
+}
+
+fn notes_cell(repo: &RepoConfig, state: Option<&RepoState>) -> String {
+    let mut notes = Vec::new();
+    if state.is_some_and(RepoState::last_backup_failed) {
+        notes.push("backup failing".to_string());
+    }
+    if let Some(max_age_days) = repo.max_verify_age_days {
+        let stale = match state {
+            Some(state) => state.verify_is_stale(max_age_days),
+            None => true,
+        };
+        if stale {
+            notes.push(format!("verify overdue (> {max_age_days}d)"));
+        }
+    }
+    if notes.is_empty() {
+        "-".to_string()
+    } else {
+        notes.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ChangedDuringBackup;
+    use crate::restic;
+    use crate::state::{BackupRecord, VerifyMethod, VerifyRecord};
+
+    fn repo(name: &str, max_verify_age_days: Option<u32>) -> RepoConfig {
+        RepoConfig {
+            name: name.into(),
+            sources: vec![],
+            repo: "/tmp/repo".into(),
+            retention: restic::RetentionPolicy::default(),
+            prune: false,
+            changed_during_backup: ChangedDuringBackup::Ignore,
+            max_verify_age_days,
+            symlinks: restic::SymlinkPolicy::default(),
+            compression: None,
+            no_scan: false,
+            read_concurrency: None,
+            excludes: vec![],
+            exclude_file: None,
+            digest_ignore: Vec::new(),
+            strict_paths: false,
+            tags: vec![],
+            check_before_backup: false,
+            restic_memory_limit_mb: None,
+            auto_init: false,
+            password: Some("testpass".to_string()),
+            password_file: None,
+            password_command: None,
+            password_source: None,
+            limit_upload: None,
+            limit_download: None,
+            allow_initial_backup: false,
+            first_backup_size_threshold_mb: None,
+            cache_dir: None,
+            no_cache: false,
+            restore_sparse: false,
+            restore_flags: vec![],
+            hostname: None,
+            one_file_system: false,
+            auto_unlock_stale: false,
+            max_auto_forget: None,
+            warning_threshold: None,
+            s3: None,
+            cold_storage_command: None,
+            pre_hook: None,
+            post_hook: None,
+            archive_after_failed_backup: false,
+            archive_delay_hours: None,
+            archive_unverified: false,
+            min_backup_interval_hours: None,
+            max_backup_interval_days: None,
+            extra_env_passthrough: Vec::new(),
+            allow_network_sources: false,
+        }
+    }
+
+    #[test]
+    fn renders_never_backed_up_and_never_verified_repos_plainly() {
+        let repos = vec![repo("home", None)];
+        let state = StateFile::default();
+        let markdown = render_markdown(&repos, &state);
+        assert!(markdown.contains("| home | never | never | - |"));
+    }
+
+    #[test]
+    fn flags_a_failed_last_backup_in_the_notes_column() {
+        let repos = vec![repo("home", None)];
+        let mut state = StateFile::default();
+        let mut repo_state = RepoState::default();
+        repo_state.record_backup_failure("boom", None);
+        state.repos.insert("home".to_string(), repo_state);
+        let markdown = render_markdown(&repos, &state);
+        assert!(markdown.contains("**failed**"));
+        assert!(markdown.contains("backup failing"));
+    }
+
+    #[test]
+    fn flags_a_stale_verification_against_the_repo_s_own_max_age() {
+        let repos = vec![repo("home", Some(7))];
+        let mut state = StateFile::default();
+        let mut repo_state = RepoState::default();
+        repo_state.last_verified = Some(VerifyRecord {
+            at: 0,
+            method: VerifyMethod::CheckMetadata,
+        });
+        state.repos.insert("home".to_string(), repo_state);
+        let markdown = render_markdown(&repos, &state);
+        assert!(markdown.contains("verify overdue (> 7d)"));
+    }
+
+    #[test]
+    fn does_not_flag_a_fresh_verification() {
+        let repos = vec![repo("home", Some(7))];
+        let mut state = StateFile::default();
+        let mut repo_state = RepoState::default();
+        repo_state.record_verify(VerifyMethod::CheckMetadata);
+        repo_state.last_backup = Some(BackupRecord {
+            at: 1,
+            outcome: BackupOutcome::Success { duration_secs: 5, bytes_added: None, warnings: crate::restic::WarningSummary::default(), average_throughput_bytes_per_sec: None, peak_throughput_bytes_per_sec: None },
+            snapshot_id: None,
+            restic_version: None,
+            halley_version: None,
+        });
+        state.repos.insert("home".to_string(), repo_state);
+        let markdown = render_markdown(&repos, &state);
+        assert!(markdown.contains("| - |"));
+    }
+}
@@ -0,0 +1,798 @@
+//! `restic backup` argument construction and progress parsing.
+//!
+//! This is its own submodule because the backup command accumulates more
+//! per-repo knobs, and more output to make sense of, than any other restic
+//! subcommand.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::HalleyError;
+
+use super::WrappedCall;
+
+/// How to treat symlinks encountered under a source path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    /// Back up the symlink itself (restic's own default).
+    #[default]
+    Preserve,
+    /// Follow the symlink and back up its target.
+    Follow,
+    /// Don't back up symlinks at all.
+    Skip,
+}
+
+/// A `restic backup --compression` level (restic 0.14+, repository format
+/// v2 only).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompressionLevel {
+    Off,
+    /// restic's own default: compress unless it's not worth the CPU.
+    #[default]
+    Auto,
+    Max,
+}
+
+impl CompressionLevel {
+    fn as_flag_value(self) -> &'static str {
+        match self {
+            CompressionLevel::Off => "off",
+            CompressionLevel::Auto => "auto",
+            CompressionLevel::Max => "max",
+        }
+    }
+}
+
+/// Options controlling a single `restic backup` invocation.
+#[derive(Debug, Clone, Default)]
+pub struct BackupOptions {
+    pub symlinks: SymlinkPolicy,
+    /// `--compression` level. `None` leaves restic's own default in place;
+    /// dropped entirely (with a caller-surfaced warning) against a restic
+    /// older than [`super::MIN_COMPRESSION_VERSION`], which doesn't
+    /// understand the flag at all. See
+    /// [`crate::engine::compression_warnings`].
+    pub compression: Option<CompressionLevel>,
+    /// Skip restic's pre-backup scan pass. Saves a full tree walk before
+    /// huge backups, at the cost of losing the up-front progress estimate.
+    pub no_scan: bool,
+    /// Number of concurrent file-reading goroutines restic uses.
+    pub read_concurrency: Option<u32>,
+    /// Glob patterns passed as `--exclude`, in order.
+    pub excludes: Vec<String>,
+    /// A file of exclude patterns, passed as `--exclude-file`.
+    pub exclude_file: Option<PathBuf>,
+    /// Tags stamped on the resulting snapshot, in order, via `--tag`.
+    pub tags: Vec<String>,
+    /// Skip restic's local cache. Set when a [`super::MemoryLimit`] decided
+    /// the configured memory budget is too tight to spare for it.
+    pub no_cache: bool,
+    /// Caps restic's upload rate, in KiB/s, via `--limit-upload`.
+    pub limit_upload: Option<i64>,
+    /// Caps restic's download rate, in KiB/s, via `--limit-download`.
+    pub limit_download: Option<i64>,
+    /// Restic's local cache directory, via `--cache-dir`. Ignored when
+    /// `no_cache` is set.
+    pub cache_dir: Option<PathBuf>,
+    /// Overrides restic's implicit hostname, via `--host`.
+    pub hostname: Option<String>,
+    /// Don't cross filesystem boundaries under a source path, via
+    /// `--one-file-system`. Useful for backing up `/` without descending
+    /// into mounted media.
+    pub one_file_system: bool,
+    /// Run restic itself in dry-run mode, via `--dry-run`: it reports what
+    /// it would have done without writing a snapshot. Set by
+    /// [`crate::engine::dry_run_backup_cycle`]; a normal backup leaves it off.
+    pub dry_run: bool,
+}
+
+/// One line of `restic backup --json` output.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "message_type", rename_all = "lowercase")]
+pub enum BackupEvent {
+    Status(BackupStatus),
+    Summary(BackupSummary),
+    Error(BackupWarning),
+    /// Anything else restic emits (verbose_status, ...) that Halley doesn't
+    /// currently act on.
+    #[serde(other)]
+    Other,
+}
+
+/// A non-fatal `error` message emitted mid-backup, e.g. a permission
+/// failure on one file among thousands -- the backup as a whole still
+/// succeeds and produces a snapshot, but the item this refers to didn't
+/// make it in. See [`crate::restic::warnings::categorize`], which sorts
+/// these into the categories [`crate::engine::RunOutcome::warnings`]
+/// reports.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BackupWarning {
+    #[serde(default)]
+    pub item: String,
+    #[serde(default, rename = "error")]
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BackupStatus {
+    #[serde(default)]
+    pub percent_done: f64,
+    #[serde(default)]
+    pub files_done: u64,
+    #[serde(default)]
+    pub bytes_done: u64,
+    /// Wall-clock seconds since restic started, per this status line. Used
+    /// alongside `bytes_done` to derive a throughput; see
+    /// [`summarize_throughput`].
+    #[serde(default)]
+    pub seconds_elapsed: u64,
+    /// restic's own estimate of time left, when it's had enough of the scan
+    /// to make one -- absent from early status lines, and always absent
+    /// with `--no-scan` set.
+    #[serde(default)]
+    pub seconds_remaining: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BackupSummary {
+    #[serde(default)]
+    pub files_new: u64,
+    #[serde(default)]
+    pub files_changed: u64,
+    #[serde(default)]
+    pub total_bytes_processed: u64,
+    #[serde(default)]
+    pub snapshot_id: String,
+}
+
+/// The outcome of a backup invocation: every progress/summary line restic
+/// emitted, plus the summary pulled out for convenience.
+#[derive(Debug, Clone, Default)]
+pub struct BackupResult {
+    pub events: Vec<BackupEvent>,
+    pub summary: Option<BackupSummary>,
+}
+
+/// How many recent [`BackupStatus`] lines [`summarize_throughput`] smooths
+/// over. Restic emits a status line roughly once a second, so this covers a
+/// few seconds -- enough to ride out a single jittery line without lagging
+/// far behind an actual change in rate.
+pub const DEFAULT_THROUGHPUT_WINDOW: usize = 5;
+
+/// Average/peak throughput, and a smoothed ETA, derived from a backup's
+/// [`BackupStatus`] lines by [`summarize_throughput`]. Every field is `None`
+/// when there weren't enough status lines to derive it from -- e.g. a backup
+/// small enough to finish before restic emitted a second one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ThroughputSummary {
+    pub average_bytes_per_sec: Option<u64>,
+    pub peak_bytes_per_sec: Option<u64>,
+    /// restic's own `seconds_remaining`, smoothed over the window so it
+    /// doesn't visibly jump between status lines.
+    pub eta_secs: Option<u64>,
+}
+
+/// Smooths `bytes_done`/`seconds_elapsed` and restic's own `seconds_remaining`
+/// over a sliding window of recent samples, so a single jittery restic status
+/// line doesn't swing the reported throughput or ETA. Fed one sample at a
+/// time via [`ThroughputTracker::sample`]; [`summarize_throughput`] is the
+/// usual way to build one from a whole backup's events.
+///
+/// Only the last `window` samples count towards the current/peak rate and
+/// the ETA, so throughput reacts to an actual change (e.g. hitting a slow
+/// network share partway through) within a few status lines rather than the
+/// whole backup. `average_bytes_per_sec` is the one exception: it's measured
+/// from the very first sample ever seen, since "average" should mean the
+/// whole backup, not just what's still in the window.
+#[derive(Debug, Clone)]
+pub struct ThroughputTracker {
+    window: usize,
+    first: Option<(u64, u64)>,
+    recent: std::collections::VecDeque<(u64, u64)>,
+    recent_eta: std::collections::VecDeque<u64>,
+    peak_bytes_per_sec: Option<u64>,
+}
+
+impl ThroughputTracker {
+    /// `window` is clamped to at least 1, since a zero-sized window has
+    /// nothing to average over.
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            first: None,
+            recent: std::collections::VecDeque::new(),
+            recent_eta: std::collections::VecDeque::new(),
+            peak_bytes_per_sec: None,
+        }
+    }
+
+    /// Records one restic status line's `seconds_elapsed`, `bytes_done`, and
+    /// `seconds_remaining` (when restic provided one).
+    pub fn sample(&mut self, seconds_elapsed: u64, bytes_done: u64, seconds_remaining: Option<u64>) {
+        self.first.get_or_insert((seconds_elapsed, bytes_done));
+        self.recent.push_back((seconds_elapsed, bytes_done));
+        while self.recent.len() > self.window {
+            self.recent.pop_front();
+        }
+        if let Some(rate) = self.current_bytes_per_sec() {
+            self.peak_bytes_per_sec = Some(self.peak_bytes_per_sec.unwrap_or(0).max(rate));
+        }
+        if let Some(remaining) = seconds_remaining {
+            self.recent_eta.push_back(remaining);
+            while self.recent_eta.len() > self.window {
+                self.recent_eta.pop_front();
+            }
+        }
+    }
+
+    /// Bytes/sec smoothed over the current window: the byte and time delta
+    /// between its oldest and newest sample. `None` with fewer than two
+    /// samples in the window, or when they share a `seconds_elapsed` (restic
+    /// emitted two status lines within the same second).
+    pub fn current_bytes_per_sec(&self) -> Option<u64> {
+        let (first_secs, first_bytes) = *self.recent.front()?;
+        let (last_secs, last_bytes) = *self.recent.back()?;
+        let elapsed = last_secs.checked_sub(first_secs).filter(|secs| *secs > 0)?;
+        Some(last_bytes.saturating_sub(first_bytes) / elapsed)
+    }
+
+    /// Bytes/sec across the whole backup so far, from the very first sample
+    /// to the most recent one still in the window.
+    pub fn average_bytes_per_sec(&self) -> Option<u64> {
+        let (first_secs, first_bytes) = self.first?;
+        let (last_secs, last_bytes) = *self.recent.back()?;
+        let elapsed = last_secs.checked_sub(first_secs).filter(|secs| *secs > 0)?;
+        Some(last_bytes.saturating_sub(first_bytes) / elapsed)
+    }
+
+    pub fn peak_bytes_per_sec(&self) -> Option<u64> {
+        self.peak_bytes_per_sec
+    }
+
+    pub fn smoothed_eta_secs(&self) -> Option<u64> {
+        if self.recent_eta.is_empty() {
+            return None;
+        }
+        Some(self.recent_eta.iter().sum::<u64>() / self.recent_eta.len() as u64)
+    }
+}
+
+/// Derives a [`ThroughputSummary`] from every [`BackupEvent::Status`] line in
+/// `events`, smoothed over a `window`-sample sliding window (see
+/// [`ThroughputTracker`]).
+pub fn summarize_throughput(events: &[BackupEvent], window: usize) -> ThroughputSummary {
+    let mut tracker = ThroughputTracker::new(window);
+    for event in events {
+        if let BackupEvent::Status(status) = event {
+            tracker.sample(status.seconds_elapsed, status.bytes_done, status.seconds_remaining);
+        }
+    }
+    ThroughputSummary {
+        average_bytes_per_sec: tracker.average_bytes_per_sec(),
+        peak_bytes_per_sec: tracker.peak_bytes_per_sec(),
+        eta_secs: tracker.smoothed_eta_secs(),
+    }
+}
+
+/// Builds the argument list for `restic backup` from `sources` and
+/// `options`. Always requests `--json` output so the caller can parse
+/// progress instead of scraping restic's human-readable text.
+pub fn prepare_backup(sources: &[PathBuf], options: &BackupOptions) -> Vec<String> {
+    let mut args = vec!["backup".to_string(), "--json".to_string()];
+
+    match options.symlinks {
+        SymlinkPolicy::Preserve => {}
+        SymlinkPolicy::Follow => args.push("--follow-symlinks".to_string()),
+        SymlinkPolicy::Skip => args.push("--exclude-symlinks".to_string()),
+    }
+
+    if options.no_scan {
+        args.push("--no-scan".to_string());
+    }
+    if options.one_file_system {
+        args.push("--one-file-system".to_string());
+    }
+    if options.dry_run {
+        args.push("--dry-run".to_string());
+    }
+    if let Some(compression) = options.compression {
+        args.push("--compression".to_string());
+        args.push(compression.as_flag_value().to_string());
+    }
+    if options.no_cache {
+        args.push("--no-cache".to_string());
+    } else if let Some(cache_dir) = &options.cache_dir {
+        args.push("--cache-dir".to_string());
+        args.push(cache_dir.display().to_string());
+    }
+    if let Some(concurrency) = options.read_concurrency {
+        args.push("--read-concurrency".to_string());
+        args.push(concurrency.to_string());
+    }
+    if let Some(limit) = options.limit_upload {
+        args.push("--limit-upload".to_string());
+        args.push(limit.to_string());
+    }
+    if let Some(limit) = options.limit_download {
+        args.push("--limit-download".to_string());
+        args.push(limit.to_string());
+    }
+    if let Some(hostname) = &options.hostname {
+        args.push("--host".to_string());
+        args.push(hostname.clone());
+    }
+    for pattern in &options.excludes {
+        args.push("--exclude".to_string());
+        args.push(pattern.clone());
+    }
+    if let Some(exclude_file) = &options.exclude_file {
+        args.push("--exclude-file".to_string());
+        args.push(exclude_file.display().to_string());
+    }
+    for tag in &options.tags {
+        args.push("--tag".to_string());
+        args.push(tag.clone());
+    }
+
+    args.extend(sources.iter().map(|p| p.display().to_string()));
+    args
+}
+
+/// Parses each line of `restic backup --json` stdout into a [`BackupEvent`],
+/// silently skipping lines that aren't valid JSON (restic sometimes
+/// interleaves plain-text warnings on stdout).
+pub fn parse_backup_events(stdout: &str) -> Vec<BackupEvent> {
+    stdout
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Runs `restic backup` over `sources`, parsing its JSON progress stream.
+pub fn backup<C: WrappedCall>(
+    call: &C,
+    sources: &[PathBuf],
+    options: &BackupOptions,
+) -> Result<BackupResult, HalleyError> {
+    let args = prepare_backup(sources, options);
+    let out = call.call(&args)?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    let events = parse_backup_events(&out.stdout);
+    let summary = events.iter().find_map(|e| match e {
+        BackupEvent::Summary(s) => Some(s.clone()),
+        _ => None,
+    });
+    Ok(BackupResult { events, summary })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::restic::mock::MockCall;
+    use crate::restic::CallOutput;
+
+    #[test]
+    fn preserve_adds_no_flag() {
+        let args = prepare_backup(&[PathBuf::from("/home")], &BackupOptions::default());
+        assert_eq!(args, vec!["backup", "--json", "/home"]);
+    }
+
+    #[test]
+    fn follow_adds_follow_flag() {
+        let options = BackupOptions {
+            symlinks: SymlinkPolicy::Follow,
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/home")], &options);
+        assert_eq!(args, vec!["backup", "--json", "--follow-symlinks", "/home"]);
+    }
+
+    #[test]
+    fn skip_adds_exclude_flag() {
+        let options = BackupOptions {
+            symlinks: SymlinkPolicy::Skip,
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/home")], &options);
+        assert_eq!(args, vec!["backup", "--json", "--exclude-symlinks", "/home"]);
+    }
+
+    #[test]
+    fn no_scan_and_read_concurrency_are_appended() {
+        let options = BackupOptions {
+            no_scan: true,
+            read_concurrency: Some(8),
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/data")], &options);
+        assert_eq!(
+            args,
+            vec!["backup", "--json", "--no-scan", "--read-concurrency", "8", "/data"]
+        );
+    }
+
+    #[test]
+    fn excludes_and_exclude_file_are_appended_in_order() {
+        let options = BackupOptions {
+            excludes: vec!["*.log".to_string(), "target/*".to_string()],
+            exclude_file: Some(PathBuf::from("/etc/halley/excludes.txt")),
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/home")], &options);
+        assert_eq!(
+            args,
+            vec![
+                "backup",
+                "--json",
+                "--exclude",
+                "*.log",
+                "--exclude",
+                "target/*",
+                "--exclude-file",
+                "/etc/halley/excludes.txt",
+                "/home",
+            ]
+        );
+    }
+
+    #[test]
+    fn tags_are_appended_after_excludes() {
+        let options = BackupOptions {
+            excludes: vec!["*.log".to_string()],
+            tags: vec!["halley".to_string(), "nightly".to_string()],
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/home")], &options);
+        assert_eq!(
+            args,
+            vec![
+                "backup",
+                "--json",
+                "--exclude",
+                "*.log",
+                "--tag",
+                "halley",
+                "--tag",
+                "nightly",
+                "/home",
+            ]
+        );
+    }
+
+    #[test]
+    fn no_cache_is_appended_alongside_no_scan() {
+        let options = BackupOptions {
+            no_scan: true,
+            no_cache: true,
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/data")], &options);
+        assert_eq!(args, vec!["backup", "--json", "--no-scan", "--no-cache", "/data"]);
+    }
+
+    #[test]
+    fn cache_dir_is_appended_when_no_cache_is_unset() {
+        let options = BackupOptions {
+            cache_dir: Some(PathBuf::from("/var/cache/halley")),
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/data")], &options);
+        assert_eq!(
+            args,
+            vec!["backup", "--json", "--cache-dir", "/var/cache/halley", "/data"]
+        );
+    }
+
+    #[test]
+    fn no_cache_takes_precedence_over_cache_dir() {
+        let options = BackupOptions {
+            no_cache: true,
+            cache_dir: Some(PathBuf::from("/var/cache/halley")),
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/data")], &options);
+        assert_eq!(args, vec!["backup", "--json", "--no-cache", "/data"]);
+    }
+
+    #[test]
+    fn one_file_system_is_appended() {
+        let options = BackupOptions {
+            one_file_system: true,
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/")], &options);
+        assert_eq!(args, vec!["backup", "--json", "--one-file-system", "/"]);
+    }
+
+    #[test]
+    fn dry_run_is_appended() {
+        let options = BackupOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/home")], &options);
+        assert_eq!(args, vec!["backup", "--json", "--dry-run", "/home"]);
+    }
+
+    #[test]
+    fn one_file_system_and_dry_run_combine_with_no_scan() {
+        let options = BackupOptions {
+            no_scan: true,
+            one_file_system: true,
+            dry_run: true,
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/")], &options);
+        assert_eq!(
+            args,
+            vec!["backup", "--json", "--no-scan", "--one-file-system", "--dry-run", "/"]
+        );
+    }
+
+    #[test]
+    fn compression_off_is_appended() {
+        let options = BackupOptions {
+            compression: Some(CompressionLevel::Off),
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/home")], &options);
+        assert_eq!(args, vec!["backup", "--json", "--compression", "off", "/home"]);
+    }
+
+    #[test]
+    fn compression_auto_is_appended() {
+        let options = BackupOptions {
+            compression: Some(CompressionLevel::Auto),
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/home")], &options);
+        assert_eq!(args, vec!["backup", "--json", "--compression", "auto", "/home"]);
+    }
+
+    #[test]
+    fn compression_max_is_appended() {
+        let options = BackupOptions {
+            compression: Some(CompressionLevel::Max),
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/home")], &options);
+        assert_eq!(args, vec!["backup", "--json", "--compression", "max", "/home"]);
+    }
+
+    #[test]
+    fn compression_is_omitted_when_unset() {
+        let args = prepare_backup(&[PathBuf::from("/home")], &BackupOptions::default());
+        assert!(!args.iter().any(|a| a == "--compression"));
+    }
+
+    #[test]
+    fn hostname_is_appended_as_host_flag() {
+        let options = BackupOptions {
+            hostname: Some("laptop".to_string()),
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/data")], &options);
+        assert_eq!(args, vec!["backup", "--json", "--host", "laptop", "/data"]);
+    }
+
+    #[test]
+    fn upload_and_download_limits_are_appended_before_excludes() {
+        let options = BackupOptions {
+            limit_upload: Some(500),
+            limit_download: Some(2000),
+            excludes: vec!["*.tmp".to_string()],
+            ..Default::default()
+        };
+        let args = prepare_backup(&[PathBuf::from("/data")], &options);
+        assert_eq!(
+            args,
+            vec![
+                "backup",
+                "--json",
+                "--limit-upload",
+                "500",
+                "--limit-download",
+                "2000",
+                "--exclude",
+                "*.tmp",
+                "/data",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_backup_events_extracts_status_and_summary() {
+        let stdout = concat!(
+            r#"{"message_type":"status","percent_done":0.5,"files_done":2,"bytes_done":100}"#,
+            "\n",
+            r#"{"message_type":"summary","files_new":2,"files_changed":0,"total_bytes_processed":100,"snapshot_id":"abc123"}"#,
+        );
+        let events = parse_backup_events(stdout);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], BackupEvent::Status(_)));
+        assert!(matches!(events[1], BackupEvent::Summary(_)));
+    }
+
+    #[test]
+    fn parse_backup_events_skips_garbage_lines() {
+        let stdout = "not json\n{\"message_type\":\"summary\",\"snapshot_id\":\"abc\"}\n";
+        let events = parse_backup_events(stdout);
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn backup_returns_parsed_summary() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 0,
+                stdout: r#"{"message_type":"summary","files_new":1,"files_changed":0,"total_bytes_processed":10,"snapshot_id":"deadbeef"}"#.into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let result = backup(&call, &[PathBuf::from("/home")], &BackupOptions::default()).unwrap();
+        assert_eq!(result.summary.unwrap().snapshot_id, "deadbeef");
+    }
+
+    #[test]
+    fn backup_passes_bandwidth_limits_through_to_the_wrapped_call() {
+        let call = MockCall::ok();
+        let options = BackupOptions {
+            limit_upload: Some(500),
+            limit_download: Some(2000),
+            ..Default::default()
+        };
+        backup(&call, &[PathBuf::from("/home")], &options).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[0].windows(2).any(|w| w == ["--limit-upload", "500"]));
+        assert!(calls[0].windows(2).any(|w| w == ["--limit-download", "2000"]));
+    }
+
+    #[test]
+    fn backup_passes_the_cache_dir_through_to_the_wrapped_call() {
+        let call = MockCall::ok();
+        let options = BackupOptions {
+            cache_dir: Some(PathBuf::from("/var/cache/halley")),
+            ..Default::default()
+        };
+        backup(&call, &[PathBuf::from("/home")], &options).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[0]
+            .windows(2)
+            .any(|w| w == ["--cache-dir", "/var/cache/halley"]));
+    }
+
+    #[test]
+    fn backup_passes_one_file_system_and_dry_run_through_to_the_wrapped_call() {
+        let call = MockCall::ok();
+        let options = BackupOptions {
+            one_file_system: true,
+            dry_run: true,
+            ..Default::default()
+        };
+        backup(&call, &[PathBuf::from("/")], &options).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[0].contains(&"--one-file-system".to_string()));
+        assert!(calls[0].contains(&"--dry-run".to_string()));
+    }
+
+    #[test]
+    fn backup_passes_compression_through_to_the_wrapped_call() {
+        let call = MockCall::ok();
+        let options = BackupOptions {
+            compression: Some(CompressionLevel::Max),
+            ..Default::default()
+        };
+        backup(&call, &[PathBuf::from("/home")], &options).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[0].windows(2).any(|w| w == ["--compression", "max"]));
+    }
+
+    #[test]
+    fn throughput_tracker_reports_nothing_with_fewer_than_two_samples() {
+        let mut tracker = ThroughputTracker::new(3);
+        tracker.sample(0, 0, None);
+        assert_eq!(tracker.current_bytes_per_sec(), None);
+        assert_eq!(tracker.average_bytes_per_sec(), None);
+        assert_eq!(tracker.peak_bytes_per_sec(), None);
+    }
+
+    #[test]
+    fn throughput_tracker_computes_a_steady_rate() {
+        let mut tracker = ThroughputTracker::new(5);
+        for secs in 0..=4u64 {
+            tracker.sample(secs, secs * 1_000_000, None);
+        }
+        assert_eq!(tracker.current_bytes_per_sec(), Some(1_000_000));
+        assert_eq!(tracker.average_bytes_per_sec(), Some(1_000_000));
+    }
+
+    #[test]
+    fn throughput_tracker_windows_out_old_samples_from_the_current_rate() {
+        // A fast start (10 MB/s) followed by a slow stretch (1 MB/s) should
+        // leave `average` blended across both, but `current` reflecting only
+        // the slow stretch once the fast samples have aged out of the window.
+        let mut tracker = ThroughputTracker::new(3);
+        for secs in 0..=3u64 {
+            tracker.sample(secs, secs * 10_000_000, None);
+        }
+        for secs in 4..=8u64 {
+            tracker.sample(secs, 30_000_000 + (secs - 3) * 1_000_000, None);
+        }
+        assert_eq!(tracker.current_bytes_per_sec(), Some(1_000_000));
+        assert!(tracker.average_bytes_per_sec().unwrap() > 1_000_000);
+    }
+
+    #[test]
+    fn throughput_tracker_tracks_the_peak_rate_even_after_slowing_down() {
+        let mut tracker = ThroughputTracker::new(2);
+        tracker.sample(0, 0, None);
+        tracker.sample(1, 20_000_000, None);
+        tracker.sample(2, 21_000_000, None);
+        assert_eq!(tracker.peak_bytes_per_sec(), Some(20_000_000));
+        assert_eq!(tracker.current_bytes_per_sec(), Some(1_000_000));
+    }
+
+    #[test]
+    fn throughput_tracker_smooths_eta_over_the_window() {
+        let mut tracker = ThroughputTracker::new(3);
+        tracker.sample(0, 0, Some(100));
+        tracker.sample(1, 1_000, Some(10));
+        tracker.sample(2, 2_000, Some(94));
+        // Averaging the last 3 ETAs smooths out the single jittery reading.
+        assert_eq!(tracker.smoothed_eta_secs(), Some((100 + 10 + 94) / 3));
+    }
+
+    #[test]
+    fn throughput_tracker_ignores_status_lines_with_no_eta_yet() {
+        let mut tracker = ThroughputTracker::new(3);
+        tracker.sample(0, 0, None);
+        tracker.sample(1, 1_000, Some(50));
+        assert_eq!(tracker.smoothed_eta_secs(), Some(50));
+    }
+
+    #[test]
+    fn summarize_throughput_ignores_non_status_events() {
+        let events = vec![
+            BackupEvent::Status(BackupStatus { bytes_done: 0, seconds_elapsed: 0, ..Default::default() }),
+            BackupEvent::Summary(BackupSummary::default()),
+            BackupEvent::Status(BackupStatus {
+                bytes_done: 5_000_000,
+                seconds_elapsed: 5,
+                seconds_remaining: Some(3),
+                ..Default::default()
+            }),
+        ];
+        let summary = summarize_throughput(&events, DEFAULT_THROUGHPUT_WINDOW);
+        assert_eq!(summary.average_bytes_per_sec, Some(1_000_000));
+        assert_eq!(summary.peak_bytes_per_sec, Some(1_000_000));
+        assert_eq!(summary.eta_secs, Some(3));
+    }
+
+    #[test]
+    fn summarize_throughput_is_empty_with_no_status_lines() {
+        let events = vec![BackupEvent::Summary(BackupSummary::default())];
+        let summary = summarize_throughput(&events, DEFAULT_THROUGHPUT_WINDOW);
+        assert_eq!(summary, ThroughputSummary::default());
+    }
+
+    #[test]
+    fn backup_passes_the_hostname_through_to_the_wrapped_call() {
+        let call = MockCall::ok();
+        let options = BackupOptions {
+            hostname: Some("laptop".to_string()),
+            ..Default::default()
+        };
+        backup(&call, &[PathBuf::from("/home")], &options).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[0].windows(2).any(|w| w == ["--host", "laptop"]));
+    }
+}
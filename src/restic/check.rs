@@ -0,0 +1,72 @@
+//! `restic check`, used to verify repository integrity without restoring
+//! anything.
+
+use crate::error::HalleyError;
+
+use super::WrappedCall;
+
+/// Builds the argument list for `restic check`, optionally scoped to a
+/// `--read-data-subset` (e.g. `"5%"` or a byte range) instead of a full
+/// metadata-only pass.
+pub fn prepare_check(read_data_subset: Option<&str>) -> Vec<String> {
+    let mut args = vec!["check".to_string()];
+    if let Some(subset) = read_data_subset {
+        args.push("--read-data-subset".to_string());
+        args.push(subset.to_string());
+    }
+    args
+}
+
+/// Runs `restic check`, surfacing restic's stderr on failure.
+pub fn check<C: WrappedCall>(call: &C, read_data_subset: Option<&str>) -> Result<(), HalleyError> {
+    let args = prepare_check(read_data_subset);
+    let out = call.call(&args)?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::restic::mock::MockCall;
+
+    #[test]
+    fn prepare_check_without_subset() {
+        assert_eq!(prepare_check(None), vec!["check"]);
+    }
+
+    #[test]
+    fn prepare_check_with_subset() {
+        assert_eq!(
+            prepare_check(Some("5%")),
+            vec!["check", "--read-data-subset", "5%"]
+        );
+    }
+
+    #[test]
+    fn check_surfaces_restic_failure() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: crate::restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: pack file corrupt".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let err = check(&call, None).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { status: 1, .. }));
+    }
+
+    #[test]
+    fn check_calls_wrapped_call_with_prepared_args() {
+        let call = MockCall::ok();
+        check(&call, Some("10%")).unwrap();
+        assert_eq!(
+            call.calls.borrow()[0],
+            vec!["check", "--read-data-subset", "10%"]
+        );
+    }
+}
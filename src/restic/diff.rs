@@ -0,0 +1,197 @@
+//! `restic diff`, used to summarize what changed between two snapshots for
+//! the run report.
+
+use serde::Deserialize;
+
+use crate::error::HalleyError;
+use crate::util::human_bytes;
+
+use super::WrappedCall;
+
+/// The `added`/`removed` half of a `restic diff --json` statistics line.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DiffStats {
+    #[serde(default)]
+    pub bytes: u64,
+}
+
+/// One line of `restic diff --json` output. Only the final `statistics`
+/// line is acted on; everything else (per-file `+`/`-`/`M` change lines) is
+/// parsed just enough to be skipped.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "message_type", rename_all = "snake_case")]
+enum DiffEvent {
+    Statistics {
+        #[serde(default)]
+        changed_files: u64,
+        #[serde(default)]
+        added: DiffStats,
+        #[serde(default)]
+        removed: DiffStats,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// What changed between two snapshots, boiled down to what's worth putting
+/// in a run report.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffSummary {
+    pub added_bytes: u64,
+    pub removed_bytes: u64,
+    pub changed_files: u64,
+}
+
+/// Renders `summary` as `"added 1.2 GiB, removed 300.0 MiB, 1234 files
+/// changed"`, for the INFO log after a backup.
+pub fn format_diff_summary(summary: &DiffSummary) -> String {
+    format!(
+        "added {}, removed {}, {} files changed",
+        human_bytes(summary.added_bytes),
+        human_bytes(summary.removed_bytes),
+        summary.changed_files
+    )
+}
+
+/// Builds the argument list for `restic diff --json <old> <new>`.
+pub fn prepare_diff(old_snapshot: &str, new_snapshot: &str) -> Vec<String> {
+    vec![
+        "diff".to_string(),
+        "--json".to_string(),
+        old_snapshot.to_string(),
+        new_snapshot.to_string(),
+    ]
+}
+
+/// Parses each line of `restic diff --json` stdout, silently skipping lines
+/// that aren't valid JSON, mirroring [`super::backup::parse_backup_events`].
+fn parse_diff_events(stdout: &str) -> Vec<DiffEvent> {
+    stdout
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Runs `restic diff` between `old_snapshot` and `new_snapshot` and parses
+/// its trailing statistics line into a [`DiffSummary`].
+pub fn diff<C: WrappedCall>(
+    call: &C,
+    old_snapshot: &str,
+    new_snapshot: &str,
+) -> Result<DiffSummary, HalleyError> {
+    let args = prepare_diff(old_snapshot, new_snapshot);
+    let out = call.call(&args)?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    parse_diff_events(&out.stdout)
+        .into_iter()
+        .find_map(|event| match event {
+            DiffEvent::Statistics {
+                changed_files,
+                added,
+                removed,
+            } => Some(DiffSummary {
+                added_bytes: added.bytes,
+                removed_bytes: removed.bytes,
+                changed_files,
+            }),
+            DiffEvent::Other => None,
+        })
+        .ok_or_else(|| HalleyError::Parse("restic diff: no statistics line in output".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::restic::mock::MockCall;
+    use crate::restic::CallOutput;
+
+    #[test]
+    fn prepare_diff_builds_the_expected_args() {
+        assert_eq!(
+            prepare_diff("abc123", "def456"),
+            vec!["diff", "--json", "abc123", "def456"]
+        );
+    }
+
+    #[test]
+    fn parses_a_captured_statistics_line_among_change_lines() {
+        let stdout = concat!(
+            "{\"message_type\":\"change\",\"path\":\"/home/user/file1\",\"modifier\":\"+\"}\n",
+            "{\"message_type\":\"change\",\"path\":\"/home/user/file2\",\"modifier\":\"-\"}\n",
+            "{\"message_type\":\"statistics\",\"source_snapshot\":\"abc\",\"target_snapshot\":\"def\",",
+            "\"changed_files\":1234,",
+            "\"added\":{\"files\":1,\"dirs\":0,\"others\":0,\"data_blobs\":1,\"tree_blobs\":1,\"bytes\":1288490188},",
+            "\"removed\":{\"files\":1,\"dirs\":0,\"others\":0,\"data_blobs\":1,\"tree_blobs\":1,\"bytes\":314572800}}\n",
+        );
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 0,
+                stdout: stdout.to_string(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let summary = diff(&call, "abc", "def").unwrap();
+        assert_eq!(summary.changed_files, 1234);
+        assert_eq!(summary.added_bytes, 1288490188);
+        assert_eq!(summary.removed_bytes, 314572800);
+    }
+
+    #[test]
+    fn errors_when_no_statistics_line_is_present() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 0,
+                stdout: "{\"message_type\":\"change\",\"path\":\"/x\",\"modifier\":\"+\"}\n".to_string(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let err = diff(&call, "abc", "def").unwrap_err();
+        assert!(matches!(err, HalleyError::Parse(_)));
+    }
+
+    #[test]
+    fn diff_surfaces_restic_failure() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 1,
+                stderr: "Fatal: snapshot not found".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let err = diff(&call, "abc", "missing").unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { status: 1, .. }));
+    }
+
+    #[test]
+    fn diff_calls_wrapped_call_with_prepared_args() {
+        let call = MockCall::sequence(vec![CallOutput {
+            status: 0,
+            stdout: "{\"message_type\":\"statistics\",\"changed_files\":0,\"added\":{\"bytes\":0},\"removed\":{\"bytes\":0}}\n".to_string(),
+            ..Default::default()
+        }]);
+        diff(&call, "abc", "def").unwrap();
+        assert_eq!(call.calls.borrow()[0], vec!["diff", "--json", "abc", "def"]);
+    }
+
+    #[test]
+    fn format_diff_summary_matches_the_expected_shape() {
+        let summary = DiffSummary {
+            added_bytes: 1288490188,
+            removed_bytes: 314572800,
+            changed_files: 1234,
+        };
+        assert_eq!(
+            format_diff_summary(&summary),
+            "added 1.2 GiB, removed 300.0 MiB, 1234 files changed"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! `restic init`, and the `auto_init` race where two hosts try to
+//! initialize the same repository at once.
+
+use crate::error::{HalleyError, ResticErrorKind};
+
+use super::{stderr_tail, WrappedCall};
+
+pub fn prepare_init() -> Vec<String> {
+    vec!["init".to_string()]
+}
+
+/// Runs `restic init`, treating a lost `auto_init` race as success rather
+/// than a hard failure.
+///
+/// `restic init` and `restic cat config` fail the same way (non-zero exit,
+/// message on stderr), so a "config file already exists" error is
+/// indistinguishable at the call site from any other init failure until we
+/// look at the message. Once we recognize it, it's only safe to continue
+/// the cycle after confirming the winning host initialized the repository
+/// with our own password too — otherwise we'd proceed against a repository
+/// we can't actually read, and every later restic call would fail with a
+/// far less obvious error.
+pub fn init<C: WrappedCall>(call: &C) -> Result<(), HalleyError> {
+    let out = call.call(&prepare_init())?;
+    if out.success() {
+        return Ok(());
+    }
+    let err = out.into_restic_error();
+    if err.restic_error_kind() != Some(ResticErrorKind::RepoAlreadyExists) {
+        return Err(err);
+    }
+    verify_readable_after_race(call)
+}
+
+/// Confirms a repository some other host just won the `init` race on is
+/// readable with our password, via `restic cat config` — cheap, since it
+/// only decrypts the config blob rather than touching the pack data.
+fn verify_readable_after_race<C: WrappedCall>(call: &C) -> Result<(), HalleyError> {
+    let out = call.call(&["cat".to_string(), "config".to_string()])?;
+    if out.success() {
+        return Ok(());
+    }
+    Err(HalleyError::Restic {
+        status: out.status,
+        stderr: format!(
+            "repository was already initialized by another host, but our password can't read it (repository password mismatch): {}",
+            stderr_tail(&out.stderr)
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::restic::mock::MockCall;
+    use crate::restic::CallOutput;
+
+    #[test]
+    fn prepare_init_has_no_flags() {
+        assert_eq!(prepare_init(), vec!["init"]);
+    }
+
+    #[test]
+    fn init_succeeds_on_a_fresh_repository() {
+        let call = MockCall::ok();
+        init(&call).unwrap();
+        assert_eq!(call.calls.borrow().len(), 1);
+        assert_eq!(call.calls.borrow()[0], vec!["init"]);
+    }
+
+    #[test]
+    fn init_treats_a_lost_race_as_success_when_the_password_matches() {
+        let call = MockCall::sequence(vec![
+            CallOutput {
+                status: 1,
+                stderr: "Fatal: create repository at s3:bucket/repo failed: config file already exists".into(),
+                ..Default::default()
+            },
+            CallOutput {
+                status: 0,
+                ..Default::default()
+            },
+        ]);
+        init(&call).unwrap();
+        let calls = call.calls.borrow();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0], vec!["init"]);
+        assert_eq!(calls[1], vec!["cat", "config"]);
+    }
+
+    #[test]
+    fn init_truncates_the_stderr_tail_when_the_race_check_itself_is_chatty() {
+        let lines: Vec<String> = (1..=25).map(|n| format!("line {n}")).collect();
+        let call = MockCall::sequence(vec![
+            CallOutput {
+                status: 1,
+                stderr: "Fatal: create repository at s3:bucket/repo failed: config file already exists".into(),
+                ..Default::default()
+            },
+            CallOutput {
+                status: 1,
+                stderr: lines.join("\n"),
+                ..Default::default()
+            },
+        ]);
+        let err = init(&call).unwrap_err();
+        match err {
+            HalleyError::Restic { stderr, .. } => {
+                assert!(stderr.contains("password mismatch"));
+                assert!(!stderr.contains("line 1\n"));
+                assert!(stderr.contains("line 25"));
+            }
+            other => panic!("expected HalleyError::Restic, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn init_fails_hard_on_a_lost_race_with_a_different_password() {
+        let call = MockCall::sequence(vec![
+            CallOutput {
+                status: 1,
+                stderr: "Fatal: create repository at s3:bucket/repo failed: config file already exists".into(),
+                ..Default::default()
+            },
+            CallOutput {
+                status: 1,
+                stderr: "Fatal: wrong password or no key found".into(),
+                ..Default::default()
+            },
+        ]);
+        let err = init(&call).unwrap_err();
+        match err {
+            HalleyError::Restic { stderr, .. } => {
+                assert!(stderr.contains("password mismatch"));
+                assert!(stderr.contains("wrong password or no key found"));
+            }
+            other => panic!("expected HalleyError::Restic, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn init_fails_hard_with_only_the_stderr_tail_on_a_chatty_failure() {
+        let lines: Vec<String> = (1..=25).map(|n| format!("line {n}")).collect();
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 1,
+                stderr: lines.join("\n"),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let err = init(&call).unwrap_err();
+        match err {
+            HalleyError::Restic { stderr, .. } => {
+                assert!(!stderr.contains("line 1\n"));
+                assert!(stderr.contains("line 25"));
+            }
+            other => panic!("expected HalleyError::Restic, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn init_surfaces_unrelated_failures_without_a_race_check() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 1,
+                stderr: "Fatal: unable to open bucket: access denied".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let err = init(&call).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { .. }));
+        // No `cat config` race check should have run for an unrelated error.
+        assert_eq!(call.calls.borrow().len(), 1);
+    }
+}
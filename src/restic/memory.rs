@@ -0,0 +1,114 @@
+//! Translating a per-repo memory budget into restic's `GOGC`/`GOMEMLIMIT`
+//! environment knobs and cache footprint, for hosts too small to let restic
+//! use unlimited memory.
+
+use super::version::GoVersion;
+
+/// `GOMEMLIMIT` only exists from this Go release onward; older restic
+/// builds silently ignore it, so it's not worth setting (and the caller
+/// should warn instead).
+const GOMEMLIMIT_MIN_GO: GoVersion = GoVersion { major: 1, minor: 19 };
+
+/// Below this budget, restic's own on-disk cache competes with the process
+/// for memory (via the OS page cache) enough that it's worth trading a
+/// bigger download for a smaller footprint.
+const NO_CACHE_THRESHOLD_MB: u64 = 512;
+
+/// A conservative `GOGC` percentage that trades CPU for a smaller live
+/// heap. restic's own default (100) doesn't collect aggressively enough on
+/// constrained hosts.
+const CONSTRAINED_GOGC: &str = "20";
+
+/// The memory-limiting configuration applied to a single restic invocation.
+/// Kept around (rather than applied and discarded) so it can be echoed into
+/// the run report and correlated with failures later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryLimit {
+    pub limit_mb: u64,
+    pub gogc: String,
+    pub gomemlimit: Option<String>,
+    pub no_cache: bool,
+    /// Set when the installed restic can't honor `GOMEMLIMIT` (built with a
+    /// Go older than 1.19), or when its Go version couldn't be determined.
+    pub gomemlimit_unsupported: bool,
+}
+
+impl MemoryLimit {
+    /// Computes the limit to apply for `limit_mb` of available memory,
+    /// given the installed restic's Go runtime version, if known.
+    pub fn for_budget(limit_mb: u64, go_version: Option<GoVersion>) -> Self {
+        let supports_gomemlimit = go_version.map(|v| v >= GOMEMLIMIT_MIN_GO).unwrap_or(false);
+        Self {
+            limit_mb,
+            gogc: CONSTRAINED_GOGC.to_string(),
+            gomemlimit: supports_gomemlimit.then(|| format!("{limit_mb}MiB")),
+            no_cache: limit_mb < NO_CACHE_THRESHOLD_MB,
+            gomemlimit_unsupported: !supports_gomemlimit,
+        }
+    }
+
+    /// The environment variables to set on the restic child process.
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut env = vec![("GOGC".to_string(), self.gogc.clone())];
+        if let Some(gomemlimit) = &self.gomemlimit {
+            env.push(("GOMEMLIMIT".to_string(), gomemlimit.clone()));
+        }
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_gomemlimit_when_go_runtime_supports_it() {
+        let limit = MemoryLimit::for_budget(512, Some(GoVersion { major: 1, minor: 21 }));
+        assert_eq!(limit.gomemlimit, Some("512MiB".to_string()));
+        assert!(!limit.gomemlimit_unsupported);
+    }
+
+    #[test]
+    fn omits_gomemlimit_on_older_go_runtime() {
+        let limit = MemoryLimit::for_budget(512, Some(GoVersion { major: 1, minor: 18 }));
+        assert_eq!(limit.gomemlimit, None);
+        assert!(limit.gomemlimit_unsupported);
+    }
+
+    #[test]
+    fn omits_gomemlimit_when_go_version_is_unknown() {
+        let limit = MemoryLimit::for_budget(512, None);
+        assert_eq!(limit.gomemlimit, None);
+        assert!(limit.gomemlimit_unsupported);
+    }
+
+    #[test]
+    fn disables_cache_below_the_threshold() {
+        let limit = MemoryLimit::for_budget(256, Some(GoVersion { major: 1, minor: 21 }));
+        assert!(limit.no_cache);
+    }
+
+    #[test]
+    fn keeps_cache_above_the_threshold() {
+        let limit = MemoryLimit::for_budget(2048, Some(GoVersion { major: 1, minor: 21 }));
+        assert!(!limit.no_cache);
+    }
+
+    #[test]
+    fn env_includes_gogc_and_gomemlimit_in_order() {
+        let limit = MemoryLimit::for_budget(1024, Some(GoVersion { major: 1, minor: 21 }));
+        assert_eq!(
+            limit.env(),
+            vec![
+                ("GOGC".to_string(), "20".to_string()),
+                ("GOMEMLIMIT".to_string(), "1024MiB".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn env_omits_gomemlimit_when_unsupported() {
+        let limit = MemoryLimit::for_budget(1024, None);
+        assert_eq!(limit.env(), vec![("GOGC".to_string(), "20".to_string())]);
+    }
+}
@@ -0,0 +1,58 @@
+//! `restic migrate`, used to move a repository onto a newer format (e.g. to
+//! pick up compression support).
+
+use crate::error::HalleyError;
+
+use super::WrappedCall;
+
+/// A named repository migration, as accepted by `restic migrate <name>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Migration {
+    /// Upgrades a repository to format v2, which is required before restic
+    /// will compress newly written data.
+    UpgradeRepoV2,
+}
+
+impl Migration {
+    fn restic_name(self) -> &'static str {
+        match self {
+            Migration::UpgradeRepoV2 => "upgrade_repo_v2",
+        }
+    }
+}
+
+/// Builds the argument list for `restic migrate <name>`.
+pub fn prepare_migrate(migration: Migration) -> Vec<String> {
+    vec!["migrate".to_string(), migration.restic_name().to_string()]
+}
+
+/// Runs a repository migration.
+pub fn migrate<C: WrappedCall>(call: &C, migration: Migration) -> Result<(), HalleyError> {
+    let args = prepare_migrate(migration);
+    let out = call.call(&args)?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::restic::mock::MockCall;
+
+    #[test]
+    fn prepare_migrate_builds_expected_args() {
+        assert_eq!(
+            prepare_migrate(Migration::UpgradeRepoV2),
+            vec!["migrate", "upgrade_repo_v2"]
+        );
+    }
+
+    #[test]
+    fn migrate_calls_wrapped_call() {
+        let call = MockCall::ok();
+        migrate(&call, Migration::UpgradeRepoV2).unwrap();
+        assert_eq!(call.calls.borrow()[0], vec!["migrate", "upgrade_repo_v2"]);
+    }
+}
@@ -0,0 +1,880 @@
+//! A thin wrapper around the `restic` CLI.
+//!
+//! Every operation is split into a `prepare_*` function, which builds the
+//! argument list, and a thin driver that hands those arguments to a
+//! [`WrappedCall`]. Splitting the two lets tests assert on argument
+//! construction with [`MockCall`] without ever shelling out to a real
+//! `restic` binary.
+
+use serde::Deserialize;
+
+use crate::error::HalleyError;
+
+pub mod backup;
+pub use backup::{BackupOptions, CompressionLevel, SymlinkPolicy};
+pub mod version;
+pub use version::{ensure_supported_version, GoVersion, MIN_COMPRESSION_VERSION, Version};
+pub mod migrate;
+pub use migrate::Migration;
+pub mod check;
+pub use check::check;
+pub mod memory;
+pub use memory::MemoryLimit;
+pub mod init;
+pub use init::init;
+pub mod restore;
+pub use restore::{restore, restore_latest, RestoreOptions};
+pub mod diff;
+pub use diff::{diff, format_diff_summary, DiffSummary};
+pub mod warnings;
+pub use warnings::WarningSummary;
+
+/// Abstraction over "run restic with these arguments and give me the
+/// result", so tests can substitute [`MockCall`] for the real process.
+pub trait WrappedCall {
+    fn call(&self, args: &[String]) -> Result<CallOutput, HalleyError>;
+}
+
+/// The result of a single restic invocation.
+#[derive(Debug, Clone, Default)]
+pub struct CallOutput {
+    pub status: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// How many trailing lines of a failed invocation's stderr are kept in the
+/// resulting error. restic can be extremely chatty on a failure it retried
+/// internally several times before giving up, and the useful line is
+/// usually the last one.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Truncates `stderr` to its last [`STDERR_TAIL_LINES`] lines, unchanged if
+/// it's already shorter.
+pub(crate) fn stderr_tail(stderr: &str) -> String {
+    let lines: Vec<&str> = stderr.lines().collect();
+    if lines.len() <= STDERR_TAIL_LINES {
+        return stderr.to_string();
+    }
+    lines[lines.len() - STDERR_TAIL_LINES..].join("\n")
+}
+
+impl CallOutput {
+    pub fn success(&self) -> bool {
+        self.status == 0
+    }
+
+    /// Builds the [`HalleyError::Restic`] for a failed invocation, keeping
+    /// only the tail of `stderr` (see [`stderr_tail`]) so a chatty failure
+    /// doesn't bury the line that actually explains it.
+    pub fn into_restic_error(self) -> HalleyError {
+        HalleyError::Restic {
+            status: self.status,
+            stderr: stderr_tail(&self.stderr),
+        }
+    }
+}
+
+/// A [`WrappedCall`] that shells out to a real `restic` binary.
+pub struct RealCall {
+    pub binary: String,
+    /// Extra environment variables set on every child process, e.g.
+    /// `GOGC`/`GOMEMLIMIT` from a per-repo [`memory::MemoryLimit`].
+    pub env: Vec<(String, String)>,
+    /// Kills the child (and its process group) if it's still running after
+    /// this long, e.g. [`crate::config::Config::command_timeout`]. `None`
+    /// lets it run indefinitely, same as before this existed.
+    pub timeout: Option<std::time::Duration>,
+    /// Extra variable names passed through from halley's own environment,
+    /// beyond [`BASE_ENV_VARS`] and `env`, e.g. a repo whose
+    /// `password_command` shells out to something that needs `SSH_AUTH_SOCK`
+    /// or a cloud CLI's own credential variables. See
+    /// [`crate::config::RepoConfig::extra_env_passthrough`].
+    pub extra_env_passthrough: Vec<String>,
+}
+
+impl Default for RealCall {
+    fn default() -> Self {
+        Self {
+            binary: "restic".to_string(),
+            env: Vec::new(),
+            timeout: None,
+            extra_env_passthrough: Vec::new(),
+        }
+    }
+}
+
+/// The child's environment is built from scratch rather than inherited
+/// wholesale, so a stray `AWS_PROFILE` or `AWS_SESSION_TOKEN` left over from
+/// some other tool in halley's own environment can't silently change which
+/// backend restic talks to. These are the bare minimum restic (and anything
+/// it shells out to, e.g. an rclone backend) needs to run at all; anything
+/// beyond that has to be either a variable halley itself set (`env`, e.g. the
+/// `RESTIC_PASSWORD*` pair from [`crate::config::RepoConfig::password_env`])
+/// or explicitly allowed through per repo (see
+/// [`RealCall::extra_env_passthrough`]).
+pub(crate) const BASE_ENV_VARS: &[&str] = &["PATH", "HOME", "TMPDIR", "LANG", "LC_ALL"];
+
+/// How often [`RealCall::call_with_timeout`] polls a running child for
+/// completion. Small enough that a fast command isn't kept waiting past its
+/// own runtime, large enough not to busy-loop.
+const TIMEOUT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+impl WrappedCall for RealCall {
+    fn call(&self, args: &[String]) -> Result<CallOutput, HalleyError> {
+        match self.timeout {
+            None => self.call_without_timeout(args),
+            Some(timeout) => self.call_with_timeout(args, timeout),
+        }
+    }
+}
+
+impl RealCall {
+    /// The child's full environment: [`BASE_ENV_VARS`] and
+    /// [`RealCall::extra_env_passthrough`], both read from halley's own
+    /// environment and only included if actually set, followed by `env`
+    /// (halley's own explicitly-set variables, which win on any name
+    /// collision).
+    fn child_env(&self) -> Vec<(String, String)> {
+        let mut env: Vec<(String, String)> = BASE_ENV_VARS
+            .iter()
+            .copied()
+            .chain(self.extra_env_passthrough.iter().map(String::as_str))
+            .filter_map(|name| std::env::var(name).ok().map(|value| (name.to_string(), value)))
+            .collect();
+        env.extend(self.env.iter().cloned());
+        env
+    }
+
+    /// Turns a failure to spawn the child at all into
+    /// [`HalleyError::ResticNotAvailable`] when it's specifically because
+    /// [`RealCall::binary`] couldn't be found, so a host that's missing
+    /// restic entirely gets a clear, typed error instead of a generic io
+    /// error indistinguishable from e.g. a permissions problem.
+    fn spawn_error(&self, err: std::io::Error) -> HalleyError {
+        if err.kind() == std::io::ErrorKind::NotFound {
+            HalleyError::ResticNotAvailable {
+                binary: self.binary.clone(),
+            }
+        } else {
+            HalleyError::Io(err)
+        }
+    }
+
+    fn call_without_timeout(&self, args: &[String]) -> Result<CallOutput, HalleyError> {
+        let output = std::process::Command::new(&self.binary)
+            .args(args)
+            .env_clear()
+            .envs(self.child_env())
+            .output()
+            .map_err(|e| self.spawn_error(e))?;
+        Ok(CallOutput {
+            status: output.status.code().unwrap_or(-1),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+
+    /// Spawns the child in its own process group (so restic and anything it
+    /// shells out to, e.g. an rclone backend, can be killed together),
+    /// polls for completion every [`TIMEOUT_POLL_INTERVAL`], and kills the
+    /// group if `timeout` elapses first.
+    ///
+    /// stdout/stderr are drained on background threads while polling, so a
+    /// chatty child that fills its pipe buffer can't deadlock the wait. If a
+    /// poll comes back much later than [`TIMEOUT_POLL_INTERVAL`] — the host
+    /// having suspended mid-wait, not just a slow scheduler — the deadline is
+    /// pushed out by the gap (see [`crate::clock::SleepDetector`]) so a restic invocation
+    /// that was still perfectly healthy before the laptop lid closed doesn't
+    /// get killed the instant it wakes back up.
+    fn call_with_timeout(&self, args: &[String], timeout: std::time::Duration) -> Result<CallOutput, HalleyError> {
+        use std::io::Read;
+        use std::os::unix::process::CommandExt;
+
+        let mut child = std::process::Command::new(&self.binary)
+            .args(args)
+            .env_clear()
+            .envs(self.child_env())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .map_err(|e| self.spawn_error(e))?;
+
+        let pgid = child.id();
+        let mut stdout_pipe = child.stdout.take().expect("stdout was piped");
+        let mut stderr_pipe = child.stderr.take().expect("stderr was piped");
+        let stdout_reader = std::thread::spawn(move || {
+            let mut buf = String::new();
+            let _ = stdout_pipe.read_to_string(&mut buf);
+            buf
+        });
+        let stderr_reader = std::thread::spawn(move || {
+            let mut buf = String::new();
+            let _ = stderr_pipe.read_to_string(&mut buf);
+            buf
+        });
+
+        let status = crate::clock::poll_until(
+            TIMEOUT_POLL_INTERVAL,
+            timeout,
+            |gap| {
+                eprintln!(
+                    "warning: system may have slept for {gap:?} while waiting on '{}'; extending its timeout deadline accordingly",
+                    self.binary
+                );
+            },
+            || Ok(child.try_wait()?),
+        )?;
+
+        let Some(status) = status else {
+            kill_process_group(pgid);
+            let _ = child.wait();
+            let _ = stdout_reader.join();
+            let _ = stderr_reader.join();
+            return Err(HalleyError::Timeout {
+                minutes: timeout.as_secs() / 60,
+            });
+        };
+
+        Ok(CallOutput {
+            status: status.code().unwrap_or(-1),
+            stdout: stdout_reader.join().unwrap_or_default(),
+            stderr: stderr_reader.join().unwrap_or_default(),
+        })
+    }
+}
+
+/// Kills every process in `pgid` (the timed-out child and anything it
+/// spawned), via the `kill` binary rather than a `libc` dependency this
+/// crate doesn't otherwise need.
+fn kill_process_group(pgid: u32) {
+    let _ = std::process::Command::new("kill")
+        .arg("-KILL")
+        .arg(format!("-{pgid}"))
+        .status();
+}
+
+/// A pruning policy, translated 1:1 into restic's `--keep-*` flags.
+///
+/// Any field left `None` is simply omitted from the invocation, letting
+/// restic fall back to its own default of keeping everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    pub keep_last: Option<u32>,
+    pub keep_daily: Option<u32>,
+    pub keep_weekly: Option<u32>,
+    pub keep_monthly: Option<u32>,
+    pub keep_yearly: Option<u32>,
+    pub prune: bool,
+}
+
+impl Default for RetentionPolicy {
+    /// A conservative default used when a repo doesn't configure its own
+    /// retention: keep a generation's worth of snapshots without ever
+    /// pruning automatically.
+    fn default() -> Self {
+        Self {
+            keep_last: Some(10),
+            keep_daily: Some(7),
+            keep_weekly: Some(4),
+            keep_monthly: Some(6),
+            keep_yearly: Some(2),
+            prune: false,
+        }
+    }
+}
+
+/// Builds the argument list for `restic forget` from a [`RetentionPolicy`].
+///
+/// `tag` restricts forget to snapshots carrying it, so retention only ever
+/// touches snapshots Halley itself created. `hostname`, when set, restricts
+/// it further to snapshots recorded under that host, matching whatever
+/// `--host` the corresponding backup used.
+pub fn prepare_forget(policy: &RetentionPolicy, tag: &str, hostname: Option<&str>) -> Vec<String> {
+    let mut args = vec!["forget".to_string(), "--tag".to_string(), tag.to_string()];
+
+    if let Some(hostname) = hostname {
+        args.push("--host".to_string());
+        args.push(hostname.to_string());
+    }
+
+    let mut push = |flag: &str, value: Option<u32>| {
+        if let Some(v) = value {
+            args.push(flag.to_string());
+            args.push(v.to_string());
+        }
+    };
+    push("--keep-last", policy.keep_last);
+    push("--keep-daily", policy.keep_daily);
+    push("--keep-weekly", policy.keep_weekly);
+    push("--keep-monthly", policy.keep_monthly);
+    push("--keep-yearly", policy.keep_yearly);
+
+    if policy.prune {
+        args.push("--prune".to_string());
+    }
+    args
+}
+
+/// Runs `restic forget` against `repo` using the given retention policy,
+/// restricted to snapshots carrying `tag` (and, if set, `hostname`).
+pub fn forget<C: WrappedCall>(
+    call: &C,
+    policy: &RetentionPolicy,
+    tag: &str,
+    hostname: Option<&str>,
+) -> Result<(), HalleyError> {
+    let args = prepare_forget(policy, tag, hostname);
+    let out = call.call(&args)?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    Ok(())
+}
+
+/// Builds the argument list for a dry-run `restic forget --json`, previewing
+/// what a real forget would remove without touching the repository.
+pub fn prepare_forget_dry_run(policy: &RetentionPolicy, tag: &str, hostname: Option<&str>) -> Vec<String> {
+    let mut args = prepare_forget(policy, tag, hostname);
+    args.push("--dry-run".to_string());
+    args.push("--json".to_string());
+    args
+}
+
+/// One group from `restic forget --dry-run --json`'s output: the snapshots
+/// it would keep and remove for one tag/host/path combination.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ForgetGroup {
+    #[serde(default)]
+    pub keep: Vec<Snapshot>,
+    #[serde(default)]
+    pub remove: Vec<Snapshot>,
+}
+
+/// Previews `restic forget` without applying it, returning every snapshot
+/// across all groups that a real forget with the same arguments would
+/// remove.
+pub fn forget_dry_run<C: WrappedCall>(
+    call: &C,
+    policy: &RetentionPolicy,
+    tag: &str,
+    hostname: Option<&str>,
+) -> Result<Vec<Snapshot>, HalleyError> {
+    let args = prepare_forget_dry_run(policy, tag, hostname);
+    let out = call.call(&args)?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    let groups: Vec<ForgetGroup> = serde_json::from_str(&out.stdout)
+        .map_err(|e| HalleyError::Parse(format!("restic forget --dry-run: {e}")))?;
+    Ok(groups.into_iter().flat_map(|g| g.remove).collect())
+}
+
+/// Builds the argument list for `restic prune`.
+pub fn prepare_prune() -> Vec<String> {
+    vec!["prune".to_string()]
+}
+
+/// Runs `restic prune`, reclaiming space freed up by a prior `forget`.
+///
+/// Restic's own stderr and exit code are surfaced verbatim via
+/// [`HalleyError::Restic`] so the caller can decide how to report it.
+pub fn prune<C: WrappedCall>(call: &C) -> Result<(), HalleyError> {
+    let args = prepare_prune();
+    let out = call.call(&args)?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    Ok(())
+}
+
+/// Builds the argument list for `restic unlock`.
+pub fn prepare_unlock() -> Vec<String> {
+    vec!["unlock".to_string()]
+}
+
+/// Runs `restic unlock`, clearing a stale lock left behind by a previous
+/// run that was killed mid-backup.
+pub fn unlock<C: WrappedCall>(call: &C) -> Result<(), HalleyError> {
+    let args = prepare_unlock();
+    let out = call.call(&args)?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    Ok(())
+}
+
+/// Parsed output of `restic stats --json`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RepositoryStats {
+    pub total_size: u64,
+    pub total_file_count: u64,
+    #[serde(default)]
+    pub total_blob_count: u64,
+    #[serde(default)]
+    pub snapshots_count: u64,
+}
+
+/// Builds the argument list for `restic stats --json`.
+pub fn prepare_stats() -> Vec<String> {
+    vec!["stats".to_string(), "--json".to_string()]
+}
+
+/// Runs `restic stats` and parses its JSON output into [`RepositoryStats`].
+pub fn stats<C: WrappedCall>(call: &C) -> Result<RepositoryStats, HalleyError> {
+    let args = prepare_stats();
+    let out = call.call(&args)?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    serde_json::from_str(&out.stdout)
+        .map_err(|e| HalleyError::Parse(format!("restic stats: {e}")))
+}
+
+/// A single entry from `restic snapshots --json`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub short_id: String,
+    pub time: String,
+    #[serde(default)]
+    pub hostname: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+/// Builds the argument list for `restic snapshots --json`.
+pub fn prepare_snapshots() -> Vec<String> {
+    vec!["snapshots".to_string(), "--json".to_string()]
+}
+
+/// Lists every snapshot in the repository.
+pub fn snapshots<C: WrappedCall>(call: &C) -> Result<Vec<Snapshot>, HalleyError> {
+    let args = prepare_snapshots();
+    let out = call.call(&args)?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    serde_json::from_str(&out.stdout)
+        .map_err(|e| HalleyError::Parse(format!("restic snapshots: {e}")))
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Records every invocation it receives and replays a canned result.
+    ///
+    /// `results` lets a test script different outcomes for successive
+    /// calls (e.g. "backup succeeds, forget succeeds, prune fails"); once
+    /// exhausted, `result` is replayed for every remaining call.
+    pub struct MockCall {
+        pub calls: RefCell<Vec<Vec<String>>>,
+        pub result: CallOutput,
+        pub results: RefCell<std::collections::VecDeque<CallOutput>>,
+    }
+
+    impl MockCall {
+        /// A generic success, with `stdout` set to `"[]"` so a caller that
+        /// happens to parse it as JSON (e.g. [`super::snapshots`]) sees an
+        /// empty list rather than a parse error.
+        pub fn ok() -> Self {
+            Self {
+                calls: RefCell::new(Vec::new()),
+                result: CallOutput {
+                    status: 0,
+                    stdout: "[]".to_string(),
+                    ..Default::default()
+                },
+                results: RefCell::new(Default::default()),
+            }
+        }
+
+        /// A mock that replays `results` in order, then falls back to a
+        /// generic success for any further calls.
+        pub fn sequence(results: Vec<CallOutput>) -> Self {
+            Self {
+                calls: RefCell::new(Vec::new()),
+                result: CallOutput {
+                    status: 0,
+                    stdout: "[]".to_string(),
+                    ..Default::default()
+                },
+                results: RefCell::new(results.into()),
+            }
+        }
+    }
+
+    impl WrappedCall for MockCall {
+        fn call(&self, args: &[String]) -> Result<CallOutput, HalleyError> {
+            self.calls.borrow_mut().push(args.to_vec());
+            match self.results.borrow_mut().pop_front() {
+                Some(result) => Ok(result),
+                None => Ok(self.result.clone()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockCall;
+    use super::*;
+
+    #[test]
+    fn real_call_with_no_timeout_runs_to_completion() {
+        let call = RealCall {
+            binary: "echo".to_string(),
+            env: Vec::new(),
+            timeout: None,
+            extra_env_passthrough: Vec::new(),
+        };
+        let out = call.call(&["hello".to_string()]).unwrap();
+        assert!(out.success());
+        assert_eq!(out.stdout.trim(), "hello");
+    }
+
+    #[test]
+    fn real_call_reports_a_missing_binary_as_a_typed_error_not_a_generic_io_error() {
+        let call = RealCall {
+            binary: "halley-test-binary-that-does-not-exist".to_string(),
+            env: Vec::new(),
+            timeout: None,
+            extra_env_passthrough: Vec::new(),
+        };
+        let err = call.call(&["version".to_string()]).unwrap_err();
+        assert!(matches!(err, HalleyError::ResticNotAvailable { .. }));
+    }
+
+    #[test]
+    fn real_call_with_timeout_also_reports_a_missing_binary_as_a_typed_error() {
+        let call = RealCall {
+            binary: "halley-test-binary-that-does-not-exist".to_string(),
+            env: Vec::new(),
+            timeout: Some(std::time::Duration::from_secs(5)),
+            extra_env_passthrough: Vec::new(),
+        };
+        let err = call.call(&["version".to_string()]).unwrap_err();
+        assert!(matches!(err, HalleyError::ResticNotAvailable { .. }));
+    }
+
+    #[test]
+    fn real_call_within_the_timeout_still_succeeds() {
+        let call = RealCall {
+            binary: "sleep".to_string(),
+            env: Vec::new(),
+            timeout: Some(std::time::Duration::from_secs(5)),
+            extra_env_passthrough: Vec::new(),
+        };
+        let out = call.call(&["0".to_string()]).unwrap();
+        assert!(out.success());
+    }
+
+    #[test]
+    fn real_call_kills_a_hung_process_on_timeout() {
+        let call = RealCall {
+            binary: "sleep".to_string(),
+            env: Vec::new(),
+            timeout: Some(std::time::Duration::from_millis(300)),
+            extra_env_passthrough: Vec::new(),
+        };
+        let started = std::time::Instant::now();
+        let err = call.call(&["60".to_string()]).unwrap_err();
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert!(matches!(err, HalleyError::Timeout { .. }));
+    }
+
+    /// `CARGO_MANIFEST_DIR` is a real variable halley's own process has set
+    /// (cargo sets it when running the test binary, not just at compile
+    /// time), so it stands in for an arbitrary variable that happens to be
+    /// in halley's environment without halley itself having set it -- e.g.
+    /// the `AWS_PROFILE` scenario from the bug report this guards against.
+    #[test]
+    fn real_call_does_not_leak_arbitrary_variables_from_halleys_own_environment() {
+        assert!(std::env::var("CARGO_MANIFEST_DIR").is_ok());
+        let call = RealCall {
+            binary: "sh".to_string(),
+            env: Vec::new(),
+            timeout: None,
+            extra_env_passthrough: Vec::new(),
+        };
+        let out = call.call(&["-c".to_string(), "env".to_string()]).unwrap();
+        assert!(out.success());
+        assert!(!out.stdout.contains("CARGO_MANIFEST_DIR"));
+    }
+
+    #[test]
+    fn real_call_passes_through_env_and_allowlisted_variables() {
+        let call = RealCall {
+            binary: "sh".to_string(),
+            env: vec![("HALLEY_TEST_VAR".to_string(), "explicit".to_string())],
+            timeout: None,
+            extra_env_passthrough: vec!["CARGO_MANIFEST_DIR".to_string()],
+        };
+        let out = call.call(&["-c".to_string(), "env".to_string()]).unwrap();
+        assert!(out.success());
+        assert!(out.stdout.contains("HALLEY_TEST_VAR=explicit"));
+        assert!(out.stdout.contains(&format!(
+            "CARGO_MANIFEST_DIR={}",
+            std::env::var("CARGO_MANIFEST_DIR").unwrap()
+        )));
+    }
+
+    #[test]
+    fn prepare_forget_includes_only_set_fields() {
+        let policy = RetentionPolicy {
+            keep_last: Some(5),
+            keep_daily: None,
+            keep_weekly: None,
+            keep_monthly: Some(3),
+            keep_yearly: None,
+            prune: true,
+        };
+        let args = prepare_forget(&policy, "halley", None);
+        assert_eq!(
+            args,
+            vec![
+                "forget", "--tag", "halley", "--keep-last", "5", "--keep-monthly", "3", "--prune"
+            ]
+        );
+    }
+
+    #[test]
+    fn prepare_forget_includes_the_host_when_set() {
+        let policy = RetentionPolicy {
+            keep_last: Some(5),
+            keep_daily: None,
+            keep_weekly: None,
+            keep_monthly: None,
+            keep_yearly: None,
+            prune: false,
+        };
+        let args = prepare_forget(&policy, "halley", Some("laptop"));
+        assert_eq!(
+            args,
+            vec!["forget", "--tag", "halley", "--host", "laptop", "--keep-last", "5"]
+        );
+    }
+
+    #[test]
+    fn forget_calls_wrapped_call_with_prepared_args() {
+        let call = MockCall::ok();
+        forget(&call, &RetentionPolicy::default(), "halley", None).unwrap();
+        let calls = call.calls.borrow();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0][0], "forget");
+        assert_eq!(calls[0][1], "--tag");
+        assert_eq!(calls[0][2], "halley");
+    }
+
+    #[test]
+    fn forget_surfaces_restic_failure() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 1,
+                stderr: "Fatal: repository locked".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let err = forget(&call, &RetentionPolicy::default(), "halley", None).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { status: 1, .. }));
+    }
+
+    #[test]
+    fn prepare_forget_dry_run_appends_dry_run_and_json() {
+        let policy = RetentionPolicy {
+            keep_last: Some(5),
+            keep_daily: None,
+            keep_weekly: None,
+            keep_monthly: None,
+            keep_yearly: None,
+            prune: false,
+        };
+        let args = prepare_forget_dry_run(&policy, "halley", None);
+        assert_eq!(
+            args,
+            vec!["forget", "--tag", "halley", "--keep-last", "5", "--dry-run", "--json"]
+        );
+    }
+
+    #[test]
+    fn forget_dry_run_collects_removed_snapshots_across_groups() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 0,
+                stdout: r#"[
+                    {"keep": [], "remove": [{"id": "a", "short_id": "a", "time": "2026-01-01T00:00:00Z"}]},
+                    {"keep": [], "remove": [
+                        {"id": "b", "short_id": "b", "time": "2026-01-02T00:00:00Z"},
+                        {"id": "c", "short_id": "c", "time": "2026-01-03T00:00:00Z"}
+                    ]}
+                ]"#
+                .into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let removed = forget_dry_run(&call, &RetentionPolicy::default(), "halley", None).unwrap();
+        assert_eq!(removed.len(), 3);
+        assert_eq!(call.calls.borrow()[0].last().unwrap(), "--json");
+    }
+
+    #[test]
+    fn forget_dry_run_surfaces_restic_failure() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 1,
+                stderr: "Fatal: repository locked".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let err = forget_dry_run(&call, &RetentionPolicy::default(), "halley", None).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { status: 1, .. }));
+    }
+
+    #[test]
+    fn prune_calls_wrapped_call() {
+        let call = MockCall::ok();
+        prune(&call).unwrap();
+        assert_eq!(call.calls.borrow()[0], vec!["prune".to_string()]);
+    }
+
+    #[test]
+    fn prune_surfaces_restic_failure() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 1,
+                stderr: "Fatal: unable to allocate index".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let err = prune(&call).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { status: 1, .. }));
+    }
+
+    #[test]
+    fn prepare_unlock_has_no_flags() {
+        assert_eq!(prepare_unlock(), vec!["unlock"]);
+    }
+
+    #[test]
+    fn unlock_calls_wrapped_call() {
+        let call = MockCall::ok();
+        unlock(&call).unwrap();
+        assert_eq!(call.calls.borrow()[0], vec!["unlock".to_string()]);
+    }
+
+    #[test]
+    fn unlock_surfaces_restic_failure() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 1,
+                stderr: "Fatal: unable to remove lock".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let err = unlock(&call).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { status: 1, .. }));
+    }
+
+    #[test]
+    fn stats_parses_json_output() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 0,
+                stdout: r#"{"total_size":1024,"total_file_count":3,"total_blob_count":5,"snapshots_count":2}"#.into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let stats = stats(&call).unwrap();
+        assert_eq!(stats.total_size, 1024);
+        assert_eq!(stats.total_file_count, 3);
+        assert_eq!(stats.snapshots_count, 2);
+        assert_eq!(call.calls.borrow()[0], vec!["stats", "--json"]);
+    }
+
+    #[test]
+    fn stats_surfaces_malformed_json() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 0,
+                stdout: "not json".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        assert!(matches!(stats(&call).unwrap_err(), HalleyError::Parse(_)));
+    }
+
+    #[test]
+    fn stderr_tail_keeps_short_stderr_unchanged() {
+        let stderr = "line one\nline two\nline three";
+        assert_eq!(stderr_tail(stderr), stderr);
+    }
+
+    #[test]
+    fn stderr_tail_truncates_to_the_last_lines() {
+        let lines: Vec<String> = (1..=30).map(|n| format!("line {n}")).collect();
+        let stderr = lines.join("\n");
+        let tail = stderr_tail(&stderr);
+        assert!(!tail.contains("line 1\n"));
+        assert!(!tail.contains("line 10\n"));
+        assert!(tail.starts_with("line 11"));
+        assert!(tail.ends_with("line 30"));
+        assert_eq!(tail.lines().count(), STDERR_TAIL_LINES);
+    }
+
+    #[test]
+    fn into_restic_error_carries_only_the_stderr_tail() {
+        let lines: Vec<String> = (1..=25).map(|n| format!("line {n}")).collect();
+        let out = CallOutput {
+            status: 1,
+            stdout: String::new(),
+            stderr: lines.join("\n"),
+        };
+        let err = out.into_restic_error();
+        match err {
+            HalleyError::Restic { status, stderr } => {
+                assert_eq!(status, 1);
+                assert!(!stderr.contains("line 1\n"));
+                assert!(stderr.contains("line 25"));
+                assert_eq!(stderr.lines().count(), STDERR_TAIL_LINES);
+            }
+            other => panic!("expected HalleyError::Restic, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn snapshots_parses_json_array() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 0,
+                stdout: r#"[{"id":"abc123","short_id":"abc123","time":"2026-08-01T00:00:00Z","hostname":"box","tags":["halley"],"paths":["/home"]}]"#.into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let snaps = snapshots(&call).unwrap();
+        assert_eq!(snaps.len(), 1);
+        assert_eq!(snaps[0].short_id, "abc123");
+        assert_eq!(call.calls.borrow()[0], vec!["snapshots", "--json"]);
+    }
+}
@@ -0,0 +1,230 @@
+//! `restic restore`, for pulling a snapshot back out of a repository.
+
+use std::path::{Path, PathBuf};
+
+use crate::error::HalleyError;
+
+use super::WrappedCall;
+
+/// The snapshot ID `restic restore` accepts to mean "most recent".
+pub const LATEST_SNAPSHOT: &str = "latest";
+
+/// Options controlling a single `restic restore` invocation. Kept separate
+/// from [`super::BackupOptions`]: restic's restore-side ACL/sparse-file
+/// behaviour has no backup-side equivalent, so there's nothing to share.
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// Write restored files sparsely, via `--sparse`.
+    pub sparse: bool,
+    /// Additional raw flags (e.g. restic's platform-specific ACL restore
+    /// flags), passed through verbatim after `--sparse`. Each entry must
+    /// start with `--`; see [`crate::config::RepoConfig::validate`].
+    pub extra_flags: Vec<String>,
+}
+
+/// Builds the argument list for `restic restore`. `snapshot` defaults to
+/// [`LATEST_SNAPSHOT`] when `None`. `include` patterns are passed through
+/// as `--include`, in order, restricting the restore to matching paths.
+pub fn prepare_restore(
+    snapshot: Option<&str>,
+    target: &Path,
+    include: &[String],
+    options: &RestoreOptions,
+) -> Vec<String> {
+    let mut args = vec![
+        "restore".to_string(),
+        snapshot.unwrap_or(LATEST_SNAPSHOT).to_string(),
+        "--target".to_string(),
+        target.display().to_string(),
+    ];
+    if options.sparse {
+        args.push("--sparse".to_string());
+    }
+    args.extend(options.extra_flags.iter().cloned());
+    for pattern in include {
+        args.push("--include".to_string());
+        args.push(pattern.clone());
+    }
+    args
+}
+
+/// Restores `snapshot` (or the latest one) into `target`, creating `target`
+/// first if it doesn't exist yet.
+pub fn restore<C: WrappedCall>(
+    call: &C,
+    snapshot: Option<&str>,
+    target: &Path,
+    include: &[String],
+    options: &RestoreOptions,
+) -> Result<(), HalleyError> {
+    std::fs::create_dir_all(target)?;
+    let args = prepare_restore(snapshot, target, include, options);
+    let out = call.call(&args)?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    Ok(())
+}
+
+/// Convenience wrapper for the common "just give me the latest snapshot"
+/// case.
+pub fn restore_latest<C: WrappedCall>(
+    call: &C,
+    target: &Path,
+    include: &[String],
+    options: &RestoreOptions,
+) -> Result<(), HalleyError> {
+    restore(call, None, target, include, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::restic::mock::MockCall;
+
+    #[test]
+    fn prepare_restore_defaults_to_latest() {
+        let args = prepare_restore(
+            None,
+            &PathBuf::from("/tmp/restore"),
+            &[],
+            &RestoreOptions::default(),
+        );
+        assert_eq!(args, vec!["restore", "latest", "--target", "/tmp/restore"]);
+    }
+
+    #[test]
+    fn prepare_restore_uses_the_given_snapshot() {
+        let args = prepare_restore(
+            Some("abc123"),
+            &PathBuf::from("/tmp/restore"),
+            &[],
+            &RestoreOptions::default(),
+        );
+        assert_eq!(args, vec!["restore", "abc123", "--target", "/tmp/restore"]);
+    }
+
+    #[test]
+    fn prepare_restore_appends_include_patterns_in_order() {
+        let args = prepare_restore(
+            Some("abc123"),
+            &PathBuf::from("/tmp/restore"),
+            &[
+                "/home/user/docs".to_string(),
+                "/home/user/photos".to_string(),
+            ],
+            &RestoreOptions::default(),
+        );
+        assert_eq!(
+            args,
+            vec![
+                "restore",
+                "abc123",
+                "--target",
+                "/tmp/restore",
+                "--include",
+                "/home/user/docs",
+                "--include",
+                "/home/user/photos",
+            ]
+        );
+    }
+
+    #[test]
+    fn prepare_restore_appends_sparse_before_extra_flags_and_includes() {
+        let options = RestoreOptions {
+            sparse: true,
+            extra_flags: vec!["--no-lock".to_string()],
+        };
+        let args = prepare_restore(
+            Some("abc123"),
+            &PathBuf::from("/tmp/restore"),
+            &["/home/user/docs".to_string()],
+            &options,
+        );
+        assert_eq!(
+            args,
+            vec![
+                "restore",
+                "abc123",
+                "--target",
+                "/tmp/restore",
+                "--sparse",
+                "--no-lock",
+                "--include",
+                "/home/user/docs",
+            ]
+        );
+    }
+
+    #[test]
+    fn restore_creates_the_target_directory_and_calls_wrapped_call() {
+        let dir = std::env::temp_dir().join("halley-restore-target-test");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let call = MockCall::ok();
+        restore(
+            &call,
+            Some("abc123"),
+            &dir,
+            &[],
+            &RestoreOptions::default(),
+        )
+        .unwrap();
+
+        assert!(dir.is_dir());
+        assert_eq!(call.calls.borrow()[0][0], "restore");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restore_surfaces_restic_failure() {
+        let call = MockCall {
+            calls: Default::default(),
+            result: crate::restic::CallOutput {
+                status: 1,
+                stderr: "Fatal: no matching snapshot found".into(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        };
+        let dir = std::env::temp_dir().join("halley-restore-failure-test");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let err = restore(&call, None, &dir, &[], &RestoreOptions::default()).unwrap_err();
+        assert!(matches!(err, HalleyError::Restic { status: 1, .. }));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restore_latest_omits_a_snapshot_id() {
+        let dir = std::env::temp_dir().join("halley-restore-latest-test");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let call = MockCall::ok();
+        restore_latest(&call, &dir, &[], &RestoreOptions::default()).unwrap();
+        assert_eq!(
+            call.calls.borrow()[0],
+            vec!["restore", "latest", "--target", dir.display().to_string()]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restore_passes_sparse_and_extra_flags_through_to_the_wrapped_call() {
+        let call = MockCall::ok();
+        let dir = std::env::temp_dir().join("halley-restore-sparse-test");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let options = RestoreOptions {
+            sparse: true,
+            extra_flags: vec!["--no-lock".to_string()],
+        };
+        restore(&call, None, &dir, &[], &options).unwrap();
+        let calls = call.calls.borrow();
+        assert!(calls[0].iter().any(|a| a == "--sparse"));
+        assert!(calls[0].iter().any(|a| a == "--no-lock"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,192 @@
+//! Detecting and validating the installed `restic` version.
+//!
+//! Halley relies on flags and JSON output shapes that only exist from a
+//! certain restic version onward, so it's worth failing fast with a clear
+//! message rather than hitting a confusing "unknown flag" error mid-run.
+
+use crate::error::HalleyError;
+
+use super::WrappedCall;
+
+/// A parsed `major.minor.patch` restic version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+/// The oldest restic release Halley is tested against.
+pub const MIN_SUPPORTED_VERSION: Version = Version {
+    major: 0,
+    minor: 14,
+    patch: 0,
+};
+
+/// The oldest restic release that understands `--compression`. Below this,
+/// the flag is an unknown-flag error rather than a no-op, so it's worth
+/// gating on rather than always emitting it.
+pub const MIN_COMPRESSION_VERSION: Version = Version {
+    major: 0,
+    minor: 14,
+    patch: 0,
+};
+
+impl Version {
+    pub fn is_supported(&self) -> bool {
+        *self >= MIN_SUPPORTED_VERSION
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Builds the argument list for `restic version`.
+pub fn prepare_version() -> Vec<String> {
+    vec!["version".to_string()]
+}
+
+/// Runs `restic version` and parses the version number out of its output,
+/// e.g. `restic 0.16.4 compiled with go1.21.5 on linux/amd64`.
+pub fn version<C: WrappedCall>(call: &C) -> Result<Version, HalleyError> {
+    let out = call.call(&prepare_version())?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    parse_version(&out.stdout)
+}
+
+fn parse_version(output: &str) -> Result<Version, HalleyError> {
+    let token = output
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| HalleyError::Parse("no version token in restic version output".into()))?;
+
+    let mut parts = token.split('.');
+    let mut next_component = || {
+        parts
+            .next()
+            .and_then(|s| s.parse::<u32>().ok())
+            .ok_or_else(|| HalleyError::Parse(format!("unparsable restic version '{token}'")))
+    };
+    Ok(Version {
+        major: next_component()?,
+        minor: next_component()?,
+        patch: next_component()?,
+    })
+}
+
+/// The Go runtime a restic binary was compiled with, e.g. `go1.21.5` in
+/// `restic 0.16.4 compiled with go1.21.5 on linux/amd64`. Only major/minor
+/// matter for feature gating, so the patch component isn't kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GoVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+/// Extracts the Go runtime version from a `restic version` output line.
+/// Returns `None` if the line doesn't have the expected "compiled with
+/// goX.Y..." shape, e.g. a custom restic build.
+pub fn parse_go_version(output: &str) -> Option<GoVersion> {
+    let token = output
+        .split_whitespace()
+        .skip_while(|word| *word != "with")
+        .nth(1)?
+        .strip_prefix("go")?;
+    let mut parts = token.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some(GoVersion { major, minor })
+}
+
+/// Runs `restic version` and extracts the Go runtime it was compiled with.
+pub fn go_version<C: WrappedCall>(call: &C) -> Result<Option<GoVersion>, HalleyError> {
+    let out = call.call(&prepare_version())?;
+    if !out.success() {
+        return Err(out.into_restic_error());
+    }
+    Ok(parse_go_version(&out.stdout))
+}
+
+/// Runs `restic version` and returns an error if it's older than
+/// [`MIN_SUPPORTED_VERSION`].
+pub fn ensure_supported_version<C: WrappedCall>(call: &C) -> Result<Version, HalleyError> {
+    let version = version(call)?;
+    if !version.is_supported() {
+        return Err(HalleyError::Parse(format!(
+            "restic {}.{}.{} is older than the minimum supported version {}.{}.{}",
+            version.major,
+            version.minor,
+            version.patch,
+            MIN_SUPPORTED_VERSION.major,
+            MIN_SUPPORTED_VERSION.minor,
+            MIN_SUPPORTED_VERSION.patch,
+        )));
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::restic::mock::MockCall;
+    use crate::restic::CallOutput;
+
+    fn call_with_output(stdout: &str) -> MockCall {
+        MockCall {
+            calls: Default::default(),
+            result: CallOutput {
+                status: 0,
+                stdout: stdout.to_string(),
+                ..Default::default()
+            },
+            results: Default::default(),
+        }
+    }
+
+    #[test]
+    fn parses_a_typical_version_line() {
+        let call = call_with_output("restic 0.16.4 compiled with go1.21.5 on linux/amd64\n");
+        let version = version(&call).unwrap();
+        assert_eq!(version, Version { major: 0, minor: 16, patch: 4 });
+    }
+
+    #[test]
+    fn ensure_supported_version_rejects_old_restic() {
+        let call = call_with_output("restic 0.9.6 compiled with go1.13 on linux/amd64\n");
+        assert!(ensure_supported_version(&call).is_err());
+    }
+
+    #[test]
+    fn ensure_supported_version_accepts_current_restic() {
+        let call = call_with_output("restic 0.17.0 compiled with go1.22 on linux/amd64\n");
+        assert!(ensure_supported_version(&call).is_ok());
+    }
+
+    #[test]
+    fn version_displays_as_major_minor_patch() {
+        let version = Version { major: 0, minor: 16, patch: 4 };
+        assert_eq!(version.to_string(), "0.16.4");
+    }
+
+    #[test]
+    fn parses_go_version_from_a_typical_version_line() {
+        let go = parse_go_version("restic 0.16.4 compiled with go1.21.5 on linux/amd64\n");
+        assert_eq!(go, Some(GoVersion { major: 1, minor: 21 }));
+    }
+
+    #[test]
+    fn parse_go_version_returns_none_without_a_compiler_line() {
+        assert_eq!(parse_go_version("restic 0.16.4\n"), None);
+    }
+
+    #[test]
+    fn go_version_reads_it_via_wrapped_call() {
+        let call = call_with_output("restic 0.16.4 compiled with go1.19.0 on linux/amd64\n");
+        assert_eq!(go_version(&call).unwrap(), Some(GoVersion { major: 1, minor: 19 }));
+    }
+}
@@ -0,0 +1,180 @@
+//! Categorizes the non-fatal `error` messages restic can emit mid-backup
+//! (see [`super::backup::BackupWarning`]) into buckets worth calling out in
+//! a run report, so a permission problem on a FUSE mount or a file that
+//! changed mid-read doesn't just scroll past in the log.
+
+use serde::{Deserialize, Serialize};
+
+use super::backup::{BackupEvent, BackupWarning};
+
+/// How many raw warning messages [`summarize`] keeps a sample of, regardless
+/// of how many actually occurred. Enough to see a representative few
+/// without a chatty backup bloating the statefile.
+pub const SAMPLE_CAP: usize = 5;
+
+/// What kind of problem a [`BackupWarning`] describes, guessed from its
+/// message text -- restic doesn't give these a structured type of their
+/// own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningCategory {
+    /// A permissions error reading the source, e.g. "permission denied".
+    Permission,
+    /// A source entry restic can't back up at all, e.g. a socket or FIFO
+    /// ("unsupported node type"/"unsupported file type").
+    UnsupportedNodeType,
+    /// The file was modified while restic was reading it, e.g. "file
+    /// changed"/"file size changed".
+    ChangedDuringRead,
+    /// A network filesystem hiccup, e.g. "i/o timeout"/"connection reset"/
+    /// "network is unreachable".
+    NetworkStall,
+    /// Doesn't match any of the above.
+    Other,
+}
+
+/// Guesses `message`'s [`WarningCategory`] from a handful of substrings
+/// restic's own wording tends to use. Falls back to
+/// [`WarningCategory::Other`] rather than guessing wrong.
+pub fn categorize(message: &str) -> WarningCategory {
+    let lower = message.to_lowercase();
+    if lower.contains("permission denied") || lower.contains("access is denied") {
+        WarningCategory::Permission
+    } else if lower.contains("unsupported node type") || lower.contains("unsupported file type") {
+        WarningCategory::UnsupportedNodeType
+    } else if lower.contains("file changed") || lower.contains("file size changed") || lower.contains("has changed") {
+        WarningCategory::ChangedDuringRead
+    } else if lower.contains("i/o timeout")
+        || lower.contains("connection reset")
+        || lower.contains("network is unreachable")
+        || lower.contains("no route to host")
+    {
+        WarningCategory::NetworkStall
+    } else {
+        WarningCategory::Other
+    }
+}
+
+/// Categorized warning counts from one backup run, plus a capped sample of
+/// the raw messages for a human to skim. Attached to
+/// [`crate::engine::RunOutcome::warnings`] and persisted alongside it in
+/// [`crate::state::BackupOutcome::Success`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WarningSummary {
+    #[serde(default)]
+    pub permission: usize,
+    #[serde(default)]
+    pub unsupported_node_type: usize,
+    #[serde(default)]
+    pub changed_during_read: usize,
+    #[serde(default)]
+    pub network_stall: usize,
+    #[serde(default)]
+    pub other: usize,
+    /// Up to [`SAMPLE_CAP`] raw warning messages, in the order they were
+    /// emitted.
+    #[serde(default)]
+    pub sample: Vec<String>,
+}
+
+impl WarningSummary {
+    pub fn total(&self) -> usize {
+        self.permission + self.unsupported_node_type + self.changed_during_read + self.network_stall + self.other
+    }
+
+    fn record(&mut self, warning: &BackupWarning) {
+        match categorize(&warning.message) {
+            WarningCategory::Permission => self.permission += 1,
+            WarningCategory::UnsupportedNodeType => self.unsupported_node_type += 1,
+            WarningCategory::ChangedDuringRead => self.changed_during_read += 1,
+            WarningCategory::NetworkStall => self.network_stall += 1,
+            WarningCategory::Other => self.other += 1,
+        }
+        if self.sample.len() < SAMPLE_CAP {
+            self.sample.push(warning.message.clone());
+        }
+    }
+}
+
+/// Builds a [`WarningSummary`] from every [`BackupEvent::Error`] among
+/// `events`, in order.
+pub fn summarize(events: &[BackupEvent]) -> WarningSummary {
+    let mut summary = WarningSummary::default();
+    for event in events {
+        if let BackupEvent::Error(warning) = event {
+            summary.record(warning);
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn categorize_recognizes_a_permission_error() {
+        assert_eq!(categorize("lstat /mnt/foo: permission denied"), WarningCategory::Permission);
+    }
+
+    #[test]
+    fn categorize_recognizes_an_unsupported_node_type() {
+        assert_eq!(
+            categorize("/mnt/foo/socket: unsupported node type"),
+            WarningCategory::UnsupportedNodeType
+        );
+    }
+
+    #[test]
+    fn categorize_recognizes_a_changed_during_read_warning() {
+        assert_eq!(categorize("file /mnt/foo/db.sqlite has changed"), WarningCategory::ChangedDuringRead);
+    }
+
+    #[test]
+    fn categorize_recognizes_a_network_stall() {
+        assert_eq!(categorize("read /mnt/nfs/foo: i/o timeout"), WarningCategory::NetworkStall);
+        assert_eq!(categorize("dial tcp: connection reset by peer"), WarningCategory::NetworkStall);
+    }
+
+    #[test]
+    fn categorize_falls_back_to_other_for_an_unrecognized_message() {
+        assert_eq!(categorize("something restic has never said before"), WarningCategory::Other);
+    }
+
+    fn warning(message: &str) -> BackupWarning {
+        BackupWarning { item: "/mnt/foo".to_string(), message: message.to_string() }
+    }
+
+    #[test]
+    fn summarize_counts_each_category_across_events() {
+        let events = vec![
+            BackupEvent::Error(warning("permission denied")),
+            BackupEvent::Error(warning("permission denied")),
+            BackupEvent::Error(warning("unsupported node type")),
+            BackupEvent::Error(warning("i/o timeout")),
+            BackupEvent::Other,
+        ];
+        let summary = summarize(&events);
+        assert_eq!(summary.permission, 2);
+        assert_eq!(summary.unsupported_node_type, 1);
+        assert_eq!(summary.network_stall, 1);
+        assert_eq!(summary.total(), 4);
+    }
+
+    #[test]
+    fn summarize_caps_the_sample_without_dropping_the_count() {
+        let events: Vec<_> = (0..SAMPLE_CAP + 3)
+            .map(|i| BackupEvent::Error(warning(&format!("permission denied #{i}"))))
+            .collect();
+        let summary = summarize(&events);
+        assert_eq!(summary.total(), SAMPLE_CAP + 3);
+        assert_eq!(summary.sample.len(), SAMPLE_CAP);
+    }
+
+    #[test]
+    fn summarize_of_no_errors_is_empty() {
+        let events = vec![BackupEvent::Other];
+        let summary = summarize(&events);
+        assert_eq!(summary.total(), 0);
+        assert!(summary.sample.is_empty());
+    }
+}
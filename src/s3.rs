@@ -0,0 +1,678 @@
+//! Operations specific to S3-backed (Glacier-tiered) repositories: thawing
+//! archived objects before restic can read them, freezing them again
+//! afterwards, and guarding against runaway restore costs.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::config::{RepoConfig, S3RepoConfig};
+use crate::error::HalleyError;
+
+/// Caps the number of restore (thaw) requests a single run is allowed to
+/// issue against a bucket, so a bug or an unexpectedly large repo can't run
+/// up a surprise Glacier bill.
+#[derive(Debug, Clone, Copy)]
+pub struct RestoreBudget {
+    max_requests: u32,
+    used: u32,
+}
+
+impl RestoreBudget {
+    pub fn new(max_requests: u32) -> Self {
+        Self {
+            max_requests,
+            used: 0,
+        }
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.max_requests.saturating_sub(self.used)
+    }
+
+    /// Reserves `n` restore requests against the budget, failing without
+    /// mutating state if that would exceed the cap.
+    pub fn try_reserve(&mut self, n: u32) -> Result<(), HalleyError> {
+        if n > self.remaining() {
+            return Err(HalleyError::S3(format!(
+                "restore budget exceeded: {n} requested but only {} of {} remaining this run",
+                self.remaining(),
+                self.max_requests
+            )));
+        }
+        self.used += n;
+        Ok(())
+    }
+}
+
+/// Which Glacier retrieval speed a restore (thaw) request uses. `Expedited`
+/// is roughly 10x the cost of `Standard`, so it's worth gating behind
+/// [`expedited_restore_decision`] rather than letting a config typo run up a
+/// surprise bill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RetrievalTier {
+    #[default]
+    Standard,
+    Expedited,
+    Bulk,
+}
+
+/// What [`expedited_restore_decision`] decided, given a restore's tier and
+/// object count against `S3RepoConfig::expedited_restore_confirm_above`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreConfirmationDecision {
+    /// Not `Expedited`, confirmation disabled, no threshold configured, or
+    /// under the threshold: proceed with the restore as configured.
+    Proceed,
+    /// `Expedited` and at or above the configured threshold: the restore
+    /// should be skipped until a human confirms it.
+    RequiresConfirmation { object_count: u64 },
+}
+
+/// Decides whether an `Expedited` restore of `object_count` objects is clear
+/// to run automatically. Only ever requires confirmation for `Expedited`
+/// (`Standard`/`Bulk` are cheap enough not to gate); `confirm_expedited`
+/// lets a repo disable the gate entirely, and `expedited_restore_confirm_above`
+/// (`None` disables it the same way) sets how many objects triggers it.
+pub fn expedited_restore_decision(
+    tier: RetrievalTier,
+    object_count: u64,
+    confirm_expedited: bool,
+    expedited_restore_confirm_above: Option<u32>,
+) -> RestoreConfirmationDecision {
+    if tier != RetrievalTier::Expedited || !confirm_expedited {
+        return RestoreConfirmationDecision::Proceed;
+    }
+    match expedited_restore_confirm_above {
+        Some(threshold) if object_count >= u64::from(threshold) => {
+            RestoreConfirmationDecision::RequiresConfirmation { object_count }
+        }
+        _ => RestoreConfirmationDecision::Proceed,
+    }
+}
+
+/// One page of listed object keys, as a real listing call (once one
+/// exists) would yield from a single `ListObjectsV2` request.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectPage {
+    pub keys: Vec<String>,
+    pub common_prefix: Option<String>,
+}
+
+/// A running snapshot of pagination progress, emitted after each page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingProgress {
+    pub pages_fetched: u32,
+    pub objects_so_far: u64,
+    pub current_prefix: Option<String>,
+}
+
+/// Walks `pages` iteratively, invoking `on_progress` once per page with a
+/// running total, and returns the final tally.
+///
+/// Halley has no S3 object-listing call yet ([`RestoreBudget`] only guards
+/// restore requests once objects are already known), so this is the
+/// self-contained counting half of that future integration: whatever
+/// eventually fetches pages from S3 can drive this loop directly rather
+/// than recursing into itself for "fetch the next page", which would risk
+/// a stack blowup against a bucket with millions of objects.
+pub fn track_listing_progress<'a>(
+    pages: impl IntoIterator<Item = &'a ObjectPage>,
+    mut on_progress: impl FnMut(ListingProgress),
+) -> ListingProgress {
+    let mut progress = ListingProgress {
+        pages_fetched: 0,
+        objects_so_far: 0,
+        current_prefix: None,
+    };
+    for page in pages {
+        progress.pages_fetched += 1;
+        progress.objects_so_far += page.keys.len() as u64;
+        progress.current_prefix = page.common_prefix.clone();
+        on_progress(progress.clone());
+    }
+    progress
+}
+
+/// Describes a disaster-recovery clone of one repository's objects into a
+/// scratch bucket: copy everything (optionally under `prefix`) from
+/// `source_bucket` into `target_bucket`, leaving the original untouched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloneRequest {
+    pub source_bucket: String,
+    pub target_bucket: String,
+    pub prefix: Option<String>,
+}
+
+/// A frozen (Glacier-tiered) object can't be the source of a cross-bucket
+/// `CopyObject` call — S3 refuses it until the object is thawed — and
+/// Halley has no per-object frozen/thawed tracking to skip objects that
+/// happen to already be readable (see [`ObjectPage`]'s note that there's no
+/// listing call yet either). So a clone always restores the whole source
+/// bucket first, via the same [`crate::cold_storage::ColdStorageBackend`]
+/// used for a normal thaw, rather than trying to copy selectively.
+pub const CLONE_REQUIRES_A_FULL_THAW_FIRST: bool = true;
+
+/// Splits `keys` into batches of at most `batch_size`, so a future
+/// multipart-capable copier can run each batch concurrently instead of
+/// copying one object at a time. `batch_size` of `0` is treated as `1`
+/// rather than looping forever.
+pub fn plan_clone_fanout(keys: &[String], batch_size: usize) -> Vec<Vec<String>> {
+    let batch_size = batch_size.max(1);
+    keys.chunks(batch_size).map(|chunk| chunk.to_vec()).collect()
+}
+
+/// Renders a `[[repos]]` TOML block for a clone's target bucket, so a DR
+/// drill has something to validate and run `restic restore` against
+/// without hand-writing a repo definition from scratch.
+///
+/// `source.repo` is treated as an opaque restic backend string that
+/// happens to contain the source bucket's name (as an `s3:` URL normally
+/// would); `request.source_bucket` is substituted for `request.target_bucket`
+/// within it verbatim. This is a best-effort rewrite, not a real URL
+/// parse — the caller should check the emitted `repo` line before trusting
+/// it, especially if the bucket name also appears somewhere unrelated in
+/// the string.
+///
+/// Deliberately omits `source.password`/`source.password_command`: nothing
+/// that writes a file to disk should carry a repository's actual secret,
+/// the same reasoning [`crate::debug_dump::sanitized_config`] applies to a
+/// debug dump. The generated block always needs a password source filled
+/// in by hand.
+pub fn generate_clone_repo_config(
+    source: &RepoConfig,
+    request: &CloneRequest,
+    new_name: &str,
+) -> String {
+    let repo = source
+        .repo
+        .replace(&request.source_bucket, &request.target_bucket);
+    let mut out = String::new();
+    out.push_str("[[repos]]\n");
+    out.push_str(&format!("name = \"{new_name}\"\n"));
+    out.push_str(&format!("repo = \"{repo}\"\n"));
+    out.push_str("# Fill in a password source before using this repo -- the source repo's\n");
+    out.push_str("# password is deliberately not copied into this generated file.\n");
+    out.push_str("password_file = \"/etc/halley/dr-clone.pass\"\n");
+    if !source.sources.is_empty() {
+        let sources: Vec<String> = source
+            .sources
+            .iter()
+            .map(|p| format!("\"{}\"", p.display()))
+            .collect();
+        out.push_str(&format!("sources = [{}]\n", sources.join(", ")));
+    }
+    if let Some(prefix) = &request.prefix {
+        out.push_str(&format!("# clone limited to prefix: {prefix}\n"));
+    }
+    out.push_str("\n[repos.s3]\n");
+    out.push_str(&format!("bucket = \"{}\"\n", request.target_bucket));
+    let max_restore = source
+        .s3
+        .as_ref()
+        .map(|s3| s3.max_restore_requests_per_run)
+        .unwrap_or(5);
+    out.push_str(&format!("max_restore_requests_per_run = {max_restore}\n"));
+    out
+}
+
+/// Resolved AWS credentials for talking to a bucket, however they were
+/// obtained -- inline in config or from `credential_command`'s stdout.
+///
+/// No `Debug` impl, for the same reason [`crate::config::S3RepoConfig`]'s
+/// manual one redacts these values: an incidental `{:?}` print must not be
+/// able to leak `secret`/`token`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct S3Credentials {
+    pub id: String,
+    pub secret: String,
+    pub token: Option<String>,
+}
+
+/// The JSON shape `credential_command` must print on stdout.
+#[derive(Deserialize)]
+struct CredentialCommandOutput {
+    id: String,
+    secret: String,
+    #[serde(default)]
+    token: Option<String>,
+}
+
+/// How long `credential_command` is allowed to run before it's treated as
+/// hung. Short and fixed, unlike `pre_hook`/`post_hook`'s configurable
+/// timeout: a credential helper is expected to be a fast local lookup (a
+/// password manager call, a metadata-service request), not a long-running
+/// job.
+const CREDENTIAL_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Parses `raw` as the same `{"id": ..., "secret": ..., "token": null}`
+/// shape both `credential_command` and `credential_source` produce.
+/// `described_by` names whichever of the two produced `raw`, for an error
+/// that points at the misconfiguration without echoing the secret itself.
+fn parse_credential_json(raw: &str, described_by: &str) -> Result<S3Credentials, HalleyError> {
+    let parsed: CredentialCommandOutput = serde_json::from_str(raw)
+        .map_err(|_| HalleyError::S3(format!("{described_by} did not produce valid JSON credentials")))?;
+    Ok(S3Credentials {
+        id: parsed.id,
+        secret: parsed.secret,
+        token: parsed.token,
+    })
+}
+
+/// Resolves a repo's AWS credentials: inline `access_key_id`/
+/// `secret_access_key` if both are set, `credential_command`'s JSON output
+/// if that's set instead, `credential_source` resolved through halley's own
+/// secret provider (see [`crate::secret::RealSecretProvider`]) and parsed
+/// as the same JSON shape if that's set instead, or `None` if none of the
+/// three is configured -- leaving restic's own AWS credential resolution
+/// (environment, instance role) in place.
+/// [`crate::config::RepoConfig::validate`] warns if more than one is set.
+///
+/// `credential_command`/`credential_source` are resolved once per call, the
+/// same way they would be at handler construction and again before each
+/// restic invocation, rather than cached -- a credential helper backing
+/// temporary credentials is expected to hand back fresh ones on every call.
+/// Neither's output is ever written to disk or logged; any error names the
+/// command/source itself, never what it printed, since that output might
+/// contain the very secret that failed to parse.
+pub fn resolve_credentials(config: &S3RepoConfig) -> Result<Option<S3Credentials>, HalleyError> {
+    if let (Some(id), Some(secret)) = (&config.access_key_id, &config.secret_access_key) {
+        return Ok(Some(S3Credentials {
+            id: id.clone(),
+            secret: secret.clone(),
+            token: config.session_token.clone(),
+        }));
+    }
+    if let Some(command) = &config.credential_command {
+        let output = crate::util::run_hook(command, &[], Some(CREDENTIAL_COMMAND_TIMEOUT)).map_err(|e| match e {
+            HalleyError::Timeout { minutes } => HalleyError::S3(format!(
+                "credential command `{command}` timed out after {minutes} minute(s)"
+            )),
+            other => other,
+        })?;
+        if !output.success() {
+            return Err(HalleyError::S3(format!(
+                "credential command `{command}` exited with status {}",
+                output.status
+            )));
+        }
+        return parse_credential_json(&output.stdout, &format!("credential command `{command}`")).map(Some);
+    }
+    let Some(source) = &config.credential_source else {
+        return Ok(None);
+    };
+    let secret = crate::secret::RealSecretProvider.resolve(source)?;
+    parse_credential_json(secret.expose(), "credential_source").map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::restic::{RetentionPolicy, SymlinkPolicy};
+    use std::path::PathBuf;
+
+    fn minimal_repo(name: &str, repo: &str, bucket: Option<&str>) -> RepoConfig {
+        RepoConfig {
+            name: name.into(),
+            sources: vec![PathBuf::from("/home/user")],
+            repo: repo.into(),
+            retention: RetentionPolicy::default(),
+            prune: false,
+            changed_during_backup: Default::default(),
+            max_verify_age_days: None,
+            symlinks: SymlinkPolicy::default(),
+            compression: None,
+            no_scan: false,
+            read_concurrency: None,
+            excludes: vec![],
+            exclude_file: None,
+            digest_ignore: Vec::new(),
+            strict_paths: false,
+            tags: vec![],
+            check_before_backup: false,
+            restic_memory_limit_mb: None,
+            auto_init: false,
+            password: Some("testpass".to_string()),
+            password_file: None,
+            password_command: None,
+            password_source: None,
+            limit_upload: None,
+            limit_download: None,
+            allow_initial_backup: false,
+            first_backup_size_threshold_mb: None,
+            cache_dir: None,
+            no_cache: false,
+            restore_sparse: false,
+            restore_flags: vec![],
+            hostname: None,
+            one_file_system: false,
+            auto_unlock_stale: false,
+            max_auto_forget: None,
+            warning_threshold: None,
+            s3: bucket.map(|bucket| crate::config::S3RepoConfig {
+                bucket: bucket.into(),
+                max_restore_requests_per_run: 7,
+                restore_tier: RetrievalTier::Standard,
+                expedited_restore_confirm_above: None,
+                confirm_expedited: true,
+                on_archive_complete: None,
+                on_restore_complete: None,
+                access_key_id: None,
+                secret_access_key: None,
+                session_token: None,
+                credential_command: None,
+                credential_source: None,
+            }),
+            cold_storage_command: None,
+            pre_hook: None,
+            post_hook: None,
+            archive_after_failed_backup: false,
+            archive_delay_hours: None,
+            archive_unverified: false,
+            min_backup_interval_hours: None,
+            max_backup_interval_days: None,
+            extra_env_passthrough: Vec::new(),
+            allow_network_sources: false,
+        }
+    }
+
+    #[test]
+    fn plan_clone_fanout_chunks_keys_into_batches() {
+        let keys: Vec<String> = (0..5).map(|i| format!("obj-{i}")).collect();
+        let batches = plan_clone_fanout(&keys, 2);
+        assert_eq!(
+            batches,
+            vec![
+                vec!["obj-0".to_string(), "obj-1".to_string()],
+                vec!["obj-2".to_string(), "obj-3".to_string()],
+                vec!["obj-4".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_clone_fanout_treats_a_zero_batch_size_as_one() {
+        let keys = vec!["a".to_string(), "b".to_string()];
+        let batches = plan_clone_fanout(&keys, 0);
+        assert_eq!(batches, vec![vec!["a".to_string()], vec!["b".to_string()]]);
+    }
+
+    #[test]
+    fn plan_clone_fanout_on_no_keys_returns_no_batches() {
+        let keys: Vec<String> = vec![];
+        assert_eq!(plan_clone_fanout(&keys, 4), Vec::<Vec<String>>::new());
+    }
+
+    #[test]
+    fn expedited_restore_decision_proceeds_for_standard_and_bulk_regardless_of_count() {
+        assert_eq!(
+            expedited_restore_decision(RetrievalTier::Standard, 1_000_000, true, Some(1)),
+            RestoreConfirmationDecision::Proceed
+        );
+        assert_eq!(
+            expedited_restore_decision(RetrievalTier::Bulk, 1_000_000, true, Some(1)),
+            RestoreConfirmationDecision::Proceed
+        );
+    }
+
+    #[test]
+    fn expedited_restore_decision_proceeds_under_the_threshold() {
+        assert_eq!(
+            expedited_restore_decision(RetrievalTier::Expedited, 99, true, Some(100)),
+            RestoreConfirmationDecision::Proceed
+        );
+    }
+
+    #[test]
+    fn expedited_restore_decision_requires_confirmation_at_or_above_the_threshold() {
+        assert_eq!(
+            expedited_restore_decision(RetrievalTier::Expedited, 100, true, Some(100)),
+            RestoreConfirmationDecision::RequiresConfirmation { object_count: 100 }
+        );
+    }
+
+    #[test]
+    fn expedited_restore_decision_proceeds_when_confirmation_is_disabled() {
+        assert_eq!(
+            expedited_restore_decision(RetrievalTier::Expedited, 1_000_000, false, Some(1)),
+            RestoreConfirmationDecision::Proceed
+        );
+    }
+
+    #[test]
+    fn expedited_restore_decision_proceeds_when_no_threshold_is_configured() {
+        assert_eq!(
+            expedited_restore_decision(RetrievalTier::Expedited, 1_000_000, true, None),
+            RestoreConfirmationDecision::Proceed
+        );
+    }
+
+    #[test]
+    fn generate_clone_repo_config_rewrites_the_bucket_in_the_repo_string() {
+        let source = minimal_repo("prod", "s3:https://s3.amazonaws.com/prod-bucket/path", Some("prod-bucket"));
+        let request = CloneRequest {
+            source_bucket: "prod-bucket".to_string(),
+            target_bucket: "dr-scratch".to_string(),
+            prefix: None,
+        };
+        let toml = generate_clone_repo_config(&source, &request, "prod-dr-clone");
+        assert!(toml.contains("name = \"prod-dr-clone\"\n"));
+        assert!(toml.contains("repo = \"s3:https://s3.amazonaws.com/dr-scratch/path\"\n"));
+        assert!(toml.contains("bucket = \"dr-scratch\"\n"));
+        assert!(toml.contains("max_restore_requests_per_run = 7\n"));
+        assert!(toml.contains("sources = [\"/home/user\"]\n"));
+    }
+
+    #[test]
+    fn generate_clone_repo_config_never_carries_the_source_password() {
+        let source = minimal_repo("prod", "s3:https://s3.amazonaws.com/prod-bucket/path", Some("prod-bucket"));
+        let request = CloneRequest {
+            source_bucket: "prod-bucket".to_string(),
+            target_bucket: "dr-scratch".to_string(),
+            prefix: None,
+        };
+        let toml = generate_clone_repo_config(&source, &request, "prod-dr-clone");
+        assert!(!toml.contains("testpass"));
+        assert!(toml.contains("password_file ="));
+    }
+
+    #[test]
+    fn generate_clone_repo_config_notes_a_requested_prefix() {
+        let source = minimal_repo("prod", "s3:https://s3.amazonaws.com/prod-bucket", Some("prod-bucket"));
+        let request = CloneRequest {
+            source_bucket: "prod-bucket".to_string(),
+            target_bucket: "dr-scratch".to_string(),
+            prefix: Some("2026/".to_string()),
+        };
+        let toml = generate_clone_repo_config(&source, &request, "prod-dr-clone");
+        assert!(toml.contains("# clone limited to prefix: 2026/"));
+    }
+
+    #[test]
+    fn reserves_within_budget() {
+        let mut budget = RestoreBudget::new(10);
+        budget.try_reserve(4).unwrap();
+        budget.try_reserve(6).unwrap();
+        assert_eq!(budget.remaining(), 0);
+    }
+
+    #[test]
+    fn refuses_to_exceed_budget() {
+        let mut budget = RestoreBudget::new(10);
+        budget.try_reserve(8).unwrap();
+        let err = budget.try_reserve(3).unwrap_err();
+        assert!(matches!(err, HalleyError::S3(_)));
+        // The failed reservation must not have partially consumed the budget.
+        assert_eq!(budget.remaining(), 2);
+    }
+
+    #[test]
+    fn track_listing_progress_emits_a_running_total_per_page() {
+        let pages = vec![
+            ObjectPage {
+                keys: vec!["a".into(), "b".into()],
+                common_prefix: Some("2024/".into()),
+            },
+            ObjectPage {
+                keys: vec!["c".into()],
+                common_prefix: Some("2025/".into()),
+            },
+            ObjectPage {
+                keys: vec!["d".into(), "e".into(), "f".into()],
+                common_prefix: None,
+            },
+        ];
+        let mut seen = Vec::new();
+        let final_progress = track_listing_progress(&pages, |p| seen.push(p));
+        assert_eq!(
+            seen,
+            vec![
+                ListingProgress {
+                    pages_fetched: 1,
+                    objects_so_far: 2,
+                    current_prefix: Some("2024/".into()),
+                },
+                ListingProgress {
+                    pages_fetched: 2,
+                    objects_so_far: 3,
+                    current_prefix: Some("2025/".into()),
+                },
+                ListingProgress {
+                    pages_fetched: 3,
+                    objects_so_far: 6,
+                    current_prefix: None,
+                },
+            ]
+        );
+        assert_eq!(final_progress, seen[2].clone());
+    }
+
+    #[test]
+    fn track_listing_progress_on_no_pages_reports_zero() {
+        let pages: Vec<ObjectPage> = vec![];
+        let mut calls = 0;
+        let final_progress = track_listing_progress(&pages, |_| calls += 1);
+        assert_eq!(calls, 0);
+        assert_eq!(final_progress.pages_fetched, 0);
+        assert_eq!(final_progress.objects_so_far, 0);
+    }
+
+    fn s3_config(bucket: &str) -> S3RepoConfig {
+        S3RepoConfig {
+            bucket: bucket.into(),
+            max_restore_requests_per_run: 5,
+            restore_tier: RetrievalTier::Standard,
+            expedited_restore_confirm_above: None,
+            confirm_expedited: true,
+            on_archive_complete: None,
+            on_restore_complete: None,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            credential_command: None,
+            credential_source: None,
+        }
+    }
+
+    #[test]
+    fn resolve_credentials_with_neither_source_configured_is_none() {
+        assert!(resolve_credentials(&s3_config("bucket")).unwrap().is_none());
+    }
+
+    #[test]
+    fn resolve_credentials_prefers_inline_keys() {
+        let mut config = s3_config("bucket");
+        config.access_key_id = Some("AKIAINLINE".to_string());
+        config.secret_access_key = Some("inline-secret".to_string());
+        config.session_token = Some("inline-token".to_string());
+
+        let creds = resolve_credentials(&config).unwrap().unwrap();
+        assert_eq!(creds.id, "AKIAINLINE");
+        assert_eq!(creds.secret, "inline-secret");
+        assert_eq!(creds.token.as_deref(), Some("inline-token"));
+    }
+
+    #[test]
+    fn resolve_credentials_runs_a_helper_script_and_parses_its_json() {
+        let mut config = s3_config("bucket");
+        config.credential_command =
+            Some(r#"printf '{"id":"AKIAHELPER","secret":"helper-secret","token":"helper-token"}'"#.to_string());
+
+        let creds = resolve_credentials(&config).unwrap().unwrap();
+        assert_eq!(creds.id, "AKIAHELPER");
+        assert_eq!(creds.secret, "helper-secret");
+        assert_eq!(creds.token.as_deref(), Some("helper-token"));
+    }
+
+    #[test]
+    fn resolve_credentials_allows_a_missing_token() {
+        let mut config = s3_config("bucket");
+        config.credential_command =
+            Some(r#"printf '{"id":"AKIAHELPER","secret":"helper-secret","token":null}'"#.to_string());
+
+        let creds = resolve_credentials(&config).unwrap().unwrap();
+        assert_eq!(creds.token, None);
+    }
+
+    #[test]
+    fn resolve_credentials_surfaces_a_nonzero_exit_naming_the_command_not_its_output() {
+        let mut config = s3_config("bucket");
+        config.credential_command = Some("echo super-secret-leak >&2; exit 1".to_string());
+
+        let err = resolve_credentials(&config).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("credential command"));
+        assert!(!message.contains("super-secret-leak"));
+    }
+
+    #[test]
+    fn resolve_credentials_surfaces_invalid_json_naming_the_command_not_its_output() {
+        let mut config = s3_config("bucket");
+        config.credential_command = Some("printf 'not json but maybe a leaked secret'".to_string());
+
+        let err = resolve_credentials(&config).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("credential command"));
+        assert!(!message.contains("leaked secret"));
+    }
+
+    #[test]
+    fn resolve_credentials_resolves_a_credential_source_and_parses_its_json() {
+        let mut config = s3_config("bucket");
+        config.credential_source = Some(crate::secret::SecretSource::Inline(
+            r#"{"id":"AKIASOURCE","secret":"source-secret","token":"source-token"}"#.to_string(),
+        ));
+
+        let creds = resolve_credentials(&config).unwrap().unwrap();
+        assert_eq!(creds.id, "AKIASOURCE");
+        assert_eq!(creds.secret, "source-secret");
+        assert_eq!(creds.token.as_deref(), Some("source-token"));
+    }
+
+    #[test]
+    fn resolve_credentials_prefers_credential_command_over_credential_source() {
+        let mut config = s3_config("bucket");
+        config.credential_command =
+            Some(r#"printf '{"id":"AKIAHELPER","secret":"helper-secret","token":null}'"#.to_string());
+        config.credential_source = Some(crate::secret::SecretSource::Inline(
+            r#"{"id":"AKIASOURCE","secret":"source-secret","token":null}"#.to_string(),
+        ));
+
+        let creds = resolve_credentials(&config).unwrap().unwrap();
+        assert_eq!(creds.id, "AKIAHELPER");
+    }
+
+    #[test]
+    fn resolve_credentials_surfaces_invalid_json_from_a_credential_source_naming_the_source_not_its_output() {
+        let mut config = s3_config("bucket");
+        config.credential_source = Some(crate::secret::SecretSource::Inline(
+            "not json but maybe a leaked secret".to_string(),
+        ));
+
+        let err = resolve_credentials(&config).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("credential_source"));
+        assert!(!message.contains("leaked secret"));
+    }
+}
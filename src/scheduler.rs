@@ -0,0 +1,216 @@
+//! A generic priority scheduler: given a set of candidates, filters out
+//! ineligible ones and orders what's left by a documented scoring
+//! pipeline (eligibility filters, then ordering keys), so a caller juggling
+//! several competing selection concerns doesn't end up hand-rolling its own
+//! tangle of special cases.
+//!
+//! [`crate::engine::due_repos`] is the adapter that feeds it: the
+//! min-interval and failure-backoff checks decide `enabled`, and a failed or
+//! past-max-interval repo is `forced`. There are no groups, run windows or
+//! pauses, and every candidate's `priority` is left at `0`.
+
+/// One repository (or other schedulable unit) as the scheduler sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub name: String,
+    /// The one eligibility filter, which `forced` can't bypass.
+    /// [`crate::engine::due_repos`] clears it for a repo backed up too
+    /// recently or still backing off after failed backups.
+    pub enabled: bool,
+    /// Seconds since this candidate last ran (successfully or at all,
+    /// depending on what the caller feeds in). Larger sorts first among
+    /// otherwise-tied eligible candidates: the longest-overdue candidate
+    /// wins.
+    pub effective_age_secs: u64,
+    /// Lower runs first among candidates with the same effective age.
+    /// Defaults to `0` for callers with no notion of priority yet.
+    pub priority: i32,
+    /// Bypasses ordering by age/priority entirely and always sorts ahead of
+    /// every non-forced candidate, as long as it's still `enabled`.
+    pub forced: bool,
+}
+
+impl Candidate {
+    /// A candidate with no priority or forcing, only a name/enabled flag
+    /// and an effective age — the common case before those other knobs
+    /// exist anywhere in config.
+    pub fn new(name: impl Into<String>, enabled: bool, effective_age_secs: u64) -> Self {
+        Self {
+            name: name.into(),
+            enabled,
+            effective_age_secs,
+            priority: 0,
+            forced: false,
+        }
+    }
+}
+
+/// Picks a run order from a set of [`Candidate`]s.
+///
+/// Stateless by design: every input the scoring pipeline needs is on the
+/// candidate itself, so this can be unit-tested with plain data and no
+/// mock call layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Scheduler;
+
+impl Scheduler {
+    /// Eligibility filter: only `enabled` candidates ever run, forced or
+    /// not.
+    fn eligible(candidate: &Candidate) -> bool {
+        candidate.enabled
+    }
+
+    /// Ordering key: forced first, then oldest effective age, then lowest
+    /// priority number, then name as a stable, deterministic tie-break.
+    fn ordering_key(candidate: &Candidate) -> (std::cmp::Reverse<bool>, std::cmp::Reverse<u64>, i32, &str) {
+        (
+            std::cmp::Reverse(candidate.forced),
+            std::cmp::Reverse(candidate.effective_age_secs),
+            candidate.priority,
+            candidate.name.as_str(),
+        )
+    }
+
+    /// Returns the names of every eligible candidate, in the order they
+    /// should run.
+    pub fn next_up(candidates: &[Candidate]) -> Vec<String> {
+        let mut eligible: Vec<&Candidate> = candidates.iter().filter(|c| Self::eligible(c)).collect();
+        eligible.sort_by_key(|c| Self::ordering_key(c));
+        eligible.into_iter().map(|c| c.name.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_candidates_are_never_selected() {
+        let candidates = vec![Candidate::new("off", false, 1_000_000), Candidate::new("on", true, 1)];
+        assert_eq!(Scheduler::next_up(&candidates), vec!["on".to_string()]);
+    }
+
+    #[test]
+    fn oldest_effective_age_wins_among_equal_priority() {
+        let candidates = vec![
+            Candidate::new("younger", true, 100),
+            Candidate::new("older", true, 500),
+        ];
+        assert_eq!(
+            Scheduler::next_up(&candidates),
+            vec!["older".to_string(), "younger".to_string()]
+        );
+    }
+
+    #[test]
+    fn lower_priority_number_wins_at_equal_age() {
+        let mut low = Candidate::new("urgent", true, 100);
+        low.priority = 0;
+        let mut high = Candidate::new("routine", true, 100);
+        high.priority = 10;
+        assert_eq!(
+            Scheduler::next_up(&[high, low]),
+            vec!["urgent".to_string(), "routine".to_string()]
+        );
+    }
+
+    #[test]
+    fn ties_break_on_name_for_a_stable_order() {
+        let candidates = vec![Candidate::new("b", true, 100), Candidate::new("a", true, 100)];
+        assert_eq!(Scheduler::next_up(&candidates), vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn forced_candidate_runs_first_regardless_of_age_or_priority() {
+        let mut forced = Candidate::new("just_added", true, 1);
+        forced.forced = true;
+        let overdue = Candidate::new("overdue", true, 1_000_000);
+        assert_eq!(
+            Scheduler::next_up(&[overdue, forced]),
+            vec!["just_added".to_string(), "overdue".to_string()]
+        );
+    }
+
+    #[test]
+    fn forced_still_respects_enabled_false() {
+        let mut forced_but_disabled = Candidate::new("disabled", false, 1);
+        forced_but_disabled.forced = true;
+        let candidates = vec![forced_but_disabled, Candidate::new("normal", true, 1)];
+        assert_eq!(Scheduler::next_up(&candidates), vec!["normal".to_string()]);
+    }
+
+    #[test]
+    fn empty_candidate_list_selects_nothing() {
+        assert_eq!(Scheduler::next_up(&[]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn all_disabled_selects_nothing() {
+        let candidates = vec![Candidate::new("a", false, 100), Candidate::new("b", false, 200)];
+        assert_eq!(Scheduler::next_up(&candidates), Vec::<String>::new());
+    }
+
+    /// A tiny deterministic LCG, standing in for a property-testing library
+    /// this crate doesn't depend on, so the property below still runs many
+    /// randomized cases without adding a new dependency.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1);
+            self.0
+        }
+
+        fn next_range(&mut self, max: u64) -> u64 {
+            self.next_u64() % (max + 1)
+        }
+    }
+
+    #[test]
+    fn property_the_oldest_eligible_candidate_always_wins() {
+        let mut rng = Lcg(0xC0FFEE);
+        for round in 0u64..200 {
+            let count: u64 = 2 + (round % 6);
+            // Distinct ages so there's a single, unambiguous oldest
+            // candidate, with priority held uniform so it can't interfere.
+            let mut ages: Vec<u64> = (0..count).map(|i| i * 1000 + rng.next_range(999)).collect();
+            for i in 0..ages.len() {
+                let j = rng.next_range((ages.len() - 1) as u64) as usize;
+                ages.swap(i, j);
+            }
+            let candidates: Vec<Candidate> = ages
+                .iter()
+                .enumerate()
+                .map(|(i, age)| Candidate::new(format!("repo-{round}-{i}"), true, *age))
+                .collect();
+            let expected_first = candidates
+                .iter()
+                .max_by_key(|c| c.effective_age_secs)
+                .unwrap()
+                .name
+                .clone();
+            let order = Scheduler::next_up(&candidates);
+            assert_eq!(order[0], expected_first);
+        }
+    }
+
+    #[test]
+    fn property_a_forced_candidate_always_beats_every_non_forced_one() {
+        let mut rng = Lcg(0xF00D);
+        for round in 0u64..200 {
+            let count: u64 = 2 + (round % 6);
+            let forced_index = rng.next_range(count - 1);
+            let candidates: Vec<Candidate> = (0..count)
+                .map(|i| {
+                    let mut c = Candidate::new(format!("repo-{round}-{i}"), true, rng.next_range(1_000_000));
+                    c.priority = rng.next_range(5) as i32;
+                    c.forced = i == forced_index;
+                    c
+                })
+                .collect();
+            let forced_name = candidates[forced_index as usize].name.clone();
+            let order = Scheduler::next_up(&candidates);
+            assert_eq!(order[0], forced_name);
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Unifies the ad-hoc ways Halley obtains a secret -- inline config, a
+//! file, a shell command, an environment variable, an age-encrypted file --
+//! behind one [`SecretProvider`] trait, resolved through [`SecretSource`].
+//!
+//! This doesn't replace the existing `password`/`password_file`/
+//! `password_command` fields on [`crate::config::RepoConfig`], or
+//! `credential_command` on [`crate::config::S3RepoConfig`]: those hand the
+//! *pointer* to a secret (a path, a command) to restic or are parsed into
+//! their own multi-field shape, and resolving them here first would either
+//! put a password in halley's own environment needlessly (see
+//! [`crate::config::RepoConfig::password_env`]) or not fit a single string.
+//! [`SecretSource`] is for a new source of one, e.g.
+//! [`crate::config::RepoConfig::password_source`]'s `age` variant, for
+//! which there's no existing ad-hoc mechanism to defer to.
+//!
+//! [`crate::config::RepoConfig::password_env`]: crate::config::RepoConfig::password_env
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::HalleyError;
+use crate::util;
+
+/// How long an [`SecretSource::Command`] or [`SecretSource::Age`] decrypt is
+/// allowed to run before it's treated as hung. Same rationale as
+/// [`crate::s3::resolve_credentials`]'s `credential_command` timeout: a
+/// secret lookup is expected to be a fast local operation, not a
+/// long-running job.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where a secret's value comes from, accepted anywhere Halley needs one
+/// outside of handing a pointer to restic directly.
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SecretSource {
+    /// The value itself, inline in config. Prefer one of the other
+    /// variants where possible -- see
+    /// [`crate::config::RepoConfig::password`] for why.
+    Inline(String),
+    /// The contents of a file, trimmed of a trailing newline.
+    File(PathBuf),
+    /// Stdout of a shell command (run via [`util::run_hook`]), trimmed of a
+    /// trailing newline.
+    Command(String),
+    /// An environment variable name, read from halley's own environment at
+    /// resolve time. Distinct from `extra_env_passthrough`, which passes a
+    /// variable through to restic rather than reading it for halley's own
+    /// use.
+    Env(String),
+    /// An age-encrypted file, decrypted with `age --decrypt -i <identity>`.
+    Age { file: PathBuf, identity: PathBuf },
+}
+
+impl std::fmt::Debug for SecretSource {
+    /// Redacts the value carried by `Inline`, the only variant that holds
+    /// the secret itself rather than a pointer to it.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecretSource::Inline(_) => f.write_str("Inline(REDACTED)"),
+            SecretSource::File(path) => f.debug_tuple("File").field(path).finish(),
+            SecretSource::Command(command) => f.debug_tuple("Command").field(command).finish(),
+            SecretSource::Env(name) => f.debug_tuple("Env").field(name).finish(),
+            SecretSource::Age { file, identity } => f
+                .debug_struct("Age")
+                .field("file", file)
+                .field("identity", identity)
+                .finish(),
+        }
+    }
+}
+
+/// A resolved secret value, zeroized on drop so it doesn't linger in memory
+/// past its last use. Hand-rolled rather than pulling in the `secrecy`
+/// crate -- the footprint here is a single `String`, and Halley already
+/// prefers a page of code over a dependency for something this small (see
+/// [`crate::lock`] doing its own advisory locking rather than reaching for
+/// `fs2`).
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // Best-effort: overwrites every byte before the allocation is
+        // freed. Unlike a real zeroizing crate, nothing here stops the
+        // compiler from proving the write is dead and eliding it -- this
+        // guards against an incidental memory dump, not a determined
+        // attacker with a debugger already attached.
+        for byte in unsafe { self.0.as_bytes_mut() } {
+            *byte = 0;
+        }
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(REDACTED)")
+    }
+}
+
+/// Resolves a [`SecretSource`] to its value. One trait with one dispatch
+/// point (see [`RealSecretProvider`]) rather than a provider per variant
+/// with its own method, so a caller holding a `SecretSource` never needs to
+/// know which kind it is to resolve it.
+pub trait SecretProvider {
+    fn resolve(&self, source: &SecretSource) -> Result<Secret, HalleyError>;
+}
+
+/// Trims exactly one trailing `\n` (and a preceding `\r`, for a
+/// Windows-authored file), the same convention restic itself uses for
+/// `--password-file`.
+fn trim_trailing_newline(mut value: String) -> String {
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    value
+}
+
+/// The real resolver: dispatches to a concrete provider per [`SecretSource`]
+/// variant. Use a fake [`SecretProvider`] in tests instead of driving this
+/// against a real file/command/environment.
+pub struct RealSecretProvider;
+
+impl SecretProvider for RealSecretProvider {
+    fn resolve(&self, source: &SecretSource) -> Result<Secret, HalleyError> {
+        match source {
+            SecretSource::Inline(value) => Ok(Secret(value.clone())),
+            SecretSource::File(path) => {
+                let contents = std::fs::read_to_string(path)?;
+                Ok(Secret(trim_trailing_newline(contents)))
+            }
+            SecretSource::Command(command) => {
+                let output = util::run_hook(command, &[], Some(RESOLVE_TIMEOUT))?;
+                if !output.success() {
+                    return Err(HalleyError::Parse(format!(
+                        "secret command `{command}` exited with status {}",
+                        output.status
+                    )));
+                }
+                Ok(Secret(trim_trailing_newline(output.stdout)))
+            }
+            SecretSource::Env(name) => std::env::var(name)
+                .map(Secret)
+                .map_err(|_| HalleyError::Parse(format!("environment variable '{name}' is not set"))),
+            SecretSource::Age { file, identity } => {
+                let command = format!(
+                    "age --decrypt -i {} {}",
+                    shell_quote(&identity.display().to_string()),
+                    shell_quote(&file.display().to_string())
+                );
+                let output = util::run_hook(&command, &[], Some(RESOLVE_TIMEOUT))?;
+                if !output.success() {
+                    return Err(HalleyError::Parse(format!(
+                        "age decryption of '{}' failed: {}",
+                        file.display(),
+                        output.stderr.trim()
+                    )));
+                }
+                Ok(Secret(trim_trailing_newline(output.stdout)))
+            }
+        }
+    }
+}
+
+/// Single-quotes `value` for safe interpolation into the `sh -c` command
+/// [`util::run_hook`] runs, the same way a hand-written `pre_hook` pointing
+/// at a path with spaces would have to.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Write;
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("halley-secret-test-{name}-{}", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn resolves_an_inline_secret() {
+        let secret = RealSecretProvider.resolve(&SecretSource::Inline("hunter2".to_string())).unwrap();
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn resolves_a_file_secret_trimming_the_trailing_newline() {
+        let path = temp_file("file", b"hunter2\n");
+        let secret = RealSecretProvider.resolve(&SecretSource::File(path.clone())).unwrap();
+        assert_eq!(secret.expose(), "hunter2");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn file_secret_without_a_trailing_newline_is_unchanged() {
+        let path = temp_file("file-no-newline", b"hunter2");
+        let secret = RealSecretProvider.resolve(&SecretSource::File(path.clone())).unwrap();
+        assert_eq!(secret.expose(), "hunter2");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn file_secret_on_a_missing_path_is_an_error() {
+        let path = std::env::temp_dir().join("halley-secret-test-missing-does-not-exist");
+        assert!(RealSecretProvider.resolve(&SecretSource::File(path)).is_err());
+    }
+
+    #[test]
+    fn resolves_a_command_secret() {
+        let secret = RealSecretProvider
+            .resolve(&SecretSource::Command("echo hunter2".to_string()))
+            .unwrap();
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn command_secret_surfaces_a_nonzero_exit() {
+        let err = RealSecretProvider
+            .resolve(&SecretSource::Command("exit 1".to_string()))
+            .unwrap_err();
+        assert!(err.to_string().contains("exited with status 1"));
+    }
+
+    #[test]
+    fn resolves_an_env_secret() {
+        let name = format!("HALLEY_SECRET_TEST_{}", std::process::id());
+        std::env::set_var(&name, "hunter2");
+        let secret = RealSecretProvider.resolve(&SecretSource::Env(name.clone())).unwrap();
+        assert_eq!(secret.expose(), "hunter2");
+        std::env::remove_var(&name);
+    }
+
+    #[test]
+    fn env_secret_on_an_unset_variable_is_an_error() {
+        let name = format!("HALLEY_SECRET_TEST_UNSET_{}", std::process::id());
+        std::env::remove_var(&name);
+        assert!(RealSecretProvider.resolve(&SecretSource::Env(name)).is_err());
+    }
+
+    #[test]
+    fn debug_redacts_only_the_inline_variant() {
+        assert_eq!(format!("{:?}", SecretSource::Inline("hunter2".to_string())), "Inline(REDACTED)");
+        let file = SecretSource::File(PathBuf::from("/etc/halley/pw"));
+        assert!(format!("{file:?}").contains("/etc/halley/pw"));
+    }
+
+    #[test]
+    fn debug_never_exposes_a_resolved_secret() {
+        let secret = Secret("hunter2".to_string());
+        assert_eq!(format!("{secret:?}"), "Secret(REDACTED)");
+    }
+
+    struct FakeSecretProvider(HashMap<String, String>);
+
+    impl SecretProvider for FakeSecretProvider {
+        fn resolve(&self, source: &SecretSource) -> Result<Secret, HalleyError> {
+            let SecretSource::Inline(key) = source else {
+                return Err(HalleyError::Parse("fake provider only resolves Inline".to_string()));
+            };
+            self.0
+                .get(key)
+                .cloned()
+                .map(Secret)
+                .ok_or_else(|| HalleyError::Parse(format!("no fake secret registered for '{key}'")))
+        }
+    }
+
+    #[test]
+    fn a_fake_provider_can_stand_in_for_tests_that_need_a_canned_secret() {
+        let provider = FakeSecretProvider(HashMap::from([("db".to_string(), "s3cr3t".to_string())]));
+        let secret = provider.resolve(&SecretSource::Inline("db".to_string())).unwrap();
+        assert_eq!(secret.expose(), "s3cr3t");
+    }
+}
@@ -0,0 +1,1455 @@
+//! On-disk state tracked per repository across runs, separate from the
+//! user-authored config.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cold_storage::RestoreProgress;
+use crate::config::RepoConfig;
+use crate::error::HalleyError;
+use crate::lock::LockGuard;
+
+/// How a repository's integrity was last checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerifyMethod {
+    CheckMetadata,
+    CheckReadData { percent: u8 },
+    SampleRestore,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyRecord {
+    pub at: u64,
+    pub method: VerifyMethod,
+}
+
+/// The result of a backup attempt, recorded so a failed run leaves a
+/// visible trace instead of just leaving `last_backup` at its previous
+/// (possibly much older) value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackupOutcome {
+    Success {
+        /// How long the backup cycle took, wall-clock. `#[serde(default)]`
+        /// so a statefile written before this field existed still parses,
+        /// just with a `0` that was never actually measured.
+        #[serde(default)]
+        duration_secs: u64,
+        /// Bytes added since the previous snapshot, from
+        /// [`crate::engine::backup_diff_report`]. `None` when there was no
+        /// previous snapshot to diff against (e.g. a repo's first backup) or
+        /// the diff itself failed.
+        #[serde(default)]
+        bytes_added: Option<u64>,
+        /// Categorized restic warnings from this backup, from
+        /// [`crate::restic::warnings::summarize`].
+        #[serde(default)]
+        warnings: crate::restic::WarningSummary,
+        /// Bytes/sec smoothed over the whole backup, from
+        /// [`crate::restic::backup::summarize_throughput`]. `None` when
+        /// restic reported no status lines to derive a rate from (e.g. a
+        /// backup too small or fast to emit one).
+        #[serde(default)]
+        average_throughput_bytes_per_sec: Option<u64>,
+        /// The highest smoothed rate seen at any point during the backup,
+        /// from the same [`crate::restic::backup::summarize_throughput`]
+        /// call as `average_throughput_bytes_per_sec`.
+        #[serde(default)]
+        peak_throughput_bytes_per_sec: Option<u64>,
+    },
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupRecord {
+    pub at: u64,
+    pub outcome: BackupOutcome,
+    /// The snapshot restic created for this backup, if it succeeded far
+    /// enough to produce one. Kept so the next backup's run report can
+    /// `restic diff` against it; see [`crate::restic::diff`].
+    #[serde(default)]
+    pub snapshot_id: Option<String>,
+    /// The probed restic version this backup ran against (see
+    /// [`crate::restic::version::version`]), if it was known -- e.g. `None`
+    /// for a repo that skipped the probe. Also embedded in the snapshot's
+    /// own tags as `halley-restic-<version>` (see
+    /// [`crate::engine::backup_cycle`]), so it survives even if this
+    /// statefile is lost.
+    #[serde(default)]
+    pub restic_version: Option<String>,
+    /// Halley's own version at the time of this backup, so a restore that
+    /// behaves oddly months later can be traced back to the code that wrote
+    /// it. `None` only for records written before this field existed.
+    #[serde(default)]
+    pub halley_version: Option<String>,
+}
+
+/// A cold-storage transition (see [`crate::cold_storage::ColdStorageBackend`])
+/// that's been started but not yet confirmed finished, recorded in
+/// [`RepoState::pending_action`] so a run killed partway through doesn't
+/// leave a bucket sitting thawed (and billing for it) with nothing tracking
+/// that it still needs re-freezing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PendingAction {
+    /// A thaw ([`ColdStorageBackend::restore_all`]/`restore_blocking`) was
+    /// started; it's unclear whether the data is actually usable yet.
+    Thaw,
+    /// The repo is thawed and a backup may already have run against it, but
+    /// [`ColdStorageBackend::archive_all`] hasn't been confirmed to finish.
+    ///
+    /// [`ColdStorageBackend::archive_all`]: crate::cold_storage::ColdStorageBackend::archive_all
+    Freeze,
+}
+
+/// Everything Halley remembers about one repository between runs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RepoState {
+    pub last_verified: Option<VerifyRecord>,
+    pub last_backup: Option<BackupRecord>,
+    /// A fingerprint of the repo's configured source paths as of the last
+    /// backup attempt, recorded so a statefile entry orphaned by a repo
+    /// rename can still be recognized later. See
+    /// [`fingerprint_sources`]/[`StateFile::adoption_candidates`].
+    #[serde(default)]
+    pub source_fingerprint: Option<String>,
+    /// Which of this repo's cold-storage keys have already been confirmed
+    /// restored, so a resumed thaw doesn't reconfirm everything from
+    /// scratch. See [`RestoreProgress`]/[`crate::cold_storage::resumable_restore_plan`].
+    #[serde(default)]
+    pub restore_progress: RestoreProgress,
+    /// A cold-storage thaw or freeze started but not yet confirmed finished,
+    /// e.g. because Halley was killed between backing up and re-archiving.
+    /// See [`crate::engine::resume_pending_cold_storage_action`], which
+    /// finishes it before starting anything else on the next run.
+    #[serde(default)]
+    pub pending_action: Option<PendingAction>,
+    /// The unix timestamp at which a deferred re-archive (see
+    /// [`crate::config::RepoConfig::archive_delay_hours`]) becomes due.
+    /// `None` means either the repo doesn't delay archiving, or nothing is
+    /// currently deferred. Cleared once the archive actually runs.
+    #[serde(default)]
+    pub archive_due_at: Option<u64>,
+    /// How many backups in a row have failed for this repo, reset to `0` by
+    /// [`RepoState::record_backup_success`]. Drives
+    /// [`RepoState::failure_backoff_active`], so one permanently broken
+    /// repo backs off instead of being retried (and starving the healthy
+    /// repos of their turn) every single run.
+    #[serde(default)]
+    pub consecutive_failures: u32,
+    /// This repo's configured source paths, each hashed as of the last
+    /// backup attempt (see [`crate::digest::needs_update`]), keyed by the
+    /// path's string form. Lets the run report name which paths actually
+    /// changed instead of just "something did somewhere". A statefile with
+    /// no entry for a path -- including every statefile written before this
+    /// field existed -- treats that path as changed, so it's never silently
+    /// skipped on the strength of state that was never actually recorded.
+    #[serde(default)]
+    pub digests: std::collections::HashMap<String, crate::digest::HexDigest>,
+}
+
+impl RepoState {
+    pub fn record_verify(&mut self, method: VerifyMethod) {
+        self.last_verified = Some(VerifyRecord { at: now(), method });
+    }
+
+    /// Records `sources` as this entry's current fingerprint, so a later
+    /// rename of the repo id can still be matched back to this state. Meant
+    /// to be called alongside [`RepoState::record_backup_success`]/
+    /// [`RepoState::record_backup_failure`] with the repo's configured
+    /// sources, regardless of the backup's outcome.
+    pub fn record_source_fingerprint(&mut self, sources: &[PathBuf]) {
+        self.source_fingerprint = Some(fingerprint_sources(sources));
+    }
+
+    pub fn record_backup_success(
+        &mut self,
+        snapshot_id: Option<String>,
+        duration_secs: u64,
+        bytes_added: Option<u64>,
+        warnings: crate::restic::WarningSummary,
+        restic_version: Option<String>,
+        average_throughput_bytes_per_sec: Option<u64>,
+        peak_throughput_bytes_per_sec: Option<u64>,
+    ) {
+        self.last_backup = Some(BackupRecord {
+            at: now(),
+            outcome: BackupOutcome::Success {
+                duration_secs,
+                bytes_added,
+                warnings,
+                average_throughput_bytes_per_sec,
+                peak_throughput_bytes_per_sec,
+            },
+            snapshot_id,
+            restic_version,
+            halley_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        });
+        self.consecutive_failures = 0;
+    }
+
+    pub fn record_backup_failure(&mut self, error: impl Into<String>, restic_version: Option<String>) {
+        self.last_backup = Some(BackupRecord {
+            at: now(),
+            outcome: BackupOutcome::Failed { error: error.into() },
+            snapshot_id: None,
+            restic_version,
+            halley_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        });
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+    }
+
+    /// True if the last backup attempt failed, so [`due_repos`] can treat
+    /// this repo as due regardless of how recently that failed attempt ran
+    /// -- a backup that never happened shouldn't get to hide behind
+    /// `backup_age_secs` just because it failed quickly.
+    ///
+    /// [`due_repos`]: crate::engine::due_repos
+    pub fn last_backup_failed(&self) -> bool {
+        matches!(
+            self.last_backup.as_ref().map(|record| &record.outcome),
+            Some(BackupOutcome::Failed { .. })
+        )
+    }
+
+    /// True while this repo is still serving out its exponential backoff
+    /// after `consecutive_failures` in a row: `base_hours * 2^consecutive_failures`
+    /// (capped at `max_hours`) since the failing backup. `false` once
+    /// nothing has failed yet or the window has elapsed, so [`due_repos`]
+    /// can go back to treating this repo the same as one that never failed.
+    ///
+    /// [`due_repos`]: crate::engine::due_repos
+    pub fn failure_backoff_active(&self, base_hours: u32, max_hours: u32) -> bool {
+        if self.consecutive_failures == 0 {
+            return false;
+        }
+        let Some(record) = &self.last_backup else {
+            return false;
+        };
+        let multiplier = 1u64.checked_shl(self.consecutive_failures.min(32)).unwrap_or(u64::MAX);
+        let backoff_hours = u64::from(base_hours).saturating_mul(multiplier).min(u64::from(max_hours));
+        now().saturating_sub(record.at) < backoff_hours * 3600
+    }
+
+    /// True when the last verification (if any) is older than
+    /// `max_age_days`, or none has ever been recorded.
+    pub fn verify_is_stale(&self, max_age_days: u32) -> bool {
+        match &self.last_verified {
+            None => true,
+            Some(record) => {
+                let age_secs = now().saturating_sub(record.at);
+                age_secs > u64::from(max_age_days) * 24 * 60 * 60
+            }
+        }
+    }
+
+    /// Seconds since the last backup attempt (successful or not), for
+    /// ordering repos by staleness (see [`crate::engine::due_repos`]).
+    /// `u64::MAX` if none has ever been recorded, so a never-backed-up repo
+    /// always sorts as the most overdue.
+    pub fn backup_age_secs(&self) -> u64 {
+        match &self.last_backup {
+            None => u64::MAX,
+            Some(record) => now().saturating_sub(record.at),
+        }
+    }
+
+    /// Defers a re-archive until `delay_hours` from now, unless one is
+    /// already deferred -- a run that finds `archive_due_at` already set
+    /// shouldn't push the due-time back out just for having checked again.
+    pub fn defer_archive(&mut self, delay_hours: u32) {
+        if self.archive_due_at.is_none() {
+            self.archive_due_at = Some(now() + u64::from(delay_hours) * 60 * 60);
+        }
+    }
+
+    /// True once a deferred archive's due-time has passed. `true` when
+    /// nothing is deferred at all, so callers that only check this after
+    /// confirming a delay is actually configured don't need a separate
+    /// `is_some` check first.
+    pub fn archive_is_due(&self) -> bool {
+        match self.archive_due_at {
+            None => true,
+            Some(due) => now() >= due,
+        }
+    }
+
+    pub fn clear_archive_due(&mut self) {
+        self.archive_due_at = None;
+    }
+}
+
+/// One problem found by [`fsck`], naming exactly where it was found so a
+/// user can go straighten it out by hand even without `--repair`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsckIssue {
+    /// The repo name the entry was found under in the statefile -- not
+    /// necessarily still present in config, e.g. a leftover entry for a
+    /// repo that's since been removed.
+    pub repo: String,
+    /// Which field of the entry the problem is in, e.g. `"last_backup.at"`.
+    pub field: String,
+    pub problem: String,
+    /// Whether [`repair`] knows how to fix this automatically.
+    pub repairable: bool,
+}
+
+/// Checks `state` for problems `#[serde(default)]` migrations, external
+/// edits, or a stale statefile could have left behind: timestamps in the
+/// future, a malformed [`fingerprint_sources`] digest, `consecutive_failures`
+/// left nonzero after a recorded success, an [`RepoState::archive_due_at`]
+/// left set without a pending freeze to go with it, and statefile entries
+/// for repos no longer in `repos`.
+///
+/// Read-only; pair with [`repair`] to act on what it finds.
+pub fn fsck(state: &StateFile, repos: &[RepoConfig]) -> Vec<FsckIssue> {
+    let mut issues = Vec::new();
+    let now = now();
+    let known: std::collections::HashSet<&str> = repos.iter().map(|r| r.name.as_str()).collect();
+
+    for (name, repo_state) in &state.repos {
+        if !known.contains(name.as_str()) {
+            issues.push(FsckIssue {
+                repo: name.clone(),
+                field: "<entry>".to_string(),
+                problem: "no repo with this name exists in config".to_string(),
+                repairable: false,
+            });
+        }
+
+        if let Some(record) = &repo_state.last_backup {
+            if record.at > now {
+                issues.push(FsckIssue {
+                    repo: name.clone(),
+                    field: "last_backup.at".to_string(),
+                    problem: format!("timestamp {} is in the future", record.at),
+                    repairable: true,
+                });
+            }
+        }
+        if let Some(record) = &repo_state.last_verified {
+            if record.at > now {
+                issues.push(FsckIssue {
+                    repo: name.clone(),
+                    field: "last_verified.at".to_string(),
+                    problem: format!("timestamp {} is in the future", record.at),
+                    repairable: true,
+                });
+            }
+        }
+        if let Some(fingerprint) = &repo_state.source_fingerprint {
+            if fingerprint.len() != 16 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+                issues.push(FsckIssue {
+                    repo: name.clone(),
+                    field: "source_fingerprint".to_string(),
+                    problem: format!("'{fingerprint}' is not a 16-digit hex digest"),
+                    repairable: true,
+                });
+            }
+        }
+        let backup_succeeded = matches!(
+            repo_state.last_backup.as_ref().map(|record| &record.outcome),
+            Some(BackupOutcome::Success { .. })
+        );
+        if backup_succeeded && repo_state.consecutive_failures != 0 {
+            issues.push(FsckIssue {
+                repo: name.clone(),
+                field: "consecutive_failures".to_string(),
+                problem: format!(
+                    "{} after a last backup that succeeded",
+                    repo_state.consecutive_failures
+                ),
+                repairable: true,
+            });
+        }
+        if repo_state.archive_due_at.is_some() && repo_state.pending_action != Some(PendingAction::Freeze) {
+            issues.push(FsckIssue {
+                repo: name.clone(),
+                field: "archive_due_at".to_string(),
+                problem: "set without a pending freeze to go with it".to_string(),
+                repairable: true,
+            });
+        }
+    }
+    issues
+}
+
+/// Applies every repairable issue from `issues` to `state` in place, and
+/// returns how many it fixed. Meant to run against a fresh [`fsck`] result
+/// for the same `state`, after the caller has already backed up the
+/// original file -- this has no backup mechanism of its own.
+pub fn repair(state: &mut StateFile, issues: &[FsckIssue]) -> usize {
+    let now = now();
+    let mut repaired = 0;
+    for issue in issues {
+        if !issue.repairable {
+            continue;
+        }
+        let Some(repo_state) = state.repos.get_mut(&issue.repo) else {
+            continue;
+        };
+        match issue.field.as_str() {
+            "last_backup.at" => {
+                if let Some(record) = repo_state.last_backup.as_mut() {
+                    record.at = now;
+                    repaired += 1;
+                }
+            }
+            "last_verified.at" => {
+                if let Some(record) = repo_state.last_verified.as_mut() {
+                    record.at = now;
+                    repaired += 1;
+                }
+            }
+            "source_fingerprint" => {
+                repo_state.source_fingerprint = None;
+                repaired += 1;
+            }
+            "consecutive_failures" => {
+                repo_state.consecutive_failures = 0;
+                repaired += 1;
+            }
+            "archive_due_at" => {
+                repo_state.archive_due_at = None;
+                repaired += 1;
+            }
+            _ => {}
+        }
+    }
+    repaired
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// A fingerprint of a repo's configured source paths, stable under
+/// reordering so it survives a config file being reformatted or its
+/// `sources` list being re-sorted by hand.
+///
+/// This is [`DefaultHasher`], not a cryptographic hash: it only needs to
+/// tell a user's own configured repos apart from each other, not resist a
+/// motivated attacker.
+pub fn fingerprint_sources(sources: &[PathBuf]) -> String {
+    let mut sorted: Vec<&PathBuf> = sources.iter().collect();
+    sorted.sort();
+    let mut hasher = DefaultHasher::new();
+    sorted.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// A statefile entry left behind by a repo rename: `old_name` still holds
+/// state whose source fingerprint matches `new_name`'s current config, and
+/// `new_name` has no state of its own yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdoptionCandidate {
+    pub old_name: String,
+    pub new_name: String,
+}
+
+/// The current on-disk statefile format version, written by
+/// [`StateFile::save`].
+///
+/// Every field added to [`RepoState`]/[`StateFile`] so far has been an
+/// additive `#[serde(default)]` one, so every step in [`MIGRATIONS`] today
+/// is a no-op beyond bumping the version number -- this exists so a future
+/// change that isn't just additive -- e.g. changing what an existing field
+/// means -- has somewhere to actually put that transform, before
+/// [`StateFile::load`] ever hands the JSON to serde.
+pub const STATEFILE_VERSION: u32 = 2;
+
+/// One step in the statefile migration pipeline: transforms the raw JSON of
+/// a statefile written as version `N` into the shape version `N + 1`
+/// expects. `MIGRATIONS[N]` is the step from version `N` to `N + 1`, so its
+/// length must always equal [`STATEFILE_VERSION`].
+type MigrationStep = fn(serde_json::Value) -> serde_json::Value;
+
+const MIGRATIONS: &[MigrationStep] = &[migrate_v0_to_v1, migrate_v1_to_v2];
+
+/// Nothing to reshape between v0 and v1 -- every field v1 added
+/// (`version` itself, chiefly) is `#[serde(default)]`.
+fn migrate_v0_to_v1(value: serde_json::Value) -> serde_json::Value {
+    value
+}
+
+/// Nothing to reshape between v1 and v2 either -- `duration_secs` and the
+/// other fields that arrived in v2 are all `#[serde(default)]` too.
+fn migrate_v1_to_v2(value: serde_json::Value) -> serde_json::Value {
+    value
+}
+
+/// Reads the `version` field out of a statefile's raw JSON, defaulting to
+/// `0` for a statefile written before the field existed.
+fn read_raw_version(value: &serde_json::Value) -> u32 {
+    value
+        .get("version")
+        .and_then(|v| v.as_u64())
+        .and_then(|v| u32::try_from(v).ok())
+        .unwrap_or(0)
+}
+
+/// Upgrades a statefile's raw JSON from whatever version it was written as
+/// up to [`STATEFILE_VERSION`], running each [`MigrationStep`] between them
+/// in order and stamping the result with the new version, before it's
+/// deserialized into a [`StateFile`]. Refuses with a clear error on a
+/// statefile newer than this build of Halley understands, rather than
+/// guessing at what an unknown future field means.
+fn migrate_statefile(value: serde_json::Value) -> Result<serde_json::Value, HalleyError> {
+    let from_version = read_raw_version(&value);
+    if from_version > STATEFILE_VERSION {
+        return Err(HalleyError::Parse(format!(
+            "statefile is version {from_version}, newer than the version {STATEFILE_VERSION} this build of \
+             Halley understands; upgrade Halley before running it against this statefile"
+        )));
+    }
+    let mut value = value;
+    for step in &MIGRATIONS[from_version as usize..] {
+        value = step(value);
+    }
+    if let serde_json::Value::Object(map) = &mut value {
+        map.insert("version".to_string(), serde_json::Value::from(STATEFILE_VERSION));
+    }
+    Ok(value)
+}
+
+/// The statefile: one [`RepoState`] per configured repository, keyed by
+/// repo name.
+///
+/// A [`BTreeMap`] rather than a `HashMap` on purpose: it serializes in
+/// sorted key order, so two runs that leave the same logical state behind
+/// produce byte-identical JSON, and the statefile can live under version
+/// control without every run producing a spurious diff.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StateFile {
+    #[serde(default)]
+    pub version: u32,
+    pub repos: BTreeMap<String, RepoState>,
+}
+
+impl StateFile {
+    /// Acquires an advisory lock over the statefile in `state_dir`,
+    /// separate from Halley's whole-run [`crate::lock::LockGuard`] acquired
+    /// in `main`, so the read-modify-write cycle of a
+    /// [`StateFile::load`]/[`StateFile::save`] pair is protected on its own
+    /// even for a caller that doesn't hold the run lock for its whole
+    /// lifetime. Callers should acquire this before [`StateFile::load`] and
+    /// hold the returned guard until after the matching [`StateFile::save`]
+    /// -- it's released on every exit path, including an early `return` or
+    /// a panic, the same as the run lock.
+    ///
+    /// Fails with [`HalleyError::Locked`], naming the holder's PID, if
+    /// another Halley process already holds it.
+    pub fn lock(state_dir: &Path) -> Result<LockGuard, HalleyError> {
+        LockGuard::acquire_named(state_dir, "statefile.lock")
+    }
+
+    /// Parses `contents` as a statefile, running it through
+    /// [`migrate_statefile`] first so an older statefile is upgraded to
+    /// [`STATEFILE_VERSION`] in memory rather than either failing to parse
+    /// or, worse, silently deserializing into a near-empty [`StateFile`]
+    /// that looks like every repo's history was lost.
+    fn parse(contents: &str) -> Result<Self, HalleyError> {
+        let raw: serde_json::Value =
+            serde_json::from_str(contents).map_err(|e| HalleyError::Parse(format!("invalid statefile: {e}")))?;
+        let migrated = migrate_statefile(raw)?;
+        serde_json::from_value(migrated).map_err(|e| HalleyError::Parse(format!("invalid statefile: {e}")))
+    }
+
+    /// Tries `path`'s `.tmp` sibling (the freshest attempted write) and
+    /// then its `.bak` sibling (the previous good state), returning
+    /// whichever parses first and warning loudly (`reason` names why the
+    /// primary statefile itself couldn't be used) about it. `None` if
+    /// neither exists or parses.
+    fn recover_from_sibling(path: &Path, reason: &str) -> Option<Self> {
+        for suffix in [".tmp", ".bak"] {
+            let sibling = PathBuf::from(format!("{}{suffix}", path.display()));
+            if let Ok(contents) = fs::read_to_string(&sibling) {
+                if let Ok(state) = Self::parse(&contents) {
+                    eprintln!("statefile '{}' {reason}; recovered from '{}'", path.display(), sibling.display());
+                    return Some(state);
+                }
+            }
+        }
+        None
+    }
+
+    /// Loads the statefile at `path` (see [`StateFile::parse`]). If it's
+    /// missing or corrupt -- a crash mid-write that [`StateFile::save`]'s
+    /// fsync couldn't fully prevent (including between its two renames,
+    /// which can leave `path` itself missing entirely), a stray edit, disk
+    /// corruption -- falls back to its `.tmp`/`.bak` siblings (see
+    /// [`StateFile::recover_from_sibling`]). Only defaults to an empty
+    /// [`StateFile`] when `path` never existed and no recoverable sibling
+    /// does either, and only propagates a parse error when `path` exists
+    /// but is unreadable and no sibling recovers -- so this never silently
+    /// discards history the way returning a near-empty default on any read
+    /// failure would.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HalleyError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::recover_from_sibling(path, "is missing").unwrap_or_default());
+        }
+        let contents = fs::read_to_string(path)?;
+        match Self::parse(&contents) {
+            Ok(state) => Ok(state),
+            Err(e) => {
+                let reason = format!("is corrupt ({e})");
+                Self::recover_from_sibling(path, &reason).map(Ok).unwrap_or(Err(e))
+            }
+        }
+    }
+
+    /// Writes the statefile via a temp-file-plus-rename, fsyncing the temp
+    /// file first, so a run killed mid-write (Ctrl-C, an OOM kill, a host
+    /// power loss) leaves either the old statefile or the new one intact,
+    /// never a half-written one that fails to parse on the next run. The
+    /// statefile this replaces is kept as a rotating `.bak` rather than
+    /// discarded, so [`StateFile::load`] has something to recover from if
+    /// the new one turns out to be corrupt after all.
+    ///
+    /// This only protects the write itself -- interrupting a run *between*
+    /// phases (e.g. to skip straight to re-freezing a thawed S3 repo before
+    /// exiting) is [`crate::cancel::CancellationToken`]'s job, checked at
+    /// each phase boundary in [`crate::engine::backup_cycle`].
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), HalleyError> {
+        let path = path.as_ref();
+        let mut versioned = self.clone();
+        versioned.version = STATEFILE_VERSION;
+        let json = serde_json::to_string_pretty(&versioned)
+            .expect("StateFile serializes without error");
+
+        let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+        let mut tmp_file = fs::File::create(&tmp_path)?;
+        tmp_file.write_all(json.as_bytes())?;
+        tmp_file.sync_all()?;
+        drop(tmp_file);
+
+        if path.exists() {
+            let bak_path = PathBuf::from(format!("{}.bak", path.display()));
+            fs::rename(path, &bak_path)?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Finds statefile entries that look like they belong to a repo that's
+    /// since been renamed in `repos`: an old name with a recorded
+    /// [`RepoState::source_fingerprint`] matching a configured repo whose
+    /// name isn't already in this statefile.
+    ///
+    /// Doesn't mutate anything; pair with [`StateFile::adopt`] to act on a
+    /// candidate.
+    pub fn adoption_candidates(&self, repos: &[RepoConfig]) -> Vec<AdoptionCandidate> {
+        let mut candidates = Vec::new();
+        for repo in repos {
+            if self.repos.contains_key(&repo.name) {
+                continue;
+            }
+            let fingerprint = fingerprint_sources(&repo.sources);
+            for (old_name, old_state) in &self.repos {
+                if old_state.source_fingerprint.as_deref() == Some(fingerprint.as_str()) {
+                    candidates.push(AdoptionCandidate {
+                        old_name: old_name.clone(),
+                        new_name: repo.name.clone(),
+                    });
+                }
+            }
+        }
+        candidates
+    }
+
+    /// Moves the state recorded under `old_name` to `new_name`, as when a
+    /// repo has been renamed in config and its history should follow.
+    ///
+    /// Returns `false` without changing anything if `old_name` has no state,
+    /// or `new_name` already does.
+    pub fn adopt(&mut self, old_name: &str, new_name: &str) -> bool {
+        if self.repos.contains_key(new_name) {
+            return false;
+        }
+        let Some(state) = self.repos.remove(old_name) else {
+            return false;
+        };
+        self.repos.insert(new_name.to_string(), state);
+        true
+    }
+}
+
+/// Confirms the directory that will hold the statefile can actually be
+/// written to, by creating and immediately deleting a probe file.
+///
+/// Meant to run before a repository run starts doing expensive or billable
+/// work: a disk that's already full or a read-only mount fails the same way
+/// `StateFile::save` eventually would, except upfront, so a run doesn't
+/// finish a whole backup cycle only to lose the record of it having
+/// happened.
+pub fn ensure_state_dir_writable(dir: &Path) -> Result<(), HalleyError> {
+    fs::create_dir_all(dir).map_err(|e| {
+        HalleyError::StateDir(format!(
+            "cannot create state directory '{}': {e}",
+            dir.display()
+        ))
+    })?;
+
+    let probe = dir.join(".halley-writable-probe");
+    let write_result = fs::write(&probe, b"probe");
+    match write_result {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::StorageFull => Err(HalleyError::StateDir(
+            format!("state directory '{}' is full (ENOSPC)", dir.display()),
+        )),
+        Err(e) => Err(HalleyError::StateDir(format!(
+            "state directory '{}' is not writable: {e}",
+            dir.display()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo_named(name: &str, sources: &[&str]) -> RepoConfig {
+        RepoConfig {
+            name: name.into(),
+            sources: sources.iter().map(PathBuf::from).collect(),
+            repo: "/srv/backups/x".into(),
+            retention: Default::default(),
+            prune: false,
+            changed_during_backup: Default::default(),
+            max_verify_age_days: None,
+            symlinks: Default::default(),
+            compression: None,
+            no_scan: false,
+            read_concurrency: None,
+            excludes: vec![],
+            exclude_file: None,
+            digest_ignore: Vec::new(),
+            strict_paths: false,
+            tags: vec![],
+            check_before_backup: false,
+            restic_memory_limit_mb: None,
+            auto_init: true,
+            password: None,
+            password_file: None,
+            password_command: None,
+            password_source: None,
+            limit_upload: None,
+            limit_download: None,
+            allow_initial_backup: false,
+            first_backup_size_threshold_mb: None,
+            cache_dir: None,
+            no_cache: false,
+            restore_sparse: false,
+            restore_flags: vec![],
+            hostname: None,
+            one_file_system: false,
+            auto_unlock_stale: false,
+            max_auto_forget: None,
+            warning_threshold: None,
+            s3: None,
+            cold_storage_command: None,
+            pre_hook: None,
+            post_hook: None,
+            archive_after_failed_backup: false,
+            archive_delay_hours: None,
+            archive_unverified: false,
+            min_backup_interval_hours: None,
+            max_backup_interval_days: None,
+            extra_env_passthrough: Vec::new(),
+            allow_network_sources: false,
+        }
+    }
+
+    #[test]
+    fn fingerprint_sources_is_stable_under_reordering() {
+        let a = fingerprint_sources(&[PathBuf::from("/a"), PathBuf::from("/b")]);
+        let b = fingerprint_sources(&[PathBuf::from("/b"), PathBuf::from("/a")]);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn fingerprint_sources_differs_for_different_sources() {
+        let a = fingerprint_sources(&[PathBuf::from("/a")]);
+        let b = fingerprint_sources(&[PathBuf::from("/a"), PathBuf::from("/b")]);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn adoption_candidates_matches_a_renamed_repo_by_fingerprint() {
+        let mut file = StateFile::default();
+        file.repos
+            .entry("home-old".into())
+            .or_default()
+            .record_source_fingerprint(&[PathBuf::from("/home/user")]);
+
+        let repos = vec![repo_named("home-new", &["/home/user"])];
+        let candidates = file.adoption_candidates(&repos);
+
+        assert_eq!(
+            candidates,
+            vec![AdoptionCandidate {
+                old_name: "home-old".into(),
+                new_name: "home-new".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn adoption_candidates_ignores_a_repo_that_already_has_state() {
+        let mut file = StateFile::default();
+        file.repos
+            .entry("home".into())
+            .or_default()
+            .record_source_fingerprint(&[PathBuf::from("/home/user")]);
+
+        let repos = vec![repo_named("home", &["/home/user"])];
+        assert!(file.adoption_candidates(&repos).is_empty());
+    }
+
+    #[test]
+    fn adoption_candidates_ignores_a_fingerprint_mismatch() {
+        let mut file = StateFile::default();
+        file.repos
+            .entry("home-old".into())
+            .or_default()
+            .record_source_fingerprint(&[PathBuf::from("/home/user")]);
+
+        let repos = vec![repo_named("home-new", &["/srv/other"])];
+        assert!(file.adoption_candidates(&repos).is_empty());
+    }
+
+    #[test]
+    fn adopt_moves_state_from_the_old_name_to_the_new_one() {
+        let mut file = StateFile::default();
+        file.repos
+            .entry("home-old".into())
+            .or_default()
+            .record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+
+        assert!(file.adopt("home-old", "home-new"));
+        assert!(!file.repos.contains_key("home-old"));
+        assert!(file.repos["home-new"].last_backup.is_some());
+    }
+
+    #[test]
+    fn adopt_refuses_to_overwrite_existing_state_on_the_new_name() {
+        let mut file = StateFile::default();
+        file.repos.entry("home-old".into()).or_default().record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        file.repos.entry("home-new".into()).or_default().record_backup_failure("boom", None);
+
+        assert!(!file.adopt("home-old", "home-new"));
+        assert!(file.repos.contains_key("home-old"));
+        match &file.repos["home-new"].last_backup.as_ref().unwrap().outcome {
+            BackupOutcome::Failed { error } => assert_eq!(error, "boom"),
+            BackupOutcome::Success { .. } => panic!("expected the pre-existing failure to survive"),
+        }
+    }
+
+    #[test]
+    fn adopt_is_a_no_op_when_the_old_name_has_no_state() {
+        let mut file = StateFile::default();
+        assert!(!file.adopt("nonexistent", "home-new"));
+    }
+
+    #[test]
+    fn fresh_state_is_always_stale() {
+        let state = RepoState::default();
+        assert!(state.verify_is_stale(30));
+    }
+
+    #[test]
+    fn recent_verify_is_not_stale() {
+        let mut state = RepoState::default();
+        state.record_verify(VerifyMethod::CheckMetadata);
+        assert!(!state.verify_is_stale(30));
+    }
+
+    #[test]
+    fn record_backup_success_sets_last_backup() {
+        let mut state = RepoState::default();
+        state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        assert!(matches!(state.last_backup.unwrap().outcome, BackupOutcome::Success { .. }));
+    }
+
+    #[test]
+    fn record_backup_success_keeps_the_snapshot_id() {
+        let mut state = RepoState::default();
+        state.record_backup_success(Some("abc123".to_string()), 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        assert_eq!(state.last_backup.unwrap().snapshot_id.as_deref(), Some("abc123"));
+    }
+
+    #[test]
+    fn record_backup_success_keeps_the_restic_version_and_stamps_the_halley_version() {
+        let mut state = RepoState::default();
+        state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), Some("0.16.4".to_string()), None, None);
+        let record = state.last_backup.unwrap();
+        assert_eq!(record.restic_version.as_deref(), Some("0.16.4"));
+        assert_eq!(record.halley_version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn record_backup_failure_keeps_the_restic_version_and_stamps_the_halley_version() {
+        let mut state = RepoState::default();
+        state.record_backup_failure("boom", Some("0.16.4".to_string()));
+        let record = state.last_backup.unwrap();
+        assert_eq!(record.restic_version.as_deref(), Some("0.16.4"));
+        assert_eq!(record.halley_version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn record_backup_failure_clears_the_snapshot_id() {
+        let mut state = RepoState::default();
+        state.record_backup_success(Some("abc123".to_string()), 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        state.record_backup_failure("boom", None);
+        assert!(state.last_backup.unwrap().snapshot_id.is_none());
+    }
+
+    #[test]
+    fn record_backup_failure_keeps_the_error_message() {
+        let mut state = RepoState::default();
+        state.record_backup_failure("restic command exceeded the 60 minute timeout and was killed", None);
+        match state.last_backup.unwrap().outcome {
+            BackupOutcome::Failed { error } => assert!(error.contains("timeout")),
+            BackupOutcome::Success { .. } => panic!("expected a Failed outcome"),
+        }
+    }
+
+    #[test]
+    fn record_backup_failure_increments_consecutive_failures() {
+        let mut state = RepoState::default();
+        state.record_backup_failure("boom", None);
+        state.record_backup_failure("boom again", None);
+        assert_eq!(state.consecutive_failures, 2);
+    }
+
+    #[test]
+    fn record_backup_success_resets_consecutive_failures() {
+        let mut state = RepoState::default();
+        state.record_backup_failure("boom", None);
+        state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        assert_eq!(state.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn failure_backoff_active_is_false_with_no_failures() {
+        let state = RepoState::default();
+        assert!(!state.failure_backoff_active(1, 168));
+    }
+
+    #[test]
+    fn failure_backoff_active_is_true_right_after_a_failure() {
+        let mut state = RepoState::default();
+        state.record_backup_failure("boom", None);
+        assert!(state.failure_backoff_active(1, 168));
+    }
+
+    #[test]
+    fn failure_backoff_active_is_false_once_the_window_has_elapsed() {
+        let mut state = RepoState::default();
+        state.record_backup_failure("boom", None);
+        state.last_backup.as_mut().unwrap().at = 0;
+        assert!(!state.failure_backoff_active(1, 168));
+    }
+
+    #[test]
+    fn failure_backoff_active_grows_exponentially_with_consecutive_failures() {
+        let mut state = RepoState::default();
+        for _ in 0..3 {
+            state.record_backup_failure("boom", None);
+        }
+        // 1h base * 2^3 = 8h backoff; a failure 2h ago is still within it.
+        state.last_backup.as_mut().unwrap().at = now() - 2 * 3600;
+        assert!(state.failure_backoff_active(1, 168));
+    }
+
+    #[test]
+    fn failure_backoff_active_is_capped_at_max_hours() {
+        let mut state = RepoState::default();
+        for _ in 0..10 {
+            state.record_backup_failure("boom", None);
+        }
+        // 1h base * 2^10 would be over 1000h, but max_hours caps it at 2.
+        state.last_backup.as_mut().unwrap().at = now() - 3 * 3600;
+        assert!(!state.failure_backoff_active(1, 2));
+    }
+
+    #[test]
+    fn backup_age_secs_is_max_when_never_backed_up() {
+        let state = RepoState::default();
+        assert_eq!(state.backup_age_secs(), u64::MAX);
+    }
+
+    #[test]
+    fn backup_age_secs_is_small_just_after_a_backup() {
+        let mut state = RepoState::default();
+        state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        assert!(state.backup_age_secs() < 5);
+    }
+
+    #[test]
+    fn digests_round_trip_through_disk() {
+        let path = std::env::temp_dir().join("halley-statefile-digests-roundtrip-test.json");
+        let mut file = StateFile::default();
+        file.repos.entry("home".into()).or_default().digests = std::collections::HashMap::from([(
+            "/home/user".to_string(),
+            crate::digest::HexDigest("deadbeef".to_string()),
+        )]);
+        file.save(&path).unwrap();
+
+        let loaded = StateFile::load(&path).unwrap();
+        assert_eq!(
+            loaded.repos["home"].digests.get("/home/user"),
+            Some(&crate::digest::HexDigest("deadbeef".to_string()))
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn a_statefile_with_no_digests_field_loads_with_an_empty_map() {
+        let path = std::env::temp_dir().join("halley-statefile-no-digests-test.json");
+        fs::write(&path, r#"{"repos":{"home":{"last_verified":null,"last_backup":null}}}"#).unwrap();
+
+        let loaded = StateFile::load(&path).unwrap();
+        assert!(loaded.repos["home"].digests.is_empty());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn state_file_round_trips_through_disk() {
+        let path = std::env::temp_dir().join("halley-statefile-roundtrip-test.json");
+        let mut file = StateFile::default();
+        file.repos
+            .entry("home".into())
+            .or_default()
+            .record_verify(VerifyMethod::CheckReadData { percent: 5 });
+        file.save(&path).unwrap();
+
+        let loaded = StateFile::load(&path).unwrap();
+        assert!(loaded.repos["home"].last_verified.is_some());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn state_file_save_leaves_no_leftover_tmp_file_behind() {
+        let path = std::env::temp_dir().join("halley-statefile-atomic-save-test.json");
+        StateFile::default().save(&path).unwrap();
+
+        let tmp_path = std::env::temp_dir().join("halley-statefile-atomic-save-test.json.tmp");
+        assert!(!tmp_path.exists());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn state_file_save_stamps_the_current_version() {
+        let path = std::env::temp_dir().join("halley-statefile-version-test.json");
+        StateFile::default().save(&path).unwrap();
+
+        let loaded = StateFile::load(&path).unwrap();
+        assert_eq!(loaded.version, STATEFILE_VERSION);
+        let _ = fs::remove_file(&path);
+    }
+
+    /// A statefile written before `version`/`duration_secs` existed has
+    /// neither field in its JSON. Both are `#[serde(default)]`, so it must
+    /// still load -- migrated up to [`STATEFILE_VERSION`] in memory (see
+    /// [`migrate_statefile`]), with `duration_secs` at `0` for any recorded
+    /// success -- rather than failing to parse.
+    #[test]
+    fn state_file_load_migrates_a_legacy_unversioned_statefile() {
+        let path = std::env::temp_dir().join("halley-statefile-legacy-migration-test.json");
+        fs::write(
+            &path,
+            r#"{"repos":{"home":{"last_verified":null,"last_backup":{"at":1000,"outcome":{"success":{}}}}}}"#,
+        )
+        .unwrap();
+
+        let loaded = StateFile::load(&path).unwrap();
+        assert_eq!(loaded.version, STATEFILE_VERSION);
+        match &loaded.repos["home"].last_backup.as_ref().unwrap().outcome {
+            BackupOutcome::Success {
+                duration_secs,
+                bytes_added,
+                warnings,
+                average_throughput_bytes_per_sec,
+                peak_throughput_bytes_per_sec,
+            } => {
+                assert_eq!(*duration_secs, 0);
+                assert_eq!(*bytes_added, None);
+                assert_eq!(*warnings, crate::restic::WarningSummary::default());
+                assert_eq!(*average_throughput_bytes_per_sec, None);
+                assert_eq!(*peak_throughput_bytes_per_sec, None);
+            }
+            BackupOutcome::Failed { .. } => panic!("expected a Success outcome"),
+        }
+
+        loaded.save(&path).unwrap();
+        assert_eq!(StateFile::load(&path).unwrap().version, STATEFILE_VERSION);
+        let _ = fs::remove_file(&path);
+    }
+
+    /// A fixture statefile for each historical version, exactly as it would
+    /// have been written by the Halley build that wrote that version --
+    /// version `0` has no `version` key at all, matching a statefile from
+    /// before the field existed.
+    const V0_FIXTURE: &str = r#"{"repos":{"home":{"last_verified":null,"last_backup":{"at":1000,"outcome":{"success":{}}}}}}"#;
+    const V1_FIXTURE: &str = r#"{"version":1,"repos":{"home":{"last_verified":null,"last_backup":{"at":1000,"outcome":{"success":{"duration_secs":42}}}}}}"#;
+    const V2_FIXTURE: &str = r#"{"version":2,"repos":{"home":{"last_verified":null,"last_backup":{"at":1000,"outcome":{"success":{"duration_secs":42,"bytes_added":100}}}}}}"#;
+
+    #[test]
+    fn migrate_statefile_upgrades_every_historical_fixture_to_the_current_version() {
+        for fixture in [V0_FIXTURE, V1_FIXTURE, V2_FIXTURE] {
+            let raw: serde_json::Value = serde_json::from_str(fixture).unwrap();
+            let migrated = migrate_statefile(raw).unwrap();
+            assert_eq!(migrated["version"], serde_json::Value::from(STATEFILE_VERSION));
+            let state: StateFile = serde_json::from_value(migrated).unwrap();
+            assert_eq!(state.version, STATEFILE_VERSION);
+            assert!(state.repos.contains_key("home"));
+        }
+    }
+
+    #[test]
+    fn load_upgrades_a_v1_fixture_statefile_and_keeps_its_recorded_duration() {
+        let path = std::env::temp_dir().join("halley-statefile-v1-fixture-test.json");
+        fs::write(&path, V1_FIXTURE).unwrap();
+
+        let loaded = StateFile::load(&path).unwrap();
+        assert_eq!(loaded.version, STATEFILE_VERSION);
+        match &loaded.repos["home"].last_backup.as_ref().unwrap().outcome {
+            BackupOutcome::Success { duration_secs, .. } => assert_eq!(*duration_secs, 42),
+            BackupOutcome::Failed { .. } => panic!("expected a Success outcome"),
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn migrate_statefile_refuses_a_version_newer_than_this_build_understands() {
+        let raw: serde_json::Value = serde_json::from_str(r#"{"version":9999,"repos":{}}"#).unwrap();
+        let err = migrate_statefile(raw).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("9999"));
+        assert!(message.contains("upgrade Halley"));
+    }
+
+    #[test]
+    fn load_refuses_a_statefile_newer_than_this_build_understands() {
+        let path = std::env::temp_dir().join("halley-statefile-future-version-test.json");
+        fs::write(&path, r#"{"version":9999,"repos":{}}"#).unwrap();
+
+        assert!(StateFile::load(&path).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    /// A malformed statefile must fail loudly rather than silently
+    /// deserializing into a near-empty [`StateFile`] that looks like every
+    /// repo's recorded history was simply lost.
+    #[test]
+    fn load_refuses_a_malformed_statefile_instead_of_silently_discarding_it() {
+        let path = std::env::temp_dir().join("halley-statefile-malformed-test.json");
+        fs::write(&path, "not valid json at all").unwrap();
+
+        assert!(StateFile::load(&path).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn state_file_save_rotates_the_previous_statefile_into_a_bak_sibling() {
+        let path = std::env::temp_dir().join("halley-statefile-bak-rotation-test.json");
+        let bak_path = std::env::temp_dir().join("halley-statefile-bak-rotation-test.json.bak");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&bak_path);
+
+        StateFile::default().save(&path).unwrap();
+        assert!(!bak_path.exists());
+
+        let mut second = StateFile::default();
+        second.repos.entry("home".into()).or_default().record_verify(VerifyMethod::CheckReadData { percent: 5 });
+        second.save(&path).unwrap();
+        assert!(bak_path.exists());
+        assert!(StateFile::parse(&fs::read_to_string(&bak_path).unwrap()).unwrap().repos.is_empty());
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&bak_path);
+    }
+
+    /// A truncated primary statefile (a crash mid-write, despite the fsync --
+    /// e.g. a filesystem that lost power before the rename itself landed)
+    /// must recover from a `.bak` sibling rather than failing the whole run.
+    #[test]
+    fn load_recovers_from_a_bak_sibling_when_the_primary_statefile_is_truncated() {
+        let path = std::env::temp_dir().join("halley-statefile-recover-from-bak-test.json");
+        let bak_path = std::env::temp_dir().join("halley-statefile-recover-from-bak-test.json.bak");
+
+        let mut good = StateFile::default();
+        good.repos.entry("home".into()).or_default().record_verify(VerifyMethod::CheckReadData { percent: 5 });
+        fs::write(&bak_path, serde_json::to_string_pretty(&good).unwrap()).unwrap();
+        fs::write(&path, "{\"repos\":{\"home\":{\"last_verif").unwrap();
+
+        let loaded = StateFile::load(&path).unwrap();
+        assert!(loaded.repos["home"].last_verified.is_some());
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&bak_path);
+    }
+
+    /// A `.tmp` sibling left behind by a write that crashed before the final
+    /// rename is the freshest attempted state, so it's tried before `.bak`.
+    #[test]
+    fn load_recovers_from_a_tmp_sibling_when_the_primary_statefile_is_truncated() {
+        let path = std::env::temp_dir().join("halley-statefile-recover-from-tmp-test.json");
+        let tmp_path = std::env::temp_dir().join("halley-statefile-recover-from-tmp-test.json.tmp");
+
+        let mut good = StateFile::default();
+        good.repos.entry("home".into()).or_default().record_verify(VerifyMethod::CheckReadData { percent: 5 });
+        fs::write(&tmp_path, serde_json::to_string_pretty(&good).unwrap()).unwrap();
+        fs::write(&path, "{\"repos\":{\"home\":{\"last_verif").unwrap();
+
+        let loaded = StateFile::load(&path).unwrap();
+        assert!(loaded.repos["home"].last_verified.is_some());
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    /// Neither sibling existing (or parsing) is the common case -- most
+    /// corrupt statefiles aren't sitting next to a recoverable backup -- so
+    /// this must still fail instead of papering over real data loss.
+    #[test]
+    fn load_still_fails_when_no_recoverable_sibling_exists() {
+        let path = std::env::temp_dir().join("halley-statefile-no-sibling-test.json");
+        fs::write(&path, "not valid json at all").unwrap();
+
+        assert!(StateFile::load(&path).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    /// [`StateFile::save`] does `rename(path, path.bak)` then
+    /// `rename(tmp, path)` as two separate syscalls; a process killed
+    /// between them leaves `path` missing entirely, with the previous good
+    /// state sitting in `.bak`. This is the exact crash window the `.bak`
+    /// rotation exists to cover, so it must recover here too, not just when
+    /// `path` exists but fails to parse.
+    #[test]
+    fn load_recovers_from_a_bak_sibling_when_the_primary_statefile_is_missing() {
+        let path = std::env::temp_dir().join("halley-statefile-recover-missing-primary-test.json");
+        let bak_path = std::env::temp_dir().join("halley-statefile-recover-missing-primary-test.json.bak");
+        let _ = fs::remove_file(&path);
+
+        let mut good = StateFile::default();
+        good.repos.entry("home".into()).or_default().record_verify(VerifyMethod::CheckReadData { percent: 5 });
+        fs::write(&bak_path, serde_json::to_string_pretty(&good).unwrap()).unwrap();
+
+        let loaded = StateFile::load(&path).unwrap();
+        assert!(loaded.repos["home"].last_verified.is_some());
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&bak_path);
+    }
+
+    /// A statefile written before `pending_action` existed has no such key
+    /// in its JSON. `#[serde(default)]` means it must still load, with
+    /// `pending_action` at `None` -- there's nothing to resume for a repo
+    /// this old statefile never recorded a cold-storage transition for.
+    #[test]
+    fn state_file_load_defaults_pending_action_to_none_for_a_legacy_statefile() {
+        let path = std::env::temp_dir().join("halley-statefile-legacy-pending-action-test.json");
+        fs::write(&path, r#"{"repos":{"home":{"last_verified":null,"last_backup":null}}}"#).unwrap();
+
+        let loaded = StateFile::load(&path).unwrap();
+        assert_eq!(loaded.repos["home"].pending_action, None);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn pending_action_round_trips_through_save_and_load() {
+        let path = std::env::temp_dir().join("halley-statefile-pending-action-roundtrip-test.json");
+        let mut file = StateFile::default();
+        file.repos.entry("cold".into()).or_default().pending_action = Some(PendingAction::Freeze);
+        file.save(&path).unwrap();
+
+        let loaded = StateFile::load(&path).unwrap();
+        assert_eq!(loaded.repos["cold"].pending_action, Some(PendingAction::Freeze));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    /// `repos` is a [`BTreeMap`], and every history it holds is a single
+    /// optional record rather than an append-only log, so serializing the
+    /// same logical state twice — even after inserting repos in a different
+    /// order — must produce byte-identical output. Without that, the
+    /// statefile can't be checked into version control or diffed by hand.
+    #[test]
+    fn serialization_is_byte_identical_regardless_of_insertion_order() {
+        let mut first = StateFile::default();
+        first.repos.entry("zeta".into()).or_default().record_backup_success(Some("abc".into()), 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        first.repos.entry("alpha".into()).or_default().record_verify(VerifyMethod::CheckMetadata);
+
+        let mut second = StateFile::default();
+        second.repos.entry("alpha".into()).or_default().record_verify(VerifyMethod::CheckMetadata);
+        second.repos.entry("zeta".into()).or_default().record_backup_success(Some("abc".into()), 0, None, crate::restic::WarningSummary::default(), None, None, None);
+
+        // `record_verify`/`record_backup_success` stamp `at` with the current
+        // time, which would otherwise make the two entries differ; overwrite
+        // it so this test compares everything except the clock.
+        for repos in [&mut first.repos, &mut second.repos] {
+            if let Some(record) = repos.get_mut("alpha").and_then(|s| s.last_verified.as_mut()) {
+                record.at = 0;
+            }
+            if let Some(record) = repos.get_mut("zeta").and_then(|s| s.last_backup.as_mut()) {
+                record.at = 0;
+            }
+        }
+
+        let first_json = serde_json::to_string_pretty(&first).unwrap();
+        let second_json = serde_json::to_string_pretty(&second).unwrap();
+        assert_eq!(first_json, second_json);
+    }
+
+    #[test]
+    fn ensure_state_dir_writable_succeeds_and_leaves_no_probe_behind() {
+        let dir = std::env::temp_dir().join("halley-state-dir-writable-test");
+        let _ = fs::remove_dir_all(&dir);
+
+        ensure_state_dir_writable(&dir).unwrap();
+
+        assert!(dir.is_dir());
+        assert!(!dir.join(".halley-writable-probe").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn fsck_flags_an_entry_for_a_repo_no_longer_in_config() {
+        let mut file = StateFile::default();
+        file.repos.entry("gone".into()).or_default();
+        let issues = fsck(&file, &[]);
+        assert!(issues.iter().any(|i| i.repo == "gone" && !i.repairable));
+    }
+
+    #[test]
+    fn fsck_flags_a_future_backup_timestamp() {
+        let mut file = StateFile::default();
+        let mut state = RepoState::default();
+        state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        state.last_backup.as_mut().unwrap().at = now() + 3600;
+        file.repos.insert("home".into(), state);
+        let issues = fsck(&file, &[repo_named("home", &[])]);
+        assert!(issues.iter().any(|i| i.repo == "home" && i.field == "last_backup.at" && i.repairable));
+    }
+
+    #[test]
+    fn fsck_flags_a_malformed_source_fingerprint() {
+        let mut file = StateFile::default();
+        let mut state = RepoState::default();
+        state.source_fingerprint = Some("not-a-digest".into());
+        file.repos.insert("home".into(), state);
+        let issues = fsck(&file, &[repo_named("home", &[])]);
+        assert!(issues.iter().any(|i| i.repo == "home" && i.field == "source_fingerprint"));
+    }
+
+    #[test]
+    fn fsck_flags_nonzero_consecutive_failures_after_a_success() {
+        let mut file = StateFile::default();
+        let mut state = RepoState::default();
+        state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        state.consecutive_failures = 3;
+        file.repos.insert("home".into(), state);
+        let issues = fsck(&file, &[repo_named("home", &[])]);
+        assert!(issues.iter().any(|i| i.repo == "home" && i.field == "consecutive_failures"));
+    }
+
+    #[test]
+    fn fsck_flags_an_archive_due_at_with_no_pending_freeze() {
+        let mut file = StateFile::default();
+        let mut state = RepoState::default();
+        state.archive_due_at = Some(0);
+        file.repos.insert("home".into(), state);
+        let issues = fsck(&file, &[repo_named("home", &[])]);
+        assert!(issues.iter().any(|i| i.repo == "home" && i.field == "archive_due_at"));
+    }
+
+    #[test]
+    fn fsck_is_clean_for_a_healthy_entry() {
+        let mut file = StateFile::default();
+        let mut state = RepoState::default();
+        state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        state.record_source_fingerprint(&[PathBuf::from("/home/user")]);
+        file.repos.insert("home".into(), state);
+        assert!(fsck(&file, &[repo_named("home", &["/home/user"])]).is_empty());
+    }
+
+    #[test]
+    fn repair_resets_a_future_timestamp_to_now() {
+        let mut file = StateFile::default();
+        let mut state = RepoState::default();
+        state.record_backup_success(None, 0, None, crate::restic::WarningSummary::default(), None, None, None);
+        state.last_backup.as_mut().unwrap().at = now() + 3600;
+        file.repos.insert("home".into(), state);
+
+        let issues = fsck(&file, &[repo_named("home", &[])]);
+        let repaired = repair(&mut file, &issues);
+
+        assert_eq!(repaired, 1);
+        assert!(file.repos["home"].last_backup.as_ref().unwrap().at <= now());
+    }
+
+    #[test]
+    fn repair_drops_a_malformed_source_fingerprint() {
+        let mut file = StateFile::default();
+        let mut state = RepoState::default();
+        state.source_fingerprint = Some("garbage".into());
+        file.repos.insert("home".into(), state);
+
+        let issues = fsck(&file, &[repo_named("home", &[])]);
+        repair(&mut file, &issues);
+
+        assert!(file.repos["home"].source_fingerprint.is_none());
+    }
+
+    #[test]
+    fn repair_leaves_unrepairable_issues_alone() {
+        let mut file = StateFile::default();
+        file.repos.entry("gone".into()).or_default();
+
+        let issues = fsck(&file, &[]);
+        let repaired = repair(&mut file, &issues);
+
+        assert_eq!(repaired, 0);
+        assert!(file.repos.contains_key("gone"));
+    }
+
+    #[test]
+    fn ensure_state_dir_writable_fails_on_a_read_only_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("halley-state-dir-readonly-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o500)).unwrap();
+
+        let err = ensure_state_dir_writable(&dir).unwrap_err();
+        assert!(matches!(err, HalleyError::StateDir(_)));
+
+        // Restore permissions so the temp dir can be cleaned up.
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,275 @@
+//! Human-readable formatting helpers shared by the engine summaries, S3
+//! transition reports, CLI tables and notifications, so a duration or size
+//! never has to be spelled out ad hoc at the call site. Also home to
+//! [`run_hook`], the shell-command runner behind per-repo `pre_hook`/
+//! `post_hook`.
+
+use std::time::Duration;
+
+use crate::error::HalleyError;
+
+pub mod retry;
+
+/// The exit status and captured output of a [`run_hook`] invocation.
+#[derive(Debug, Clone)]
+pub struct HookOutput {
+    pub status: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl HookOutput {
+    pub fn success(&self) -> bool {
+        self.status == 0
+    }
+}
+
+/// How often [`run_hook`] polls a running hook for completion. Same interval
+/// as [`crate::restic::RealCall`]'s child-process polling.
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Runs `command` through `sh -c`, with `env` set on top of the hook's own
+/// inherited environment, killing it (and anything it spawned) if it's
+/// still running after `timeout`. `None` lets it run indefinitely, same
+/// convention as [`crate::restic::RealCall::timeout`].
+///
+/// Mirrors [`crate::restic::RealCall::call_with_timeout`]'s
+/// process-group-and-poll approach, since a hook is just as capable of
+/// hanging (a stuck `pg_dump`) as a restic invocation is.
+pub fn run_hook(command: &str, env: &[(String, String)], timeout: Option<Duration>) -> Result<HookOutput, HalleyError> {
+    match timeout {
+        None => run_hook_without_timeout(command, env),
+        Some(timeout) => run_hook_with_timeout(command, env, timeout),
+    }
+}
+
+fn run_hook_without_timeout(command: &str, env: &[(String, String)]) -> Result<HookOutput, HalleyError> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().cloned())
+        .output()?;
+    Ok(HookOutput {
+        status: output.status.code().unwrap_or(-1),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+fn run_hook_with_timeout(
+    command: &str,
+    env: &[(String, String)],
+    timeout: Duration,
+) -> Result<HookOutput, HalleyError> {
+    use std::io::Read;
+    use std::os::unix::process::CommandExt;
+
+    let mut child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().cloned())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+
+    let pgid = child.id();
+    let mut stdout_pipe = child.stdout.take().expect("stdout was piped");
+    let mut stderr_pipe = child.stderr.take().expect("stderr was piped");
+    let stdout_reader = std::thread::spawn(move || {
+        let mut buf = String::new();
+        let _ = stdout_pipe.read_to_string(&mut buf);
+        buf
+    });
+    let stderr_reader = std::thread::spawn(move || {
+        let mut buf = String::new();
+        let _ = stderr_pipe.read_to_string(&mut buf);
+        buf
+    });
+
+    let status = crate::clock::poll_until(HOOK_POLL_INTERVAL, timeout, |_gap| {}, || Ok(child.try_wait()?))?;
+
+    let Some(status) = status else {
+        kill_hook_process_group(pgid);
+        let _ = child.wait();
+        let _ = stdout_reader.join();
+        let _ = stderr_reader.join();
+        return Err(HalleyError::Timeout {
+            minutes: timeout.as_secs() / 60,
+        });
+    };
+
+    Ok(HookOutput {
+        status: status.code().unwrap_or(-1),
+        stdout: stdout_reader.join().unwrap_or_default(),
+        stderr: stderr_reader.join().unwrap_or_default(),
+    })
+}
+
+/// Kills every process in `pgid` (the timed-out hook and anything it
+/// spawned), via the `kill` binary rather than a `libc` dependency this
+/// crate doesn't otherwise need. Same approach `restic::RealCall` uses for
+/// a timed-out restic invocation.
+fn kill_hook_process_group(pgid: u32) {
+    let _ = std::process::Command::new("kill")
+        .arg("-KILL")
+        .arg(format!("-{pgid}"))
+        .status();
+}
+
+/// Binary-unit byte sizes, e.g. `1536` -> `"1.5 KiB"`. Anything under a
+/// KiB is shown as a bare integer, since a fractional byte count would be
+/// nonsensical.
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for candidate in &UNITS[1..] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = candidate;
+    }
+    format!("{value:.1} {unit}")
+}
+
+/// A compact `<days>d <hours>h <minutes>m <seconds>s` rendering of
+/// `duration`, dropping any leading units that are zero. Durations under a
+/// second are shown in milliseconds instead, since "0s" would hide how
+/// long a fast operation actually took.
+pub fn human_duration(duration: Duration) -> String {
+    if duration.is_zero() {
+        return "0s".to_string();
+    }
+    if duration.as_secs() == 0 {
+        return format!("{}ms", duration.subsec_millis());
+    }
+
+    let total_secs = duration.as_secs();
+    let days = total_secs / 86_400;
+    let hours = (total_secs % 86_400) / 3_600;
+    let minutes = (total_secs % 3_600) / 60;
+    let seconds = total_secs % 60;
+
+    let mut parts = Vec::new();
+    if days > 0 {
+        parts.push(format!("{days}d"));
+    }
+    if hours > 0 {
+        parts.push(format!("{hours}h"));
+    }
+    if minutes > 0 {
+        parts.push(format!("{minutes}m"));
+    }
+    if seconds > 0 || parts.is_empty() {
+        parts.push(format!("{seconds}s"));
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn human_bytes_shows_bare_bytes_under_a_kib() {
+        assert_eq!(human_bytes(0), "0 B");
+        assert_eq!(human_bytes(1023), "1023 B");
+    }
+
+    #[test]
+    fn human_bytes_picks_the_largest_fitting_unit() {
+        assert_eq!(human_bytes(1024), "1.0 KiB");
+        assert_eq!(human_bytes(1536), "1.5 KiB");
+        assert_eq!(human_bytes(10 * 1024 * 1024), "10.0 MiB");
+    }
+
+    #[test]
+    fn human_bytes_handles_pib_scale() {
+        let two_pib = 2 * 1024u64.pow(5);
+        assert_eq!(human_bytes(two_pib), "2.0 PiB");
+    }
+
+    #[test]
+    fn human_duration_zero_is_zero_seconds() {
+        assert_eq!(human_duration(Duration::from_secs(0)), "0s");
+    }
+
+    #[test]
+    fn human_duration_sub_second_is_shown_in_milliseconds() {
+        assert_eq!(human_duration(Duration::from_millis(420)), "420ms");
+    }
+
+    #[test]
+    fn human_duration_drops_zero_leading_units() {
+        assert_eq!(human_duration(Duration::from_secs(65)), "1m 5s");
+        assert_eq!(human_duration(Duration::from_secs(3)), "3s");
+    }
+
+    /// Writes `contents` to a fresh temp `.sh` file and returns its path,
+    /// so a test can exercise [`run_hook`] against a real script instead of
+    /// an inline `sh -c` one-liner.
+    fn temp_script(name: &str, contents: &str) -> std::path::PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("halley-hook-test-{name}-{}.sh", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn run_hook_captures_stdout_on_success() {
+        let script = temp_script("stdout", "#!/bin/sh\necho hello from hook\n");
+        let out = run_hook(&script.display().to_string(), &[], None).unwrap();
+        let _ = std::fs::remove_file(&script);
+        assert!(out.success());
+        assert_eq!(out.stdout.trim(), "hello from hook");
+    }
+
+    #[test]
+    fn run_hook_reports_a_nonzero_exit_without_erroring() {
+        let script = temp_script("exit-nonzero", "#!/bin/sh\necho boom >&2\nexit 3\n");
+        let out = run_hook(&script.display().to_string(), &[], None).unwrap();
+        let _ = std::fs::remove_file(&script);
+        assert!(!out.success());
+        assert_eq!(out.status, 3);
+        assert_eq!(out.stderr.trim(), "boom");
+    }
+
+    #[test]
+    fn run_hook_passes_through_the_given_environment() {
+        let script = temp_script("env", "#!/bin/sh\necho \"$HALLEY_REPO_ID:$HALLEY_RESULT\"\n");
+        let env = vec![
+            ("HALLEY_REPO_ID".to_string(), "home".to_string()),
+            ("HALLEY_RESULT".to_string(), "success".to_string()),
+        ];
+        let out = run_hook(&script.display().to_string(), &env, None).unwrap();
+        let _ = std::fs::remove_file(&script);
+        assert_eq!(out.stdout.trim(), "home:success");
+    }
+
+    #[test]
+    fn run_hook_within_the_timeout_still_succeeds() {
+        let out = run_hook("sleep 0", &[], Some(Duration::from_secs(5))).unwrap();
+        assert!(out.success());
+    }
+
+    #[test]
+    fn run_hook_kills_a_hung_command_on_timeout() {
+        let started = std::time::Instant::now();
+        let err = run_hook("sleep 60", &[], Some(Duration::from_millis(300))).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(matches!(err, HalleyError::Timeout { .. }));
+    }
+
+    #[test]
+    fn human_duration_over_a_day_includes_days() {
+        // 1 day, 1 hour, 1 minute, 1 second.
+        assert_eq!(human_duration(Duration::from_secs(90_061)), "1d 1h 1m 1s");
+    }
+}
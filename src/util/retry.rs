@@ -0,0 +1,178 @@
+//! A small retry-with-backoff loop, so a caller that needs "try, then try
+//! again after a pause, up to N times" doesn't have to hand-roll its own
+//! sleep loop. [`Policy`] configures the shape; [`Policy::run`] drives it,
+//! asking a caller-supplied predicate whether each failure is worth another
+//! attempt rather than assuming.
+//!
+//! [`crate::engine::run_phase_with_retry`] is the first caller built on
+//! this. [`crate::notify`]'s webhook redelivery has a similar
+//! exponential-backoff shape but a fundamentally different execution
+//! model -- a failed delivery is persisted and picked up on some later poll
+//! of the notification queue, not retried in a blocking loop -- so it isn't
+//! a fit for this module and hasn't been migrated onto it.
+
+use std::time::Duration;
+
+/// Waits out a retry's backoff. Abstracted the same way [`crate::clock::Clock`]
+/// abstracts elapsed-time reads, so a multi-attempt [`Policy`] can be driven
+/// in a test without an actual multi-second test run.
+pub trait Sleeper {
+    fn sleep(&self, duration: Duration);
+}
+
+/// Sleeps for real via [`std::thread::sleep`].
+pub struct RealSleeper;
+
+impl Sleeper for RealSleeper {
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// How many times to try an operation, and how long to wait between
+/// attempts.
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    /// Total attempts, including the first: `1` never retries, `2` retries
+    /// once, and so on.
+    pub max_attempts: u32,
+    /// How long to wait before each retry attempt.
+    pub backoff: Duration,
+}
+
+impl Policy {
+    /// A policy that retries exactly once after `pause`, e.g.
+    /// [`crate::engine::RetryPolicy`]'s shape.
+    pub fn once(pause: Duration) -> Self {
+        Self { max_attempts: 2, backoff: pause }
+    }
+
+    /// Runs `op` up to `self.max_attempts` times via `sleeper`, calling
+    /// `should_retry` on each failure to decide whether another attempt is
+    /// worth it. Stops as soon as `op` succeeds, `should_retry` says no, or
+    /// attempts are exhausted, whichever comes first.
+    ///
+    /// `op` is passed the current attempt number (starting at `1`) so a
+    /// caller that wants to report per-attempt detail (e.g.
+    /// [`crate::engine::Attempt`]) can build it without this module needing
+    /// to know what that detail looks like. Returns the number of attempts
+    /// made alongside the final result.
+    pub fn run<T, E>(
+        &self,
+        sleeper: &dyn Sleeper,
+        mut op: impl FnMut(u32) -> Result<T, E>,
+        mut should_retry: impl FnMut(&E) -> bool,
+    ) -> (u32, Result<T, E>) {
+        let mut attempt = 1;
+        loop {
+            let result = op(attempt);
+            let retry = match &result {
+                Ok(_) => false,
+                Err(e) => attempt < self.max_attempts.max(1) && should_retry(e),
+            };
+            if !retry {
+                return (attempt, result);
+            }
+            sleeper.sleep(self.backoff);
+            attempt += 1;
+        }
+    }
+
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Records every requested sleep instead of actually pausing, so a
+    /// multi-attempt [`Policy`] can be tested without a real multi-second
+    /// test run.
+    #[derive(Default)]
+    pub struct FakeSleeper {
+        pub sleeps: RefCell<Vec<Duration>>,
+    }
+
+    impl Sleeper for FakeSleeper {
+        fn sleep(&self, duration: Duration) {
+            self.sleeps.borrow_mut().push(duration);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::FakeSleeper;
+    use super::*;
+
+    #[test]
+    fn run_stops_after_the_first_success() {
+        let policy = Policy::once(Duration::from_secs(30));
+        let sleeper = FakeSleeper::default();
+        let mut calls = 0;
+        let (attempts, result) = policy.run(
+            &sleeper,
+            |_| {
+                calls += 1;
+                Ok::<_, &str>(())
+            },
+            |_| true,
+        );
+        assert_eq!(attempts, 1);
+        assert!(result.is_ok());
+        assert_eq!(calls, 1);
+        assert!(sleeper.sleeps.borrow().is_empty());
+    }
+
+    #[test]
+    fn run_retries_once_then_gives_up_at_max_attempts() {
+        let policy = Policy::once(Duration::from_secs(30));
+        let sleeper = FakeSleeper::default();
+        let (attempts, result) = policy.run(&sleeper, |_| Err::<(), _>("boom"), |_| true);
+        assert_eq!(attempts, 2);
+        assert!(result.is_err());
+        assert_eq!(*sleeper.sleeps.borrow(), vec![Duration::from_secs(30)]);
+    }
+
+    #[test]
+    fn run_does_not_retry_when_should_retry_says_no() {
+        let policy = Policy::once(Duration::from_secs(30));
+        let sleeper = FakeSleeper::default();
+        let (attempts, result) = policy.run(&sleeper, |_| Err::<(), _>("boom"), |_| false);
+        assert_eq!(attempts, 1);
+        assert!(result.is_err());
+        assert!(sleeper.sleeps.borrow().is_empty());
+    }
+
+    #[test]
+    fn run_succeeds_on_the_retry_attempt() {
+        let policy = Policy::once(Duration::from_secs(30));
+        let sleeper = FakeSleeper::default();
+        let mut calls = 0;
+        let (attempts, result) = policy.run(
+            &sleeper,
+            |attempt| {
+                calls += 1;
+                if attempt == 1 {
+                    Err("boom")
+                } else {
+                    Ok(())
+                }
+            },
+            |_| true,
+        );
+        assert_eq!(attempts, 2);
+        assert!(result.is_ok());
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn max_attempts_of_one_never_retries_regardless_of_should_retry() {
+        let policy = Policy { max_attempts: 1, backoff: Duration::from_secs(30) };
+        let sleeper = FakeSleeper::default();
+        let (attempts, result) = policy.run(&sleeper, |_| Err::<(), _>("boom"), |_| true);
+        assert_eq!(attempts, 1);
+        assert!(result.is_err());
+        assert!(sleeper.sleeps.borrow().is_empty());
+    }
+}
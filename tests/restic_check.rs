@@ -0,0 +1,31 @@
+//! Integration test that exercises the real `restic` binary.
+//!
+//! Marked `#[ignore]`: needs `restic` on `PATH`. Run with
+//! `cargo test -- --ignored`.
+
+use std::process::Command;
+
+#[test]
+#[ignore]
+fn check_passes_on_a_freshly_initialized_repo() {
+    let dir = std::env::temp_dir().join("halley-check-it-test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let repo = dir.join("repo");
+    let status = Command::new("restic")
+        .args(["-r", repo.to_str().unwrap(), "init"])
+        .env("RESTIC_PASSWORD", "test")
+        .status()
+        .expect("restic must be on PATH for this test");
+    assert!(status.success());
+
+    let status = Command::new("restic")
+        .args(["-r", repo.to_str().unwrap(), "check"])
+        .env("RESTIC_PASSWORD", "test")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
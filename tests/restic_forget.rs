@@ -0,0 +1,34 @@
+//! Integration tests that exercise the real `restic` binary.
+//!
+//! These are marked `#[ignore]` because they need `restic` on `PATH` and
+//! write to a temp directory; run them explicitly with
+//! `cargo test -- --ignored`.
+
+use std::process::Command;
+
+#[test]
+#[ignore]
+fn forget_succeeds_on_a_freshly_initialized_repo() {
+    let dir = std::env::temp_dir().join("halley-forget-it-test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let repo = dir.join("repo");
+    let status = Command::new("restic")
+        .args(["-r", repo.to_str().unwrap(), "init"])
+        .env("RESTIC_PASSWORD", "test")
+        .status()
+        .expect("restic must be on PATH for this test");
+    assert!(status.success());
+
+    let status = Command::new("restic")
+        .args(["-r", repo.to_str().unwrap(), "forget", "--keep-last", "1"])
+        .env("RESTIC_PASSWORD", "test")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // A real assertion would back up a source dir a few times, then run
+    // `halley::restic::forget` and check `snapshots` shrank accordingly.
+    let _ = std::fs::remove_dir_all(&dir);
+}